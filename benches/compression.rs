@@ -0,0 +1,46 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use spot_messages::{
+    compression::{decode_batch, encode_batch, Codec},
+    keys, CellScan, Gps, Message, Payload,
+};
+
+/// A mapper's hour: scans with a GPS report in between
+fn traffic() -> Vec<Message> {
+    let key = keys::file::File::create_key().unwrap();
+    (0..60)
+        .map(|i| {
+            let payload = if i % 4 == 0 {
+                Payload::CellScan(CellScan::random())
+            } else {
+                Payload::Gps(Gps::rounded())
+            };
+            Message::from_payload_signed(&key, payload).unwrap()
+        })
+        .collect()
+}
+
+fn ratio(c: &mut Criterion) {
+    let msgs = traffic();
+    let plain = encode_batch(&msgs, Codec::None).unwrap().len();
+    let codecs = [
+        ("zstd", Codec::Zstd { level: 3 }),
+        ("deflate", Codec::Deflate { level: 6 }),
+    ];
+    for (name, codec) in codecs {
+        let batch = encode_batch(&msgs, codec).unwrap();
+        println!(
+            "{name}: {plain} -> {} bytes, ratio {:.2}",
+            batch.len(),
+            plain as f64 / batch.len() as f64
+        );
+        c.bench_function(&format!("encode_batch {name}"), |b| {
+            b.iter(|| encode_batch(black_box(&msgs), codec).unwrap())
+        });
+        c.bench_function(&format!("decode_batch {name}"), |b| {
+            b.iter(|| decode_batch(black_box(&batch)).unwrap())
+        });
+    }
+}
+
+criterion_group!(benches, ratio);
+criterion_main!(benches);
@@ -0,0 +1,45 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use spot_messages::{keys, CellScan, Message, Payload, ProtoMessage};
+
+fn encode(c: &mut Criterion) {
+    let payload = Payload::CellScan(CellScan::random());
+
+    c.bench_function("encode clone + fresh vec", |b| {
+        b.iter(|| {
+            let mut buf = Vec::new();
+            let proto = spot_messages::helium_proto::MapperPayload {
+                message: Some(black_box(&payload).clone().into()),
+            };
+            proto.encode(&mut buf).unwrap();
+            buf
+        })
+    });
+
+    let mut buf = Vec::new();
+    c.bench_function("encode borrow + reused buf", |b| {
+        b.iter(|| {
+            buf.clear();
+            black_box(&payload).to_proto().encode(&mut buf).unwrap();
+        })
+    });
+}
+
+fn sign(c: &mut Criterion) {
+    let key = keys::file::File::create_key().unwrap();
+    let payload = Payload::CellScan(CellScan::random());
+
+    c.bench_function("from_payload_signed", |b| {
+        b.iter(|| Message::from_payload_signed(&key, black_box(payload.clone())).unwrap())
+    });
+
+    let mut buf = Vec::new();
+    c.bench_function("from_payload_signed_with_buf", |b| {
+        b.iter(|| {
+            Message::from_payload_signed_with_buf(&key, black_box(payload.clone()), &mut buf)
+                .unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, encode, sign);
+criterion_main!(benches);
@@ -0,0 +1,72 @@
+//! A cell attach from the mapper that makes it to the aggregator that counts it:
+//!
+//! 1. the mapper scans, picks a candidate and attaches, numbered by its `Workflow`
+//! 2. it sends the attach as a signed LoRa frame
+//! 3. the receiving side verifies the frame against the mapper's pubkey
+//! 4. converts the attach to its proto for storage or forwarding
+//! 5. and aggregates attach outcomes per cell
+//!
+//! Mappers with an internet backhaul send a signed proto `Message` instead, which is shown last.
+//!
+//! Run with `cargo run --example attach_lifecycle`
+use spot_messages::{
+    analytics::AttachStats,
+    keys::{self, KeyTrait},
+    workflow::Workflow,
+    CellAttach, CellAttachResult, CellScanResult, Gps, LoraDecode, LoraEncode, Message, Payload,
+    ProtoMessage,
+};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let key = keys::file::File::create_key()?;
+
+    // mapper: scan, then attach to the best cell of our network
+    let mut workflow = Workflow::default();
+    workflow.start_scan()?;
+    let scan_fix = Gps::rounded();
+    let scan = workflow.finish_scan(
+        scan_fix,
+        vec![
+            CellScanResult::random(),
+            CellScanResult::random_our_network(),
+        ],
+    )?;
+    println!("{scan}");
+    let candidate = *workflow
+        .best_candidate()
+        .ok_or("no candidate on our network")?;
+    let mut attach_fix = scan_fix;
+    attach_fix.timestamp += chrono::Duration::seconds(12);
+    let attach = workflow.finish_attach(&candidate, attach_fix, CellAttachResult::Connected)?;
+    let frame = attach.to_lora_bytes_with_signature(&key)?;
+    println!("{attach} as a {} byte LoRa frame", frame.len());
+
+    // receiver: the pubkey is known from onboarding, everything else comes from the frame
+    let pubkey = key.pubkey()?;
+    let received = CellAttach::from_lora_slice_with_verified_signature(&pubkey, &frame)?;
+    // the fix is rounded to what the frame carries, so nothing is lost
+    assert_eq!(received, attach);
+
+    let proto = Payload::CellAttach(received).to_proto();
+    println!("{} byte MapperPayload", proto.encode_to_vec().len());
+
+    let mut stats = AttachStats::new(chrono::Duration::hours(24));
+    stats.record(&pubkey, &received);
+
+    // the same attach over an internet backhaul
+    let mut bytes = Vec::new();
+    Message::from_payload_signed(&key, Payload::CellAttach(attach))?.encode_to(&mut bytes)?;
+    let msg = Message::decode_from_with_signature_verification(&bytes)?;
+    stats.record_message(&msg);
+
+    let cell = stats
+        .cell(received.candidate.cell_id)
+        .ok_or("cell not counted")?;
+    println!(
+        "cell {:x}: {} attaches, success ratio {:?}",
+        received.candidate.cell_id,
+        cell.total(),
+        cell.success_ratio()
+    );
+    Ok(())
+}
@@ -0,0 +1,439 @@
+//! Shared metric definitions computed by oracles over streams of messages.
+//!
+//! `AttachStats` checkpoints to a versioned binary format, so that jobs can restart without
+//! replaying the window. All integers are big endian:
+//!
+//! | field       | size     |                                          |
+//! |-------------|----------|------------------------------------------|
+//! | magic       | 3        | `b"SPA"`                                 |
+//! | version     | 1        | currently 2                              |
+//! | window      | 8        | i64 seconds                              |
+//! | event count | 4        |                                          |
+//! | events      |          | oldest first, each as below              |
+//! | timestamp   | 8        | i64 milliseconds since the unix epoch    |
+//! | device      | 2 + len  | b58 pubkey, utf-8                        |
+//! | cell_id     | 4        |                                          |
+//! | coverage    | 8        | h3 `CoverageCell` of the fix, 0 if none  |
+//! | result      | 1        | as in `MapperAttachResult`               |
+//!
+//! Version 1 checkpoints have no coverage field and still load, without coverage cells. Counts
+//! are not stored but rebuilt from the events.
+use super::{
+    CellAttach, CellAttachResult, CoverageCell, DateTime, Deserialize, Error, Message, Payload,
+    PublicKey, Result, Serialize, Utc,
+};
+use chrono::TimeZone;
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{Read, Write},
+    path::Path,
+};
+
+const CHECKPOINT_MAGIC: &[u8; 3] = b"SPA";
+/// Version of the checkpoint format, bumped on any incompatible change
+pub const CHECKPOINT_VERSION: u8 = 2;
+
+/// Attach outcomes by result
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttachCounts {
+    pub connected: u64,
+    pub limited_service: u64,
+    pub no_connection: u64,
+    pub search: u64,
+    pub no_network_service: u64,
+    pub no_attach: u64,
+}
+
+impl AttachCounts {
+    fn slot(&mut self, result: CellAttachResult) -> &mut u64 {
+        match result {
+            CellAttachResult::Connected => &mut self.connected,
+            CellAttachResult::LimitedService => &mut self.limited_service,
+            CellAttachResult::NoConnection => &mut self.no_connection,
+            CellAttachResult::Search => &mut self.search,
+            CellAttachResult::NoNetworkService => &mut self.no_network_service,
+            CellAttachResult::NoAttach => &mut self.no_attach,
+        }
+    }
+
+    pub fn record(&mut self, result: CellAttachResult) {
+        *self.slot(result) += 1;
+    }
+
+    fn forget(&mut self, result: CellAttachResult) {
+        let slot = self.slot(result);
+        *slot = slot.saturating_sub(1);
+    }
+
+    pub fn total(&self) -> u64 {
+        self.connected
+            + self.limited_service
+            + self.no_connection
+            + self.search
+            + self.no_network_service
+            + self.no_attach
+    }
+
+    /// Fraction of attempts that reached `Connected`, None without attempts
+    pub fn success_ratio(&self) -> Option<f64> {
+        let total = self.total();
+        (total > 0).then(|| self.connected as f64 / total as f64)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct AttachEvent {
+    timestamp: DateTime<Utc>,
+    device: String,
+    cell_id: u32,
+    coverage: Option<CoverageCell>,
+    result: CellAttachResult,
+}
+
+/// Attach success accumulator over a sliding time window, with per cell, per coverage cell and
+/// per device breakdowns. Attaches are windowed by their GPS timestamp; events older than `window` before
+/// the newest recorded attach are dropped.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AttachStats {
+    window_secs: i64,
+    events: VecDeque<AttachEvent>,
+    total: AttachCounts,
+    by_cell: HashMap<u32, AttachCounts>,
+    by_coverage: HashMap<CoverageCell, AttachCounts>,
+    /// keyed by b58 pubkey
+    by_device: HashMap<String, AttachCounts>,
+}
+
+impl AttachStats {
+    pub fn new(window: chrono::Duration) -> Self {
+        Self {
+            window_secs: window.num_seconds(),
+            events: VecDeque::new(),
+            total: AttachCounts::default(),
+            by_cell: HashMap::new(),
+            by_coverage: HashMap::new(),
+            by_device: HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, device: &PublicKey, attach: &CellAttach) {
+        self.record_event(AttachEvent {
+            timestamp: attach.gps.timestamp,
+            device: device.to_string(),
+            cell_id: attach.candidate.cell_id,
+            coverage: CoverageCell::from_gps(&attach.gps).ok(),
+            result: attach.result,
+        });
+    }
+
+    fn record_event(&mut self, event: AttachEvent) {
+        self.total.record(event.result);
+        self.by_cell
+            .entry(event.cell_id)
+            .or_default()
+            .record(event.result);
+        if let Some(coverage) = event.coverage {
+            self.by_coverage
+                .entry(coverage)
+                .or_default()
+                .record(event.result);
+        }
+        self.by_device
+            .entry(event.device.clone())
+            .or_default()
+            .record(event.result);
+        // keep events sorted so that expiry only looks at the front
+        let position = self
+            .events
+            .iter()
+            .rposition(|e| e.timestamp <= event.timestamp)
+            .map_or(0, |i| i + 1);
+        self.events.insert(position, event);
+        self.expire();
+    }
+
+    /// Records the message if it carries a CellAttach, ignoring other payloads
+    pub fn record_message(&mut self, msg: &Message) {
+        if let Payload::CellAttach(attach) = &msg.payload {
+            self.record(&msg.pubkey, attach);
+        }
+    }
+
+    /// Writes a checkpoint that `load` restores
+    pub fn save<W: Write>(&self, mut writer: W) -> Result {
+        let mut buf = Vec::with_capacity(16 + self.events.len() * 64);
+        buf.extend_from_slice(CHECKPOINT_MAGIC);
+        buf.push(CHECKPOINT_VERSION);
+        buf.extend_from_slice(&self.window_secs.to_be_bytes());
+        let count = u32::try_from(self.events.len())
+            .map_err(|_| Error::InvalidCheckpoint("more than u32::MAX events"))?;
+        buf.extend_from_slice(&count.to_be_bytes());
+        for event in &self.events {
+            buf.extend_from_slice(&event.timestamp.timestamp_millis().to_be_bytes());
+            let device = u16::try_from(event.device.len())
+                .map_err(|_| Error::InvalidCheckpoint("device longer than u16::MAX"))?;
+            buf.extend_from_slice(&device.to_be_bytes());
+            buf.extend_from_slice(event.device.as_bytes());
+            buf.extend_from_slice(&event.cell_id.to_be_bytes());
+            let coverage = event.coverage.map_or(0, |cell| u64::from(cell.index()));
+            buf.extend_from_slice(&coverage.to_be_bytes());
+            buf.push(result_to_u8(event.result));
+        }
+        writer.write_all(&buf)?;
+        Ok(())
+    }
+
+    pub fn load<R: Read>(mut reader: R) -> Result<Self> {
+        let mut header = [0u8; 4];
+        reader.read_exact(&mut header)?;
+        if &header[..3] != CHECKPOINT_MAGIC {
+            return Err(Error::InvalidCheckpoint("bad magic"));
+        }
+        let version = header[3];
+        if !(1..=CHECKPOINT_VERSION).contains(&version) {
+            return Err(Error::InvalidCheckpoint("unsupported version"));
+        }
+        let mut stats = AttachStats {
+            window_secs: i64::from_be_bytes(read_array(&mut reader)?),
+            ..AttachStats::new(chrono::Duration::zero())
+        };
+        let count = u32::from_be_bytes(read_array(&mut reader)?);
+        for _ in 0..count {
+            let timestamp = Utc
+                .timestamp_millis_opt(i64::from_be_bytes(read_array(&mut reader)?))
+                .single()
+                .ok_or(Error::InvalidCheckpoint("timestamp out of range"))?;
+            let mut device = vec![0; u16::from_be_bytes(read_array(&mut reader)?).into()];
+            reader.read_exact(&mut device)?;
+            let device = String::from_utf8(device)
+                .map_err(|_| Error::InvalidCheckpoint("device is not utf-8"))?;
+            let cell_id = u32::from_be_bytes(read_array(&mut reader)?);
+            let coverage = match version {
+                1 => None,
+                _ => match u64::from_be_bytes(read_array(&mut reader)?) {
+                    0 => None,
+                    index => Some(
+                        h3o::CellIndex::try_from(index)
+                            .ok()
+                            .and_then(|cell| CoverageCell::new(cell).ok())
+                            .ok_or(Error::InvalidCheckpoint("invalid coverage cell"))?,
+                    ),
+                },
+            };
+            let [result] = read_array(&mut reader)?;
+            stats.record_event(AttachEvent {
+                timestamp,
+                device,
+                cell_id,
+                coverage,
+                result: result_from_u8(result)
+                    .ok_or(Error::InvalidCheckpoint("unknown attach result"))?,
+            });
+        }
+        Ok(stats)
+    }
+
+    /// Saves to a temporary file renamed over `path`, so that a crash leaves either the old or
+    /// the new checkpoint
+    pub fn save_to_path<P: AsRef<Path>>(&self, path: P) -> Result {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut file = std::fs::File::create(&tmp)?;
+        self.save(&mut file)?;
+        file.sync_all()?;
+        std::fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    pub fn load_from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::load(std::io::BufReader::new(std::fs::File::open(path)?))
+    }
+
+    fn expire(&mut self) {
+        let Some(newest) = self.events.back().map(|e| e.timestamp) else {
+            return;
+        };
+        while let Some(event) = self.events.front() {
+            if newest - event.timestamp <= chrono::Duration::seconds(self.window_secs) {
+                break;
+            }
+            let event = self.events.pop_front().expect("front exists");
+            self.total.forget(event.result);
+            forget_in(&mut self.by_cell, &event.cell_id, event.result);
+            if let Some(coverage) = &event.coverage {
+                forget_in(&mut self.by_coverage, coverage, event.result);
+            }
+            forget_in(&mut self.by_device, &event.device, event.result);
+        }
+    }
+
+    pub fn total(&self) -> AttachCounts {
+        self.total
+    }
+
+    pub fn cell(&self, cell_id: u32) -> Option<AttachCounts> {
+        self.by_cell.get(&cell_id).copied()
+    }
+
+    /// Attaches made from within `cell`, by the fix they were made at
+    pub fn coverage(&self, cell: CoverageCell) -> Option<AttachCounts> {
+        self.by_coverage.get(&cell).copied()
+    }
+
+    pub fn device(&self, device: &PublicKey) -> Option<AttachCounts> {
+        self.by_device.get(&device.to_string()).copied()
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = (u32, AttachCounts)> + '_ {
+        self.by_cell
+            .iter()
+            .map(|(cell_id, counts)| (*cell_id, *counts))
+    }
+
+    pub fn coverage_cells(&self) -> impl Iterator<Item = (CoverageCell, AttachCounts)> + '_ {
+        self.by_coverage
+            .iter()
+            .map(|(cell, counts)| (*cell, *counts))
+    }
+
+    /// Devices by b58 pubkey
+    pub fn devices(&self) -> impl Iterator<Item = (&str, AttachCounts)> + '_ {
+        self.by_device
+            .iter()
+            .map(|(device, counts)| (device.as_str(), *counts))
+    }
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn result_to_u8(result: CellAttachResult) -> u8 {
+    match result {
+        CellAttachResult::NoAttach => 0,
+        CellAttachResult::Connected => 1,
+        CellAttachResult::LimitedService => 2,
+        CellAttachResult::NoConnection => 3,
+        CellAttachResult::Search => 4,
+        CellAttachResult::NoNetworkService => 5,
+    }
+}
+
+fn result_from_u8(value: u8) -> Option<CellAttachResult> {
+    Some(match value {
+        0 => CellAttachResult::NoAttach,
+        1 => CellAttachResult::Connected,
+        2 => CellAttachResult::LimitedService,
+        3 => CellAttachResult::NoConnection,
+        4 => CellAttachResult::Search,
+        5 => CellAttachResult::NoNetworkService,
+        _ => return None,
+    })
+}
+
+fn forget_in<K: std::hash::Hash + Eq>(
+    map: &mut HashMap<K, AttachCounts>,
+    key: &K,
+    result: CellAttachResult,
+) {
+    if let Some(counts) = map.get_mut(key) {
+        counts.forget(result);
+        if counts.total() == 0 {
+            map.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{keys, keys::KeyTrait, AttachCandidate, CellScanResult, Gps};
+
+    fn attach(seconds: i64, cell_id: u32, result: CellAttachResult) -> CellAttach {
+        let mut gps = Gps::rounded();
+        gps.timestamp = gps.timestamp + chrono::Duration::seconds(seconds);
+        let mut candidate = AttachCandidate::from(CellScanResult::random());
+        candidate.cell_id = cell_id;
+        CellAttach {
+            attach_counter: seconds as u32,
+            gps,
+            candidate,
+            result,
+            failure_cause: None,
+            sim: None,
+        }
+    }
+
+    #[test]
+    fn windowed_breakdowns() {
+        let a = keys::file::File::create_key().unwrap().pubkey().unwrap();
+        let b = keys::file::File::create_key().unwrap().pubkey().unwrap();
+        let mut stats = AttachStats::new(chrono::Duration::seconds(60));
+
+        stats.record(&a, &attach(0, 1, CellAttachResult::NoConnection));
+        stats.record(&a, &attach(10, 1, CellAttachResult::Connected));
+        stats.record(&b, &attach(20, 2, CellAttachResult::LimitedService));
+        assert_eq!(stats.total().total(), 3);
+        assert_eq!(stats.cell(1).unwrap().success_ratio(), Some(0.5));
+        assert_eq!(stats.device(&b).unwrap().limited_service, 1);
+        let home = CoverageCell::from_gps(&Gps::rounded()).unwrap();
+        assert_eq!(stats.coverage(home).unwrap().total(), 3);
+
+        // pushes the first attach out of the window
+        stats.record(&b, &attach(65, 2, CellAttachResult::Connected));
+        assert_eq!(stats.total().total(), 3);
+        assert_eq!(stats.cell(1).unwrap().success_ratio(), Some(1.0));
+        assert_eq!(stats.device(&a).unwrap().no_connection, 0);
+
+        stats.record(&b, &attach(200, 3, CellAttachResult::Search));
+        assert_eq!(stats.cell(1), None);
+        assert_eq!(stats.device(&a), None);
+        assert_eq!(stats.total().search, 1);
+        assert_eq!(stats.coverage_cells().count(), 1);
+        assert_eq!(stats.coverage(home).unwrap().total(), 1);
+    }
+
+    #[test]
+    fn checkpoint_roundtrip() {
+        let a = keys::file::File::create_key().unwrap().pubkey().unwrap();
+        let mut stats = AttachStats::new(chrono::Duration::seconds(60));
+        stats.record(&a, &attach(0, 1, CellAttachResult::NoConnection));
+        stats.record(&a, &attach(30, 2, CellAttachResult::Connected));
+
+        let mut checkpoint = Vec::new();
+        stats.save(&mut checkpoint).unwrap();
+        let mut restored = AttachStats::load(checkpoint.as_slice()).unwrap();
+        assert_eq!(restored, stats);
+
+        // the window carries over
+        restored.record(&a, &attach(70, 2, CellAttachResult::Connected));
+        assert_eq!(restored.cell(1), None);
+        assert_eq!(restored.device(&a).unwrap().connected, 2);
+
+        checkpoint[3] = CHECKPOINT_VERSION + 1;
+        assert!(matches!(
+            AttachStats::load(checkpoint.as_slice()),
+            Err(Error::InvalidCheckpoint("unsupported version"))
+        ));
+    }
+
+    #[test]
+    fn version_1_checkpoint_loads_without_coverage() {
+        let a = keys::file::File::create_key().unwrap().pubkey().unwrap();
+        let mut stats = AttachStats::new(chrono::Duration::seconds(60));
+        stats.record(&a, &attach(0, 1, CellAttachResult::Connected));
+        let mut checkpoint = Vec::new();
+        stats.save(&mut checkpoint).unwrap();
+
+        // a version 1 event is the same without the 8 coverage bytes before the result
+        let result = checkpoint.len() - 1;
+        checkpoint.drain(result - 8..result);
+        checkpoint[3] = 1;
+        let restored = AttachStats::load(checkpoint.as_slice()).unwrap();
+        assert_eq!(restored.cell(1).unwrap().connected, 1);
+        assert_eq!(restored.coverage_cells().count(), 0);
+    }
+}
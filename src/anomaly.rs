@@ -0,0 +1,206 @@
+//! Report rate anomalies per device and payload kind, for censorship and gaming detection.
+//!
+//! `RateTracker` keeps two exponentially weighted moving averages of the interval between
+//! reports: a slow baseline and a fast one following recent traffic. A burst is the fast
+//! interval falling `burst_factor` times below the baseline; a silence is no report for
+//! `silence_factor` baseline intervals. Each is reported once, when it starts.
+use super::{DateTime, Deserialize, Message, PayloadKind, PublicKey, Serialize, Utc};
+use std::collections::HashMap;
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnomalyConfig {
+    /// Weight of a new interval in the baseline
+    pub slow_alpha: f64,
+    /// Weight of a new interval in the recent average
+    pub fast_alpha: f64,
+    pub burst_factor: f64,
+    pub silence_factor: f64,
+    /// Intervals seen before a device is judged, so that fresh devices don't raise anomalies
+    pub min_samples: u32,
+}
+
+impl Default for AnomalyConfig {
+    fn default() -> Self {
+        Self {
+            slow_alpha: 0.02,
+            fast_alpha: 0.5,
+            burst_factor: 10.0,
+            silence_factor: 20.0,
+            min_samples: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Anomaly {
+    Burst {
+        #[serde(with = "crate::serde_helpers::pubkey")]
+        pubkey: PublicKey,
+        kind: PayloadKind,
+        at: DateTime<Utc>,
+        baseline_interval_s: f64,
+        recent_interval_s: f64,
+    },
+    Silence {
+        #[serde(with = "crate::serde_helpers::pubkey")]
+        pubkey: PublicKey,
+        kind: PayloadKind,
+        last_seen: DateTime<Utc>,
+        baseline_interval_s: f64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct DeviceRate {
+    pubkey: PublicKey,
+    last_seen: DateTime<Utc>,
+    slow_interval_s: f64,
+    fast_interval_s: f64,
+    samples: u32,
+    in_burst: bool,
+    silent: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RateTracker {
+    config: AnomalyConfig,
+    /// keyed by b58 pubkey and kind
+    devices: HashMap<(String, PayloadKind), DeviceRate>,
+}
+
+impl RateTracker {
+    pub fn new(config: AnomalyConfig) -> Self {
+        Self {
+            config,
+            devices: HashMap::new(),
+        }
+    }
+
+    /// Records a report received at `at`, returning the burst it starts if any. Reports older
+    /// than the last one of the device are ignored.
+    pub fn observe(
+        &mut self,
+        pubkey: &PublicKey,
+        kind: PayloadKind,
+        at: DateTime<Utc>,
+    ) -> Option<Anomaly> {
+        let b58 = pubkey.to_string();
+        let Some(rate) = self.devices.get_mut(&(b58.clone(), kind)) else {
+            self.devices.insert(
+                (b58, kind),
+                DeviceRate {
+                    pubkey: pubkey.clone(),
+                    last_seen: at,
+                    slow_interval_s: 0.0,
+                    fast_interval_s: 0.0,
+                    samples: 0,
+                    in_burst: false,
+                    silent: false,
+                },
+            );
+            return None;
+        };
+        if at < rate.last_seen {
+            return None;
+        }
+        let interval_s = (at - rate.last_seen).num_milliseconds() as f64 / 1000.0;
+        rate.last_seen = at;
+        rate.silent = false;
+        if rate.samples == 0 {
+            rate.slow_interval_s = interval_s;
+            rate.fast_interval_s = interval_s;
+        } else {
+            rate.slow_interval_s += self.config.slow_alpha * (interval_s - rate.slow_interval_s);
+            rate.fast_interval_s += self.config.fast_alpha * (interval_s - rate.fast_interval_s);
+        }
+        rate.samples = rate.samples.saturating_add(1);
+
+        let bursting = rate.samples >= self.config.min_samples
+            && rate.fast_interval_s * self.config.burst_factor <= rate.slow_interval_s;
+        let started = bursting && !rate.in_burst;
+        rate.in_burst = bursting;
+        started.then(|| Anomaly::Burst {
+            pubkey: pubkey.clone(),
+            kind,
+            at,
+            baseline_interval_s: rate.slow_interval_s,
+            recent_interval_s: rate.fast_interval_s,
+        })
+    }
+
+    /// Records a message at its reception time, or its fix time when it has no `IngestMeta`
+    pub fn observe_message(&mut self, msg: &Message) -> Option<Anomaly> {
+        let at = msg
+            .ingest_meta
+            .as_ref()
+            .map_or(msg.payload.gps().timestamp, |meta| meta.received_at);
+        self.observe(&msg.pubkey, msg.payload.kind(), at)
+    }
+
+    /// Silences that started by `now`, each reported once until the device reports again
+    pub fn check_silences(&mut self, now: DateTime<Utc>) -> Vec<Anomaly> {
+        let config = self.config;
+        self.devices
+            .iter_mut()
+            .filter(|(_, rate)| !rate.silent && rate.samples >= config.min_samples)
+            .filter(|(_, rate)| {
+                let silent_s = (now - rate.last_seen).num_milliseconds() as f64 / 1000.0;
+                silent_s > rate.slow_interval_s * config.silence_factor
+            })
+            .map(|((_, kind), rate)| {
+                rate.silent = true;
+                Anomaly::Silence {
+                    pubkey: rate.pubkey.clone(),
+                    kind: *kind,
+                    last_seen: rate.last_seen,
+                    baseline_interval_s: rate.slow_interval_s,
+                }
+            })
+            .collect()
+    }
+
+    /// Stops tracking devices silent since before `cutoff`
+    pub fn forget_before(&mut self, cutoff: DateTime<Utc>) {
+        self.devices.retain(|_, rate| rate.last_seen >= cutoff);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::keys::{self, KeyTrait};
+    use chrono::Duration;
+
+    #[test]
+    fn bursts_and_silences_are_reported_once() {
+        let pubkey = keys::file::File::create_key().unwrap().pubkey().unwrap();
+        let mut tracker = RateTracker::default();
+        let mut at = crate::Gps::rounded().timestamp;
+        for _ in 0..20 {
+            assert_eq!(tracker.observe(&pubkey, PayloadKind::Beacon, at), None);
+            at += Duration::seconds(60);
+        }
+
+        let mut bursts = Vec::new();
+        for _ in 0..20 {
+            at += Duration::seconds(1);
+            bursts.extend(tracker.observe(&pubkey, PayloadKind::Beacon, at));
+        }
+        assert!(matches!(
+            bursts.as_slice(),
+            [Anomaly::Burst {
+                kind: PayloadKind::Beacon,
+                ..
+            }]
+        ));
+
+        assert!(tracker.check_silences(at + Duration::minutes(5)).is_empty());
+        let silences = tracker.check_silences(at + Duration::days(1));
+        assert!(matches!(
+            silences.as_slice(),
+            [Anomaly::Silence { last_seen, .. }] if *last_seen == at
+        ));
+        assert!(tracker.check_silences(at + Duration::days(2)).is_empty());
+    }
+}
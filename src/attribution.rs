@@ -0,0 +1,172 @@
+//! How the reward of a witnessed message is split between its gateways. Oracles should all go
+//! through `Message::witness_shares_with` so that they agree on the split to the last digit.
+use super::{Deserialize, LoraGw, Message, PublicKey, Serialize};
+use rust_decimal::Decimal;
+
+/// Weight of a witness before normalization. Weights must not be negative; a zero weight earns
+/// nothing.
+pub trait WitnessWeighting {
+    /// `index` is the position of the witness in the order gateways were reported in
+    fn weight(&self, index: usize, witness: &LoraGw) -> Decimal;
+}
+
+/// Every witness gets the same share
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Equal;
+
+impl WitnessWeighting for Equal {
+    fn weight(&self, _index: usize, _witness: &LoraGw) -> Decimal {
+        Decimal::ONE
+    }
+}
+
+/// Weighted by how far the SNR is above `floor_db`. Witnesses at or below it earn nothing.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BySnr {
+    pub floor_db: Decimal,
+}
+
+impl Default for BySnr {
+    /// -20 dB, the demodulation floor at SF12
+    fn default() -> Self {
+        Self {
+            floor_db: Decimal::new(-20, 0),
+        }
+    }
+}
+
+impl WitnessWeighting for BySnr {
+    fn weight(&self, _index: usize, witness: &LoraGw) -> Decimal {
+        (witness.snr - self.floor_db).max(Decimal::ZERO)
+    }
+}
+
+/// Weighted by how far the RSSI is above `floor_dbm`. Witnesses at or below it earn nothing.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ByRssi {
+    pub floor_dbm: Decimal,
+}
+
+impl Default for ByRssi {
+    fn default() -> Self {
+        Self {
+            floor_dbm: Decimal::new(-140, 0),
+        }
+    }
+}
+
+impl WitnessWeighting for ByRssi {
+    fn weight(&self, _index: usize, witness: &LoraGw) -> Decimal {
+        (witness.rssi - self.floor_dbm).max(Decimal::ZERO)
+    }
+}
+
+/// The first reported witness weighs 1, each following one `decay` times the previous
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FirstSeen {
+    pub decay: Decimal,
+}
+
+impl Default for FirstSeen {
+    fn default() -> Self {
+        Self {
+            decay: Decimal::new(5, 1),
+        }
+    }
+}
+
+impl WitnessWeighting for FirstSeen {
+    fn weight(&self, index: usize, _witness: &LoraGw) -> Decimal {
+        (0..index).fold(Decimal::ONE, |weight, _| weight * self.decay)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WitnessShare {
+    #[serde(with = "crate::serde_helpers::pubkey")]
+    pub pubkey: PublicKey,
+    /// Fraction of the reward, from 0 to 1
+    pub share: Decimal,
+}
+
+impl Message {
+    /// `witness_shares_with` weighted by SNR
+    pub fn witness_shares(&self) -> Vec<WitnessShare> {
+        self.witness_shares_with(&BySnr::default())
+    }
+
+    /// Shares of the reward per gateway, in the order gateways were first reported in, summing
+    /// to 1 up to the last digit of `Decimal`. A gateway reported more than once counts once,
+    /// with its best weight. Gateways with a zero weight are left out, and nobody earns if all
+    /// weights are zero.
+    pub fn witness_shares_with<W: WitnessWeighting + ?Sized>(
+        &self,
+        weighting: &W,
+    ) -> Vec<WitnessShare> {
+        let mut weights: Vec<(&PublicKey, Decimal)> = Vec::new();
+        for (index, witness) in self.lora_gws.iter().enumerate() {
+            let weight = weighting.weight(index, witness).max(Decimal::ZERO);
+            match weights
+                .iter_mut()
+                .find(|(pubkey, _)| **pubkey == witness.pubkey)
+            {
+                Some((_, best)) => *best = (*best).max(weight),
+                None => weights.push((&witness.pubkey, weight)),
+            }
+        }
+        weights.retain(|(_, weight)| !weight.is_zero());
+        let total: Decimal = weights.iter().map(|(_, weight)| weight).sum();
+        weights
+            .into_iter()
+            .map(|(pubkey, weight)| WitnessShare {
+                pubkey: pubkey.clone(),
+                share: weight / total,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::keys;
+
+    fn witness(snr: i64) -> LoraGw {
+        LoraGw {
+            snr: Decimal::new(snr, 0),
+            rssi: Decimal::new(-100, 0),
+            ..LoraGw::random()
+        }
+    }
+
+    #[test]
+    fn shares_are_normalized() {
+        let key = keys::file::File::create_key().unwrap();
+        let mut msg =
+            Message::from_payload_signed(&key, crate::Payload::Gps(crate::Gps::rounded())).unwrap();
+        let (strong, weak, deaf) = (witness(10), witness(-10), witness(-25));
+        msg.lora_gws = vec![weak.clone(), strong.clone(), deaf, strong.clone()].into();
+
+        let shares = msg.witness_shares();
+        let split: Vec<_> = shares.iter().map(|s| (&s.pubkey, s.share)).collect();
+        assert_eq!(
+            split,
+            [
+                (&weak.pubkey, Decimal::new(25, 2)),
+                (&strong.pubkey, Decimal::new(75, 2))
+            ]
+        );
+
+        let first_seen = msg.witness_shares_with(&FirstSeen::default());
+        let total: Decimal = first_seen.iter().map(|s| s.share).sum();
+        assert_eq!(first_seen.len(), 3);
+        assert_eq!(total.round_dp(20), Decimal::ONE);
+        assert_eq!(
+            first_seen[0].share.round_dp(20),
+            (Decimal::new(4, 0) / Decimal::new(7, 0)).round_dp(20)
+        );
+
+        msg.lora_gws.strip();
+        assert!(msg.witness_shares_with(&Equal).is_empty());
+    }
+}
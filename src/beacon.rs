@@ -1,21 +1,155 @@
 use super::{
+    burst::BurstTag,
+    epoch::{Epoch, VersionedCodec},
+    gps::LoraFix,
     gps::{altitude, hdop, latlon, speed, time, Gps},
-    mapper_msg_with_payload, Deserialize, Error, IntoFromLoraPayload, Payload, Result, Serialize,
+    lora_payload::split_fixed,
+    Deserialize, DumpFields, EncodeMode, EnvelopeSig, Error, FieldDump, IntoFromLoraPayload,
+    LoraDecode, LoraEncode, Message, Payload, PublicKey, Result, Serialize, TruncatedDeviceSig,
 };
 use helium_proto::MapperBeaconV1;
 use modular_bitfield_msb::{bitfield, specifiers::*};
+use rust_decimal::{prelude::ToPrimitive, Decimal};
 
 #[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub struct Beacon {
     pub gps: Gps,
-    pub signature: Vec<u8>,
+    /// Commitment to the mapper's last scan, not the signature of this message. See
+    /// `TruncatedDeviceSig`.
+    pub signature: TruncatedDeviceSig,
+    /// Set on beacons sent in a burst. Only the `BEACON_V2` and later layouts carry it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub burst: Option<BurstTag>,
+    /// Conducted transmit power. The proto extension and the `BEACON_V3` and later layouts carry
+    /// it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx_power_dbm: Option<i8>,
+    /// Gain of the mapper antenna, in 0.25 dBi steps on the air and 0.01 dBi steps in the proto
+    /// extension. The `BEACON_V3` and later layouts carry it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub antenna_gain_dbi: Option<Decimal>,
 }
 
 const PAYLOAD_SIZE: usize = 17;
 
+/// `Versioned` layout adding the fix time's hundredths of a second after the v1 frame, for
+/// Doppler and TDOA research. The high bit of that byte flags a `BurstTag` in the 2 bytes after.
+pub const BEACON_V2: u8 = 2;
+
+const HAS_BURST: u8 = 0x80;
+
+/// `BEACON_V2` followed by a byte of transmit power and one of antenna gain, so coverage models
+/// can compare signal levels of mappers with different radios. The high bit of each byte flags
+/// that the value is known.
+pub const BEACON_V3: u8 = 3;
+
+/// `BEACON_V3` with the fix in an adaptive precision frame. When the HDOP, as the frame carries
+/// it, is at least `COARSE_HDOP` the position is in 1e-4 degree steps (11.1 m) and the frame is
+/// 16 bytes instead of 17; otherwise it is in 1e-5 steps as in the other layouts. A leading bit
+/// says which, so other fields keep their usual precision.
+///
+/// Decoding is exact for fine fixes. Coarse fixes decode with lat and lon rounded half to even
+/// at 4 places, and as the choice is made on the carried HDOP, encoding a decoded beacon again
+/// gives the same bytes.
+pub const BEACON_V4: u8 = 4;
+
+/// `BEACON_V4` followed by a byte of horizontal and one of vertical accuracy of the fix, in 1 m
+/// steps, for receivers that report them (`Gps::h_acc_m`, `Gps::v_acc_m`). The high bit of each
+/// byte flags that the value is known. `EncodeMode::Saturating` sends accuracies above 127 m as
+/// 127 m, which still keeps the fix from counting as locked, and `EncodeMode::Strict` rejects
+/// them, as it does negative ones.
+pub const BEACON_V5: u8 = 5;
+
+/// About 20 m horizontal accuracy, see `GNSS_UERE_M`
+pub const COARSE_HDOP: Decimal = Decimal::from_parts(4_00, 0, 0, false, 2);
+
+const COARSE_PAYLOAD_SIZE: usize = 16;
+
+const HAS_RADIO_VALUE: u8 = 0x80;
+const TX_POWER_OFFSET_DBM: i128 = 20;
+const ANTENNA_GAIN_OFFSET_QUARTERS: i128 = 32;
+
 impl Beacon {
-    pub fn new(gps: Gps, signature: Vec<u8>) -> Self {
-        Self { gps, signature }
+    pub fn new(gps: Gps, signature: impl Into<TruncatedDeviceSig>) -> Self {
+        Self {
+            gps,
+            signature: signature.into(),
+            burst: None,
+            tx_power_dbm: None,
+            antenna_gain_dbi: None,
+        }
+    }
+
+    pub fn with_burst(mut self, burst: BurstTag) -> Self {
+        self.burst = Some(burst);
+        self
+    }
+
+    pub fn with_radio(mut self, tx_power_dbm: i8, antenna_gain_dbi: Decimal) -> Self {
+        self.tx_power_dbm = Some(tx_power_dbm);
+        self.antenna_gain_dbi = Some(antenna_gain_dbi);
+        self
+    }
+
+    /// Effective isotropic radiated power, ignoring cable losses. `None` unless both the
+    /// transmit power and antenna gain are known.
+    pub fn eirp_dbm(&self) -> Option<Decimal> {
+        Some(Decimal::from(self.tx_power_dbm?) + self.antenna_gain_dbi?)
+    }
+
+    /// What a gateway would have received with `rssi` had the beacon been sent at
+    /// `reference_eirp_dbm`, for comparing witnesses of mappers with different radios
+    pub fn normalize_rssi(&self, rssi: Decimal, reference_eirp_dbm: Decimal) -> Option<Decimal> {
+        Some(rssi + reference_eirp_dbm - self.eirp_dbm()?)
+    }
+
+    pub fn device_commitment(&self) -> &TruncatedDeviceSig {
+        &self.signature
+    }
+
+    /// Whether the beacon commits to the scan `policy` points at, as signed by `pubkey`. The
+    /// commitment can't be verified on its own: it is matched against the tail of the full
+    /// signature of the committed scan, which is what authenticates it.
+    ///
+    /// Fails if the committed scan's own signature does not verify.
+    pub fn matches_truncated_signature(
+        &self,
+        pubkey: &PublicKey,
+        policy: &TruncatedSigPolicy,
+    ) -> Result<bool> {
+        match policy {
+            TruncatedSigPolicy::Skip => Ok(true),
+            TruncatedSigPolicy::CommittedScan(scan) => {
+                if &scan.pubkey != pubkey {
+                    return Ok(false);
+                }
+                scan.verify_envelope()?;
+                Ok(self.signature.is_tail_of(&scan.signature))
+            }
+            TruncatedSigPolicy::CommittedSignature(signature) => {
+                Ok(self.signature.is_tail_of(signature))
+            }
+        }
+    }
+}
+
+/// How `Beacon::matches_truncated_signature` checks the commitment
+#[derive(Debug, Copy, Clone)]
+pub enum TruncatedSigPolicy<'a> {
+    /// Every commitment matches, for deployments that don't track scans
+    Skip,
+    /// The scan message the beacon should commit to. Its signature is verified as well.
+    CommittedScan(&'a Message),
+    /// The full signature of the committed scan, already verified by the caller, eg: as
+    /// stored when the scan was ingested
+    CommittedSignature(&'a EnvelopeSig),
+}
+
+impl std::fmt::Display for Beacon {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Beacon{")?;
+        self.gps.fmt_fix(f)?;
+        f.write_str("}")
     }
 }
 
@@ -25,9 +159,9 @@ impl IntoFromLoraPayload<PAYLOAD_SIZE> for Beacon {
         lora_payload.into_bytes()
     }
 
-    fn from_lora_bytes(bytes: [u8; PAYLOAD_SIZE]) -> Self {
+    fn from_lora_bytes(bytes: [u8; PAYLOAD_SIZE]) -> Result<Self> {
         let lora_payload = LoraPayload::from_bytes(bytes);
-        lora_payload.into()
+        Ok(lora_payload.into())
     }
 
     fn label() -> &'static str {
@@ -35,14 +169,163 @@ impl IntoFromLoraPayload<PAYLOAD_SIZE> for Beacon {
     }
 }
 
+impl LoraEncode for Beacon {
+    type Bytes = [u8; PAYLOAD_SIZE];
+
+    fn to_lora_bytes_with_mode(&self, mode: EncodeMode) -> Result<Self::Bytes> {
+        Ok(LoraPayload::encode(self, mode)?.into_bytes())
+    }
+}
+
+impl LoraDecode for Beacon {
+    fn from_lora_slice(bytes: &[u8]) -> Result<(Self, usize)> {
+        let frame = split_fixed(Self::label(), bytes)?;
+        Ok((Self::from_lora_bytes(frame)?, PAYLOAD_SIZE))
+    }
+}
+
+impl DumpFields for Beacon {
+    fn dump_lora_frame(bytes: &[u8]) -> Result<Vec<FieldDump>> {
+        let payload = LoraPayload::from_bytes(split_fixed(Self::label(), bytes)?);
+        let mut fields = payload.fix().dump();
+        let signature = payload.signature();
+        fields.push(FieldDump::new(
+            "signature",
+            signature,
+            format!("{signature:04x}"),
+        ));
+        Ok(fields)
+    }
+}
+
+impl VersionedCodec for Beacon {
+    const LABEL: &'static str = "Beacon";
+    const VERSION: u8 = 1;
+    const VERSIONS: &'static [u8] = &[1, BEACON_V2, BEACON_V3, BEACON_V4, BEACON_V5];
+
+    fn encode_versioned(&self, version: u8, epoch: &Epoch, mode: EncodeMode) -> Result<Vec<u8>> {
+        let mut bytes = match version {
+            1 | BEACON_V2 | BEACON_V3 => LoraPayload::encode_in(self, epoch, mode)?
+                .into_bytes()
+                .to_vec(),
+            BEACON_V4 | BEACON_V5 => encode_adaptive(self, epoch, mode)?,
+            _ => return Err(Self::unsupported(version)),
+        };
+        if version >= BEACON_V2 {
+            // leap seconds (nanos of 1e9 and above) count as the last hundredth
+            let centis = (self.gps.timestamp.timestamp_subsec_millis() / 10).min(99) as u8;
+            match self.burst {
+                Some(burst) => {
+                    bytes.push(centis | HAS_BURST);
+                    bytes.extend_from_slice(&burst.to_bytes());
+                }
+                None => bytes.push(centis),
+            }
+        }
+        if version >= BEACON_V3 {
+            let tx_power = self
+                .tx_power_dbm
+                .map(|dbm| i128::from(dbm) + TX_POWER_OFFSET_DBM);
+            let antenna_gain = self.antenna_gain_dbi.map(|dbi| {
+                (dbi * Decimal::from(4))
+                    .round()
+                    .to_i128()
+                    .unwrap_or(i128::MAX)
+                    + ANTENNA_GAIN_OFFSET_QUARTERS
+            });
+            for (field, value) in [
+                ("tx_power_dbm", tx_power),
+                ("antenna_gain_dbi", antenna_gain),
+            ] {
+                bytes.push(match value {
+                    Some(value) => mode.fit(field, value, 7)? as u8 | HAS_RADIO_VALUE,
+                    None => 0,
+                });
+            }
+        }
+        if version >= BEACON_V5 {
+            for (field, accuracy) in [("h_acc_m", self.gps.h_acc_m), ("v_acc_m", self.gps.v_acc_m)]
+            {
+                bytes.push(match accuracy {
+                    Some(m) => mode.fit_scaled(field, m, 7)? as u8 | HAS_RADIO_VALUE,
+                    None => 0,
+                });
+            }
+        }
+        Ok(bytes)
+    }
+
+    fn decode_versioned(bytes: &[u8], version: u8, epoch: &Epoch) -> Result<(Self, usize)> {
+        let (mut beacon, fix_size) = match version {
+            1 | BEACON_V2 | BEACON_V3 => {
+                let frame = split_fixed(Self::LABEL, bytes)?;
+                (
+                    LoraPayload::from_bytes(frame).decode_in(epoch),
+                    PAYLOAD_SIZE,
+                )
+            }
+            BEACON_V4 | BEACON_V5 => decode_adaptive(bytes, epoch)?,
+            _ => return Err(Self::unsupported(version)),
+        };
+        let short = |expected| Error::InvalidVecForParsingLoraPayload {
+            payload: Self::LABEL,
+            size: bytes.len(),
+            expected,
+        };
+        if version == 1 {
+            return Ok((beacon, PAYLOAD_SIZE));
+        }
+        let flagged = *bytes.get(fix_size).ok_or(short(fix_size + 1))?;
+        let centis = flagged & !HAS_BURST;
+        if centis > 99 {
+            return Err(Error::InvalidLoraField {
+                payload: Self::LABEL,
+                field: "centiseconds",
+                bit_offset: fix_size * 8,
+                raw: centis.into(),
+            });
+        }
+        beacon.gps.timestamp += chrono::Duration::milliseconds(i64::from(centis) * 10);
+        let mut used = fix_size + 1;
+        if flagged & HAS_BURST != 0 {
+            let burst = bytes.get(used..used + 2).ok_or(short(used + 2))?;
+            beacon.burst = Some(BurstTag::from_bytes(burst.try_into().unwrap())?);
+            used += 2;
+        }
+        if version >= BEACON_V3 {
+            let radio = bytes.get(used..used + 2).ok_or(short(used + 2))?;
+            let value = |byte: u8| {
+                (byte & HAS_RADIO_VALUE != 0).then_some(i128::from(byte & !HAS_RADIO_VALUE))
+            };
+            beacon.tx_power_dbm = value(radio[0]).map(|raw| (raw - TX_POWER_OFFSET_DBM) as i8);
+            beacon.antenna_gain_dbi = value(radio[1])
+                .map(|raw| Decimal::new((raw - ANTENNA_GAIN_OFFSET_QUARTERS) as i64 * 25, 2));
+            used += 2;
+        }
+        if version >= BEACON_V5 {
+            let accuracy = bytes.get(used..used + 2).ok_or(short(used + 2))?;
+            let m = |byte: u8| {
+                (byte & HAS_RADIO_VALUE != 0).then_some(Decimal::from(byte & !HAS_RADIO_VALUE))
+            };
+            beacon.gps.h_acc_m = m(accuracy[0]);
+            beacon.gps.v_acc_m = m(accuracy[1]);
+            used += 2;
+        }
+        Ok((beacon, used))
+    }
+}
+
 impl TryFrom<MapperBeaconV1> for Beacon {
     type Error = Error;
 
     fn try_from(proto: MapperBeaconV1) -> Result<Self> {
         if let Some(gps) = proto.gps {
             Ok(Self {
-                gps: gps.into(),
-                signature: proto.signature,
+                gps: gps.try_into()?,
+                signature: proto.signature.into(),
+                burst: None,
+                tx_power_dbm: None,
+                antenna_gain_dbi: None,
             })
         } else {
             Err(Error::ProtoHasNone("gps"))
@@ -50,11 +333,56 @@ impl TryFrom<MapperBeaconV1> for Beacon {
     }
 }
 
+impl Beacon {
+    /// The radio fields, in the extension of the payload carrying the beacon
+    pub(crate) fn to_proto_ext(&self) -> Option<crate::proto_ext::BeaconExtV1> {
+        let ext = crate::proto_ext::BeaconExtV1 {
+            tx_power_dbm: self.tx_power_dbm.map(i32::from),
+            antenna_gain_cdbi: self.antenna_gain_dbi.map(|dbi| {
+                (dbi * Decimal::ONE_HUNDRED)
+                    .round()
+                    .to_i32()
+                    .unwrap_or(if dbi.is_sign_negative() {
+                        i32::MIN
+                    } else {
+                        i32::MAX
+                    })
+            }),
+        };
+        (ext != Default::default()).then_some(ext)
+    }
+
+    pub(crate) fn set_proto_ext(&mut self, ext: &crate::proto_ext::BeaconExtV1) -> Result<()> {
+        self.tx_power_dbm = ext
+            .tx_power_dbm
+            .map(|dbm| {
+                i8::try_from(dbm).map_err(|_| Error::OutOfRange {
+                    field: "tx_power_dbm",
+                    value: dbm.into(),
+                })
+            })
+            .transpose()?;
+        self.antenna_gain_dbi = ext
+            .antenna_gain_cdbi
+            .map(|cdbi| Decimal::new(cdbi.into(), 2));
+        Ok(())
+    }
+}
+
 impl From<Beacon> for MapperBeaconV1 {
     fn from(beacon: Beacon) -> Self {
         Self {
             gps: Some(beacon.gps.into()),
-            signature: beacon.signature,
+            signature: beacon.signature.into(),
+        }
+    }
+}
+
+impl From<&Beacon> for MapperBeaconV1 {
+    fn from(beacon: &Beacon) -> Self {
+        Self {
+            gps: Some(beacon.gps.into()),
+            signature: beacon.signature.as_bytes().to_vec(),
         }
     }
 }
@@ -68,9 +396,12 @@ impl From<Beacon> for helium_proto::mapper_payload::Message {
     }
 }
 
-impl From<Beacon> for helium_proto::MapperMsg {
-    fn from(beacon: Beacon) -> Self {
-        mapper_msg_with_payload(beacon.into())
+impl From<&Beacon> for helium_proto::mapper_payload::Message {
+    fn from(beacon: &Beacon) -> Self {
+        use helium_proto::{mapper_beacon, mapper_payload, MapperBeacon};
+        mapper_payload::Message::Beacon(MapperBeacon {
+            version: Some(mapper_beacon::Version::BeaconV1(beacon.into())),
+        })
     }
 }
 
@@ -92,42 +423,74 @@ impl From<Beacon> for Payload {
 
 impl From<LoraPayload> for Beacon {
     fn from(lora_payload: LoraPayload) -> Self {
+        lora_payload.decode_in(&Epoch::GENESIS)
+    }
+}
+
+impl LoraPayload {
+    fn fix(&self) -> LoraFix {
+        LoraFix {
+            time: self.time(),
+            lat: self.lat(),
+            lon: self.lon(),
+            hdop: self.hdop(),
+            alt: self.alt(),
+            speed: self.speed(),
+            num_sats: self.num_sats(),
+        }
+    }
+
+    fn decode_in(&self, epoch: &Epoch) -> Beacon {
         use latlon::Unit;
-        Self {
+        Beacon {
             gps: Gps {
-                timestamp: time::from_lora_units(lora_payload.time()),
-                lat: latlon::from_lora_units(Unit::Lat(lora_payload.lat())),
-                lon: latlon::from_lora_units(Unit::Lon(lora_payload.lon())),
-                hdop: hdop::from_units(lora_payload.hdop().into()),
-                altitude: altitude::from_lora_units(lora_payload.alt().into()),
-                num_sats: lora_payload.num_sats(),
-                speed: speed::from_lora_units(lora_payload.speed().into()),
+                timestamp: time::from_lora_units_in(self.time(), epoch),
+                lat: latlon::from_lora_units(Unit::Lat(self.lat())),
+                lon: latlon::from_lora_units(Unit::Lon(self.lon())),
+                hdop: hdop::from_units(self.hdop().into()),
+                altitude: altitude::from_lora_units(self.alt().into()),
+                num_sats: self.num_sats(),
+                speed: speed::from_lora_units(self.speed().into()),
+                h_acc_m: None,
+                v_acc_m: None,
+                jamming: None,
+                spoofing: None,
             },
-            signature: lora_payload.signature().to_be_bytes().to_vec(),
+            signature: self.signature().to_be_bytes().to_vec().into(),
+            burst: None,
+            tx_power_dbm: None,
+            antenna_gain_dbi: None,
         }
     }
 }
 
 impl From<Beacon> for LoraPayload {
     fn from(p: Beacon) -> Self {
-        use latlon::Degrees;
-        let sig_len = p.signature.len();
-        LoraPayload::new()
-            .with_time(time::to_lora_units(p.gps.timestamp))
-            .with_lat(latlon::to_lora_units(Degrees::Lat(p.gps.lat)))
-            .with_lon(latlon::to_lora_units(Degrees::Lon(p.gps.lon)))
-            .with_hdop(hdop::to_units(p.gps.hdop) as u16)
-            .with_alt(altitude::to_lora_units(p.gps.altitude) as u16)
-            .with_speed(speed::to_lora_units(p.gps.speed) as u16)
-            .with_num_sats(p.gps.num_sats)
-            .with_signature(u16::from_be_bytes([
-                p.signature[sig_len - 2],
-                p.signature[sig_len - 1],
-            ]))
+        LoraPayload::encode(&p, EncodeMode::Saturating).expect("saturating encode")
+    }
+}
+
+impl LoraPayload {
+    fn encode(p: &Beacon, mode: EncodeMode) -> Result<Self> {
+        Self::encode_in(p, &Epoch::GENESIS, mode)
+    }
+
+    fn encode_in(p: &Beacon, epoch: &Epoch, mode: EncodeMode) -> Result<Self> {
+        let fix = p.gps.to_lora_fix_in(epoch, mode)?;
+        Ok(LoraPayload::new()
+            .with_time(fix.time)
+            .with_lat(fix.lat)
+            .with_lon(fix.lon)
+            .with_hdop(fix.hdop)
+            .with_alt(fix.alt)
+            .with_speed(fix.speed)
+            .with_num_sats(fix.num_sats)
+            .with_signature(p.signature.lora_tail()))
     }
 }
 
 #[bitfield]
+#[derive(Debug)]
 struct LoraPayload {
     // we take seconds from 2023-01-01 00:00:00 UTC
     // 30 bits gives us over 20 years
@@ -156,6 +519,104 @@ struct LoraPayload {
     padding: B6,
 }
 
+fn encode_adaptive(p: &Beacon, epoch: &Epoch, mode: EncodeMode) -> Result<Vec<u8>> {
+    use latlon::Degrees;
+    let fix = p.gps.to_lora_fix_in(epoch, mode)?;
+    if hdop::from_units(fix.hdop.into()) < COARSE_HDOP {
+        return Ok(FineLoraPayload::new()
+            .with_coarse(false)
+            .with_time(fix.time)
+            .with_lat(fix.lat)
+            .with_lon(fix.lon)
+            .with_hdop(fix.hdop)
+            .with_alt(fix.alt)
+            .with_speed(fix.speed)
+            .with_num_sats(fix.num_sats)
+            .with_signature(p.signature.lora_tail())
+            .into_bytes()
+            .to_vec());
+    }
+    Ok(CoarseLoraPayload::new()
+        .with_coarse(true)
+        .with_time(fix.time)
+        .with_lat(latlon::to_coarse_lora_units(Degrees::Lat(p.gps.lat), mode)?)
+        .with_lon(latlon::to_coarse_lora_units(Degrees::Lon(p.gps.lon), mode)?)
+        .with_hdop(fix.hdop)
+        .with_alt(fix.alt)
+        .with_speed(fix.speed)
+        .with_num_sats(fix.num_sats)
+        .with_signature(p.signature.lora_tail())
+        .into_bytes()
+        .to_vec())
+}
+
+fn decode_adaptive(bytes: &[u8], epoch: &Epoch) -> Result<(Beacon, usize)> {
+    use latlon::Unit;
+    // the leading bit of the frame
+    let coarse = bytes.first().is_some_and(|first| first & 0x80 != 0);
+    if !coarse {
+        let frame = FineLoraPayload::from_bytes(split_fixed(Beacon::LABEL, bytes)?);
+        let payload = LoraPayload::new()
+            .with_time(frame.time())
+            .with_lat(frame.lat())
+            .with_lon(frame.lon())
+            .with_hdop(frame.hdop())
+            .with_alt(frame.alt())
+            .with_speed(frame.speed())
+            .with_num_sats(frame.num_sats())
+            .with_signature(frame.signature());
+        return Ok((payload.decode_in(epoch), PAYLOAD_SIZE));
+    }
+    let frame = CoarseLoraPayload::from_bytes(split_fixed(Beacon::LABEL, bytes)?);
+    let mut beacon = LoraPayload::new()
+        .with_time(frame.time())
+        .with_hdop(frame.hdop())
+        .with_alt(frame.alt())
+        .with_speed(frame.speed())
+        .with_num_sats(frame.num_sats())
+        .with_signature(frame.signature())
+        .decode_in(epoch);
+    beacon.gps.lat = latlon::from_coarse_lora_units(Unit::Lat(frame.lat()));
+    beacon.gps.lon = latlon::from_coarse_lora_units(Unit::Lon(frame.lon()));
+    Ok((beacon, COARSE_PAYLOAD_SIZE))
+}
+
+/// `LoraPayload` behind a leading `coarse` bit, unset
+#[bitfield]
+#[derive(Debug)]
+struct FineLoraPayload {
+    coarse: bool,
+    time: B30,
+    lat: B25,
+    lon: B26,
+    hdop: B10,
+    alt: B10,
+    speed: B9,
+    num_sats: B4,
+    signature: B16,
+    #[allow(unused)]
+    padding: B5,
+}
+
+/// `LoraPayload` in 1e-4 degrees, behind a leading `coarse` bit, set
+#[bitfield]
+#[derive(Debug)]
+struct CoarseLoraPayload {
+    coarse: bool,
+    time: B30,
+    // shifted by 90 in 1e-4 degrees => up to 1800000 => 21 bits
+    lat: B21,
+    // shifted by 180 in 1e-4 degrees => up to 3600000 => 22 bits
+    lon: B22,
+    hdop: B10,
+    alt: B10,
+    speed: B9,
+    num_sats: B4,
+    signature: B16,
+    #[allow(unused)]
+    padding: B5,
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -176,15 +637,31 @@ mod test {
                 altitude: Decimal::new(10_25, 2),
                 num_sats: 5,
                 speed: Decimal::new(50_50, 2),
+                h_acc_m: None,
+                v_acc_m: None,
+                jamming: None,
+                spoofing: None,
             },
-            signature: vec![0xAB, 0xCD],
+            signature: vec![0xAB, 0xCD].into(),
+            burst: None,
+            tx_power_dbm: None,
+            antenna_gain_dbi: None,
         };
         let lora_payload = LoraPayload::from(payload.clone());
         let bytes = lora_payload.into_bytes();
-        let payload_returned = Beacon::from_lora_bytes(bytes);
+        let payload_returned = Beacon::from_lora_bytes(bytes).unwrap();
         assert_eq!(payload, payload_returned);
     }
 
+    #[test]
+    fn display() {
+        let beacon = Beacon::new(Gps::rounded(), vec![0xAB, 0xCD]);
+        assert_eq!(
+            beacon.to_string(),
+            "Beacon{-50.12345,120.12345 5sats hdop9.05}"
+        );
+    }
+
     #[test]
     fn payload_roundtrip_lora_signed() {
         use crate::keys::{self, KeyTrait};
@@ -202,8 +679,15 @@ mod test {
                 altitude: Decimal::new(10_25, 2),
                 num_sats: 5,
                 speed: Decimal::new(50_50, 2),
+                h_acc_m: None,
+                v_acc_m: None,
+                jamming: None,
+                spoofing: None,
             },
-            signature: vec![0xAB, 0xCD],
+            signature: vec![0xAB, 0xCD].into(),
+            burst: None,
+            tx_power_dbm: None,
+            antenna_gain_dbi: None,
         };
         let bytes = payload
             .clone()
@@ -214,4 +698,297 @@ mod test {
                 .unwrap();
         assert_eq!(payload, payload_returned);
     }
+
+    #[test]
+    fn lora_codec_traits_match_legacy() {
+        use crate::keys::{self, KeyTrait};
+        let key = keys::file::File::create_key().unwrap();
+        let payload = Beacon::new(Gps::rounded(), vec![0xAB, 0xCD]);
+        let legacy = payload
+            .clone()
+            .into_lora_bytes_with_signature(&key)
+            .unwrap();
+        let mut bytes = payload.to_lora_bytes_with_signature(&key).unwrap();
+        assert_eq!(legacy[..PAYLOAD_SIZE], bytes[..PAYLOAD_SIZE]);
+
+        let pubkey = key.pubkey().unwrap();
+        assert_eq!(
+            Beacon::from_lora_slice_with_verified_signature(&pubkey, &legacy).unwrap(),
+            payload
+        );
+        let signature_len = bytes.len() - PAYLOAD_SIZE;
+        bytes.extend_from_slice(&[0xFF; 4]);
+        let (returned, used) = Beacon::from_lora_slice(&bytes).unwrap();
+        assert_eq!((returned, used), (payload, PAYLOAD_SIZE));
+        assert_eq!(bytes.len() - used, signature_len + 4);
+        assert!(Beacon::from_lora_slice(&bytes[..PAYLOAD_SIZE - 1]).is_err());
+    }
+
+    #[test]
+    fn ed25519_signed_frame_roundtrip() {
+        use crate::keys::{self, KeyTrait};
+        let key = keys::file::File::create_key_with(helium_crypto::KeyTag {
+            network: helium_crypto::Network::MainNet,
+            key_type: helium_crypto::KeyType::Ed25519,
+        })
+        .unwrap();
+        let payload = Beacon::new(Gps::rounded(), vec![0xAB, 0xCD]);
+        let mut bytes = payload.to_lora_bytes_with_signature(&key).unwrap();
+        // raw Ed25519 signatures have no DER header to strip
+        assert_eq!(bytes.len(), PAYLOAD_SIZE + 64);
+        let pubkey = key.pubkey().unwrap();
+        assert_eq!(
+            Beacon::from_lora_slice_with_verified_signature(&pubkey, &bytes).unwrap(),
+            payload
+        );
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(matches!(
+            Beacon::from_lora_slice_with_verified_signature(&pubkey, &bytes),
+            Err(Error::SignatureVerification { .. })
+        ));
+    }
+
+    #[test]
+    fn v2_carries_hundredths() {
+        use crate::epoch::Versioned;
+        let mut gps = Gps::rounded();
+        gps.timestamp += chrono::Duration::milliseconds(1_237);
+        let beacon = Beacon::new(gps, vec![0xAB, 0xCD]);
+
+        let v1 = Versioned::new(beacon.clone(), Epoch::GENESIS).to_lora_bytes();
+        let v2 = Versioned::new(beacon, Epoch::GENESIS)
+            .with_version(BEACON_V2)
+            .unwrap()
+            .to_lora_bytes();
+        assert_eq!((v1.len(), v2.len()), (PAYLOAD_SIZE + 1, PAYLOAD_SIZE + 2));
+        assert_eq!(v2[0] >> 4, BEACON_V2);
+
+        let (decoded, _) = Versioned::<Beacon>::from_lora_slice(&v2).unwrap();
+        let offset = decoded.payload.gps.timestamp - Gps::rounded().timestamp;
+        assert_eq!(offset.num_milliseconds(), 1_230);
+        let (decoded, _) = Versioned::<Beacon>::from_lora_slice(&v1).unwrap();
+        let offset = decoded.payload.gps.timestamp - Gps::rounded().timestamp;
+        assert_eq!(offset.num_milliseconds(), 1_000);
+
+        let tagged = decoded.payload.with_burst(BurstTag::new(9, 1, 3).unwrap());
+        let v2 = Versioned::new(tagged.clone(), Epoch::GENESIS)
+            .with_version(BEACON_V2)
+            .unwrap()
+            .to_lora_bytes();
+        assert_eq!(v2.len(), PAYLOAD_SIZE + 4);
+        let (decoded, used) = Versioned::<Beacon>::from_lora_slice(&v2).unwrap();
+        assert_eq!((decoded.payload, used), (tagged, v2.len()));
+    }
+
+    #[test]
+    fn v3_carries_radio() {
+        use crate::epoch::Versioned;
+        let beacon =
+            Beacon::new(Gps::rounded(), vec![0xAB, 0xCD]).with_radio(27, Decimal::new(225, 2));
+        assert_eq!(beacon.eirp_dbm(), Some(Decimal::new(2925, 2)));
+        assert_eq!(
+            beacon.normalize_rssi(Decimal::from(-110), Decimal::from(30)),
+            Some(Decimal::new(-10925, 2))
+        );
+
+        let v3 = Versioned::new(beacon.clone(), Epoch::GENESIS)
+            .with_version(BEACON_V3)
+            .unwrap()
+            .to_lora_bytes();
+        assert_eq!(v3.len(), PAYLOAD_SIZE + 4);
+        let (decoded, used) = Versioned::<Beacon>::from_lora_slice(&v3).unwrap();
+        assert_eq!((decoded.payload, used), (beacon, v3.len()));
+
+        let unknown = Beacon::new(Gps::rounded(), vec![0xAB, 0xCD]);
+        let v3 = Versioned::new(unknown.clone(), Epoch::GENESIS)
+            .with_version(BEACON_V3)
+            .unwrap()
+            .to_lora_bytes();
+        let (decoded, _) = Versioned::<Beacon>::from_lora_slice(&v3).unwrap();
+        assert_eq!(decoded.payload.eirp_dbm(), None);
+        assert_eq!(decoded.payload, unknown);
+
+        let loud = Beacon::new(Gps::rounded(), vec![0xAB, 0xCD]).with_radio(120, Decimal::ZERO);
+        assert!(matches!(
+            loud.encode_versioned(BEACON_V3, &Epoch::GENESIS, EncodeMode::Strict),
+            Err(Error::OutOfRange {
+                field: "tx_power_dbm",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn proto_carries_radio() {
+        let key = crate::keys::file::File::create_key().unwrap();
+        let beacon =
+            Beacon::new(Gps::rounded(), vec![0xAB, 0xCD]).with_radio(-3, Decimal::new(-1_07, 2));
+        let msg = Message::from_payload_signed(&key, Payload::Beacon(beacon.clone())).unwrap();
+        let mut bytes = Vec::new();
+        msg.encode_to(&mut bytes).unwrap();
+        let received = Message::decode_from_with_signature_verification(&bytes).unwrap();
+        assert_eq!(received.payload, Payload::Beacon(beacon));
+    }
+
+    #[test]
+    fn v4_scales_precision_with_hdop() {
+        use crate::epoch::Versioned;
+        let encode = |beacon: &Beacon| {
+            Versioned::new(beacon.clone(), Epoch::GENESIS)
+                .with_version(BEACON_V4)
+                .unwrap()
+                .to_lora_bytes()
+        };
+        let mut gps = Gps::rounded();
+        gps.hdop = Decimal::new(1_50, 2);
+        let fine = Beacon::new(gps, vec![0xAB, 0xCD]).with_radio(14, Decimal::ZERO);
+        let bytes = encode(&fine);
+        assert_eq!(bytes.len(), 1 + PAYLOAD_SIZE + 3);
+        let (decoded, used) = Versioned::<Beacon>::from_lora_slice(&bytes).unwrap();
+        assert_eq!((decoded.payload, used), (fine, bytes.len()));
+
+        let coarse = Beacon::new(Gps::rounded(), vec![0xAB, 0xCD]);
+        let bytes = encode(&coarse);
+        assert_eq!(bytes.len(), 1 + COARSE_PAYLOAD_SIZE + 3);
+        let (decoded, _) = Versioned::<Beacon>::from_lora_slice(&bytes).unwrap();
+        let gps = decoded.payload.gps;
+        assert_eq!(
+            (gps.lat, gps.lon),
+            (Decimal::new(-50_1234, 4), Decimal::new(120_1234, 4))
+        );
+        assert_eq!(
+            Beacon {
+                gps: coarse.gps,
+                ..decoded.payload.clone()
+            },
+            coarse
+        );
+        assert_eq!(encode(&decoded.payload), bytes);
+    }
+
+    #[test]
+    fn v5_carries_accuracy() {
+        use crate::epoch::Versioned;
+        let encode = |beacon: &Beacon| {
+            Versioned::new(beacon.clone(), Epoch::GENESIS)
+                .with_version(BEACON_V5)
+                .unwrap()
+                .to_lora_bytes()
+        };
+        let mut gps = Gps::rounded();
+        gps.h_acc_m = Some(Decimal::from(12));
+        let beacon = Beacon::new(gps, vec![0xAB, 0xCD]);
+        let bytes = encode(&beacon);
+        assert_eq!(bytes.len(), 1 + COARSE_PAYLOAD_SIZE + 5);
+        let (decoded, used) = Versioned::<Beacon>::from_lora_slice(&bytes).unwrap();
+        assert_eq!(used, bytes.len());
+        assert_eq!(
+            (decoded.payload.gps.h_acc_m, decoded.payload.gps.v_acc_m),
+            (Some(Decimal::from(12)), None)
+        );
+
+        gps.h_acc_m = Some(Decimal::new(2_500, 1));
+        gps.v_acc_m = Some(Decimal::new(4_4, 1));
+        let (decoded, _) =
+            Versioned::<Beacon>::from_lora_slice(&encode(&Beacon::new(gps, vec![0xAB, 0xCD])))
+                .unwrap();
+        let gps = decoded.payload.gps;
+        assert_eq!(
+            (gps.h_acc_m, gps.v_acc_m),
+            (Some(Decimal::from(127)), Some(Decimal::from(4)))
+        );
+        assert!(!Gps { num_sats: 9, ..gps }.is_locked());
+    }
+
+    #[test]
+    fn v5_strict_rejects_accuracy_out_of_range() {
+        use crate::epoch::Versioned;
+        let encode = |gps: Gps, mode| {
+            Versioned::new(Beacon::new(gps, vec![0xAB, 0xCD]), Epoch::GENESIS)
+                .with_version(BEACON_V5)
+                .unwrap()
+                .to_lora_bytes_with_mode(mode)
+        };
+        let mut gps = Gps::rounded();
+        gps.h_acc_m = Some(Decimal::new(-2_5, 1));
+        assert!(matches!(
+            encode(gps, EncodeMode::Strict),
+            Err(Error::OutOfRange {
+                field: "h_acc_m",
+                value: -2
+            })
+        ));
+        gps.h_acc_m = None;
+        gps.v_acc_m = Some(Decimal::from(300));
+        assert!(matches!(
+            encode(gps, EncodeMode::Strict),
+            Err(Error::OutOfRange {
+                field: "v_acc_m",
+                value: 300
+            })
+        ));
+        let bytes = encode(gps, EncodeMode::Saturating).unwrap();
+        assert_eq!(bytes[bytes.len() - 1], 127 | HAS_RADIO_VALUE);
+    }
+
+    #[test]
+    fn dump_fields_decodes_frame() {
+        let beacon = Beacon::new(Gps::rounded(), vec![0xAB, 0xCD]);
+        let fields = beacon.dump_fields();
+        let names: Vec<_> = fields.iter().map(|field| field.name.as_ref()).collect();
+        assert_eq!(
+            names,
+            [
+                "time",
+                "lat",
+                "lon",
+                "hdop",
+                "alt",
+                "speed",
+                "num_sats",
+                "signature"
+            ]
+        );
+        assert_eq!(
+            fields[0],
+            FieldDump::new("time", 5u32, "2023-01-01T00:00:05+00:00")
+        );
+        assert_eq!(fields[1].decoded, "-50.12345");
+        assert_eq!(fields[7], FieldDump::new("signature", 0xABCDu16, "abcd"));
+        assert!(Beacon::dump_lora_frame(&[0; 3]).is_err());
+
+        let json = serde_json::to_string(&fields).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Vec<FieldDump>>(&json).unwrap(),
+            fields
+        );
+    }
+
+    #[test]
+    fn truncated_signature_matches_committed_scan() {
+        use crate::keys::{self, KeyTrait};
+        let key = keys::file::File::create_key().unwrap();
+        let scan = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap();
+        let full = scan.signature.as_bytes();
+        let beacon = Beacon::new(Gps::rounded(), full[full.len() - 2..].to_vec());
+        let pubkey = key.pubkey().unwrap();
+
+        let policy = TruncatedSigPolicy::CommittedScan(&scan);
+        assert!(beacon
+            .matches_truncated_signature(&pubkey, &policy)
+            .unwrap());
+        let other = keys::file::File::create_key().unwrap().pubkey().unwrap();
+        assert!(!beacon.matches_truncated_signature(&other, &policy).unwrap());
+
+        let mut forged = scan.clone();
+        forged.signature.as_bytes_mut()[0] ^= 0xFF;
+        assert!(beacon
+            .matches_truncated_signature(&pubkey, &TruncatedSigPolicy::CommittedScan(&forged))
+            .is_err());
+
+        let stale = Beacon::new(Gps::rounded(), vec![!full[full.len() - 2], 0]);
+        let policy = TruncatedSigPolicy::CommittedSignature(&scan.signature);
+        assert!(!stale.matches_truncated_signature(&pubkey, &policy).unwrap());
+    }
 }
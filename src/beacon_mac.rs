@@ -0,0 +1,144 @@
+//! Keyed MAC commitments for beacons, an alternative to the two trailing bytes of a truncated
+//! signature. The MAC is a 4 byte BLAKE2s over the 17 byte LoRa frame, appended to it.
+//!
+//! The key is derived with HKDF-SHA256 from a secret the device shares with the server, eg: one
+//! provisioned at manufacturing, bound to the device's pubkey. The derivation is deterministic, so
+//! the device derives the same key on every boot without storing it, and so does the server.
+use super::{Beacon, Error, LoraDecode, LoraEncode, PublicKey, Result};
+use blake2::{
+    digest::{consts::U4, KeyInit, Mac},
+    Blake2sMac,
+};
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+/// The HKDF salt, keeping the key apart from other keys derived from the same secret
+pub const DERIVATION_LABEL: &[u8] = b"spot-messages beacon mac v1";
+
+pub const MAC_SIZE: usize = 4;
+
+/// Size of a beacon frame followed by its MAC
+pub const MAC_FRAME_SIZE: usize = 17 + MAC_SIZE;
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct BeaconMacKey([u8; 32]);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BeaconMac(pub [u8; MAC_SIZE]);
+
+impl BeaconMacKey {
+    /// Derives the key of the device with `pubkey` from the secret it shares with the server
+    pub fn derive(secret: &[u8], pubkey: &PublicKey) -> Self {
+        let pubkey = pubkey.to_vec();
+        let mut key = [0; 32];
+        Hkdf::<Sha256>::new(Some(DERIVATION_LABEL), secret)
+            .expand_multi_info(&[b"beacon".as_slice(), &pubkey], &mut key)
+            .expect("32 bytes is a valid hkdf length");
+        Self(key)
+    }
+
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    fn mac(&self) -> Blake2sMac<U4> {
+        <Blake2sMac<U4> as KeyInit>::new_from_slice(&self.0).expect("32 byte blake2s key")
+    }
+
+    pub fn compute(&self, frame: &[u8]) -> BeaconMac {
+        let mut mac = self.mac();
+        mac.update(frame);
+        BeaconMac(mac.finalize().into_bytes().into())
+    }
+
+    /// Compares in constant time
+    pub fn verify(&self, frame: &[u8], mac: &BeaconMac) -> Result {
+        let mut expected = self.mac();
+        expected.update(frame);
+        expected
+            .verify_slice(&mac.0)
+            .map_err(|_| Error::InvalidBeaconMac)
+    }
+}
+
+/// Keeps the key out of logs
+impl std::fmt::Debug for BeaconMacKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("BeaconMacKey(..)")
+    }
+}
+
+impl Beacon {
+    /// MAC over the LoRa frame, commitment included
+    pub fn mac(&self, key: &BeaconMacKey) -> BeaconMac {
+        key.compute(&self.to_lora_bytes())
+    }
+
+    pub fn to_lora_bytes_with_mac(&self, key: &BeaconMacKey) -> [u8; MAC_FRAME_SIZE] {
+        let frame = self.to_lora_bytes();
+        let mut bytes = [0; MAC_FRAME_SIZE];
+        bytes[..frame.len()].copy_from_slice(&frame);
+        bytes[frame.len()..].copy_from_slice(&key.compute(&frame).0);
+        bytes
+    }
+
+    pub fn from_lora_slice_with_verified_mac(key: &BeaconMacKey, bytes: &[u8]) -> Result<Self> {
+        let (beacon, used) = Beacon::from_lora_slice(bytes)?;
+        let mac = bytes
+            .get(used..used + MAC_SIZE)
+            .and_then(|mac| mac.try_into().ok())
+            .ok_or(Error::InvalidVecForParsingLoraPayload {
+                payload: "Beacon",
+                size: bytes.len(),
+                expected: MAC_FRAME_SIZE,
+            })?;
+        key.verify(&bytes[..used], &BeaconMac(mac))?;
+        Ok(beacon)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        keys::{self, KeyTrait},
+        Gps,
+    };
+
+    #[test]
+    fn derivation_is_deterministic() {
+        let pubkey = keys::file::File::create_key().unwrap().pubkey().unwrap();
+        let other = keys::file::File::create_key().unwrap().pubkey().unwrap();
+        let key = BeaconMacKey::derive(b"shared secret", &pubkey);
+        assert_eq!(key, BeaconMacKey::derive(b"shared secret", &pubkey));
+        assert_ne!(key, BeaconMacKey::derive(b"other secret", &pubkey));
+        assert_ne!(key, BeaconMacKey::derive(b"shared secret", &other));
+    }
+
+    #[test]
+    fn derived_key_roundtrip() {
+        let pubkey = keys::file::File::create_key().unwrap().pubkey().unwrap();
+        let key = BeaconMacKey::derive(b"shared secret", &pubkey);
+        let server = BeaconMacKey::derive(b"shared secret", &pubkey);
+
+        let beacon = Beacon::new(Gps::rounded(), vec![0xAB, 0xCD]);
+        let mut bytes = beacon.to_lora_bytes_with_mac(&key);
+        assert_eq!(
+            Beacon::from_lora_slice_with_verified_mac(&server, &bytes).unwrap(),
+            Beacon::from_lora_slice(&bytes).unwrap().0
+        );
+        bytes[3] ^= 1;
+        assert!(matches!(
+            Beacon::from_lora_slice_with_verified_mac(&server, &bytes),
+            Err(Error::InvalidBeaconMac)
+        ));
+        let other = BeaconMacKey::derive(b"other secret", &pubkey);
+        assert!(other
+            .verify(&beacon.to_lora_bytes(), &beacon.mac(&key))
+            .is_err());
+    }
+}
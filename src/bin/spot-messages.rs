@@ -0,0 +1,84 @@
+//! Command line tools for mapper archives.
+//!
+//! ```text
+//! spot-messages export [--format jsonl|csv|parquet] [--columns id,lat,...] [--verify] <archive or dir>...
+//! ```
+//!
+//! Rows go to stdout, a summary to stderr. `--format parquet` needs the `parquet` feature.
+use spot_messages::export::{Column, ExportFormat, ExportOptions, Exporter};
+use std::{io, path::PathBuf, process::ExitCode};
+
+const USAGE: &str = "usage: spot-messages export [--format jsonl|csv|parquet] [--columns id,lat,...] [--verify] <archive or dir>...";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.split_first() {
+        Some((command, rest)) if command == "export" => export(rest),
+        _ => Err(USAGE.to_string()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn export(args: &[String]) -> Result<(), String> {
+    let mut options = ExportOptions::default();
+    let mut parquet = false;
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => match args.next().ok_or(USAGE)?.as_str() {
+                "parquet" if cfg!(feature = "parquet") => parquet = true,
+                format => {
+                    options.format = format.parse::<ExportFormat>().map_err(|e| e.to_string())?
+                }
+            },
+            "--columns" => {
+                options.columns = args
+                    .next()
+                    .ok_or(USAGE)?
+                    .split(',')
+                    .map(str::parse::<Column>)
+                    .collect::<Result<_, _>>()
+                    .map_err(|e| e.to_string())?;
+            }
+            "--verify" => options.verify = true,
+            path => paths.push(PathBuf::from(path)),
+        }
+    }
+    if paths.is_empty() {
+        return Err(USAGE.to_string());
+    }
+
+    let at = |path: &PathBuf, e: spot_messages::Error| format!("{}: {e}", path.display());
+    let stats = if parquet {
+        #[cfg(feature = "parquet")]
+        {
+            let mut exporter = spot_messages::export::ParquetExporter::new(
+                io::BufWriter::new(io::stdout()),
+                options.columns,
+                options.verify,
+            )
+            .map_err(|e| e.to_string())?;
+            for path in &paths {
+                exporter.export_path(path).map_err(|e| at(path, e))?;
+            }
+            exporter.finish().map_err(|e| e.to_string())?.1
+        }
+        #[cfg(not(feature = "parquet"))]
+        unreachable!("only set with the parquet feature")
+    } else {
+        let mut exporter = Exporter::new(io::BufWriter::new(io::stdout().lock()), options);
+        for path in &paths {
+            exporter.export_path(path).map_err(|e| at(path, e))?;
+        }
+        exporter.finish().map_err(|e| e.to_string())?.1
+    };
+    eprintln!("exported {}, failed {}", stats.exported, stats.failed);
+    Ok(())
+}
@@ -0,0 +1,149 @@
+//! Beacons sent in a rapid burst, eg: 3 within a second for multilateration, and tagged so that
+//! servers can find the other frames of the burst. The tag travels in the `BEACON_V2` and later
+//! layouts only. Burst ids are a single byte and wrap, so members are also required to be close
+//! in time.
+use super::{Deserialize, Error, Message, Payload, PublicKey, Result, Serialize};
+use chrono::Duration;
+use std::collections::HashMap;
+
+/// Position of a beacon in its burst
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct BurstTag {
+    pub id: u8,
+    index: u8,
+    len: u8,
+}
+
+impl BurstTag {
+    pub const MAX_LEN: u8 = 16;
+
+    /// Fails unless `index < len <= MAX_LEN`
+    pub fn new(id: u8, index: u8, len: u8) -> Result<Self> {
+        if index >= len || len > Self::MAX_LEN {
+            return Err(Error::InvalidBurst { index, len });
+        }
+        Ok(Self { id, index, len })
+    }
+
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    /// Number of beacons in the burst
+    pub fn size(&self) -> u8 {
+        self.len
+    }
+
+    /// Id, then the index and length minus one in a nibble each
+    pub(crate) fn to_bytes(self) -> [u8; 2] {
+        [self.id, (self.index << 4) | self.len.saturating_sub(1)]
+    }
+
+    pub(crate) fn from_bytes(bytes: [u8; 2]) -> Result<Self> {
+        Self::new(bytes[0], bytes[1] >> 4, (bytes[1] & 0x0F) + 1)
+    }
+}
+
+/// Beacons of one burst, ordered by index
+#[derive(Debug, Clone, PartialEq)]
+pub struct BurstGroup<'a> {
+    pub pubkey: PublicKey,
+    pub id: u8,
+    pub len: u8,
+    pub members: Vec<&'a Message>,
+}
+
+impl BurstGroup<'_> {
+    pub fn is_complete(&self) -> bool {
+        self.members.len() == usize::from(self.len)
+    }
+}
+
+fn burst_tag(msg: &Message) -> Option<BurstTag> {
+    match &msg.payload {
+        Payload::Beacon(beacon) => beacon.burst,
+        _ => None,
+    }
+}
+
+/// Groups the tagged beacons of `msgs` by device and burst. A beacon joins a group of the same
+/// id whose first fix is at most `max_span` older and that doesn't have its index yet; other
+/// messages are left out. Groups are ordered by their first fix.
+pub fn group_bursts(msgs: &[Message], max_span: Duration) -> Vec<BurstGroup<'_>> {
+    let mut tagged: Vec<(&Message, BurstTag)> = msgs
+        .iter()
+        .filter_map(|msg| Some((msg, burst_tag(msg)?)))
+        .collect();
+    tagged.sort_by_key(|(msg, tag)| (msg.payload.gps().timestamp, tag.index));
+
+    let mut groups: Vec<BurstGroup> = Vec::new();
+    // open group of each b58 pubkey and burst id
+    let mut open: HashMap<(String, u8), usize> = HashMap::new();
+    for (msg, tag) in tagged {
+        let key = (msg.pubkey.to_string(), tag.id);
+        let fix_time = msg.payload.gps().timestamp;
+        let joins = open.get(&key).copied().filter(|&group| {
+            let group = &groups[group];
+            let first = group.members[0].payload.gps().timestamp;
+            group.len == tag.len
+                && fix_time - first <= max_span
+                && !group
+                    .members
+                    .iter()
+                    .any(|member| burst_tag(member).map(|member| member.index) == Some(tag.index))
+        });
+        match joins {
+            Some(group) => groups[group].members.push(msg),
+            None => {
+                open.insert(key, groups.len());
+                groups.push(BurstGroup {
+                    pubkey: msg.pubkey.clone(),
+                    id: tag.id,
+                    len: tag.len,
+                    members: vec![msg],
+                });
+            }
+        }
+    }
+    for group in &mut groups {
+        group
+            .members
+            .sort_by_key(|member| burst_tag(member).map(|tag| tag.index));
+    }
+    groups
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{keys, Beacon, Gps};
+
+    #[test]
+    fn groups_members_of_a_burst() {
+        let key = keys::file::File::create_key().unwrap();
+        let beacon = |offset_ms, id, index| {
+            let mut gps = Gps::rounded();
+            gps.timestamp += Duration::milliseconds(offset_ms);
+            let beacon =
+                Beacon::new(gps, vec![0xAB, 0xCD]).with_burst(BurstTag::new(id, index, 3).unwrap());
+            Message::from_payload_signed(&key, Payload::Beacon(beacon)).unwrap()
+        };
+        let msgs = vec![
+            beacon(300, 7, 1),
+            beacon(0, 7, 0),
+            beacon(600, 7, 2),
+            // the id wrapped around an hour later
+            beacon(3_600_000, 7, 0),
+            Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap(),
+        ];
+        let groups = group_bursts(&msgs, Duration::seconds(5));
+        assert_eq!(groups.len(), 2);
+        assert!(groups[0].is_complete());
+        assert_eq!(groups[0].members, [&msgs[1], &msgs[0], &msgs[2]]);
+        assert!(!groups[1].is_complete());
+
+        assert!(BurstTag::new(1, 3, 3).is_err());
+        let tag = BurstTag::new(1, 15, 16).unwrap();
+        assert_eq!(BurstTag::from_bytes(tag.to_bytes()).unwrap(), tag);
+    }
+}
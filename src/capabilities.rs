@@ -0,0 +1,227 @@
+//! What a firmware supports, uplinked on `CAPABILITIES_PORT` after a join or an update, so that
+//! the server only sends downlinks the device can read. `Negotiator` keeps the latest
+//! capabilities of each device and picks formats from them.
+//!
+//! Frame: version, then the feature bitmask (u32 BE). Bits unknown to this build are kept, so
+//! that newer firmware doesn't lose them through an older server.
+use super::{
+    config, DateTime, Deserialize, EncodeMode, Error, LoraDecode, LoraEncode, PublicKey, Result,
+    Serialize, Utc,
+};
+use std::collections::HashMap;
+
+/// Version of the capabilities frame
+pub const CAPABILITIES_VERSION: u8 = 1;
+
+const FRAME_SIZE: usize = 5;
+
+/// A payload layout or protocol feature. The discriminant is its bit in the mask.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    BeaconV1 = 0,
+    BeaconV2 = 1,
+    BeaconMac = 2,
+    CellScanV1 = 3,
+    CellAttachV1 = 4,
+    /// Frames with an `epoch::Versioned` header
+    VersionedFrames = 5,
+    ConfigPatchV1 = 6,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 7] = [
+        Feature::BeaconV1,
+        Feature::BeaconV2,
+        Feature::BeaconMac,
+        Feature::CellScanV1,
+        Feature::CellAttachV1,
+        Feature::VersionedFrames,
+        Feature::ConfigPatchV1,
+    ];
+
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Capabilities(pub u32);
+
+impl Capabilities {
+    /// Assumed for devices that never sent their capabilities: the firmware released before
+    /// the capabilities frame
+    pub const LEGACY: Capabilities = Capabilities(
+        (1 << Feature::BeaconV1 as u32)
+            | (1 << Feature::CellScanV1 as u32)
+            | (1 << Feature::CellAttachV1 as u32),
+    );
+
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.0 & feature.bit() != 0
+    }
+
+    pub fn with(self, feature: Feature) -> Self {
+        Self(self.0 | feature.bit())
+    }
+
+    /// Features known to this build
+    pub fn features(&self) -> impl Iterator<Item = Feature> + '_ {
+        Feature::ALL
+            .into_iter()
+            .filter(|feature| self.supports(*feature))
+    }
+}
+
+impl FromIterator<Feature> for Capabilities {
+    fn from_iter<I: IntoIterator<Item = Feature>>(iter: I) -> Self {
+        iter.into_iter().fold(Self(0), Self::with)
+    }
+}
+
+impl LoraEncode for Capabilities {
+    type Bytes = [u8; FRAME_SIZE];
+
+    fn to_lora_bytes_with_mode(&self, _mode: EncodeMode) -> Result<Self::Bytes> {
+        let mut bytes = [CAPABILITIES_VERSION, 0, 0, 0, 0];
+        bytes[1..].copy_from_slice(&self.0.to_be_bytes());
+        Ok(bytes)
+    }
+}
+
+impl LoraDecode for Capabilities {
+    fn from_lora_slice(bytes: &[u8]) -> Result<(Self, usize)> {
+        let frame = bytes
+            .get(..FRAME_SIZE)
+            .ok_or(Error::InvalidVecForParsingLoraPayload {
+                payload: "Capabilities",
+                size: bytes.len(),
+                expected: FRAME_SIZE,
+            })?;
+        if frame[0] != CAPABILITIES_VERSION {
+            return Err(Error::UnsupportedPayloadVersion {
+                payload: "Capabilities",
+                version: frame[0],
+            });
+        }
+        let mask = u32::from_be_bytes(frame[1..].try_into().unwrap());
+        Ok((Self(mask), FRAME_SIZE))
+    }
+}
+
+/// Latest capabilities seen from each device
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Negotiator {
+    /// keyed by b58 pubkey
+    devices: HashMap<String, (DateTime<Utc>, Capabilities)>,
+}
+
+impl Negotiator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records capabilities received at `at`. Frames older than the latest one, eg: from before
+    /// a firmware update but delivered late, are ignored.
+    pub fn observe(&mut self, pubkey: &PublicKey, at: DateTime<Utc>, capabilities: Capabilities) {
+        let latest = self
+            .devices
+            .entry(pubkey.to_string())
+            .or_insert((at, capabilities));
+        if at >= latest.0 {
+            *latest = (at, capabilities);
+        }
+    }
+
+    /// `Capabilities::LEGACY` for devices never seen
+    pub fn capabilities(&self, pubkey: &PublicKey) -> Capabilities {
+        self.devices
+            .get(&pubkey.to_string())
+            .map_or(Capabilities::LEGACY, |(_, capabilities)| *capabilities)
+    }
+
+    /// The first of `preferred`, in order, that the device supports
+    pub fn pick(&self, pubkey: &PublicKey, preferred: &[Feature]) -> Option<Feature> {
+        let capabilities = self.capabilities(pubkey);
+        preferred
+            .iter()
+            .copied()
+            .find(|feature| capabilities.supports(*feature))
+    }
+
+    /// `config::ConfigPatch` schema version to send the device, `None` if it can't read any
+    pub fn config_schema_version(&self, pubkey: &PublicKey) -> Option<u8> {
+        self.pick(pubkey, &[Feature::ConfigPatchV1])
+            .map(|_| config::SCHEMA_VERSION)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::keys::{self, KeyTrait};
+
+    #[test]
+    fn negotiates_from_latest_capabilities() {
+        let pubkey = keys::file::File::create_key().unwrap().pubkey().unwrap();
+        let at = crate::Gps::rounded().timestamp;
+        let mut negotiator = Negotiator::new();
+        let preferred = [Feature::BeaconV2, Feature::BeaconV1];
+        assert_eq!(
+            negotiator.pick(&pubkey, &preferred),
+            Some(Feature::BeaconV1)
+        );
+
+        let updated: Capabilities = [Feature::BeaconV2, Feature::ConfigPatchV1]
+            .into_iter()
+            .collect();
+        negotiator.observe(&pubkey, at, updated);
+        negotiator.observe(&pubkey, at - chrono::Duration::hours(1), Capabilities(0));
+        assert_eq!(
+            negotiator.pick(&pubkey, &preferred),
+            Some(Feature::BeaconV2)
+        );
+        assert_eq!(negotiator.config_schema_version(&pubkey), Some(1));
+
+        negotiator.observe(&pubkey, at, Capabilities(0));
+        assert_eq!(negotiator.config_schema_version(&pubkey), None);
+    }
+
+    #[test]
+    fn legacy_is_the_baseline() {
+        assert_eq!(
+            Capabilities::LEGACY.features().collect::<Vec<_>>(),
+            [
+                Feature::BeaconV1,
+                Feature::CellScanV1,
+                Feature::CellAttachV1
+            ]
+        );
+        let unknown = keys::file::File::create_key().unwrap().pubkey().unwrap();
+        assert_eq!(Negotiator::new().config_schema_version(&unknown), None);
+    }
+
+    #[test]
+    fn lora_roundtrip_keeps_unknown_bits() {
+        let capabilities = Capabilities::LEGACY.with(Feature::BeaconMac);
+        let future = Capabilities(capabilities.0 | (1 << 31));
+        let bytes = future.to_lora_bytes();
+        assert_eq!(bytes[0], CAPABILITIES_VERSION);
+        let (decoded, used) = Capabilities::from_lora_slice(&bytes).unwrap();
+        assert_eq!((decoded, used), (future, FRAME_SIZE));
+        assert_eq!(
+            decoded.features().collect::<Vec<_>>(),
+            [
+                Feature::BeaconV1,
+                Feature::BeaconMac,
+                Feature::CellScanV1,
+                Feature::CellAttachV1
+            ]
+        );
+        assert!(matches!(
+            Capabilities::from_lora_slice(&bytes[..4]),
+            Err(Error::InvalidVecForParsingLoraPayload { .. })
+        ));
+    }
+}
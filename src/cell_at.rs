@@ -0,0 +1,109 @@
+//! H3 cells that carry their resolution in their type, so that a res 12 cell can't be passed
+//! where a res 8 one is expected. A `CellAt<R>` can only be built at resolution `R`; `R` above
+//! 15 fails to compile.
+use super::{Deserialize, Error, Gps, Message, Result, Serialize};
+use h3o::{CellIndex, Resolution};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "CellIndex", into = "CellIndex")]
+pub struct CellAt<const R: u8>(CellIndex);
+
+/// Cell credited for coverage, see `resolution::COVERAGE_RESOLUTION`
+pub type CoverageCell = CellAt<8>;
+/// Cell used to spot duplicates, see `resolution::DEDUPE_RESOLUTION`
+pub type DedupeCell = CellAt<12>;
+
+impl<const R: u8> CellAt<R> {
+    const VALID: () = assert!(R <= 15, "h3 resolutions go up to 15");
+
+    pub fn resolution() -> Resolution {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID;
+        Resolution::try_from(R).expect("checked by VALID")
+    }
+
+    /// Fails with `Error::WrongResolution` if `cell` is at another resolution
+    pub fn new(cell: CellIndex) -> Result<Self> {
+        let resolution = Self::resolution();
+        if cell.resolution() != resolution {
+            return Err(Error::WrongResolution {
+                expected: R,
+                found: cell.resolution().into(),
+            });
+        }
+        Ok(Self(cell))
+    }
+
+    pub fn from_gps(gps: &Gps) -> Result<Self> {
+        Ok(Self(gps.to_h3_cell(Self::resolution())?))
+    }
+
+    pub fn index(&self) -> CellIndex {
+        self.0
+    }
+
+    /// `None` if `P` is finer than `R`
+    pub fn parent<const P: u8>(&self) -> Option<CellAt<P>> {
+        self.0.parent(CellAt::<P>::resolution()).map(CellAt)
+    }
+}
+
+impl<const R: u8> TryFrom<CellIndex> for CellAt<R> {
+    type Error = Error;
+
+    fn try_from(cell: CellIndex) -> Result<Self> {
+        Self::new(cell)
+    }
+}
+
+impl<const R: u8> From<CellAt<R>> for CellIndex {
+    fn from(cell: CellAt<R>) -> Self {
+        cell.0
+    }
+}
+
+impl<const R: u8> std::fmt::Display for CellAt<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl Message {
+    /// The mapper's cell at resolution `R`, whatever the resolution policy, see
+    /// `Message::reward_cell` and `Message::dedupe_cell` for the cells the program uses
+    pub fn cell_at<const R: u8>(&self) -> Result<CellAt<R>> {
+        CellAt::from_gps(self.payload.gps())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        keys,
+        resolution::{COVERAGE_RESOLUTION, DEDUPE_RESOLUTION},
+        Payload,
+    };
+
+    #[test]
+    fn resolution_is_checked_at_construction() {
+        assert_eq!(CoverageCell::resolution(), COVERAGE_RESOLUTION);
+        assert_eq!(DedupeCell::resolution(), DEDUPE_RESOLUTION);
+
+        let key = keys::file::File::create_key().unwrap();
+        let msg = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap();
+        let coverage: CoverageCell = msg.cell_at().unwrap();
+        assert_eq!(coverage, msg.reward_cell().unwrap());
+        let dedupe: DedupeCell = msg.cell_at().unwrap();
+        assert_eq!(dedupe.parent::<8>(), Some(coverage));
+        assert_eq!(coverage.parent::<12>(), None);
+
+        assert!(matches!(
+            CoverageCell::new(dedupe.index()),
+            Err(Error::WrongResolution {
+                expected: 8,
+                found: 12
+            })
+        ));
+    }
+}
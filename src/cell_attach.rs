@@ -1,3 +1,4 @@
+use super::epoch::{Epoch, VersionedCodec};
 use super::gps::{altitude, hdop, latlon, speed, time};
 use super::*;
 use helium_proto::MapperAttach;
@@ -10,43 +11,157 @@ pub struct CellAttach {
     pub candidate: AttachCandidate,
     // did the attach succeed?
     pub result: CellAttachResult,
+    /// Detailed cause of a failed attach. The proto extension carries it; the LoRa frame only
+    /// has room for the coarse `result`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_cause: Option<FailureCause>,
+    /// Subscription state during the attach. The proto extension carries it, the LoRa frame
+    /// doesn't.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sim: Option<SimInfo>,
 }
 
 const PAYLOAD_SIZE: usize = 32;
 
+impl std::fmt::Display for CellAttach {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "CellAttach{{#{} {:?} cid={} fcn={} rsrp={} rsrq={} ",
+            self.attach_counter,
+            self.result,
+            self.candidate.cell_id,
+            self.candidate.fcn,
+            self.candidate.rsrp,
+            self.candidate.rsrq
+        )?;
+        self.gps.fmt_fix(f)?;
+        f.write_str("}")
+    }
+}
+
 impl IntoFromLoraPayload<PAYLOAD_SIZE> for CellAttach {
     fn into_lora_bytes(self) -> [u8; PAYLOAD_SIZE] {
         let lora_payload: LoraPayload = self.into();
         lora_payload.into_bytes()
     }
-    fn from_lora_bytes(bytes: [u8; PAYLOAD_SIZE]) -> Self {
-        let lora_payload = LoraPayload::from_bytes(bytes);
-        lora_payload.into()
+    fn from_lora_bytes(bytes: [u8; PAYLOAD_SIZE]) -> Result<Self> {
+        LoraPayload::from_bytes(bytes).try_into()
     }
     fn label() -> &'static str {
         "CellAttach"
     }
 }
 
+impl LoraEncode for CellAttach {
+    type Bytes = [u8; PAYLOAD_SIZE];
+
+    fn to_lora_bytes_with_mode(&self, mode: EncodeMode) -> Result<Self::Bytes> {
+        Ok(LoraPayload::encode(self, mode)?.into_bytes())
+    }
+}
+
+impl LoraDecode for CellAttach {
+    fn from_lora_slice(bytes: &[u8]) -> Result<(Self, usize)> {
+        Self::decode_versioned(bytes, Self::VERSION, &Epoch::GENESIS)
+    }
+}
+
+impl DumpFields for CellAttach {
+    fn dump_lora_frame(bytes: &[u8]) -> Result<Vec<FieldDump>> {
+        let p = LoraPayload::from_bytes(lora_payload::split_fixed(Self::label(), bytes)?);
+        let fix = gps::LoraFix {
+            time: p.time(),
+            lat: p.lat(),
+            lon: p.lon(),
+            hdop: p.hdop(),
+            alt: p.alt(),
+            speed: p.speed(),
+            num_sats: p.num_sats(),
+        };
+        let rsrp = p.rsrp();
+        let rsrq = p.rsrq();
+        let result = match p.result_or_err() {
+            Ok(result) => FieldDump::new("result", result as u8, format!("{result:?}")),
+            Err(invalid) => FieldDump::new("result", invalid.invalid_bytes(), "invalid"),
+        };
+        let mut fields = fix.dump();
+        fields.extend([
+            FieldDump::new("attach_counter", p.attach_counter(), p.attach_counter()),
+            FieldDump::new("scan_response", p.scan_response(), p.scan_response()),
+            FieldDump::new("delay", p.delay(), p.delay()),
+            FieldDump::new("cid", p.cid(), p.cid()),
+            FieldDump::new("fcn", p.fcn(), p.fcn()),
+            FieldDump::new("rsrp", rsrp, i32::from(rsrp) - RSRP_OFFSET),
+            FieldDump::new("rsrq", rsrq, i32::from(rsrq) - RSRQ_OFFSET),
+            result,
+        ]);
+        Ok(fields)
+    }
+}
+
+impl VersionedCodec for CellAttach {
+    const LABEL: &'static str = "CellAttach";
+    const VERSION: u8 = 1;
+    const VERSIONS: &'static [u8] = &[1];
+
+    fn encode_versioned(&self, version: u8, epoch: &Epoch, mode: EncodeMode) -> Result<Vec<u8>> {
+        match version {
+            1 => Ok(LoraPayload::encode_in(self, epoch, mode)?
+                .into_bytes()
+                .to_vec()),
+            _ => Err(Self::unsupported(version)),
+        }
+    }
+
+    fn decode_versioned(bytes: &[u8], version: u8, epoch: &Epoch) -> Result<(Self, usize)> {
+        match version {
+            1 => {
+                let frame = lora_payload::split_fixed(Self::LABEL, bytes)?;
+                let payload = LoraPayload::from_bytes(frame);
+                Ok((payload.decode_in(epoch)?, PAYLOAD_SIZE))
+            }
+            _ => Err(Self::unsupported(version)),
+        }
+    }
+}
+
 impl From<CellAttach> for LoraPayload {
     fn from(mapper_attach: CellAttach) -> Self {
-        use latlon::Degrees;
-        LoraPayload::new()
-            .with_time(time::to_lora_units(mapper_attach.gps.timestamp))
-            .with_lat(latlon::to_lora_units(Degrees::Lat(mapper_attach.gps.lat)))
-            .with_lon(latlon::to_lora_units(Degrees::Lon(mapper_attach.gps.lon)))
-            .with_hdop(hdop::to_units(mapper_attach.gps.hdop) as u16)
-            .with_alt(altitude::to_lora_units(mapper_attach.gps.altitude) as u16)
-            .with_speed(speed::to_lora_units(mapper_attach.gps.speed) as u16)
-            .with_num_sats(mapper_attach.gps.num_sats)
-            .with_delay(mapper_attach.candidate.delay as u16)
+        LoraPayload::encode(&mapper_attach, EncodeMode::Saturating).expect("saturating encode")
+    }
+}
+
+impl LoraPayload {
+    /// On top of the fix: delays above 1023 s, RSRP outside -150 to 105 dBm and RSRQ outside
+    /// -30 to 225 dB saturate or are rejected per `mode`.
+    fn encode(mapper_attach: &CellAttach, mode: EncodeMode) -> Result<Self> {
+        Self::encode_in(mapper_attach, &Epoch::GENESIS, mode)
+    }
+
+    fn encode_in(mapper_attach: &CellAttach, epoch: &Epoch, mode: EncodeMode) -> Result<Self> {
+        let fix = mapper_attach.gps.to_lora_fix_in(epoch, mode)?;
+        let candidate = &mapper_attach.candidate;
+        Ok(LoraPayload::new()
+            .with_time(fix.time)
+            .with_lat(fix.lat)
+            .with_lon(fix.lon)
+            .with_hdop(fix.hdop)
+            .with_alt(fix.alt)
+            .with_speed(fix.speed)
+            .with_num_sats(fix.num_sats)
+            .with_delay(mode.fit("delay", candidate.delay.into(), 10)? as u16)
             .with_attach_counter(mapper_attach.attach_counter)
-            .with_scan_response(mapper_attach.candidate.from_scan)
-            .with_cid(mapper_attach.candidate.cell_id)
-            .with_rsrp((mapper_attach.candidate.rsrp + RSRP_OFFSET) as u8)
-            .with_rsrq((mapper_attach.candidate.rsrq + RSRQ_OFFSET) as u8)
-            .with_fcn(mapper_attach.candidate.fcn)
-            .with_result(mapper_attach.result)
+            .with_scan_response(candidate.from_scan)
+            .with_cid(candidate.cell_id)
+            .with_rsrp(
+                mode.fit("rsrp", candidate.rsrp.saturating_add(RSRP_OFFSET).into(), 8)? as u8,
+            )
+            .with_rsrq(
+                mode.fit("rsrq", candidate.rsrq.saturating_add(RSRQ_OFFSET).into(), 8)? as u8,
+            )
+            .with_fcn(candidate.fcn)
+            .with_result(mapper_attach.result))
     }
 }
 
@@ -56,31 +171,64 @@ impl From<CellAttach> for Payload {
     }
 }
 
-impl From<LoraPayload> for CellAttach {
-    fn from(p: LoraPayload) -> Self {
+impl TryFrom<LoraPayload> for CellAttach {
+    type Error = Error;
+
+    fn try_from(p: LoraPayload) -> Result<Self> {
+        p.decode_in(&Epoch::GENESIS)
+    }
+}
+
+/// Bit offset of `result` in the frame
+const RESULT_BIT_OFFSET: usize = 252;
+
+impl LoraPayload {
+    /// `result` has 3 bits for 6 variants, and the getter panics on the other two
+    fn check_result(&self) -> Result {
+        match self.result_or_err() {
+            Ok(_) => Ok(()),
+            Err(invalid) => Err(Error::InvalidLoraField {
+                payload: "CellAttach",
+                field: "result",
+                bit_offset: RESULT_BIT_OFFSET,
+                raw: invalid.invalid_bytes().into(),
+            }),
+        }
+    }
+
+    /// Every decode goes through here, so frames with invalid `result` bits are rejected by all
+    /// of them
+    fn decode_in(&self, epoch: &Epoch) -> Result<CellAttach> {
         use latlon::Unit;
-        CellAttach {
+        self.check_result()?;
+        Ok(CellAttach {
             gps: Gps {
-                timestamp: time::from_lora_units(p.time()),
-                lat: latlon::from_lora_units(Unit::Lat(p.lat())),
-                lon: latlon::from_lora_units(Unit::Lon(p.lon())),
-                hdop: hdop::from_units(p.hdop().into()),
-                altitude: altitude::from_lora_units(p.alt().into()),
-                num_sats: p.num_sats(),
-                speed: speed::from_lora_units(p.speed().into()),
+                timestamp: time::from_lora_units_in(self.time(), epoch),
+                lat: latlon::from_lora_units(Unit::Lat(self.lat())),
+                lon: latlon::from_lora_units(Unit::Lon(self.lon())),
+                hdop: hdop::from_units(self.hdop().into()),
+                altitude: altitude::from_lora_units(self.alt().into()),
+                num_sats: self.num_sats(),
+                speed: speed::from_lora_units(self.speed().into()),
+                h_acc_m: None,
+                v_acc_m: None,
+                jamming: None,
+                spoofing: None,
             },
-            attach_counter: p.attach_counter(),
+            attach_counter: self.attach_counter(),
             candidate: AttachCandidate {
-                delay: p.delay() as u32,
-                from_scan: p.scan_response(),
-                rsrp: (p.rsrp() as i32) - RSRP_OFFSET,
-                rsrq: (p.rsrq() as i32) - RSRQ_OFFSET,
-                fcn: p.fcn(),
-                cell_id: p.cid(),
+                delay: self.delay() as u32,
+                from_scan: self.scan_response(),
+                rsrp: (self.rsrp() as i32) - RSRP_OFFSET,
+                rsrq: (self.rsrq() as i32) - RSRQ_OFFSET,
+                fcn: self.fcn(),
+                cell_id: self.cid(),
             },
 
-            result: p.result(),
-        }
+            result: self.result(),
+            failure_cause: None,
+            sim: None,
+        })
     }
 }
 
@@ -122,9 +270,11 @@ impl TryFrom<helium_proto::MapperCbrsAttachV1> for CellAttach {
         match (attach.gps, attach.candidate) {
             (Some(gps), Some(candidate)) => Ok(Self {
                 attach_counter: attach.attach_counter,
-                gps: gps.into(),
+                gps: gps.try_into()?,
                 candidate: candidate.into(),
                 result,
+                failure_cause: None,
+                sim: None,
             }),
             (None, _) => Err(Error::ProtoHasNone("gps")),
             (_, None) => Err(Error::ProtoHasNone("candidate")),
@@ -155,20 +305,116 @@ impl From<CellScanResult> for AttachCandidate {
     }
 }
 
+/// Largest delay representable in the 10-bit LoRa field
+pub const MAX_ATTACH_DELAY: u32 = (1 << 10) - 1;
+
+/// Validated configuration for building an AttachCandidate. Use `AttachCandidateConfig::builder`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "AttachCandidateConfigBuilder")]
 pub struct AttachCandidateConfig {
-    pub from_scan: u32,
-    pub delay: u32,
+    from_scan: u32,
+    delay: u32,
+    /// (cell_id, earfcn) of the results of the linked scan, if the config was built from one
+    scan_cells: Option<Vec<(u64, u32)>>,
+}
+
+impl AttachCandidateConfig {
+    pub fn builder() -> AttachCandidateConfigBuilder {
+        AttachCandidateConfigBuilder::default()
+    }
+
+    pub fn from_scan(&self) -> u32 {
+        self.from_scan
+    }
+
+    pub fn delay(&self) -> u32 {
+        self.delay
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AttachCandidateConfigBuilder {
+    from_scan: u32,
+    delay: u32,
+    scan_cells: Option<Vec<(u64, u32)>>,
+}
+
+impl AttachCandidateConfigBuilder {
+    /// Links the candidate to a scan counter without checking the candidate against the scan
+    pub fn from_scan_counter(mut self, from_scan: u32) -> Self {
+        self.from_scan = from_scan;
+        self.scan_cells = None;
+        self
+    }
+
+    /// Links the candidate to a scan. The scan result the candidate is built from must then be
+    /// one of the scan's results.
+    pub fn scan(mut self, scan: &CellScan) -> Self {
+        self.from_scan = scan.scan_counter;
+        self.scan_cells = Some(scan.results.iter().map(|r| (r.cell_id, r.earfcn)).collect());
+        self
+    }
+
+    /// Seconds between the scan and the attach attempt, at most `MAX_ATTACH_DELAY`
+    pub fn delay(mut self, delay: u32) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    pub fn build(self) -> Result<AttachCandidateConfig> {
+        if self.delay > MAX_ATTACH_DELAY {
+            return Err(Error::InvalidAttachDelay {
+                delay: self.delay,
+                max: MAX_ATTACH_DELAY,
+            });
+        }
+        Ok(AttachCandidateConfig {
+            from_scan: self.from_scan,
+            delay: self.delay,
+            scan_cells: self.scan_cells,
+        })
+    }
+}
+
+/// Deserialized configs are validated like built ones
+impl TryFrom<AttachCandidateConfigBuilder> for AttachCandidateConfig {
+    type Error = Error;
+
+    fn try_from(builder: AttachCandidateConfigBuilder) -> Result<Self> {
+        builder.build()
+    }
 }
 
 impl AttachCandidate {
     pub fn from_scan_result_with_config(
         scan_result: CellScanResult,
-        config: AttachCandidateConfig,
-    ) -> Self {
-        let mut ac = Self::from(scan_result);
-        ac.from_scan = config.from_scan;
-        ac.delay = config.delay;
-        ac
+        config: &AttachCandidateConfig,
+    ) -> Result<Self> {
+        if let Some(scan_cells) = &config.scan_cells {
+            if !scan_cells.contains(&(scan_result.cell_id, scan_result.earfcn)) {
+                return Err(Error::CandidateNotInScan {
+                    from_scan: config.from_scan,
+                    cell_id: scan_result.cell_id,
+                    earfcn: scan_result.earfcn,
+                });
+            }
+        }
+        let cell_id = u32::try_from(scan_result.cell_id).map_err(|_| Error::OutOfRange {
+            field: "cell_id",
+            value: scan_result.cell_id.into(),
+        })?;
+        let fcn = u16::try_from(scan_result.earfcn).map_err(|_| Error::OutOfRange {
+            field: "fcn",
+            value: scan_result.earfcn.into(),
+        })?;
+        Ok(Self {
+            from_scan: config.from_scan,
+            delay: config.delay,
+            cell_id,
+            fcn,
+            rsrp: scan_result.rsrp,
+            rsrq: scan_result.rsrq,
+        })
     }
 }
 
@@ -209,12 +455,6 @@ impl From<CellAttach> for mapper_payload::Message {
     }
 }
 
-impl From<CellAttach> for MapperMsg {
-    fn from(cell_attach: CellAttach) -> Self {
-        mapper_msg_with_payload(cell_attach.into())
-    }
-}
-
 impl TryFrom<MapperAttach> for CellAttach {
     type Error = Error;
 
@@ -229,6 +469,7 @@ impl TryFrom<MapperAttach> for CellAttach {
 use modular_bitfield_msb::{bitfield, specifiers::*, BitfieldSpecifier};
 
 #[bitfield]
+#[derive(Debug)]
 struct LoraPayload {
     // we take seconds from 2023-01-01 00:00:00 UTC
     // 30 bits gives us over 20 years
@@ -292,6 +533,159 @@ impl CellAttachResult {
     }
 }
 
+/// Why the modem failed to attach, in more detail than `CellAttachResult`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureCause {
+    /// EMM cause of an attach or tracking area update reject, 3GPP TS 24.301 section 9.9.3.9
+    Emm(u8),
+    /// Rejected by the operator outside of EMM procedures, eg: by a provisioning policy
+    OperatorReject,
+    Sim(SimError),
+    /// The modem gave up without the network ever answering
+    Timeout,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SimError {
+    NotInserted,
+    PinRequired,
+    PukRequired,
+    Failure,
+}
+
+impl FailureCause {
+    /// The `CellAttachResult` reported for this cause where the detail doesn't fit, eg: LoRa
+    pub fn coarse_result(&self) -> CellAttachResult {
+        match self {
+            // illegal UE/ME, EPS services not allowed, PLMN not allowed
+            FailureCause::Emm(3 | 6 | 7 | 8 | 11) => CellAttachResult::NoNetworkService,
+            // tracking area or roaming not allowed, no suitable cells in tracking area
+            FailureCause::Emm(12 | 13 | 15) => CellAttachResult::LimitedService,
+            FailureCause::Emm(_) | FailureCause::OperatorReject | FailureCause::Timeout => {
+                CellAttachResult::NoConnection
+            }
+            FailureCause::Sim(_) => CellAttachResult::NoNetworkService,
+        }
+    }
+
+    /// Name of well known EMM causes
+    pub fn emm_cause_name(code: u8) -> Option<&'static str> {
+        Some(match code {
+            3 => "illegal UE",
+            6 => "illegal ME",
+            7 => "EPS services not allowed",
+            8 => "EPS and non-EPS services not allowed",
+            11 => "PLMN not allowed",
+            12 => "tracking area not allowed",
+            13 => "roaming not allowed in this tracking area",
+            15 => "no suitable cells in tracking area",
+            17 => "network failure",
+            22 => "congestion",
+            _ => return None,
+        })
+    }
+}
+
+impl std::fmt::Display for FailureCause {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FailureCause::Emm(code) => match Self::emm_cause_name(*code) {
+                Some(name) => write!(f, "emm #{code} ({name})"),
+                None => write!(f, "emm #{code}"),
+            },
+            FailureCause::OperatorReject => f.write_str("operator reject"),
+            FailureCause::Sim(error) => write!(f, "sim {error:?}"),
+            FailureCause::Timeout => f.write_str("timeout"),
+        }
+    }
+}
+
+impl CellAttach {
+    /// Records a failure cause along with the coarse result it maps to
+    pub fn with_failure_cause(mut self, failure_cause: FailureCause) -> Self {
+        self.result = failure_cause.coarse_result();
+        self.failure_cause = Some(failure_cause);
+        self
+    }
+
+    /// The failure cause and SIM state, in the extension of the payload carrying the attach
+    pub(crate) fn to_proto_ext(&self) -> Option<proto_ext::AttachExtV1> {
+        let ext = proto_ext::AttachExtV1 {
+            failure_cause: self.failure_cause.map(Into::into),
+            sim: self.sim.map(Into::into),
+        };
+        (ext != Default::default()).then_some(ext)
+    }
+
+    pub(crate) fn set_proto_ext(&mut self, ext: &proto_ext::AttachExtV1) -> Result<()> {
+        self.failure_cause = ext.failure_cause.map(TryInto::try_into).transpose()?;
+        self.sim = ext.sim.clone().map(TryInto::try_into).transpose()?;
+        Ok(())
+    }
+}
+
+impl From<FailureCause> for proto_ext::attach_ext_v1::FailureCause {
+    fn from(cause: FailureCause) -> Self {
+        match cause {
+            FailureCause::Emm(code) => Self::Emm(code.into()),
+            FailureCause::OperatorReject => Self::OperatorReject(true),
+            FailureCause::Sim(error) => Self::Sim(error.into()),
+            FailureCause::Timeout => Self::Timeout(true),
+        }
+    }
+}
+
+impl TryFrom<proto_ext::attach_ext_v1::FailureCause> for FailureCause {
+    type Error = Error;
+
+    fn try_from(proto: proto_ext::attach_ext_v1::FailureCause) -> Result<Self> {
+        use proto_ext::attach_ext_v1::FailureCause as Proto;
+        Ok(match proto {
+            Proto::Emm(code) => {
+                FailureCause::Emm(u8::try_from(code).map_err(|_| Error::OutOfRange {
+                    field: "emm",
+                    value: code.into(),
+                })?)
+            }
+            Proto::OperatorReject(_) => FailureCause::OperatorReject,
+            Proto::Sim(error) => FailureCause::Sim(error.try_into()?),
+            Proto::Timeout(_) => FailureCause::Timeout,
+        })
+    }
+}
+
+impl From<SimError> for u32 {
+    fn from(error: SimError) -> Self {
+        match error {
+            SimError::NotInserted => 1,
+            SimError::PinRequired => 2,
+            SimError::PukRequired => 3,
+            SimError::Failure => 4,
+        }
+    }
+}
+
+impl TryFrom<u32> for SimError {
+    type Error = Error;
+
+    fn try_from(value: u32) -> Result<Self> {
+        Ok(match value {
+            1 => SimError::NotInserted,
+            2 => SimError::PinRequired,
+            3 => SimError::PukRequired,
+            4 => SimError::Failure,
+            _ => {
+                return Err(Error::OutOfRange {
+                    field: "sim_error",
+                    value: value.into(),
+                })
+            }
+        })
+    }
+}
+
 impl std::str::FromStr for CellAttachResult {
     type Err = Error;
 
@@ -310,6 +704,57 @@ impl std::str::FromStr for CellAttachResult {
 #[cfg(test)]
 mod test {
     use super::*;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn candidate_config_validation() {
+        assert!(matches!(
+            AttachCandidateConfig::builder()
+                .delay(MAX_ATTACH_DELAY + 1)
+                .build(),
+            Err(Error::InvalidAttachDelay { .. })
+        ));
+
+        let mut scan = CellScan::random();
+        scan.results[0].cell_id = 0x0099D01;
+        scan.results[0].earfcn = 55990;
+        let config = AttachCandidateConfig::builder()
+            .scan(&scan)
+            .delay(MAX_ATTACH_DELAY)
+            .build()
+            .unwrap();
+        let candidate =
+            AttachCandidate::from_scan_result_with_config(scan.results[0], &config).unwrap();
+        assert_eq!(candidate.from_scan, scan.scan_counter);
+        assert_eq!(candidate.delay, MAX_ATTACH_DELAY);
+
+        let mut other = scan.results[0];
+        other.earfcn += 1;
+        assert!(matches!(
+            AttachCandidate::from_scan_result_with_config(other, &config),
+            Err(Error::CandidateNotInScan { .. })
+        ));
+
+        let json = serde_json::to_string(&config).unwrap();
+        assert_eq!(
+            config,
+            serde_json::from_str::<AttachCandidateConfig>(&json).unwrap()
+        );
+        assert!(serde_json::from_str::<AttachCandidateConfig>(
+            r#"{"from_scan":1,"delay":5000,"scan_cells":null}"#
+        )
+        .is_err());
+
+        let unlinked = AttachCandidateConfig::builder()
+            .from_scan_counter(3)
+            .build()
+            .unwrap();
+        other.earfcn = 70_000;
+        assert!(matches!(
+            AttachCandidate::from_scan_result_with_config(other, &unlinked),
+            Err(Error::OutOfRange { field: "fcn", .. })
+        ));
+    }
 
     #[test]
     fn payload_roundtrip_lora() {
@@ -318,11 +763,13 @@ mod test {
             gps: Gps::rounded(),
             candidate: AttachCandidate::from(CellScanResult::random()),
             result: CellAttachResult::Connected,
+            failure_cause: None,
+            sim: None,
         };
 
         let lora_payload = LoraPayload::from(payload.clone());
         let bytes = lora_payload.into_bytes();
-        let payload_returned = CellAttach::from_lora_bytes(bytes);
+        let payload_returned = CellAttach::from_lora_bytes(bytes).unwrap();
         assert_eq!(payload, payload_returned);
     }
 
@@ -333,6 +780,8 @@ mod test {
             gps: Gps::rounded(),
             candidate: AttachCandidate::from(CellScanResult::random()),
             result: CellAttachResult::Connected,
+            failure_cause: None,
+            sim: None,
         };
         let proto: helium_proto::MapperCbrsAttachV1 = attach.clone().into();
 
@@ -355,6 +804,8 @@ mod test {
             gps: Gps::rounded(),
             candidate: AttachCandidate::from(CellScanResult::random()),
             result: CellAttachResult::Connected,
+            failure_cause: None,
+            sim: None,
         };
         let bytes = payload
             .clone()
@@ -367,4 +818,130 @@ mod test {
         .unwrap();
         assert_eq!(payload, payload_returned);
     }
+
+    #[test]
+    fn encode_mode_out_of_range() {
+        let mut payload = CellAttach {
+            attach_counter: 5,
+            gps: Gps::rounded(),
+            candidate: AttachCandidate::from(CellScanResult::random()),
+            result: CellAttachResult::Connected,
+            failure_cause: None,
+            sim: None,
+        };
+        payload.gps.hdop = Decimal::new(12_00, 2);
+        payload.candidate.rsrp = -160;
+
+        assert!(matches!(
+            payload.to_lora_bytes_with_mode(EncodeMode::Strict),
+            Err(Error::OutOfRange {
+                field: "hdop",
+                value: 1200
+            })
+        ));
+        payload.gps.hdop = Decimal::new(5_00, 2);
+        assert!(matches!(
+            payload.to_lora_bytes_with_mode(EncodeMode::Strict),
+            Err(Error::OutOfRange {
+                field: "rsrp",
+                value: -10
+            })
+        ));
+
+        payload.gps.hdop = Decimal::new(12_00, 2);
+        let bytes = payload
+            .to_lora_bytes_with_mode(EncodeMode::Saturating)
+            .unwrap();
+        let (returned, _) = CellAttach::from_lora_slice(&bytes).unwrap();
+        assert_eq!(returned.gps.hdop, Decimal::new(10_23, 2));
+        assert_eq!(returned.candidate.rsrp, -RSRP_OFFSET);
+        assert_eq!(returned.gps.lat, payload.gps.lat);
+    }
+
+    #[test]
+    fn failure_cause_is_coarse_on_lora() {
+        let attach = CellAttach {
+            attach_counter: 5,
+            gps: Gps::rounded(),
+            candidate: AttachCandidate::from(CellScanResult::random()),
+            result: CellAttachResult::Connected,
+            failure_cause: None,
+            sim: None,
+        }
+        .with_failure_cause(FailureCause::Emm(15));
+        assert_eq!(attach.result, CellAttachResult::LimitedService);
+        assert_eq!(
+            FailureCause::Emm(15).to_string(),
+            "emm #15 (no suitable cells in tracking area)"
+        );
+
+        let (returned, _) = CellAttach::from_lora_slice(&attach.to_lora_bytes()).unwrap();
+        assert_eq!(returned.result, CellAttachResult::LimitedService);
+        assert_eq!(returned.failure_cause, None);
+
+        let key = keys::file::File::create_key().unwrap();
+        let mut attach = attach;
+        attach.sim = Some(SimInfo {
+            imsi_hash: Some(ImsiHash::new("315010123456789", b"key").unwrap()),
+            sim_error: None,
+            roaming: true,
+        });
+        for cause in [
+            FailureCause::Emm(15),
+            FailureCause::OperatorReject,
+            FailureCause::Sim(SimError::PukRequired),
+            FailureCause::Timeout,
+        ] {
+            let attach = attach.with_failure_cause(cause);
+            let msg = Message::from_payload_signed(&key, Payload::CellAttach(attach)).unwrap();
+            let mut bytes = Vec::new();
+            msg.encode_to(&mut bytes).unwrap();
+            let received = Message::decode_from_with_signature_verification(&bytes).unwrap();
+            assert_eq!(received.payload, Payload::CellAttach(attach));
+        }
+
+        let json = serde_json::to_string(&attach).unwrap();
+        assert!(json.contains(r#""failure_cause":{"emm":15}"#));
+        assert_eq!(attach, serde_json::from_str(&json).unwrap());
+    }
+
+    #[test]
+    fn invalid_result_bits_are_located() {
+        let attach = CellAttach {
+            attach_counter: 5,
+            gps: Gps::rounded(),
+            candidate: AttachCandidate::from(CellScanResult::random()),
+            result: CellAttachResult::Connected,
+            failure_cause: None,
+            sim: None,
+        };
+        let mut bytes = attach.to_lora_bytes();
+        // result is the 3 bits before the trailing padding bit
+        bytes[PAYLOAD_SIZE - 1] |= 0b1110;
+        let error = CellAttach::from_lora_slice(&bytes).unwrap_err();
+        assert!(matches!(
+            error,
+            Error::InvalidLoraField {
+                field: "result",
+                bit_offset: 252,
+                raw: 7,
+                ..
+            }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "CellAttach field \"result\" at bit 252 (byte 31) has invalid raw value 7"
+        );
+
+        // the by-value codec goes through the same check
+        use crate::keys::{self, KeyTrait};
+        assert!(CellAttach::from_lora_bytes(bytes).is_err());
+        let key = keys::file::File::create_key().unwrap();
+        let mut signed = bytes.to_vec();
+        signed.extend_from_slice(&sign_lora_frame(&key, &bytes).unwrap());
+        assert!(matches!(
+            CellAttach::from_lora_vec_with_verified_signature(&key.pubkey().unwrap(), signed),
+            Err(Error::InvalidLoraField { raw: 7, .. })
+        ));
+    }
 }
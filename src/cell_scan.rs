@@ -1,4 +1,4 @@
-use super::{mapper_msg_with_payload, Deserialize, Error, Result, Serialize};
+use super::{plmn, DateTime, Deserialize, Error, Plmn, PlmnPacking, Result, Serialize, Utc};
 use helium_proto::MapperScan;
 
 use crate::Gps;
@@ -13,7 +13,50 @@ pub struct CellScan {
     pub results: Vec<CellScanResult>,
 }
 
+impl std::fmt::Display for CellScan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "CellScan{{#{} results={} ",
+            self.scan_counter,
+            self.results.len()
+        )?;
+        self.gps.fmt_fix(f)?;
+        f.write_str("}")
+    }
+}
+
+impl std::fmt::Display for CellScanResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "CellScanResult{{{:03}-{:03} cid={} earfcn={} pci={} rsrp={} rsrq={}}}",
+            self.mcc,
+            self.mnc,
+            self.cell_id,
+            self.earfcn,
+            self.physical_cell_id,
+            self.rsrp,
+            self.rsrq
+        )
+    }
+}
+
 impl CellScan {
+    /// Where each result was measured, in the order of `results`: interpolated along `track` at
+    /// the result's `observed_at`, or the scan fix if the time is unknown or outside the track
+    pub fn geotag_results(&self, track: &[Gps]) -> Vec<Gps> {
+        self.results
+            .iter()
+            .map(|result| {
+                result
+                    .observed_at
+                    .and_then(|at| crate::gps::interpolate(track, at))
+                    .unwrap_or(self.gps)
+            })
+            .collect()
+    }
+
     pub fn random() -> CellScan {
         use rand::Rng;
         let mut rng = rand::thread_rng();
@@ -29,20 +72,107 @@ impl CellScan {
     }
 }
 
-impl From<CellScan> for helium_proto::MapperCellScanV1 {
-    fn from(scan_response: CellScan) -> Self {
-        Self {
-            scan_counter: scan_response.scan_counter,
-            gps: Some(scan_response.gps.into()),
-            results: scan_response
+impl CellScan {
+    /// Removes repeated reports of the same cell (same cell_id and earfcn), keeping the entry
+    /// with the strongest RSRP. The order of first appearance is preserved.
+    pub fn dedupe(&mut self) {
+        let mut deduped: Vec<CellScanResult> = Vec::with_capacity(self.results.len());
+        for result in self.results.drain(..) {
+            match deduped
+                .iter_mut()
+                .find(|r| r.cell_id == result.cell_id && r.earfcn == result.earfcn)
+            {
+                Some(existing) if result.rsrp > existing.rsrp => *existing = result,
+                Some(_) => (),
+                None => deduped.push(result),
+            }
+        }
+        self.results = deduped;
+    }
+
+    /// Merges the results of another scan (eg: a retry) into this one and dedupes them. The
+    /// counter and gps of the more recent scan are kept; counters may roll over so recency is
+    /// judged with wrapping arithmetic.
+    pub fn merge(&mut self, other: &CellScan) {
+        if counter_is_newer(other.scan_counter, self.scan_counter) {
+            self.scan_counter = other.scan_counter;
+            self.gps = other.gps;
+        }
+        self.results.extend_from_slice(&other.results);
+        self.dedupe();
+    }
+}
+
+impl CellScan {
+    /// The result times, in the extension of the payload carrying the scan. Times before the
+    /// unix epoch are left out.
+    pub(crate) fn to_proto_ext(&self) -> Option<crate::proto_ext::ScanExtV1> {
+        let ms = |at: DateTime<Utc>| u64::try_from(at.timestamp_millis()).unwrap_or(0);
+        let observed_at_ms: Vec<u64> = self
+            .results
+            .iter()
+            .map(|result| result.observed_at.map_or(0, ms))
+            .collect();
+        observed_at_ms
+            .iter()
+            .any(|&ms| ms != 0)
+            .then_some(crate::proto_ext::ScanExtV1 { observed_at_ms })
+    }
+
+    pub(crate) fn set_proto_ext(&mut self, ext: &crate::proto_ext::ScanExtV1) -> Result<()> {
+        use chrono::TimeZone;
+        if ext.observed_at_ms.len() != self.results.len() {
+            return Err(Error::OutOfRange {
+                field: "observed_at_ms",
+                value: ext.observed_at_ms.len() as i128,
+            });
+        }
+        for (result, &ms) in self.results.iter_mut().zip(&ext.observed_at_ms) {
+            result.observed_at = match ms {
+                0 => None,
+                ms => {
+                    let ms = i64::try_from(ms).map_err(|_| Error::TimestampOutOfRange(i64::MAX))?;
+                    let at = Utc.timestamp_millis_opt(ms).single();
+                    Some(at.ok_or(Error::TimestampOutOfRange(ms / 1000))?)
+                }
+            };
+        }
+        Ok(())
+    }
+}
+
+/// true if `a` was issued after `b`, accounting for rollover
+fn counter_is_newer(a: u32, b: u32) -> bool {
+    a != b && a.wrapping_sub(b) < u32::MAX / 2
+}
+
+impl CellScan {
+    /// The proto with the result PLMNs packed as `packing`. `From` uses the default packing.
+    pub fn to_proto_with(&self, packing: PlmnPacking) -> helium_proto::MapperCellScanV1 {
+        helium_proto::MapperCellScanV1 {
+            scan_counter: self.scan_counter,
+            gps: Some(self.gps.into()),
+            results: self
                 .results
-                .into_iter()
-                .map(|r| r.into())
+                .iter()
+                .map(|r| r.to_proto_with(packing))
                 .collect(),
         }
     }
 }
 
+impl From<CellScan> for helium_proto::MapperCellScanV1 {
+    fn from(scan_response: CellScan) -> Self {
+        scan_response.to_proto_with(PlmnPacking::default())
+    }
+}
+
+impl From<&CellScan> for helium_proto::MapperCellScanV1 {
+    fn from(scan_response: &CellScan) -> Self {
+        scan_response.to_proto_with(PlmnPacking::default())
+    }
+}
+
 impl TryFrom<helium_proto::MapperCellScanV1> for CellScan {
     type Error = Error;
 
@@ -50,8 +180,18 @@ impl TryFrom<helium_proto::MapperCellScanV1> for CellScan {
         if let Some(gps) = proto.gps {
             Ok(Self {
                 scan_counter: proto.scan_counter,
-                gps: gps.into(),
-                results: proto.results.into_iter().map(|r| r.into()).collect(),
+                gps: gps.try_into()?,
+                results: proto
+                    .results
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, r)| {
+                        r.try_into().map_err(|source| Error::InvalidScanResult {
+                            index,
+                            source: Box::new(source),
+                        })
+                    })
+                    .collect::<Result<_>>()?,
             })
         } else {
             Err(Error::ProtoHasNone("gps"))
@@ -68,6 +208,15 @@ impl From<CellScan> for helium_proto::mapper_payload::Message {
     }
 }
 
+impl From<&CellScan> for helium_proto::mapper_payload::Message {
+    fn from(scan_results: &CellScan) -> Self {
+        use helium_proto::{mapper_payload, mapper_scan};
+        mapper_payload::Message::Scan(MapperScan {
+            version: Some(mapper_scan::Version::ScanV1(scan_results.into())),
+        })
+    }
+}
+
 impl TryFrom<MapperScan> for CellScan {
     type Error = Error;
 
@@ -79,9 +228,130 @@ impl TryFrom<MapperScan> for CellScan {
     }
 }
 
-impl From<CellScan> for helium_proto::MapperMsg {
-    fn from(scan_results: CellScan) -> Self {
-        mapper_msg_with_payload(scan_results.into())
+impl From<CellScan> for super::Payload {
+    fn from(scan: CellScan) -> Self {
+        super::Payload::CellScan(scan)
+    }
+}
+
+/// Physical cell id, 0 to 503 on LTE and 0 to 1007 on NR
+#[derive(
+    Debug, Copy, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(try_from = "u32", into = "u32")]
+pub struct Pci(u16);
+
+impl Pci {
+    /// Largest NR physical cell id
+    pub const MAX: u16 = 1007;
+    /// Largest LTE physical cell id
+    pub const MAX_LTE: u16 = 503;
+
+    /// Accepts the whole NR range, see `check_rat` for LTE cells
+    pub fn new(pci: u16) -> Result<Self> {
+        if pci > Self::MAX {
+            return Err(Error::InvalidPci(pci.into()));
+        }
+        Ok(Self(pci))
+    }
+
+    /// Rejects ids above `MAX_LTE` for an LTE cell, as given by `CellScanResult::lte`
+    pub fn check_rat(self, lte: bool) -> Result<Self> {
+        if lte && self.0 > Self::MAX_LTE {
+            return Err(Error::InvalidPci(self.0.into()));
+        }
+        Ok(self)
+    }
+
+    pub fn get(&self) -> u16 {
+        self.0
+    }
+}
+
+impl TryFrom<u32> for Pci {
+    type Error = Error;
+
+    fn try_from(pci: u32) -> Result<Self> {
+        Self::try_from(u64::from(pci))
+    }
+}
+
+impl TryFrom<u64> for Pci {
+    type Error = Error;
+
+    fn try_from(pci: u64) -> Result<Self> {
+        u16::try_from(pci)
+            .map_err(|_| Error::InvalidPci(pci))
+            .and_then(Self::new)
+    }
+}
+
+impl From<Pci> for u32 {
+    fn from(pci: Pci) -> Self {
+        pci.0.into()
+    }
+}
+
+impl std::fmt::Display for Pci {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Channel bandwidth in kHz (eg: 20000 for a 20 MHz LTE carrier). 0 means the modem did not
+/// report it.
+#[derive(
+    Debug, Copy, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(try_from = "u32", into = "u32")]
+pub struct BandwidthKhz(u32);
+
+impl BandwidthKhz {
+    /// Widest NR carrier. Anything larger was most likely reported in Hz.
+    pub const MAX: u32 = 400_000;
+    /// Channel bandwidths defined for LTE
+    pub const LTE: [BandwidthKhz; 6] = [
+        BandwidthKhz(1_400),
+        BandwidthKhz(3_000),
+        BandwidthKhz(5_000),
+        BandwidthKhz(10_000),
+        BandwidthKhz(15_000),
+        BandwidthKhz(20_000),
+    ];
+
+    pub fn new(khz: u32) -> Result<Self> {
+        if khz > Self::MAX {
+            return Err(Error::InvalidBandwidth(khz));
+        }
+        Ok(Self(khz))
+    }
+
+    pub fn get(&self) -> u32 {
+        self.0
+    }
+
+    pub fn is_unknown(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl TryFrom<u32> for BandwidthKhz {
+    type Error = Error;
+
+    fn try_from(khz: u32) -> Result<Self> {
+        Self::new(khz)
+    }
+}
+
+impl From<BandwidthKhz> for u32 {
+    fn from(bandwidth: BandwidthKhz) -> Self {
+        bandwidth.0
+    }
+}
+
+impl std::fmt::Display for BandwidthKhz {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}kHz", self.0)
     }
 }
 
@@ -89,16 +359,39 @@ impl From<CellScan> for helium_proto::MapperMsg {
 pub struct CellScanResult {
     pub mcc: u16,
     pub mnc: u16,
+    /// Length of the MNC, 2 or 3, when the source said. See `Plmn`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mnc_digits: Option<u8>,
     pub earfcn: u32,
-    pub physical_cell_id: u64,
+    pub physical_cell_id: Pci,
     pub rsrp: i32,
     pub rsrq: i32,
     pub cell_id: u64,
-    pub bandwidth: u32,
+    pub bandwidth: BandwidthKhz,
     pub lte: bool,
+    /// When the modem measured this cell, if it reports it. A scan can take tens of seconds,
+    /// during which the mapper may move away from the scan fix. The proto extension carries it
+    /// in ms, the LoRa frames don't.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub observed_at: Option<DateTime<Utc>>,
 }
 
 impl CellScanResult {
+    pub fn plmn(&self) -> Plmn {
+        Plmn {
+            mcc: self.mcc,
+            mnc: self.mnc,
+            mnc_digits: self.mnc_digits,
+        }
+    }
+
+    pub fn with_plmn(mut self, plmn: Plmn) -> Self {
+        self.mcc = plmn.mcc;
+        self.mnc = plmn.mnc;
+        self.mnc_digits = plmn.mnc_digits;
+        self
+    }
+
     pub fn is_our_network(&self) -> Result<bool> {
         if self.mcc == CBRS_MCC && self.mnc == CBRS_MNC {
             let top_20_bits = self.cell_id >> 8;
@@ -115,45 +408,195 @@ impl CellScanResult {
         Self {
             mcc: rng.gen_range(0..999),
             mnc: rng.gen_range(0..999),
+            mnc_digits: Some(3),
             cell_id: rng.gen_range(0..68719476735),
             earfcn: rng.gen_range(0..4294967295),
             rsrp: rng.gen_range(-144..-44),
             rsrq: rng.gen_range(-20..-3),
-            physical_cell_id: rng.gen_range(0..503),
-            bandwidth: rng.gen_range(0..4294967295),
+            physical_cell_id: Pci(rng.gen_range(0..=Pci::MAX_LTE)),
+            bandwidth: BandwidthKhz::LTE[rng.gen_range(0..BandwidthKhz::LTE.len())],
             lte: true,
+            observed_at: None,
         }
     }
-}
 
-impl From<CellScanResult> for helium_proto::MapperCellScanResult {
-    fn from(scan_result: CellScanResult) -> Self {
+    /// Same as `random` for a CBRS cell for which `is_our_network` holds, eg: to exercise
+    /// `Workflow::best_candidate`
+    pub fn random_our_network() -> Self {
+        use rand::Rng;
         Self {
-            lte: scan_result.lte,
-            cid: scan_result.cell_id,
-            plmn: ((scan_result.mcc as u32) << 16) | scan_result.mnc as u32,
-            fcn: scan_result.earfcn,
-            pci: scan_result.physical_cell_id as u32,
-            rsrp: scan_result.rsrp,
-            rsrq: scan_result.rsrq,
-            bandwidth: scan_result.bandwidth,
+            mcc: CBRS_MCC,
+            mnc: CBRS_MNC,
+            mnc_digits: Some(3),
+            cell_id: rand::thread_rng().gen_range(0x0099Du64..=0x00A00) << 8,
+            earfcn: 55990,
+            ..Self::random()
         }
     }
+
+    /// Ranks the result as an attach candidate, higher is better. Each of RSRP, RSRQ, band and
+    /// bandwidth is first mapped to 0-100 and the score is their sum weighted by `weights`:
+    ///
+    /// - RSRP from -140 dBm (0) to -44 dBm (100)
+    /// - RSRQ from -20 dB (0) to -3 dB (100)
+    /// - band 100 when the EARFCN is in `weights.preferred_band`, else 0
+    /// - bandwidth from 0 (unreported) to 20 MHz (100)
+    ///
+    /// Values past either end clamp. Only integers are involved, every division truncating, so
+    /// firmware can rank candidates exactly as the server does.
+    pub fn quality_score(&self, weights: &ScoreWeights) -> u32 {
+        let linear = |value: i64, low: i64, high: i64| {
+            ((value.clamp(low, high) - low) * 100 / (high - low)) as u32
+        };
+        let band = match weights.preferred_band {
+            Some(band) if crate::propagation::earfcn_band(self.earfcn) == Some(band) => 100,
+            _ => 0,
+        };
+        let components = [
+            (weights.rsrp, linear(self.rsrp.into(), -140, -44)),
+            (weights.rsrq, linear(self.rsrq.into(), -20, -3)),
+            (weights.band, band),
+            (
+                weights.bandwidth,
+                linear(self.bandwidth.get().into(), 0, 20_000),
+            ),
+        ];
+        components
+            .iter()
+            .map(|(weight, component)| u32::from(*weight) * component)
+            .sum()
+    }
 }
 
-impl From<helium_proto::MapperCellScanResult> for CellScanResult {
-    fn from(scan_result: helium_proto::MapperCellScanResult) -> Self {
+/// Weights of `CellScanResult::quality_score`. The default, used by `Workflow::best_candidate`,
+/// favors signal strength, then quality, then CBRS (band 48) cells, then wider carriers, for a
+/// score of at most 1000.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScoreWeights {
+    pub rsrp: u16,
+    pub rsrq: u16,
+    pub band: u16,
+    pub bandwidth: u16,
+    /// E-UTRA band earning the band component, see `propagation::earfcn_band`
+    pub preferred_band: Option<u8>,
+}
+
+impl Default for ScoreWeights {
+    fn default() -> Self {
         Self {
+            rsrp: 4,
+            rsrq: 3,
+            band: 2,
+            bandwidth: 1,
+            preferred_band: Some(48),
+        }
+    }
+}
+
+/// Column order of `CellScanResult` lines, as written by field tools
+pub const CELL_SCAN_RESULT_COLUMNS: [&str; 9] = [
+    "mcc",
+    "mnc",
+    "earfcn",
+    "physical_cell_id",
+    "rsrp",
+    "rsrq",
+    "cell_id",
+    "bandwidth",
+    "lte",
+];
+
+/// Parses a comma separated line in `CELL_SCAN_RESULT_COLUMNS` order. physical_cell_id, rsrq,
+/// bandwidth and lte may be left blank, in which case they default to 0, 0, 0 and true.
+impl std::str::FromStr for CellScanResult {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.trim().split(',').map(str::trim).collect();
+        if fields.len() > CELL_SCAN_RESULT_COLUMNS.len() {
+            return Err(Error::InvalidCsvLine(s.into()));
+        }
+        let field = |i: usize| fields.get(i).copied().filter(|f| !f.is_empty());
+        let required =
+            |i: usize| field(i).ok_or(Error::MissingCsvField(CELL_SCAN_RESULT_COLUMNS[i]));
+        let lte = field(8)
+            .map(parse_lenient_bool)
+            .transpose()?
+            .unwrap_or(true);
+        Ok(Self {
+            mcc: required(0)?.parse::<u16>()?,
+            mnc: required(1)?.parse::<u16>()?,
+            mnc_digits: plmn::mnc_digits_written(required(1)?),
+            earfcn: required(2)?.parse::<u32>()?,
+            physical_cell_id: field(3)
+                .map(|pci| Pci::try_from(pci.parse::<u64>()?)?.check_rat(lte))
+                .transpose()?
+                .unwrap_or_default(),
+            rsrp: required(4)?.parse::<i32>()?,
+            rsrq: field(5)
+                .map(str::parse::<i32>)
+                .transpose()?
+                .unwrap_or_default(),
+            cell_id: required(6)?.parse::<u64>()?,
+            bandwidth: field(7)
+                .map(|khz| BandwidthKhz::new(khz.parse::<u32>()?))
+                .transpose()?
+                .unwrap_or_default(),
+            lte,
+            observed_at: None,
+        })
+    }
+}
+
+pub(crate) fn parse_lenient_bool(s: &str) -> Result<bool> {
+    match s.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "y" => Ok(true),
+        "0" | "false" | "no" | "n" => Ok(false),
+        _ => Err(Error::InvalidCsvLine(s.into())),
+    }
+}
+
+impl CellScanResult {
+    pub fn to_proto_with(&self, packing: PlmnPacking) -> helium_proto::MapperCellScanResult {
+        helium_proto::MapperCellScanResult {
+            lte: self.lte,
+            cid: self.cell_id,
+            plmn: self.plmn().to_proto_with(packing),
+            fcn: self.earfcn,
+            pci: self.physical_cell_id.into(),
+            rsrp: self.rsrp,
+            rsrq: self.rsrq,
+            bandwidth: self.bandwidth.into(),
+        }
+    }
+}
+
+impl From<CellScanResult> for helium_proto::MapperCellScanResult {
+    fn from(scan_result: CellScanResult) -> Self {
+        scan_result.to_proto_with(PlmnPacking::default())
+    }
+}
+
+/// Fails if the pci is out of range for the cell's rat, the bandwidth is not plausibly in kHz or
+/// the plmn is not valid BCD
+impl TryFrom<helium_proto::MapperCellScanResult> for CellScanResult {
+    type Error = Error;
+
+    fn try_from(scan_result: helium_proto::MapperCellScanResult) -> Result<Self> {
+        let plmn = Plmn::from_proto(scan_result.plmn)?;
+        Ok(Self {
             lte: scan_result.lte,
+            observed_at: None,
             cell_id: scan_result.cid,
-            mcc: (scan_result.plmn >> 16) as u16,
-            mnc: scan_result.plmn as u16,
+            mcc: plmn.mcc,
+            mnc: plmn.mnc,
+            mnc_digits: plmn.mnc_digits,
             earfcn: scan_result.fcn,
-            physical_cell_id: scan_result.pci as u64,
+            physical_cell_id: Pci::try_from(scan_result.pci)?.check_rat(scan_result.lte)?,
             rsrp: scan_result.rsrp,
             rsrq: scan_result.rsrq,
-            bandwidth: scan_result.bandwidth,
-        }
+            bandwidth: scan_result.bandwidth.try_into()?,
+        })
     }
 }
 
@@ -162,6 +605,220 @@ mod test {
     use super::*;
     use helium_proto::Message;
 
+    fn result(cell_id: u64, earfcn: u32, rsrp: i32) -> CellScanResult {
+        CellScanResult {
+            cell_id,
+            earfcn,
+            rsrp,
+            ..CellScanResult::random()
+        }
+    }
+
+    #[test]
+    fn scan_json_roundtrip() {
+        let scan = CellScan::random();
+        let json = serde_json::to_string(&scan).unwrap();
+        assert_eq!(scan, serde_json::from_str::<CellScan>(&json).unwrap());
+    }
+
+    #[test]
+    fn parse_line() {
+        let result: CellScanResult = "315,10,55990,101,-97,-11,2524161,20000,1".parse().unwrap();
+        assert_eq!(result.mcc, 315);
+        assert_eq!(result.cell_id, 2524161);
+        assert!(result.lte);
+
+        let lenient: CellScanResult = "315, 10, 55990, , -97, , 2524161".parse().unwrap();
+        assert_eq!(lenient.physical_cell_id.get(), 0);
+        assert_eq!(lenient.rsrq, 0);
+        assert!(lenient.bandwidth.is_unknown());
+        assert!(lenient.lte);
+
+        assert!(matches!(
+            "315,10,55990,101,,-11,2524161".parse::<CellScanResult>(),
+            Err(Error::MissingCsvField("rsrp"))
+        ));
+    }
+
+    #[test]
+    fn proto_rejects_out_of_range_pci_and_bandwidth() {
+        let proto = helium_proto::MapperCellScanResult::from(CellScanResult::random());
+        assert!(CellScanResult::try_from(proto.clone()).is_ok());
+        assert!(matches!(
+            CellScanResult::try_from(helium_proto::MapperCellScanResult {
+                pci: 504,
+                ..proto.clone()
+            }),
+            Err(Error::InvalidPci(504))
+        ));
+        assert!(matches!(
+            CellScanResult::try_from(helium_proto::MapperCellScanResult {
+                bandwidth: 20_000_000,
+                ..proto.clone()
+            }),
+            Err(Error::InvalidBandwidth(20_000_000))
+        ));
+        assert!(
+            CellScanResult::try_from(helium_proto::MapperCellScanResult {
+                pci: 1007,
+                lte: false,
+                ..proto.clone()
+            })
+            .is_ok()
+        );
+        assert!(matches!(
+            CellScanResult::try_from(helium_proto::MapperCellScanResult {
+                pci: 1008,
+                lte: false,
+                ..proto
+            }),
+            Err(Error::InvalidPci(1008))
+        ));
+        assert!(serde_json::from_str::<Pci>("1008").is_err());
+        assert_eq!(
+            serde_json::to_string(&Pci::new(101).unwrap()).unwrap(),
+            "101"
+        );
+    }
+
+    #[test]
+    fn proto_plmn_is_legacy_unless_bcd_is_asked_for() {
+        let result = CellScanResult::random().with_plmn("315-010".parse().unwrap());
+        let proto = helium_proto::MapperCellScanResult::from(result);
+        assert_eq!(proto.plmn, (315 << 16) | 10);
+        let legacy = CellScanResult::try_from(proto.clone()).unwrap();
+        assert_eq!((legacy.mcc, legacy.mnc, legacy.mnc_digits), (315, 10, None));
+
+        let bcd = result.to_proto_with(PlmnPacking::Bcd);
+        assert_eq!(bcd.plmn, 0x8013_0510);
+        assert_eq!(CellScanResult::try_from(bcd).unwrap(), result);
+
+        let scan = CellScan {
+            scan_counter: 1,
+            gps: Gps::rounded(),
+            results: vec![result],
+        };
+        assert_eq!(
+            scan.to_proto_with(PlmnPacking::Bcd).results[0].plmn,
+            0x8013_0510
+        );
+        assert_eq!(
+            helium_proto::MapperCellScanV1::from(&scan),
+            scan.to_proto_with(PlmnPacking::Legacy)
+        );
+    }
+
+    #[test]
+    fn geotag_interpolates_observed_results() {
+        let start = Gps::rounded();
+        let end = Gps {
+            timestamp: start.timestamp + chrono::Duration::seconds(20),
+            lat: start.lat + rust_decimal::Decimal::new(2_000, 5),
+            ..start
+        };
+        let observed = CellScanResult {
+            observed_at: Some(start.timestamp + chrono::Duration::seconds(5)),
+            ..CellScanResult::random()
+        };
+        let scan = CellScan {
+            scan_counter: 1,
+            gps: start,
+            results: vec![observed, CellScanResult::random()],
+        };
+        let fixes = scan.geotag_results(&[start, end]);
+        assert_eq!(fixes[0].lat, start.lat + rust_decimal::Decimal::new(500, 5));
+        assert_eq!(fixes[1], start);
+
+        let key = crate::keys::file::File::create_key().unwrap();
+        let msg = crate::Message::from_payload_signed(&key, crate::Payload::CellScan(scan.clone()))
+            .unwrap();
+        let mut bytes = Vec::new();
+        msg.encode_to(&mut bytes).unwrap();
+        let received = crate::Message::decode_from_with_signature_verification(&bytes).unwrap();
+        assert_eq!(received.payload, crate::Payload::CellScan(scan));
+    }
+
+    #[test]
+    fn dedupe_keeps_best_rsrp() {
+        let mut scan = CellScan {
+            scan_counter: 1,
+            gps: Gps::rounded(),
+            results: vec![
+                result(1, 55990, -110),
+                result(2, 55990, -100),
+                result(1, 55990, -90),
+                result(1, 56190, -120),
+            ],
+        };
+        scan.dedupe();
+        assert_eq!(
+            scan.results
+                .iter()
+                .map(|r| (r.cell_id, r.earfcn, r.rsrp))
+                .collect::<Vec<_>>(),
+            vec![(1, 55990, -90), (2, 55990, -100), (1, 56190, -120)]
+        );
+    }
+
+    #[test]
+    fn quality_score_weighs_signal_band_and_bandwidth() {
+        let weights = ScoreWeights::default();
+        let best = CellScanResult {
+            rsrp: -44,
+            rsrq: -3,
+            bandwidth: BandwidthKhz::LTE[5],
+            ..result(1, 55990, -44)
+        };
+        assert_eq!(best.quality_score(&weights), 1000);
+
+        let weak = CellScanResult {
+            rsrp: -90,
+            rsrq: -20,
+            bandwidth: BandwidthKhz::default(),
+            ..best
+        };
+        // (50 * 100 / 96) * 4 + 200
+        assert_eq!(weak.quality_score(&weights), 408);
+        let other_band = CellScanResult {
+            earfcn: 5230,
+            ..best
+        };
+        assert_eq!(other_band.quality_score(&weights), 800);
+        assert_eq!(
+            other_band.quality_score(&ScoreWeights {
+                preferred_band: Some(13),
+                ..weights
+            }),
+            1000
+        );
+    }
+
+    #[test]
+    fn merge_keeps_newest_counter() {
+        let mut scan = CellScan {
+            scan_counter: u32::MAX,
+            gps: Gps::rounded(),
+            results: vec![result(1, 55990, -110)],
+        };
+        let retry = CellScan {
+            scan_counter: 0,
+            gps: Gps::default(),
+            results: vec![result(1, 55990, -100), result(2, 55990, -100)],
+        };
+        scan.merge(&retry);
+        assert_eq!(scan.scan_counter, 0);
+        assert_eq!(scan.gps, Gps::default());
+        assert_eq!(scan.results.len(), 2);
+        assert_eq!(scan.results[0].rsrp, -100);
+
+        let stale = CellScan {
+            scan_counter: u32::MAX - 1,
+            ..retry.clone()
+        };
+        scan.merge(&stale);
+        assert_eq!(scan.scan_counter, 0);
+    }
+
     #[test]
     fn scan_roundtrip_proto() {
         let scan_results = CellScan::random();
@@ -0,0 +1,140 @@
+//! Device clock drift, estimated from the time a device stamped on its reports against the time
+//! the server received them. Between GNSS fixes the device stamps LoRa time units from its RTC,
+//! which can drift by tens of ppm.
+//!
+//! The offset between both clocks is fitted as a line over device time. Transit delay only ever
+//! adds to the offset, so the slope comes from a least squares fit and the line is then lowered
+//! to the smallest offset seen, which is the report with the least delay.
+use super::{DateTime, Message, Utc};
+use std::collections::VecDeque;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DriftEstimate {
+    /// Device time the fit is relative to
+    pub reference: DateTime<Utc>,
+    /// Server minus device time at `reference`, in seconds
+    pub offset_s: f64,
+    /// How much faster the server clock runs, in parts per million of device time
+    pub drift_ppm: f64,
+}
+
+impl DriftEstimate {
+    /// Least squares over `(device, server)` timestamp pairs. `None` with fewer than 2 distinct
+    /// device times.
+    pub fn fit(pairs: &[(DateTime<Utc>, DateTime<Utc>)]) -> Option<Self> {
+        let reference = pairs.iter().map(|(device, _)| *device).min()?;
+        let points: Vec<(f64, f64)> = pairs
+            .iter()
+            .map(|(device, server)| (seconds(*device - reference), seconds(*server - *device)))
+            .collect();
+        let n = points.len() as f64;
+        let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+        let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+        let sxx: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+        if sxx == 0.0 {
+            return None;
+        }
+        let sxy: f64 = points
+            .iter()
+            .map(|(x, y)| (x - mean_x) * (y - mean_y))
+            .sum();
+        let slope = sxy / sxx;
+        let offset_s = points
+            .iter()
+            .map(|(x, y)| y - slope * x)
+            .fold(f64::INFINITY, f64::min);
+        Some(Self {
+            reference,
+            offset_s,
+            drift_ppm: slope * 1e6,
+        })
+    }
+
+    /// `device` expressed in server time
+    pub fn correct(&self, device: DateTime<Utc>) -> DateTime<Utc> {
+        let elapsed_s = seconds(device - self.reference);
+        let offset_s = self.offset_s + self.drift_ppm / 1e6 * elapsed_s;
+        device + chrono::Duration::microseconds((offset_s * 1e6).round() as i64)
+    }
+}
+
+fn seconds(duration: chrono::Duration) -> f64 {
+    duration
+        .num_microseconds()
+        .map_or(duration.num_seconds() as f64, |us| us as f64 / 1e6)
+}
+
+/// Keeps the last `capacity` timestamp pairs of a device
+#[derive(Debug, Clone, PartialEq)]
+pub struct DriftEstimator {
+    capacity: usize,
+    pairs: VecDeque<(DateTime<Utc>, DateTime<Utc>)>,
+}
+
+impl DriftEstimator {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(2),
+            pairs: VecDeque::new(),
+        }
+    }
+
+    pub fn observe(&mut self, device: DateTime<Utc>, server: DateTime<Utc>) {
+        if self.pairs.len() == self.capacity {
+            self.pairs.pop_front();
+        }
+        self.pairs.push_back((device, server));
+    }
+
+    /// Records the fix time of `msg` against its `IngestMeta` reception time. Returns false for
+    /// messages without `IngestMeta`.
+    pub fn observe_message(&mut self, msg: &Message) -> bool {
+        let Some(meta) = &msg.ingest_meta else {
+            return false;
+        };
+        self.observe(msg.payload.gps().timestamp, meta.received_at);
+        true
+    }
+
+    pub fn estimate(&self) -> Option<DriftEstimate> {
+        let pairs: Vec<_> = self.pairs.iter().copied().collect();
+        DriftEstimate::fit(&pairs)
+    }
+}
+
+impl Message {
+    /// Fix time of the payload corrected for the device clock drift
+    pub fn corrected_timestamp(&self, drift: &DriftEstimate) -> DateTime<Utc> {
+        drift.correct(self.payload.gps().timestamp)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn fits_drift_under_transit_delay() {
+        let start = crate::Gps::rounded().timestamp;
+        let mut estimator = DriftEstimator::new(100);
+        assert_eq!(estimator.estimate(), None);
+        // device runs 50 ppm slow and is 2 s behind, transit takes 0.1 to 0.9 s
+        for i in 0..50 {
+            let device = start + Duration::seconds(i * 600);
+            let true_offset_us = 2_000_000 + i * 600 * 50;
+            let delay_ms = 100 + (i * i * 37) % 800;
+            let server =
+                device + Duration::microseconds(true_offset_us) + Duration::milliseconds(delay_ms);
+            estimator.observe(device, server);
+        }
+        let drift = estimator.estimate().unwrap();
+        assert!((drift.drift_ppm - 50.0).abs() < 5.0, "{drift:?}");
+        assert!((drift.offset_s - 2.1).abs() < 0.2, "{drift:?}");
+
+        let later = start + Duration::hours(10);
+        let corrected = drift.correct(later) - later;
+        // 2 s, 36000 s at 50 ppm and the shortest transit
+        assert!((seconds(corrected) - 3.9).abs() < 0.2, "{corrected}");
+    }
+}
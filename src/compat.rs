@@ -0,0 +1,186 @@
+//! Decoding of attach reports from firmware that predates the current proto revision, where the
+//! candidate may be absent and results were numbered differently. Fleets mixing firmware pick a
+//! `CompatPolicy` describing what their older devices emit.
+use super::{
+    mapper_payload, AttachCandidate, CellAttach, CellAttachResult, DecodeLimits, Error,
+    ExtendedMsg, InProcessVerifier, Message, Payload, Result, UnverifiedMsg, VerifierBackend,
+};
+use helium_proto::{mapper_attach, MapperCbrsAttachV1};
+
+/// Maps the proto `result` integer, used as the index, to a `CellAttachResult`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ResultMapping(pub [CellAttachResult; 6]);
+
+impl ResultMapping {
+    /// Ordering of the current helium-proto revision
+    pub const CURRENT: Self = Self([
+        CellAttachResult::NoAttach,
+        CellAttachResult::Connected,
+        CellAttachResult::LimitedService,
+        CellAttachResult::NoConnection,
+        CellAttachResult::Search,
+        CellAttachResult::NoNetworkService,
+    ]);
+
+    pub fn result(&self, value: i32) -> Result<CellAttachResult> {
+        usize::try_from(value)
+            .ok()
+            .and_then(|index| self.0.get(index).copied())
+            .ok_or(Error::InvalidAttachResultInt { value })
+    }
+}
+
+impl Default for ResultMapping {
+    fn default() -> Self {
+        Self::CURRENT
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum MissingCandidate {
+    /// Reject the report, as current decoding does
+    #[default]
+    Reject,
+    /// Decode with zeroed candidate fields, eg: for firmware that only reported the result
+    Zeroed,
+}
+
+/// The default policy decodes exactly like `TryFrom<MapperCbrsAttachV1>`
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct CompatPolicy {
+    pub result_mapping: ResultMapping,
+    pub missing_candidate: MissingCandidate,
+}
+
+impl CompatPolicy {
+    pub fn attach(&self, proto: MapperCbrsAttachV1) -> Result<CellAttach> {
+        let result = self.result_mapping.result(proto.result)?;
+        let gps = proto.gps.ok_or(Error::ProtoHasNone("gps"))?;
+        let candidate = match (proto.candidate, self.missing_candidate) {
+            (Some(candidate), _) => candidate.into(),
+            (None, MissingCandidate::Zeroed) => AttachCandidate {
+                from_scan: 0,
+                delay: 0,
+                cell_id: 0,
+                fcn: 0,
+                rsrp: 0,
+                rsrq: 0,
+            },
+            (None, MissingCandidate::Reject) => return Err(Error::ProtoHasNone("candidate")),
+        };
+        Ok(CellAttach {
+            attach_counter: proto.attach_counter,
+            gps: gps.try_into()?,
+            candidate,
+            result,
+            failure_cause: None,
+            sim: None,
+        })
+    }
+
+    /// Decodes any payload, applying the policy to attach reports
+    pub fn payload(&self, proto: mapper_payload::Message) -> Result<Payload> {
+        match proto {
+            mapper_payload::Message::Attach(attach) => match attach.version {
+                Some(mapper_attach::Version::AttachV1(v1)) => {
+                    Ok(Payload::CellAttach(self.attach(v1)?))
+                }
+                None => Err(Error::ProtoHasNone("version")),
+            },
+            other => other.try_into(),
+        }
+    }
+}
+
+impl Message {
+    /// Decodes the message under a compat policy, verifying its signature in process. The
+    /// signature covers the payload as encoded by the device, so it verifies whatever the policy.
+    pub fn try_from_with_compat(
+        value: impl Into<ExtendedMsg>,
+        policy: &CompatPolicy,
+    ) -> Result<Self> {
+        Self::try_from_with_compat_and_verifier(
+            value,
+            policy,
+            &InProcessVerifier,
+            &DecodeLimits::DEFAULT,
+        )
+    }
+
+    /// Same as `try_from_with_compat`, verifying with the given backend under `limits`
+    pub fn try_from_with_compat_and_verifier<V: VerifierBackend + ?Sized>(
+        value: impl Into<ExtendedMsg>,
+        policy: &CompatPolicy,
+        verifier: &V,
+        limits: &DecodeLimits,
+    ) -> Result<Self> {
+        let unverified = UnverifiedMsg::try_from(value.into())?;
+        unverified.check_limits(limits)?;
+        verifier.verify(
+            &unverified.pubkey,
+            &unverified.signed_bytes(),
+            &unverified.signature,
+        )?;
+        unverified.into_message_with(limits, |payload| policy.payload(payload))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{CellScanResult, Gps, MapperMsg};
+
+    fn legacy_attach(result: i32) -> MapperCbrsAttachV1 {
+        MapperCbrsAttachV1 {
+            attach_counter: 7,
+            gps: Some(Gps::rounded().into()),
+            candidate: None,
+            result,
+        }
+    }
+
+    #[test]
+    fn default_policy_is_strict() {
+        assert!(matches!(
+            CompatPolicy::default().attach(legacy_attach(1)),
+            Err(Error::ProtoHasNone("candidate"))
+        ));
+        let mut proto = legacy_attach(1);
+        proto.candidate = Some(AttachCandidate::from(CellScanResult::random()).into());
+        let current = CellAttach::try_from(proto.clone()).unwrap();
+        assert_eq!(CompatPolicy::default().attach(proto).unwrap(), current);
+    }
+
+    #[test]
+    fn legacy_ordering_and_missing_candidate() {
+        let mut legacy_order = ResultMapping::CURRENT;
+        legacy_order.0.rotate_left(1);
+        let policy = CompatPolicy {
+            result_mapping: legacy_order,
+            missing_candidate: MissingCandidate::Zeroed,
+        };
+        let attach = policy.attach(legacy_attach(0)).unwrap();
+        assert_eq!(attach.result, CellAttachResult::Connected);
+        assert_eq!(attach.candidate.cell_id, 0);
+        assert!(matches!(
+            policy.attach(legacy_attach(6)),
+            Err(Error::InvalidAttachResultInt { value: 6 })
+        ));
+    }
+
+    #[test]
+    fn limits_apply() {
+        let key = crate::keys::file::File::create_key().unwrap();
+        let msg: MapperMsg = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded()))
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let policy = CompatPolicy::default();
+        assert!(Message::try_from_with_compat(msg.clone(), &policy).is_ok());
+        let short = DecodeLimits::DEFAULT.with_max_signature_len(1);
+        assert!(matches!(
+            Message::try_from_with_compat_and_verifier(msg, &policy, &InProcessVerifier, &short),
+            Err(Error::SignatureTooLong { max: 1, .. })
+        ));
+    }
+}
@@ -0,0 +1,157 @@
+//! Compression of MapperMsg batches for backhaul billed by the byte. A batch is the
+//! length-delimited stream of its messages, as written by `Message::encode_length_delimited_to`.
+//!
+//! Compressed batches start with `b"SPZ"` and a codec byte, so that decoders pick the codec by
+//! themselves and still accept uncompressed batches, which can't start with the magic: their
+//! first frame's payload tag follows the length. Codecs are feature gated: `zstd` and
+//! `deflate`. Decoding a batch whose codec isn't compiled in fails with `Error::UnsupportedCodec`.
+use super::{Error, Message, Result};
+use std::{borrow::Cow, io::Read};
+
+pub const MAGIC: &[u8; 3] = b"SPZ";
+
+/// Batches inflating past this are rejected, so that a small upload can't exhaust memory
+pub const MAX_DECOMPRESSED_LEN: u64 = 16 << 20;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum Codec {
+    /// Batches are sent as is, without a header
+    #[default]
+    None,
+    /// `level` from 1 to 22, 3 is zstd's default
+    #[cfg(feature = "zstd")]
+    Zstd { level: i32 },
+    /// `level` from 0 to 9
+    #[cfg(feature = "deflate")]
+    Deflate { level: u32 },
+}
+
+#[cfg(feature = "zstd")]
+const ZSTD_ID: u8 = 1;
+#[cfg(feature = "deflate")]
+const DEFLATE_ID: u8 = 2;
+
+#[cfg(any(feature = "zstd", feature = "deflate"))]
+fn header(id: u8, len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len / 2);
+    out.extend_from_slice(MAGIC);
+    out.push(id);
+    out
+}
+
+pub fn compress(bytes: &[u8], codec: Codec) -> Result<Vec<u8>> {
+    match codec {
+        Codec::None => Ok(bytes.to_vec()),
+        #[cfg(feature = "zstd")]
+        Codec::Zstd { level } => {
+            let mut out = header(ZSTD_ID, bytes.len());
+            zstd::stream::copy_encode(bytes, &mut out, level)?;
+            Ok(out)
+        }
+        #[cfg(feature = "deflate")]
+        Codec::Deflate { level } => {
+            use std::io::Write;
+            let mut encoder = flate2::write::DeflateEncoder::new(
+                header(DEFLATE_ID, bytes.len()),
+                flate2::Compression::new(level),
+            );
+            encoder.write_all(bytes)?;
+            Ok(encoder.finish()?)
+        }
+    }
+}
+
+/// Borrows `bytes` if they are not compressed
+pub fn decompress(bytes: &[u8]) -> Result<Cow<'_, [u8]>> {
+    let Some(body) = bytes.strip_prefix(MAGIC.as_slice()) else {
+        return Ok(Cow::Borrowed(bytes));
+    };
+    let (&id, body) = body.split_first().ok_or(Error::UnsupportedCodec(None))?;
+    let mut out = Vec::new();
+    decoder(id, body)?
+        .take(MAX_DECOMPRESSED_LEN + 1)
+        .read_to_end(&mut out)?;
+    if out.len() as u64 > MAX_DECOMPRESSED_LEN {
+        return Err(Error::OutOfRange {
+            field: "decompressed_len",
+            value: out.len() as i128,
+        });
+    }
+    Ok(Cow::Owned(out))
+}
+
+fn decoder(id: u8, body: &[u8]) -> Result<Box<dyn Read + '_>> {
+    match id {
+        #[cfg(feature = "zstd")]
+        ZSTD_ID => Ok(Box::new(zstd::stream::Decoder::new(body)?)),
+        #[cfg(feature = "deflate")]
+        DEFLATE_ID => Ok(Box::new(flate2::read::DeflateDecoder::new(body))),
+        id => Err(Error::UnsupportedCodec(Some(id))),
+    }
+}
+
+pub fn encode_batch(msgs: &[Message], codec: Codec) -> Result<Vec<u8>> {
+    let mut stream = Vec::new();
+    for msg in msgs {
+        msg.encode_length_delimited_to(&mut stream)?;
+    }
+    compress(&stream, codec)
+}
+
+/// Decodes a compressed or uncompressed batch without verifying signatures
+pub fn decode_batch(bytes: &[u8]) -> Result<Vec<Message>> {
+    decode_batch_with(bytes, |buf| Message::decode_length_delimited_from(buf))
+}
+
+pub fn decode_batch_with_signature_verification(bytes: &[u8]) -> Result<Vec<Message>> {
+    decode_batch_with(bytes, |buf| {
+        Message::decode_length_delimited_from_with_signature_verification(buf)
+    })
+}
+
+fn decode_batch_with(
+    bytes: &[u8],
+    decode: impl Fn(&mut &[u8]) -> Result<Message>,
+) -> Result<Vec<Message>> {
+    let stream = decompress(bytes)?;
+    let mut remaining = stream.as_ref();
+    let mut msgs = Vec::new();
+    while !remaining.is_empty() {
+        msgs.push(decode(&mut remaining)?);
+    }
+    Ok(msgs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{keys, Gps, Payload};
+
+    #[test]
+    fn batches_roundtrip_through_every_codec() {
+        let key = keys::file::File::create_key().unwrap();
+        let msgs: Vec<_> = (0..10)
+            .map(|_| Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap())
+            .collect();
+        let codecs = [
+            Codec::None,
+            #[cfg(feature = "zstd")]
+            Codec::Zstd { level: 3 },
+            #[cfg(feature = "deflate")]
+            Codec::Deflate { level: 6 },
+        ];
+        let plain = encode_batch(&msgs, Codec::None).unwrap();
+        for codec in codecs {
+            let batch = encode_batch(&msgs, codec).unwrap();
+            assert!(batch.len() <= plain.len(), "{codec:?}");
+            assert_eq!(
+                decode_batch_with_signature_verification(&batch).unwrap(),
+                msgs
+            );
+        }
+        assert!(matches!(
+            decode_batch(b"SPZ\x7f"),
+            Err(Error::UnsupportedCodec(Some(0x7f)))
+        ));
+    }
+}
@@ -0,0 +1,416 @@
+//! Mapper behavior settings shared by the firmware and the server. The server sends changes as
+//! `ConfigPatch` downlinks on `CONFIG_PORT`, computed with `MapperConfig::diff` and applied on
+//! the device with `MapperConfig::apply`.
+//!
+//! Configs and patches travel as the `MapperConfigV1` and `ConfigPatchV1` protobufs below. A
+//! downlink is the schema version byte followed by the encoded `ConfigPatchV1`.
+use super::{region::Region, Deserialize, Error, ProtoMessage, Result, Serialize};
+
+/// Version of the downlink frame, bumped on any incompatible change
+pub const SCHEMA_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MapperConfig {
+    /// Increases with every change, so that a patch is only applied to the config it was
+    /// computed from
+    pub revision: u32,
+    /// Seconds between GPS reports
+    pub report_interval_s: u32,
+    /// Seconds between cell scans
+    pub scan_interval_s: u32,
+    /// Whether the SIM is left out of attaches, see `SimInfo`
+    pub redact_sim: bool,
+    /// Regions the device may operate in
+    pub regions: Vec<Region>,
+}
+
+impl Default for MapperConfig {
+    fn default() -> Self {
+        Self {
+            revision: 0,
+            report_interval_s: 300,
+            scan_interval_s: 900,
+            redact_sim: true,
+            regions: vec![Region::US915],
+        }
+    }
+}
+
+/// Changes to a `MapperConfig`. Fields left `None` are not changed. Without a base revision the
+/// patch is a full config and must set every field.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigPatch {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_revision: Option<u32>,
+    pub revision: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub report_interval_s: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scan_interval_s: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redact_sim: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regions: Option<Vec<Region>>,
+}
+
+impl MapperConfig {
+    /// Fails if an interval is zero or no region is allowed
+    pub fn validate(&self) -> Result {
+        if self.report_interval_s == 0 {
+            return Err(Error::InvalidConfig("report_interval_s is zero"));
+        }
+        if self.scan_interval_s == 0 {
+            return Err(Error::InvalidConfig("scan_interval_s is zero"));
+        }
+        if self.regions.is_empty() {
+            return Err(Error::InvalidConfig("no region"));
+        }
+        Ok(())
+    }
+
+    /// The patch turning this config into `target`
+    pub fn diff(&self, target: &MapperConfig) -> ConfigPatch {
+        fn changed<T: PartialEq + Clone>(from: &T, to: &T) -> Option<T> {
+            (from != to).then(|| to.clone())
+        }
+        ConfigPatch {
+            base_revision: Some(self.revision),
+            revision: target.revision,
+            report_interval_s: changed(&self.report_interval_s, &target.report_interval_s),
+            scan_interval_s: changed(&self.scan_interval_s, &target.scan_interval_s),
+            redact_sim: changed(&self.redact_sim, &target.redact_sim),
+            regions: changed(&self.regions, &target.regions),
+        }
+    }
+
+    /// The patch replacing any config with this one
+    pub fn to_full_patch(&self) -> ConfigPatch {
+        ConfigPatch {
+            base_revision: None,
+            revision: self.revision,
+            report_interval_s: Some(self.report_interval_s),
+            scan_interval_s: Some(self.scan_interval_s),
+            redact_sim: Some(self.redact_sim),
+            regions: Some(self.regions.clone()),
+        }
+    }
+
+    /// The config after `patch`. Fails if the patch was computed from another revision, does
+    /// not move the revision forward or leaves the config invalid.
+    pub fn apply(&self, patch: &ConfigPatch) -> Result<MapperConfig> {
+        match patch.base_revision {
+            Some(base) if base != self.revision => {
+                return Err(Error::ConfigRevisionMismatch {
+                    expected: self.revision,
+                    found: base,
+                })
+            }
+            None if !patch.is_full() => {
+                return Err(Error::InvalidConfig("full config with unset fields"))
+            }
+            _ => (),
+        }
+        if patch.revision <= self.revision && patch.base_revision.is_some() {
+            return Err(Error::InvalidConfig("revision does not increase"));
+        }
+        let config = MapperConfig {
+            revision: patch.revision,
+            report_interval_s: patch.report_interval_s.unwrap_or(self.report_interval_s),
+            scan_interval_s: patch.scan_interval_s.unwrap_or(self.scan_interval_s),
+            redact_sim: patch.redact_sim.unwrap_or(self.redact_sim),
+            regions: patch
+                .regions
+                .clone()
+                .unwrap_or_else(|| self.regions.clone()),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+impl ConfigPatch {
+    /// Whether the patch changes nothing but the revision
+    pub fn is_empty(&self) -> bool {
+        self.report_interval_s.is_none()
+            && self.scan_interval_s.is_none()
+            && self.redact_sim.is_none()
+            && self.regions.is_none()
+    }
+
+    fn is_full(&self) -> bool {
+        self.report_interval_s.is_some()
+            && self.scan_interval_s.is_some()
+            && self.redact_sim.is_some()
+            && self.regions.is_some()
+    }
+
+    pub fn to_downlink_bytes(&self) -> Vec<u8> {
+        let proto = self.to_proto();
+        let mut bytes = Vec::with_capacity(1 + proto.encoded_len());
+        bytes.push(SCHEMA_VERSION);
+        proto.encode(&mut bytes).expect("a vec grows as needed");
+        bytes
+    }
+
+    pub fn from_downlink_bytes(bytes: &[u8]) -> Result<Self> {
+        let Some((&version, proto)) = bytes.split_first() else {
+            return Err(Error::InvalidVecForParsingLoraPayload {
+                payload: "ConfigPatch",
+                size: 0,
+                expected: 1,
+            });
+        };
+        if version != SCHEMA_VERSION {
+            return Err(Error::UnsupportedPayloadVersion {
+                payload: "ConfigPatch",
+                version,
+            });
+        }
+        ConfigPatchV1::decode(proto)?.try_into()
+    }
+
+    pub fn to_proto(&self) -> ConfigPatchV1 {
+        ConfigPatchV1 {
+            base_revision: self.base_revision,
+            revision: self.revision,
+            report_interval_s: self.report_interval_s,
+            scan_interval_s: self.scan_interval_s,
+            redact_sim: self.redact_sim,
+            regions: self.regions.as_deref().map(|regions| RegionsV1 {
+                regions: regions_to_proto(regions),
+            }),
+        }
+    }
+}
+
+impl TryFrom<ConfigPatchV1> for ConfigPatch {
+    type Error = Error;
+
+    fn try_from(proto: ConfigPatchV1) -> Result<Self> {
+        Ok(Self {
+            base_revision: proto.base_revision,
+            revision: proto.revision,
+            report_interval_s: proto.report_interval_s,
+            scan_interval_s: proto.scan_interval_s,
+            redact_sim: proto.redact_sim,
+            regions: proto
+                .regions
+                .map(|regions| regions_from_proto(&regions.regions))
+                .transpose()?,
+        })
+    }
+}
+
+/// Wire form of a `MapperConfig`. Like `raw_dump::RawModemDumpV1` these derive with the crate's
+/// own prost, which the assertion below holds to the one helium-proto builds on.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct MapperConfigV1 {
+    #[prost(uint32, tag = "1")]
+    pub revision: u32,
+    #[prost(uint32, tag = "2")]
+    pub report_interval_s: u32,
+    #[prost(uint32, tag = "3")]
+    pub scan_interval_s: u32,
+    #[prost(bool, tag = "4")]
+    pub redact_sim: bool,
+    /// 1 US915, 2 EU868, 3 AU915, 4 AS923
+    #[prost(uint32, repeated, tag = "5")]
+    pub regions: Vec<u32>,
+}
+
+/// Wire form of a `ConfigPatch`, where unset fields are left unchanged
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ConfigPatchV1 {
+    #[prost(uint32, optional, tag = "1")]
+    pub base_revision: Option<u32>,
+    #[prost(uint32, tag = "2")]
+    pub revision: u32,
+    #[prost(uint32, optional, tag = "3")]
+    pub report_interval_s: Option<u32>,
+    #[prost(uint32, optional, tag = "4")]
+    pub scan_interval_s: Option<u32>,
+    #[prost(bool, optional, tag = "5")]
+    pub redact_sim: Option<bool>,
+    /// Set to change the regions, so that a patch can tell no change from none allowed
+    #[prost(message, optional, tag = "6")]
+    pub regions: Option<RegionsV1>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RegionsV1 {
+    /// As in `MapperConfigV1::regions`
+    #[prost(uint32, repeated, tag = "1")]
+    pub regions: Vec<u32>,
+}
+
+const _: fn() = || {
+    fn helium_proto_message<M: ProtoMessage>() {}
+    helium_proto_message::<MapperConfigV1>();
+    helium_proto_message::<ConfigPatchV1>();
+};
+
+impl MapperConfig {
+    pub fn to_proto(&self) -> MapperConfigV1 {
+        MapperConfigV1 {
+            revision: self.revision,
+            report_interval_s: self.report_interval_s,
+            scan_interval_s: self.scan_interval_s,
+            redact_sim: self.redact_sim,
+            regions: regions_to_proto(&self.regions),
+        }
+    }
+
+    pub fn encode_to_vec(&self) -> Vec<u8> {
+        self.to_proto().encode_to_vec()
+    }
+
+    /// Checks that the config is valid
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        MapperConfigV1::decode(bytes)?.try_into()
+    }
+}
+
+impl TryFrom<MapperConfigV1> for MapperConfig {
+    type Error = Error;
+
+    fn try_from(proto: MapperConfigV1) -> Result<Self> {
+        let config = Self {
+            revision: proto.revision,
+            report_interval_s: proto.report_interval_s,
+            scan_interval_s: proto.scan_interval_s,
+            redact_sim: proto.redact_sim,
+            regions: regions_from_proto(&proto.regions)?,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+fn region_to_proto(region: Region) -> u32 {
+    match region {
+        Region::US915 => 1,
+        Region::EU868 => 2,
+        Region::AU915 => 3,
+        Region::AS923 => 4,
+    }
+}
+
+fn regions_to_proto(regions: &[Region]) -> Vec<u32> {
+    regions.iter().copied().map(region_to_proto).collect()
+}
+
+fn regions_from_proto(regions: &[u32]) -> Result<Vec<Region>> {
+    regions
+        .iter()
+        .map(|&value| {
+            Region::ALL
+                .into_iter()
+                .find(|region| region_to_proto(*region) == value)
+                .ok_or(Error::OutOfRange {
+                    field: "regions",
+                    value: value.into(),
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn target() -> MapperConfig {
+        MapperConfig {
+            revision: 1,
+            scan_interval_s: 600,
+            regions: vec![Region::US915, Region::AU915],
+            ..MapperConfig::default()
+        }
+    }
+
+    #[test]
+    fn diff_downlink_apply_roundtrip() {
+        let device = MapperConfig::default();
+        let patch = device.diff(&target());
+        assert_eq!(
+            (patch.report_interval_s, patch.scan_interval_s),
+            (None, Some(600))
+        );
+        let bytes = patch.to_downlink_bytes();
+        // version, base revision, revision, scan interval, regions
+        assert_eq!(bytes.len(), 1 + 2 + 2 + 3 + 6);
+        let decoded = ConfigPatch::from_downlink_bytes(&bytes).unwrap();
+        assert_eq!(decoded, patch);
+        assert_eq!(device.apply(&decoded).unwrap(), target());
+
+        let full = target().to_full_patch();
+        let decoded = ConfigPatch::from_downlink_bytes(&full.to_downlink_bytes()).unwrap();
+        assert_eq!(device.apply(&decoded).unwrap(), target());
+    }
+
+    #[test]
+    fn apply_rejects_stale_and_invalid_patches() {
+        let device = target();
+        assert!(matches!(
+            device.apply(&MapperConfig::default().diff(&target())),
+            Err(Error::ConfigRevisionMismatch {
+                expected: 1,
+                found: 0
+            })
+        ));
+        let mut no_region = device.clone();
+        no_region.revision = 2;
+        no_region.regions.clear();
+        assert!(matches!(
+            device.apply(&device.diff(&no_region)),
+            Err(Error::InvalidConfig("no region"))
+        ));
+        let mut bytes = device.diff(&MapperConfig::default()).to_downlink_bytes();
+        // field 15 of wire type 7, which doesn't exist
+        bytes.push(0x7f);
+        assert!(matches!(
+            ConfigPatch::from_downlink_bytes(&bytes),
+            Err(Error::HeliumProtoDecode(_))
+        ));
+        bytes[0] = 2;
+        assert!(matches!(
+            ConfigPatch::from_downlink_bytes(&bytes),
+            Err(Error::UnsupportedPayloadVersion { version: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn proto_roundtrip() {
+        let config = target();
+        assert_eq!(
+            MapperConfig::decode(&config.encode_to_vec()).unwrap(),
+            config
+        );
+
+        let mut proto = config.to_proto();
+        proto.regions.push(9);
+        assert!(matches!(
+            MapperConfig::try_from(proto),
+            Err(Error::OutOfRange {
+                field: "regions",
+                value: 9
+            })
+        ));
+        let empty = MapperConfigV1 {
+            regions: Vec::new(),
+            ..config.to_proto()
+        };
+        assert!(matches!(
+            MapperConfig::decode(&empty.encode_to_vec()),
+            Err(Error::InvalidConfig("no region"))
+        ));
+    }
+
+    #[test]
+    fn json_roundtrip() {
+        let json = serde_json::to_string(&target()).unwrap();
+        assert_eq!(
+            serde_json::from_str::<MapperConfig>(&json).unwrap(),
+            target()
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Persistence of the scan and attach counters. They must never go back after a reboot, or
+//! reports would look replayed and censorship detection would be blind, so `workflow::Workflow`
+//! persists every increment before emitting the payload that carries it.
+use super::{Deserialize, Error, Result, Serialize};
+use std::path::PathBuf;
+
+/// The counters the next scan and attach will carry
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Counters {
+    pub next_scan: u32,
+    pub next_attach: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Counter {
+    Scan,
+    Attach,
+}
+
+impl Counters {
+    pub fn get(&self, counter: Counter) -> u32 {
+        match counter {
+            Counter::Scan => self.next_scan,
+            Counter::Attach => self.next_attach,
+        }
+    }
+
+    /// The counters after `counter` was used once. Counters roll over.
+    pub fn incremented(mut self, counter: Counter) -> Self {
+        let value = match counter {
+            Counter::Scan => &mut self.next_scan,
+            Counter::Attach => &mut self.next_attach,
+        };
+        *value = value.wrapping_add(1);
+        self
+    }
+}
+
+pub trait CounterStore {
+    /// The persisted counters, or the defaults if none were persisted yet
+    fn load(&self) -> Result<Counters>;
+
+    fn persist(&mut self, counters: &Counters) -> Result;
+
+    /// Persists the increment and then returns the value to use, so that a crash can skip a
+    /// counter but never reuse one
+    fn increment(&mut self, counter: Counter) -> Result<u32> {
+        let counters = self.load()?;
+        self.persist(&counters.incremented(counter))?;
+        Ok(counters.get(counter))
+    }
+}
+
+impl<S: CounterStore + ?Sized> CounterStore for &mut S {
+    fn load(&self) -> Result<Counters> {
+        S::load(self)
+    }
+
+    fn persist(&mut self, counters: &Counters) -> Result {
+        S::persist(self, counters)
+    }
+}
+
+/// Keeps the counters in memory only, for tests and devices that accept losing them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InMemoryCounterStore {
+    counters: Counters,
+}
+
+impl InMemoryCounterStore {
+    pub fn new(counters: Counters) -> Self {
+        Self { counters }
+    }
+}
+
+impl CounterStore for InMemoryCounterStore {
+    fn load(&self) -> Result<Counters> {
+        Ok(self.counters)
+    }
+
+    fn persist(&mut self, counters: &Counters) -> Result {
+        self.counters = *counters;
+        Ok(())
+    }
+}
+
+/// Stores the counters as `<next_scan> <next_attach>` in a text file. Writes go to a temporary
+/// file that is then renamed over the old one, so a power cut leaves either old or new counters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileCounterStore {
+    path: PathBuf,
+}
+
+impl FileCounterStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn tmp_path(&self) -> PathBuf {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        tmp.into()
+    }
+}
+
+impl CounterStore for FileCounterStore {
+    fn load(&self) -> Result<Counters> {
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Counters::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut fields = contents.split_whitespace();
+        let mut next = || -> Result<u32> {
+            Ok(fields
+                .next()
+                .ok_or_else(|| Error::InvalidCounterFile(contents.clone()))?
+                .parse()?)
+        };
+        Ok(Counters {
+            next_scan: next()?,
+            next_attach: next()?,
+        })
+    }
+
+    fn persist(&mut self, counters: &Counters) -> Result {
+        use std::io::Write;
+        let tmp = self.tmp_path();
+        let mut file = std::fs::File::create(&tmp)?;
+        writeln!(file, "{} {}", counters.next_scan, counters.next_attach)?;
+        file.sync_all()?;
+        std::fs::rename(tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn file_store_roundtrip() {
+        let path = std::env::temp_dir().join(format!("counters-{}", rand::random::<u64>()));
+        let mut store = FileCounterStore::new(&path);
+        assert_eq!(store.load().unwrap(), Counters::default());
+        assert_eq!(store.increment(Counter::Scan).unwrap(), 0);
+        assert_eq!(store.increment(Counter::Attach).unwrap(), 0);
+        assert_eq!(store.increment(Counter::Scan).unwrap(), 1);
+
+        let reopened = FileCounterStore::new(&path);
+        assert_eq!(
+            reopened.load().unwrap(),
+            Counters {
+                next_scan: 2,
+                next_attach: 1
+            }
+        );
+        std::fs::write(&path, "2").unwrap();
+        assert!(matches!(reopened.load(), Err(Error::InvalidCounterFile(_))));
+        std::fs::remove_file(path).unwrap();
+    }
+}
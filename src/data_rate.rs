@@ -0,0 +1,58 @@
+use super::{Error, Result};
+use helium_proto::DataRate;
+
+/// Conversions between proto DataRates and the spreading factor and bandwidth reported by
+/// gateways. LoRa data rates are named `SF<n>BW<khz>` in the proto.
+pub trait DataRateExt: Sized {
+    fn from_sf_bw(spreading_factor: u8, bandwidth_khz: u32) -> Result<Self>;
+    /// None for non LoRa modulations (eg: FSK or LR-FHSS)
+    fn spreading_factor(&self) -> Option<u8>;
+    fn bandwidth_khz(&self) -> Option<u32>;
+}
+
+impl DataRateExt for DataRate {
+    fn from_sf_bw(spreading_factor: u8, bandwidth_khz: u32) -> Result<Self> {
+        DataRate::from_str_name(&format!("SF{spreading_factor}BW{bandwidth_khz}")).ok_or(
+            Error::InvalidLoraModulation {
+                spreading_factor,
+                bandwidth_khz,
+            },
+        )
+    }
+
+    fn spreading_factor(&self) -> Option<u8> {
+        lora_modulation(*self).map(|(spreading_factor, _)| spreading_factor)
+    }
+
+    fn bandwidth_khz(&self) -> Option<u32> {
+        lora_modulation(*self).map(|(_, bandwidth_khz)| bandwidth_khz)
+    }
+}
+
+fn lora_modulation(data_rate: DataRate) -> Option<(u8, u32)> {
+    let (spreading_factor, bandwidth_khz) = data_rate
+        .as_str_name()
+        .strip_prefix("SF")?
+        .split_once("BW")?;
+    Some((spreading_factor.parse().ok()?, bandwidth_khz.parse().ok()?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sf_bw_roundtrip() {
+        let data_rate = DataRate::from_sf_bw(10, 125).unwrap();
+        assert_eq!(data_rate, DataRate::Sf10bw125);
+        assert_eq!(data_rate.spreading_factor(), Some(10));
+        assert_eq!(data_rate.bandwidth_khz(), Some(125));
+        assert!(matches!(
+            DataRate::from_sf_bw(13, 125),
+            Err(Error::InvalidLoraModulation {
+                spreading_factor: 13,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,155 @@
+//! Device allow and deny lists, consulted with the pubkey of a message before its signature is
+//! verified so that revoked devices are turned away cheaply.
+use super::{Error, PublicKey, Result};
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+};
+
+pub trait DeviceRegistry {
+    /// Returns `Error::DeviceRejected` if messages from the device must not be ingested
+    fn check(&self, pubkey: &PublicKey) -> Result;
+}
+
+impl<R: DeviceRegistry + ?Sized> DeviceRegistry for &R {
+    fn check(&self, pubkey: &PublicKey) -> Result {
+        R::check(self, pubkey)
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct AllowAllDevices;
+
+impl DeviceRegistry for AllowAllDevices {
+    fn check(&self, _pubkey: &PublicKey) -> Result {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ListMode {
+    /// Only listed devices are admitted
+    Allow,
+    /// Listed devices are rejected
+    Deny,
+}
+
+/// In-memory allow or deny list that counts the devices it rejected
+#[derive(Debug)]
+pub struct InMemoryRegistry {
+    mode: ListMode,
+    pubkeys: RwLock<HashSet<Vec<u8>>>,
+    rejects: AtomicU64,
+}
+
+impl InMemoryRegistry {
+    pub fn new<'a, I: IntoIterator<Item = &'a PublicKey>>(mode: ListMode, pubkeys: I) -> Self {
+        Self {
+            mode,
+            pubkeys: RwLock::new(pubkeys.into_iter().map(|pubkey| pubkey.to_vec()).collect()),
+            rejects: AtomicU64::new(0),
+        }
+    }
+
+    pub fn allow_list<'a, I: IntoIterator<Item = &'a PublicKey>>(pubkeys: I) -> Self {
+        Self::new(ListMode::Allow, pubkeys)
+    }
+
+    pub fn deny_list<'a, I: IntoIterator<Item = &'a PublicKey>>(pubkeys: I) -> Self {
+        Self::new(ListMode::Deny, pubkeys)
+    }
+
+    pub fn mode(&self) -> ListMode {
+        self.mode
+    }
+
+    /// Returns false if the device was already listed
+    pub fn insert(&self, pubkey: &PublicKey) -> bool {
+        self.pubkeys.write().unwrap().insert(pubkey.to_vec())
+    }
+
+    /// Returns false if the device was not listed
+    pub fn remove(&self, pubkey: &PublicKey) -> bool {
+        self.pubkeys.write().unwrap().remove(&pubkey.to_vec())
+    }
+
+    pub fn contains(&self, pubkey: &PublicKey) -> bool {
+        self.pubkeys.read().unwrap().contains(&pubkey.to_vec())
+    }
+
+    /// Number of checks that rejected a device since creation
+    pub fn rejects(&self) -> u64 {
+        self.rejects.load(Ordering::Relaxed)
+    }
+}
+
+impl DeviceRegistry for InMemoryRegistry {
+    fn check(&self, pubkey: &PublicKey) -> Result {
+        let admitted = self.contains(pubkey) == (self.mode == ListMode::Allow);
+        if admitted {
+            Ok(())
+        } else {
+            self.rejects.fetch_add(1, Ordering::Relaxed);
+            Err(Error::DeviceRejected(pubkey.to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        keys, keys::KeyTrait, DecodeLimits, Gps, InProcessVerifier, MapperMsg, Message, Payload,
+    };
+
+    #[test]
+    fn deny_list_rejects_before_verification() {
+        let revoked = keys::file::File::create_key().unwrap();
+        let other = keys::file::File::create_key().unwrap();
+        let registry = InMemoryRegistry::deny_list([&revoked.pubkey().unwrap()]);
+
+        let msg = |key: &keys::file::File| -> MapperMsg {
+            let mut msg = Message::from_payload_signed(key, Payload::Gps(Gps::rounded())).unwrap();
+            // a registry rejection must not depend on the signature
+            msg.signature = vec![0; 4].into();
+            msg.try_into().unwrap()
+        };
+        assert!(matches!(
+            Message::try_from_with_registry(
+                msg(&revoked),
+                &InProcessVerifier,
+                &registry,
+                &DecodeLimits::DEFAULT
+            ),
+            Err(Error::DeviceRejected(_))
+        ));
+        assert!(matches!(
+            Message::try_from_with_registry(
+                msg(&other),
+                &InProcessVerifier,
+                &registry,
+                &DecodeLimits::DEFAULT
+            ),
+            Err(Error::SignatureVerification { .. })
+        ));
+        assert_eq!(registry.rejects(), 1);
+
+        registry.remove(&revoked.pubkey().unwrap());
+        assert!(registry.check(&revoked.pubkey().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn allow_list() {
+        let allowed = keys::file::File::create_key().unwrap().pubkey().unwrap();
+        let other = keys::file::File::create_key().unwrap().pubkey().unwrap();
+        let registry = InMemoryRegistry::allow_list([&allowed]);
+        assert!(registry.check(&allowed).is_ok());
+        assert!(registry.check(&other).is_err());
+        assert!(registry.insert(&other));
+        assert!(registry.check(&other).is_ok());
+        assert_eq!(registry.rejects(), 1);
+    }
+}
@@ -0,0 +1,281 @@
+//! Whether a message earns device rewards. A `RuleSet` runs every rule and reports all the
+//! reasons a message fails, not just the first, so that devices can be told what to fix.
+#[cfg(feature = "h3")]
+use super::CellAt;
+use super::{Deserialize, JammingState, Message, Serialize, SpoofingState};
+#[cfg(feature = "h3")]
+use h3o::{CellIndex, Resolution};
+use rust_decimal::Decimal;
+#[cfg(feature = "h3")]
+use std::collections::HashSet;
+
+/// Why a message is not eligible
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    GpsNotLocked,
+    HdopAbove {
+        hdop: Decimal,
+        max: Decimal,
+    },
+    OutsideSupportedArea,
+    InDeniedArea,
+    TooFewWitnesses {
+        count: usize,
+        min: usize,
+    },
+    /// The fix has no H3 cell, eg: its coordinates are out of range
+    NoCell,
+    GnssJamming(JammingState),
+    GnssSpoofing(SpoofingState),
+    Other(String),
+}
+
+impl std::fmt::Display for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reason::GpsNotLocked => f.write_str("gps not locked"),
+            Reason::HdopAbove { hdop, max } => write!(f, "hdop {hdop} above {max}"),
+            Reason::OutsideSupportedArea => f.write_str("outside the supported area"),
+            Reason::InDeniedArea => f.write_str("inside a denied area"),
+            Reason::TooFewWitnesses { count, min } => {
+                write!(f, "{count} witnesses, {min} required")
+            }
+            Reason::NoCell => f.write_str("fix has no h3 cell"),
+            Reason::GnssJamming(state) => write!(f, "gnss jamming {state:?}"),
+            Reason::GnssSpoofing(state) => write!(f, "gnss spoofing {state:?}"),
+            Reason::Other(reason) => f.write_str(reason),
+        }
+    }
+}
+
+/// A single check. Closures `Fn(&Message) -> Option<Reason>` are rules too.
+pub trait Rule {
+    /// `None` if the message passes
+    fn check(&self, msg: &Message) -> Option<Reason>;
+}
+
+impl<F: Fn(&Message) -> Option<Reason>> Rule for F {
+    fn check(&self, msg: &Message) -> Option<Reason> {
+        self(msg)
+    }
+}
+
+/// See `Gps::is_locked`
+#[derive(Debug, Default, Copy, Clone)]
+pub struct GpsLocked;
+
+impl Rule for GpsLocked {
+    fn check(&self, msg: &Message) -> Option<Reason> {
+        (!msg.payload.gps().is_locked()).then_some(Reason::GpsNotLocked)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct MaxHdop(pub Decimal);
+
+impl Rule for MaxHdop {
+    fn check(&self, msg: &Message) -> Option<Reason> {
+        let hdop = msg.payload.gps().hdop;
+        (hdop > self.0).then_some(Reason::HdopAbove { hdop, max: self.0 })
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct MinWitnesses(pub usize);
+
+impl Rule for MinWitnesses {
+    fn check(&self, msg: &Message) -> Option<Reason> {
+        let count = msg.lora_gws.len();
+        (count < self.0).then_some(Reason::TooFewWitnesses { count, min: self.0 })
+    }
+}
+
+/// Fails fixes whose receiver reported jamming of at least `max_jamming`, or any spoofing.
+/// Fixes without indicators pass.
+#[derive(Debug, Copy, Clone)]
+pub struct NoGnssInterference {
+    pub max_jamming: JammingState,
+}
+
+impl Default for NoGnssInterference {
+    fn default() -> Self {
+        Self {
+            max_jamming: JammingState::Critical,
+        }
+    }
+}
+
+impl Rule for NoGnssInterference {
+    fn check(&self, msg: &Message) -> Option<Reason> {
+        let gps = msg.payload.gps();
+        match (gps.spoofing, gps.jamming) {
+            (Some(spoofing), _) if spoofing >= SpoofingState::Indicated => {
+                Some(Reason::GnssSpoofing(spoofing))
+            }
+            (_, Some(jamming)) if jamming >= self.max_jamming => Some(Reason::GnssJamming(jamming)),
+            _ => None,
+        }
+    }
+}
+
+/// An area as a set of H3 cells, of any mix of resolutions. A fix is inside if it falls in any
+/// of them.
+#[cfg(feature = "h3")]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Geofence {
+    cells: HashSet<CellIndex>,
+}
+
+#[cfg(feature = "h3")]
+impl Geofence {
+    /// Takes `CellIndex`es or typed cells, eg: the `CoverageCell`s of a supported region
+    pub fn new<I: IntoIterator<Item = C>, C: Into<CellIndex>>(cells: I) -> Self {
+        Self {
+            cells: cells.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// `None` if the fix has no cell
+    pub fn contains(&self, msg: &Message) -> Option<bool> {
+        let cell = CellAt::<15>::from_gps(msg.payload.gps()).ok()?.index();
+        Some(
+            (0..=15u8)
+                .filter_map(|resolution| Resolution::try_from(resolution).ok())
+                .filter_map(|resolution| cell.parent(resolution))
+                .any(|parent| self.cells.contains(&parent)),
+        )
+    }
+}
+
+/// Only fixes inside the geofence are eligible
+#[cfg(feature = "h3")]
+#[derive(Debug, Clone)]
+pub struct InsideArea(pub Geofence);
+
+#[cfg(feature = "h3")]
+impl Rule for InsideArea {
+    fn check(&self, msg: &Message) -> Option<Reason> {
+        match self.0.contains(msg) {
+            Some(true) => None,
+            Some(false) => Some(Reason::OutsideSupportedArea),
+            None => Some(Reason::NoCell),
+        }
+    }
+}
+
+/// Fixes inside the geofence are not eligible
+#[cfg(feature = "h3")]
+#[derive(Debug, Clone)]
+pub struct OutsideArea(pub Geofence);
+
+#[cfg(feature = "h3")]
+impl Rule for OutsideArea {
+    fn check(&self, msg: &Message) -> Option<Reason> {
+        match self.0.contains(msg) {
+            Some(false) => None,
+            Some(true) => Some(Reason::InDeniedArea),
+            None => Some(Reason::NoCell),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct RuleSet {
+    rules: Vec<Box<dyn Rule + Send + Sync>>,
+}
+
+impl RuleSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, rule: impl Rule + Send + Sync + 'static) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    pub fn evaluate(&self, msg: &Message) -> Eligibility {
+        Eligibility {
+            reasons: self
+                .rules
+                .iter()
+                .filter_map(|rule| rule.check(msg))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Eligibility {
+    /// Empty if every rule passed
+    pub reasons: Vec<Reason>,
+}
+
+impl Eligibility {
+    pub fn is_eligible(&self) -> bool {
+        self.reasons.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{keys, Gps, Payload};
+
+    #[test]
+    fn reports_every_failing_rule() {
+        let key = keys::file::File::create_key().unwrap();
+        let msg = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap();
+        let home = msg.reward_cell().unwrap();
+        let rules = || {
+            RuleSet::new()
+                .with(GpsLocked)
+                .with(MaxHdop(Decimal::new(10, 0)))
+                .with(InsideArea(Geofence::new([home.parent::<2>().unwrap()])))
+        };
+        assert!(rules().evaluate(&msg).is_eligible());
+
+        let strict = rules()
+            .with(MaxHdop(Decimal::new(2, 0)))
+            .with(OutsideArea(Geofence::new([home])))
+            .with(MinWitnesses(3))
+            .with(|_: &Message| Some(Reason::Other("closed".into())));
+        assert_eq!(
+            strict.evaluate(&msg).reasons,
+            [
+                Reason::HdopAbove {
+                    hdop: Decimal::new(9_05, 2),
+                    max: Decimal::new(2, 0)
+                },
+                Reason::InDeniedArea,
+                Reason::TooFewWitnesses { count: 0, min: 3 },
+                Reason::Other("closed".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn gnss_interference() {
+        let key = keys::file::File::create_key().unwrap();
+        let mut gps = Gps::rounded();
+        gps.jamming = JammingState::from_ubx_mon_rf(0b10);
+        gps.spoofing = SpoofingState::from_ubx_nav_status(1 << 3);
+        let msg = Message::from_payload_signed(&key, Payload::Gps(gps)).unwrap();
+        assert_eq!(NoGnssInterference::default().check(&msg), None);
+        let strict = NoGnssInterference {
+            max_jamming: JammingState::Warning,
+        };
+        assert_eq!(
+            strict.check(&msg),
+            Some(Reason::GnssJamming(JammingState::Warning))
+        );
+
+        gps.spoofing = SpoofingState::from_ubx_nav_status(3 << 3);
+        let msg = Message::from_payload_signed(&key, Payload::Gps(gps)).unwrap();
+        assert_eq!(
+            NoGnssInterference::default().check(&msg),
+            Some(Reason::GnssSpoofing(SpoofingState::Multiple))
+        );
+    }
+}
@@ -0,0 +1,313 @@
+//! Zero points of the LoRa time units. The frames carry 30 bits of seconds since an epoch, which
+//! for the unversioned frames is 2023-01-01. `Versioned` frames start with a header byte naming
+//! their layout version and epoch, so that private deployments can count from their own epoch
+//! and the public network can move to a new one before the 2057 rollover.
+use super::{gps::time, EncodeMode, Error, LoraDecode, LoraEncode, Result};
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Epoch {
+    id: u8,
+    reference: i64,
+}
+
+impl Epoch {
+    /// 2023-01-01 00:00:00 UTC, implied by every unversioned frame
+    pub const GENESIS: Epoch = Epoch {
+        id: 0,
+        reference: time::REFERENCE,
+    };
+    /// Ids are carried in a nibble of the header byte
+    pub const MAX_ID: u8 = 15;
+
+    pub fn new(id: u8, start: DateTime<Utc>) -> Result<Self> {
+        if id > Self::MAX_ID {
+            return Err(Error::OutOfRange {
+                field: "epoch",
+                value: id.into(),
+            });
+        }
+        let reference = start.timestamp();
+        // every u32 of LoRa units must map to a representable time
+        chrono::NaiveDateTime::from_timestamp_opt(reference + i64::from(u32::MAX), 0)
+            .ok_or(Error::TimestampOutOfRange(reference))?;
+        Ok(Self { id, reference })
+    }
+
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    /// Unix seconds of the start of the epoch
+    pub fn reference(&self) -> i64 {
+        self.reference
+    }
+
+    pub fn start(&self) -> DateTime<Utc> {
+        time::from_lora_units_in(0, self)
+    }
+
+    /// Last time the 30 bit LoRa time field can carry
+    pub fn end(&self) -> DateTime<Utc> {
+        time::from_lora_units_in((1 << time::LORA_BITS) - 1, self)
+    }
+
+    pub fn to_lora_units(&self, datetime: DateTime<Utc>, mode: EncodeMode) -> Result<u32> {
+        time::to_lora_units_in(datetime, self, mode)
+    }
+
+    pub fn from_lora_units(&self, units: u32) -> DateTime<Utc> {
+        time::from_lora_units_in(units, self)
+    }
+
+    /// Re-expresses LoRa time units of this epoch in `to`, eg: to re-encode stored frames after
+    /// an epoch bump. Times before the start of `to` saturate or are rejected per `mode`.
+    pub fn migrate(&self, units: u32, to: &Epoch, mode: EncodeMode) -> Result<u32> {
+        to.to_lora_units(self.from_lora_units(units), mode)
+    }
+}
+
+impl Default for Epoch {
+    fn default() -> Self {
+        Self::GENESIS
+    }
+}
+
+/// The epochs a decoder knows, by id, and the one encoders should use
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EpochRegistry {
+    epochs: [Option<Epoch>; Epoch::MAX_ID as usize + 1],
+    current: u8,
+}
+
+impl Default for EpochRegistry {
+    /// Only `Epoch::GENESIS`
+    fn default() -> Self {
+        let mut epochs = [None; Epoch::MAX_ID as usize + 1];
+        epochs[0] = Some(Epoch::GENESIS);
+        Self { epochs, current: 0 }
+    }
+}
+
+impl EpochRegistry {
+    /// Adds an epoch. Registering the same epoch twice is fine, reusing its id for another is not.
+    pub fn register(&mut self, epoch: Epoch) -> Result {
+        match &mut self.epochs[epoch.id as usize] {
+            Some(known) if *known != epoch => Err(Error::EpochConflict(epoch.id)),
+            slot => {
+                *slot = Some(epoch);
+                Ok(())
+            }
+        }
+    }
+
+    /// Registers `epoch` and makes it the current one
+    pub fn with_current(mut self, epoch: Epoch) -> Result<Self> {
+        self.register(epoch)?;
+        self.current = epoch.id;
+        Ok(self)
+    }
+
+    pub fn get(&self, id: u8) -> Result<Epoch> {
+        self.epochs
+            .get(id as usize)
+            .copied()
+            .flatten()
+            .ok_or(Error::UnknownEpoch(id))
+    }
+
+    pub fn current(&self) -> Epoch {
+        self.epochs[self.current as usize].expect("current epoch registered")
+    }
+
+    /// Decodes LoRa time units of the epoch `id`
+    pub fn from_lora_units(&self, id: u8, units: u32) -> Result<DateTime<Utc>> {
+        Ok(self.get(id)?.from_lora_units(units))
+    }
+
+    /// Re-expresses LoRa time units of the epoch `id` in the current epoch
+    pub fn migrate_to_current(&self, id: u8, units: u32, mode: EncodeMode) -> Result<u32> {
+        self.get(id)?.migrate(units, &self.current(), mode)
+    }
+}
+
+/// Payloads with LoRa layouts that can be framed by `Versioned`
+pub trait VersionedCodec: Sized {
+    const LABEL: &'static str;
+    /// Layout encoded by `Versioned::new`
+    const VERSION: u8;
+    /// Every layout the payload encodes and decodes
+    const VERSIONS: &'static [u8];
+
+    fn encode_versioned(&self, version: u8, epoch: &Epoch, mode: EncodeMode) -> Result<Vec<u8>>;
+
+    /// Returns the payload and the number of bytes it used, as `LoraDecode::from_lora_slice`
+    fn decode_versioned(bytes: &[u8], version: u8, epoch: &Epoch) -> Result<(Self, usize)>;
+
+    fn unsupported(version: u8) -> Error {
+        Error::UnsupportedPayloadVersion {
+            payload: Self::LABEL,
+            version,
+        }
+    }
+}
+
+/// A LoRa frame preceded by a header byte: the layout version in the high nibble and the epoch
+/// id in the low one. Version 1 is the layout of the unversioned frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Versioned<T> {
+    /// Private so that it is one of `T::VERSIONS`, see `with_version`
+    version: u8,
+    pub epoch: Epoch,
+    pub payload: T,
+}
+
+impl<T: VersionedCodec> Versioned<T> {
+    pub fn new(payload: T, epoch: Epoch) -> Self {
+        Self {
+            version: T::VERSION,
+            epoch,
+            payload,
+        }
+    }
+
+    /// Encodes in another of the payload's layouts, eg: `BEACON_V2`. Fails with
+    /// `Error::UnsupportedPayloadVersion` for a layout the payload doesn't have.
+    pub fn with_version(mut self, version: u8) -> Result<Self> {
+        if !T::VERSIONS.contains(&version) {
+            return Err(T::unsupported(version));
+        }
+        self.version = version;
+        Ok(self)
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn into_inner(self) -> T {
+        self.payload
+    }
+
+    /// Decodes a frame whose epoch must be known to `registry`
+    pub fn from_lora_slice_with_registry(
+        bytes: &[u8],
+        registry: &EpochRegistry,
+    ) -> Result<(Self, usize)> {
+        let header = *bytes
+            .first()
+            .ok_or(Error::InvalidVecForParsingLoraPayload {
+                payload: T::LABEL,
+                size: 0,
+                expected: 1,
+            })?;
+        let (version, epoch) = (header >> 4, registry.get(header & 0x0F)?);
+        let (payload, used) = T::decode_versioned(&bytes[1..], version, &epoch)?;
+        Ok((
+            Self {
+                version,
+                epoch,
+                payload,
+            },
+            used + 1,
+        ))
+    }
+}
+
+impl<T: VersionedCodec> LoraEncode for Versioned<T> {
+    type Bytes = Vec<u8>;
+
+    fn to_lora_bytes_with_mode(&self, mode: EncodeMode) -> Result<Self::Bytes> {
+        if self.version > 0x0F {
+            return Err(T::unsupported(self.version));
+        }
+        let mut bytes = vec![self.version << 4 | self.epoch.id];
+        bytes.extend(
+            self.payload
+                .encode_versioned(self.version, &self.epoch, mode)?,
+        );
+        Ok(bytes)
+    }
+}
+
+/// Decodes with the default registry, ie: only `Epoch::GENESIS` frames
+impl<T: VersionedCodec> LoraDecode for Versioned<T> {
+    fn from_lora_slice(bytes: &[u8]) -> Result<(Self, usize)> {
+        Self::from_lora_slice_with_registry(bytes, &EpochRegistry::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Beacon, Gps};
+    use chrono::TimeZone;
+
+    #[test]
+    fn private_epoch_roundtrip() {
+        let epoch = Epoch::new(3, Utc.with_ymd_and_hms(2050, 1, 1, 0, 0, 0).unwrap()).unwrap();
+        let mut gps = Gps::rounded();
+        gps.timestamp = Utc.with_ymd_and_hms(2060, 6, 1, 12, 0, 0).unwrap();
+        // past the end of the genesis epoch
+        assert!(Epoch::GENESIS
+            .to_lora_units(gps.timestamp, EncodeMode::Strict)
+            .is_err());
+
+        let beacon = Beacon::new(gps, vec![0xAB, 0xCD]);
+        let bytes = Versioned::new(beacon.clone(), epoch).to_lora_bytes();
+        assert_eq!(bytes[0], 0x13);
+        assert!(matches!(
+            Versioned::<Beacon>::from_lora_slice(&bytes),
+            Err(Error::UnknownEpoch(3))
+        ));
+        let registry = EpochRegistry::default().with_current(epoch).unwrap();
+        let (decoded, used) =
+            Versioned::<Beacon>::from_lora_slice_with_registry(&bytes, &registry).unwrap();
+        assert_eq!(used, bytes.len());
+        assert_eq!(decoded.payload.gps.timestamp, gps.timestamp);
+    }
+
+    #[test]
+    fn unknown_version_is_rejected() {
+        let beacon = Versioned::new(
+            Beacon::new(Gps::rounded(), vec![0xAB, 0xCD]),
+            Epoch::GENESIS,
+        );
+        assert!(matches!(
+            beacon.clone().with_version(9),
+            Err(Error::UnsupportedPayloadVersion {
+                payload: "Beacon",
+                version: 9
+            })
+        ));
+        let v2 = beacon.with_version(crate::BEACON_V2).unwrap();
+        assert_eq!(v2.version(), crate::BEACON_V2);
+        assert_eq!(v2.to_lora_bytes()[0] >> 4, crate::BEACON_V2);
+    }
+
+    #[test]
+    fn registry_migrates_units() {
+        let next = Epoch::new(1, Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()).unwrap();
+        let mut registry = EpochRegistry::default().with_current(next).unwrap();
+        assert!(matches!(
+            registry.register(Epoch::new(1, Utc::now()).unwrap()),
+            Err(Error::EpochConflict(1))
+        ));
+        registry.register(next).unwrap();
+
+        let units = Epoch::GENESIS
+            .to_lora_units(
+                Utc.with_ymd_and_hms(2024, 1, 2, 0, 0, 0).unwrap(),
+                EncodeMode::Strict,
+            )
+            .unwrap();
+        let migrated = registry
+            .migrate_to_current(0, units, EncodeMode::Strict)
+            .unwrap();
+        assert_eq!(migrated, 86_400);
+        assert_eq!(
+            registry.from_lora_units(1, migrated).unwrap(),
+            registry.from_lora_units(0, units).unwrap()
+        );
+    }
+}
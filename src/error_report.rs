@@ -0,0 +1,186 @@
+//! Structured, serializable form of `Error` for failure telemetry sent across services.
+use super::{Deserialize, Error, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorReport {
+    /// `Error::variant_name`, stable across releases
+    pub code: String,
+    /// The `Error` display string, for humans
+    pub message: String,
+    /// Fields of the variant, formatted, eg: `field` and `value` for `OutOfRange`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub context: BTreeMap<String, String>,
+    /// The report of the error wrapped by `InvalidScanResult` and `InvalidWitness`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<Box<ErrorReport>>,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+impl From<&Error> for ErrorReport {
+    fn from(error: &Error) -> Self {
+        let mut source = None;
+        let context: Vec<(&str, String)> = match error {
+            Error::UnexpectedPayloadKind { expected, found } => {
+                vec![
+                    ("expected", expected.to_string()),
+                    ("found", found.to_string()),
+                ]
+            }
+            Error::InvalidAttachResultInt { value } => vec![("value", value.to_string())],
+            Error::ProtoHasNone(field) => vec![("field", field.to_string())],
+            Error::DecimalCouldNotMapToFloat { decimal } => vec![("decimal", decimal.to_string())],
+            Error::PubkeyParse { bytes, .. } => vec![("bytes", hex(bytes))],
+            Error::SignatureVerification {
+                pubkey, signature, ..
+            } => vec![
+                ("pubkey", pubkey.to_string()),
+                ("signature", hex(signature)),
+            ],
+            Error::InvalidVecForParsingLoraPayload {
+                payload,
+                size,
+                expected,
+            } => vec![
+                ("payload", payload.to_string()),
+                ("size", size.to_string()),
+                ("expected", expected.to_string()),
+            ],
+            Error::InvalidDatarate(dr) => vec![("dr", dr.to_string())],
+            Error::UnknownGatewayEui(eui) => vec![("eui", format!("{eui:016x}"))],
+            Error::InvalidRegionalDatarate { region, dr } => {
+                vec![("region", region.to_string()), ("dr", dr.to_string())]
+            }
+            Error::OutOfRange { field, value } => {
+                vec![("field", field.to_string()), ("value", value.to_string())]
+            }
+            Error::FrequencyOutOfRegion { frequency, region } => vec![
+                ("frequency", frequency.to_string()),
+                ("region", region.to_string()),
+            ],
+            Error::TooManyWitnesses { count, max } | Error::TooManyScanResults { count, max } => {
+                vec![("count", count.to_string()), ("max", max.to_string())]
+            }
+            Error::SignatureTooLong { len, max } => {
+                vec![("len", len.to_string()), ("max", max.to_string())]
+            }
+            Error::UnsupportedPayloadVersion { payload, version } => vec![
+                ("payload", payload.to_string()),
+                ("version", version.to_string()),
+            ],
+            Error::InvalidLoraField {
+                payload,
+                field,
+                bit_offset,
+                raw,
+            } => vec![
+                ("payload", payload.to_string()),
+                ("field", field.to_string()),
+                ("bit_offset", bit_offset.to_string()),
+                ("raw", raw.to_string()),
+            ],
+            Error::InvalidScanResult {
+                index,
+                source: inner,
+            }
+            | Error::InvalidWitness {
+                index,
+                source: inner,
+            } => {
+                source = Some(Box::new(ErrorReport::from(inner.as_ref())));
+                vec![("index", index.to_string())]
+            }
+            Error::MessageMismatch { expected, found } => {
+                vec![
+                    ("expected", expected.to_string()),
+                    ("found", found.to_string()),
+                ]
+            }
+            Error::ConfigRevisionMismatch { expected, found } => {
+                vec![
+                    ("expected", expected.to_string()),
+                    ("found", found.to_string()),
+                ]
+            }
+            Error::Expired { age_s, ttl_s } => {
+                vec![("age_s", age_s.to_string()), ("ttl_s", ttl_s.to_string())]
+            }
+            Error::UnknownProtoFields { len, known_len } => {
+                vec![
+                    ("len", len.to_string()),
+                    ("known_len", known_len.to_string()),
+                ]
+            }
+            Error::FirmwareLayoutMismatch {
+                firmware,
+                payload,
+                expected,
+                found,
+            } => vec![
+                ("firmware", firmware.clone()),
+                ("payload", payload.to_string()),
+                ("expected", expected.to_string()),
+                ("found", found.to_string()),
+            ],
+            Error::WrongResolution { expected, found } => {
+                vec![
+                    ("expected", expected.to_string()),
+                    ("found", found.to_string()),
+                ]
+            }
+            _ => vec![],
+        };
+        Self {
+            code: error.variant_name().to_string(),
+            message: error.to_string(),
+            context: context
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+            source,
+        }
+    }
+}
+
+impl From<Error> for ErrorReport {
+    fn from(error: Error) -> Self {
+        Self::from(&error)
+    }
+}
+
+impl Error {
+    pub fn to_report(&self) -> ErrorReport {
+        self.into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reports_nest_and_serialize() {
+        let error = Error::InvalidScanResult {
+            index: 3,
+            source: Box::new(Error::OutOfRange {
+                field: "rsrp",
+                value: -300,
+            }),
+        };
+        let report = error.to_report();
+        assert_eq!(report.code, "InvalidScanResult");
+        assert_eq!(report.context["index"], "3");
+        let source = report.source.as_ref().unwrap();
+        assert_eq!(source.code, "OutOfRange");
+        assert_eq!(source.context["value"], "-300");
+
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(serde_json::from_str::<ErrorReport>(&json).unwrap(), report);
+        assert!(!serde_json::to_string(&Error::InvalidImsi.to_report())
+            .unwrap()
+            .contains("context"));
+    }
+}
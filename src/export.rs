@@ -0,0 +1,669 @@
+//! Flattens archives of length-delimited MapperMsgs, as written by
+//! `Message::encode_length_delimited_to`, into JSONL or CSV with one row per message. Archives
+//! are streamed, so any number of them can be exported in constant memory. Messages that fail to
+//! decode or verify are counted and skipped.
+//!
+//! With the `parquet` feature `ParquetExporter` writes the same columns as a Parquet file.
+use super::{DecodeLimits, Error, ExtendedMsg, Message, Result};
+use std::{
+    fs,
+    io::{BufReader, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// Frames longer than this are taken for corruption rather than allocated
+pub const MAX_FRAME_LEN: u64 = 1 << 20;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Column {
+    Id,
+    Pubkey,
+    Kind,
+    Timestamp,
+    Lat,
+    Lon,
+    Hdop,
+    Altitude,
+    Speed,
+    NumSats,
+    /// Number of witnessing gateways
+    Witnesses,
+    /// See `Message::reward_cell`, always null without the `h3` feature
+    RewardCell,
+}
+
+impl Column {
+    pub const ALL: [Column; 12] = [
+        Column::Id,
+        Column::Pubkey,
+        Column::Kind,
+        Column::Timestamp,
+        Column::Lat,
+        Column::Lon,
+        Column::Hdop,
+        Column::Altitude,
+        Column::Speed,
+        Column::NumSats,
+        Column::Witnesses,
+        Column::RewardCell,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Column::Id => "id",
+            Column::Pubkey => "pubkey",
+            Column::Kind => "kind",
+            Column::Timestamp => "timestamp",
+            Column::Lat => "lat",
+            Column::Lon => "lon",
+            Column::Hdop => "hdop",
+            Column::Altitude => "altitude",
+            Column::Speed => "speed",
+            Column::NumSats => "num_sats",
+            Column::Witnesses => "witnesses",
+            Column::RewardCell => "reward_cell",
+        }
+    }
+
+    fn value(&self, msg: &Message) -> Value {
+        let gps = msg.payload.gps();
+        match self {
+            Column::Id => msg
+                .id()
+                .map_or(Value::Null, |id| Value::Str(id.to_string())),
+            Column::Pubkey => Value::Str(msg.pubkey.to_string()),
+            Column::Kind => Value::Str(msg.payload.kind().to_string()),
+            Column::Timestamp => Value::Str(gps.timestamp.to_rfc3339()),
+            Column::Lat => Value::Num(gps.lat.to_string()),
+            Column::Lon => Value::Num(gps.lon.to_string()),
+            Column::Hdop => Value::Num(gps.hdop.to_string()),
+            Column::Altitude => Value::Num(gps.altitude.to_string()),
+            Column::Speed => Value::Num(gps.speed.to_string()),
+            Column::NumSats => Value::Num(gps.num_sats.to_string()),
+            Column::Witnesses => Value::Num(msg.lora_gws.len().to_string()),
+            #[cfg(feature = "h3")]
+            Column::RewardCell => msg
+                .reward_cell()
+                .map_or(Value::Null, |cell| Value::Str(cell.to_string())),
+            #[cfg(not(feature = "h3"))]
+            Column::RewardCell => Value::Null,
+        }
+    }
+}
+
+impl std::fmt::Display for Column {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for Column {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Column::ALL
+            .into_iter()
+            .find(|column| column.as_str() == s)
+            .ok_or_else(|| Error::UnknownExportColumn(s.into()))
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// One JSON object per line. Decimals are written as number literals, digit for digit.
+    #[default]
+    Jsonl,
+    /// RFC 4180, with a header row. Missing values are empty.
+    Csv,
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "jsonl" => Ok(ExportFormat::Jsonl),
+            "csv" => Ok(ExportFormat::Csv),
+            _ => Err(Error::UnsupportedExportFormat(s.into())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    /// In output order
+    pub columns: Vec<Column>,
+    /// Skip messages whose signature does not verify
+    pub verify: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            format: ExportFormat::default(),
+            columns: Column::ALL.to_vec(),
+            verify: false,
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ExportStats {
+    pub exported: u64,
+    /// Messages that did not decode or verify
+    pub failed: u64,
+}
+
+/// Reads the messages of an archive one frame at a time. A message that does not decode yields
+/// its error and reading goes on; a broken frame yields its error and ends the archive, since
+/// nothing after it can be found.
+pub struct ArchiveReader<R> {
+    inner: R,
+    verify: bool,
+    done: bool,
+}
+
+impl<R: Read> ArchiveReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            verify: false,
+            done: false,
+        }
+    }
+
+    /// Whether signatures are verified, off by default
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// `None` at the end of the archive
+    fn read_len(&mut self) -> Result<Option<u64>> {
+        let mut len = 0u64;
+        for shift in (0..64).step_by(7) {
+            let mut byte = [0u8];
+            if let Err(error) = self.inner.read_exact(&mut byte) {
+                return match error.kind() {
+                    ErrorKind::UnexpectedEof if shift == 0 => Ok(None),
+                    _ => Err(error.into()),
+                };
+            }
+            len |= u64::from(byte[0] & 0x7f) << shift;
+            if byte[0] & 0x80 == 0 {
+                return Ok(Some(len));
+            }
+        }
+        Err(Error::OutOfRange {
+            field: "frame_len",
+            value: len.into(),
+        })
+    }
+
+    fn read_frame(&mut self) -> Result<Option<Vec<u8>>> {
+        let Some(len) = self.read_len()? else {
+            return Ok(None);
+        };
+        if len > MAX_FRAME_LEN {
+            return Err(Error::OutOfRange {
+                field: "frame_len",
+                value: len.into(),
+            });
+        }
+        let mut frame = vec![0; len as usize];
+        self.inner.read_exact(&mut frame)?;
+        Ok(Some(frame))
+    }
+
+    fn decode(&self, frame: &[u8]) -> Result<Message> {
+        let msg = ExtendedMsg::decode(frame)?;
+        if self.verify {
+            Message::try_from_with_signature_verification(msg)
+        } else {
+            Message::try_from_with_limits(msg, &DecodeLimits::DEFAULT)
+        }
+    }
+}
+
+impl<R: Read> Iterator for ArchiveReader<R> {
+    type Item = Result<Message>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.read_frame() {
+            Ok(Some(frame)) => Some(self.decode(&frame)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(error) => {
+                self.done = true;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+/// Writes the messages of any number of archives as one table
+pub struct Exporter<W> {
+    writer: W,
+    options: ExportOptions,
+    stats: ExportStats,
+    header_written: bool,
+}
+
+impl<W: Write> Exporter<W> {
+    pub fn new(writer: W, options: ExportOptions) -> Self {
+        Self {
+            writer,
+            options,
+            stats: ExportStats::default(),
+            header_written: false,
+        }
+    }
+
+    /// Fails only on a broken frame or if writing fails
+    pub fn export_archive<R: Read>(&mut self, archive: R) -> Result {
+        let verify = self.options.verify;
+        self.stats.failed += read_archive(archive, verify, |msg| self.write(msg))?;
+        Ok(())
+    }
+
+    /// Archives in the directory in file name order, so that exports are reproducible
+    pub fn export_dir<P: AsRef<Path>>(&mut self, dir: P) -> Result {
+        for path in archive_paths(dir)? {
+            self.export_archive(BufReader::new(fs::File::open(path)?))?;
+        }
+        Ok(())
+    }
+
+    /// `export_dir` for a directory, `export_archive` for a file
+    pub fn export_path<P: AsRef<Path>>(&mut self, path: P) -> Result {
+        let path = path.as_ref();
+        if path.is_dir() {
+            self.export_dir(path)
+        } else {
+            self.export_archive(BufReader::new(fs::File::open(path)?))
+        }
+    }
+
+    pub fn write(&mut self, msg: &Message) -> Result {
+        let values: Vec<Value> = self
+            .options
+            .columns
+            .iter()
+            .map(|column| column.value(msg))
+            .collect();
+        let line = match self.options.format {
+            ExportFormat::Jsonl => {
+                let fields: Vec<String> = self
+                    .options
+                    .columns
+                    .iter()
+                    .zip(&values)
+                    .map(|(column, value)| format!("\"{column}\":{}", value.to_json()))
+                    .collect();
+                format!("{{{}}}", fields.join(","))
+            }
+            ExportFormat::Csv => {
+                if !self.header_written {
+                    let header: Vec<&str> =
+                        self.options.columns.iter().map(Column::as_str).collect();
+                    writeln!(self.writer, "{}", header.join(","))?;
+                    self.header_written = true;
+                }
+                let fields: Vec<String> = values.iter().map(Value::to_csv).collect();
+                fields.join(",")
+            }
+        };
+        writeln!(self.writer, "{line}")?;
+        self.stats.exported += 1;
+        Ok(())
+    }
+
+    pub fn stats(&self) -> ExportStats {
+        self.stats
+    }
+
+    /// Flushes and returns the writer
+    pub fn finish(mut self) -> Result<(W, ExportStats)> {
+        self.writer.flush()?;
+        Ok((self.writer, self.stats))
+    }
+}
+
+/// Passes every message of the archive that decodes to `write`, returning how many did not
+fn read_archive<R: Read>(
+    archive: R,
+    verify: bool,
+    mut write: impl FnMut(&Message) -> Result,
+) -> Result<u64> {
+    let mut reader = ArchiveReader::new(archive).verify(verify);
+    let mut failed = 0;
+    while let Some(frame) = reader.read_frame()? {
+        match reader.decode(&frame) {
+            Ok(msg) => write(&msg)?,
+            Err(_) => failed += 1,
+        }
+    }
+    Ok(failed)
+}
+
+/// Files of the directory in file name order
+fn archive_paths<P: AsRef<Path>>(dir: P) -> Result<Vec<PathBuf>> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>>>()?;
+    paths.retain(|path| path.is_file());
+    paths.sort();
+    Ok(paths)
+}
+
+/// Messages buffered by `ParquetExporter` before they are written out as a record batch
+#[cfg(feature = "parquet")]
+pub const PARQUET_BATCH_ROWS: usize = 8192;
+
+#[cfg(feature = "parquet")]
+impl Column {
+    fn arrow_field(&self) -> arrow_schema::Field {
+        use arrow_schema::{DataType, Field, TimeUnit};
+        let (data_type, nullable) = match self {
+            Column::Id | Column::RewardCell => (DataType::Utf8, true),
+            Column::Pubkey | Column::Kind => (DataType::Utf8, false),
+            Column::Timestamp => (
+                DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+                false,
+            ),
+            Column::Lat | Column::Lon | Column::Hdop | Column::Altitude | Column::Speed => {
+                (DataType::Float64, false)
+            }
+            Column::NumSats => (DataType::UInt8, false),
+            Column::Witnesses => (DataType::UInt64, false),
+        };
+        Field::new(self.as_str(), data_type, nullable)
+    }
+
+    fn arrow_array(&self, msgs: &[Message]) -> arrow_array::ArrayRef {
+        use arrow_array::{
+            ArrayRef, Float64Array, StringArray, TimestampMillisecondArray, UInt64Array, UInt8Array,
+        };
+        use rust_decimal::{prelude::ToPrimitive, Decimal};
+        use std::sync::Arc;
+
+        let gps = || msgs.iter().map(|msg| msg.payload.gps());
+        let decimal = |field: fn(&crate::Gps) -> Decimal| -> ArrayRef {
+            Arc::new(Float64Array::from_iter_values(
+                gps().map(|gps| field(gps).to_f64().unwrap_or(f64::NAN)),
+            ))
+        };
+        // the nullable columns go through `value`, so they are null exactly where CSV is empty
+        let text = |column: &Column| -> ArrayRef {
+            Arc::new(StringArray::from_iter(msgs.iter().map(
+                |msg| match column.value(msg) {
+                    Value::Str(s) | Value::Num(s) => Some(s),
+                    Value::Null => None,
+                },
+            )))
+        };
+        match self {
+            Column::Id | Column::Pubkey | Column::Kind | Column::RewardCell => text(self),
+            Column::Timestamp => Arc::new(
+                TimestampMillisecondArray::from_iter_values(
+                    gps().map(|gps| gps.timestamp.timestamp_millis()),
+                )
+                .with_timezone("UTC"),
+            ),
+            Column::Lat => decimal(|gps| gps.lat),
+            Column::Lon => decimal(|gps| gps.lon),
+            Column::Hdop => decimal(|gps| gps.hdop),
+            Column::Altitude => decimal(|gps| gps.altitude),
+            Column::Speed => decimal(|gps| gps.speed),
+            Column::NumSats => {
+                Arc::new(UInt8Array::from_iter_values(gps().map(|gps| gps.num_sats)))
+            }
+            Column::Witnesses => Arc::new(UInt64Array::from_iter_values(
+                msgs.iter().map(|msg| msg.lora_gws.len() as u64),
+            )),
+        }
+    }
+}
+
+/// Writes the messages of any number of archives as one Parquet file, with one column per
+/// `Column`. Decimals are Float64, timestamps milliseconds in UTC and missing values null.
+#[cfg(feature = "parquet")]
+pub struct ParquetExporter<W: Write + Send> {
+    writer: parquet::arrow::ArrowWriter<W>,
+    schema: std::sync::Arc<arrow_schema::Schema>,
+    columns: Vec<Column>,
+    verify: bool,
+    rows: Vec<Message>,
+    stats: ExportStats,
+}
+
+#[cfg(feature = "parquet")]
+impl<W: Write + Send> ParquetExporter<W> {
+    /// Columns in output order
+    pub fn new(writer: W, columns: Vec<Column>, verify: bool) -> Result<Self> {
+        let schema = std::sync::Arc::new(arrow_schema::Schema::new(
+            columns.iter().map(Column::arrow_field).collect::<Vec<_>>(),
+        ));
+        Ok(Self {
+            writer: parquet::arrow::ArrowWriter::try_new(writer, schema.clone(), None)?,
+            schema,
+            columns,
+            verify,
+            rows: Vec::with_capacity(PARQUET_BATCH_ROWS),
+            stats: ExportStats::default(),
+        })
+    }
+
+    /// See `Exporter::export_archive`
+    pub fn export_archive<R: Read>(&mut self, archive: R) -> Result {
+        let verify = self.verify;
+        self.stats.failed += read_archive(archive, verify, |msg| self.write(msg))?;
+        Ok(())
+    }
+
+    /// See `Exporter::export_dir`
+    pub fn export_dir<P: AsRef<Path>>(&mut self, dir: P) -> Result {
+        for path in archive_paths(dir)? {
+            self.export_archive(BufReader::new(fs::File::open(path)?))?;
+        }
+        Ok(())
+    }
+
+    /// See `Exporter::export_path`
+    pub fn export_path<P: AsRef<Path>>(&mut self, path: P) -> Result {
+        let path = path.as_ref();
+        if path.is_dir() {
+            self.export_dir(path)
+        } else {
+            self.export_archive(BufReader::new(fs::File::open(path)?))
+        }
+    }
+
+    pub fn write(&mut self, msg: &Message) -> Result {
+        self.rows.push(msg.clone());
+        self.stats.exported += 1;
+        if self.rows.len() >= PARQUET_BATCH_ROWS {
+            self.write_rows()?;
+        }
+        Ok(())
+    }
+
+    fn write_rows(&mut self) -> Result {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let columns = self
+            .columns
+            .iter()
+            .map(|column| column.arrow_array(&self.rows))
+            .collect();
+        let batch = arrow_array::RecordBatch::try_new(self.schema.clone(), columns)?;
+        self.writer.write(&batch)?;
+        self.rows.clear();
+        Ok(())
+    }
+
+    pub fn stats(&self) -> ExportStats {
+        self.stats
+    }
+
+    /// Writes the buffered rows and the Parquet footer, and returns the writer
+    pub fn finish(mut self) -> Result<(W, ExportStats)> {
+        self.write_rows()?;
+        let mut writer = self.writer.into_inner()?;
+        writer.flush()?;
+        Ok((writer, self.stats))
+    }
+}
+
+/// Exports a single archive
+pub fn export<R: Read, W: Write>(
+    archive: R,
+    writer: W,
+    options: ExportOptions,
+) -> Result<ExportStats> {
+    let mut exporter = Exporter::new(writer, options);
+    exporter.export_archive(archive)?;
+    Ok(exporter.finish()?.1)
+}
+
+enum Value {
+    Str(String),
+    /// Already formatted
+    Num(String),
+    Null,
+}
+
+impl Value {
+    fn to_json(&self) -> String {
+        match self {
+            Value::Str(s) => {
+                let mut escaped = String::with_capacity(s.len() + 2);
+                escaped.push('"');
+                for c in s.chars() {
+                    match c {
+                        '"' => escaped.push_str("\\\""),
+                        '\\' => escaped.push_str("\\\\"),
+                        c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+                        c => escaped.push(c),
+                    }
+                }
+                escaped.push('"');
+                escaped
+            }
+            Value::Num(n) => n.clone(),
+            Value::Null => "null".into(),
+        }
+    }
+
+    fn to_csv(&self) -> String {
+        match self {
+            Value::Str(s) if s.contains([',', '"', '\n', '\r']) => {
+                format!("\"{}\"", s.replace('"', "\"\""))
+            }
+            Value::Str(s) | Value::Num(s) => s.clone(),
+            Value::Null => String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{keys, EnvelopeSig, Gps, Payload};
+
+    #[test]
+    fn exports_archive_skipping_forgeries() {
+        let key = keys::file::File::create_key().unwrap();
+        let msg = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap();
+        let mut forged = msg.clone();
+        forged.signature = EnvelopeSig::from(vec![0; 64]);
+        let mut archive = Vec::new();
+        for msg in [&msg, &forged, &msg] {
+            msg.encode_length_delimited_to(&mut archive).unwrap();
+        }
+
+        let options = ExportOptions {
+            format: ExportFormat::Csv,
+            columns: ["kind", "lat", "hdop", "witnesses"]
+                .into_iter()
+                .map(|column| column.parse().unwrap())
+                .collect(),
+            verify: true,
+        };
+        let mut out = Vec::new();
+        let stats = export(archive.as_slice(), &mut out, options.clone()).unwrap();
+        assert_eq!(
+            stats,
+            ExportStats {
+                exported: 2,
+                failed: 1
+            }
+        );
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "kind,lat,hdop,witnesses\ngps,-50.12345,9.05,0\ngps,-50.12345,9.05,0\n"
+        );
+
+        let jsonl = ExportOptions {
+            format: ExportFormat::Jsonl,
+            verify: false,
+            ..options
+        };
+        let mut out = Vec::new();
+        archive.truncate(archive.len() - 1);
+        assert!(export(archive.as_slice(), &mut out, jsonl).is_err());
+        let first = String::from_utf8(out).unwrap();
+        assert_eq!(
+            first.lines().next(),
+            Some(r#"{"kind":"gps","lat":-50.12345,"hdop":9.05,"witnesses":0}"#)
+        );
+        assert!("parquet".parse::<ExportFormat>().is_err());
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn exports_parquet() {
+        use arrow_array::{Array, Float64Array, StringArray};
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let key = keys::file::File::create_key().unwrap();
+        let msg = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap();
+        let mut archive = Vec::new();
+        for _ in 0..3 {
+            msg.encode_length_delimited_to(&mut archive).unwrap();
+        }
+
+        let mut exporter = ParquetExporter::new(Vec::new(), Column::ALL.to_vec(), true).unwrap();
+        exporter.export_archive(archive.as_slice()).unwrap();
+        let (out, stats) = exporter.finish().unwrap();
+        assert_eq!(stats.exported, 3);
+
+        let batches = ParquetRecordBatchReaderBuilder::try_new(bytes::Bytes::from(out))
+            .unwrap()
+            .build()
+            .unwrap()
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            batches.iter().map(|batch| batch.num_rows()).sum::<usize>(),
+            3
+        );
+        let batch = &batches[0];
+        assert_eq!(batch.num_columns(), Column::ALL.len());
+        let column = |name: &str| batch.column_by_name(name).unwrap().clone();
+        let lat = column("lat");
+        let lat = lat.as_any().downcast_ref::<Float64Array>().unwrap();
+        assert_eq!(lat.value(0), -50.12345);
+        let kind = column("kind");
+        let kind = kind.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(kind.value(0), "gps");
+        assert!(!column("reward_cell").is_null(0));
+    }
+}
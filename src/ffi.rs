@@ -0,0 +1,550 @@
+//! C bindings of the LoRa codec, so firmware packs and unpacks the exact frames of this crate.
+//! The types and functions are cbindgen friendly, eg:
+//! `cbindgen --crate spot-messages --output spot_messages.h`, and the library to link against
+//! is built with `cargo rustc --release --features ffi --crate-type staticlib`.
+//!
+//! Fixes use the proto units: seconds since the unix epoch, lat and lon in 1e-5 degrees, HDOP,
+//! altitude and speed in hundredths. Every function returns a `SpotStatus` and only writes its
+//! out pointers on `SpotStatus::Ok`, except `written` which also receives the size needed on
+//! `SpotStatus::BufferTooSmall`.
+//!
+//! Only the unversioned frames are covered. The signature of a frame is over the frame bytes
+//! themselves, so a secure element can sign them directly; `spot_signature_der_to_lora` and
+//! `spot_signature_lora_to_der` convert between its DER output and the tail sent on the air.
+use super::{
+    gps::{altitude, hdop, latlon, speed, time},
+    lora_payload::verify_lora_frame,
+    AttachCandidate, Beacon, CellAttach, EncodeMode, Error, Gps, LoraDecode, LoraEncode, PublicKey,
+    Result,
+};
+use helium_proto::{mapper_cbrs_attach_v1::MapperCbrsAttachCandidate, MapperCbrsAttachV1};
+use std::slice;
+
+/// Size of a Beacon frame, without signature
+pub const SPOT_BEACON_FRAME_SIZE: usize = 17;
+/// Size of a CellAttach frame, without signature
+pub const SPOT_CELL_ATTACH_FRAME_SIZE: usize = 32;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SpotStatus {
+    Ok = 0,
+    NullPointer = 1,
+    BufferTooSmall = 2,
+    /// A field doesn't fit its frame in `SpotEncodeMode::Strict`
+    OutOfRange = 3,
+    /// Too short or carrying a value no payload has
+    InvalidFrame = 4,
+    InvalidSignature = 5,
+    InvalidPubkey = 6,
+    /// A field the payload can't hold, eg: a timestamp out of range
+    InvalidValue = 7,
+}
+
+impl From<&Error> for SpotStatus {
+    fn from(error: &Error) -> Self {
+        match error {
+            Error::OutOfRange { .. } => SpotStatus::OutOfRange,
+            Error::InvalidVecForParsingLoraPayload { .. } | Error::InvalidLoraField { .. } => {
+                SpotStatus::InvalidFrame
+            }
+            Error::SignatureVerification { .. } => SpotStatus::InvalidSignature,
+            Error::PubkeyParse { .. } => SpotStatus::InvalidPubkey,
+            _ => SpotStatus::InvalidValue,
+        }
+    }
+}
+
+/// See `EncodeMode`. Functions take the mode as a `u32` holding one of these values, since C may
+/// pass any other and a Rust enum out of its range is undefined behaviour.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SpotEncodeMode {
+    Saturating = 0,
+    Strict = 1,
+}
+
+/// `None` for values that are no `SpotEncodeMode`
+fn encode_mode(mode: u32) -> Option<EncodeMode> {
+    match mode {
+        mode if mode == SpotEncodeMode::Saturating as u32 => Some(EncodeMode::Saturating),
+        mode if mode == SpotEncodeMode::Strict as u32 => Some(EncodeMode::Strict),
+        _ => None,
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct SpotGps {
+    pub timestamp: u64,
+    pub lat: i32,
+    pub lon: i32,
+    pub hdop: u32,
+    pub altitude: i32,
+    pub num_sats: u32,
+    pub speed: u32,
+}
+
+impl From<&Gps> for SpotGps {
+    fn from(gps: &Gps) -> Self {
+        Self {
+            timestamp: time::to_proto_units(gps.timestamp),
+            lat: latlon::to_proto_units(gps.lat),
+            lon: latlon::to_proto_units(gps.lon),
+            hdop: hdop::to_units(gps.hdop),
+            altitude: altitude::to_proto_units(gps.altitude),
+            num_sats: gps.num_sats.into(),
+            speed: speed::to_proto_units(gps.speed),
+        }
+    }
+}
+
+impl TryFrom<&SpotGps> for Gps {
+    type Error = Error;
+
+    fn try_from(gps: &SpotGps) -> Result<Self> {
+        Ok(Gps {
+            timestamp: time::from_proto_units(gps.timestamp)?,
+            lat: latlon::from_proto_units(gps.lat),
+            lon: latlon::from_proto_units(gps.lon),
+            hdop: hdop::from_units(gps.hdop),
+            altitude: altitude::from_proto_units(gps.altitude),
+            num_sats: gps.num_sats.min(u8::MAX.into()) as u8,
+            speed: speed::from_proto_units(gps.speed),
+            ..Gps::default()
+        })
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct SpotBeacon {
+    pub gps: SpotGps,
+    /// Last 2 bytes of the committed scan signature, big endian, as the frame carries them
+    pub signature: u16,
+}
+
+impl From<&Beacon> for SpotBeacon {
+    fn from(beacon: &Beacon) -> Self {
+        Self {
+            gps: (&beacon.gps).into(),
+            signature: beacon.signature.lora_tail(),
+        }
+    }
+}
+
+impl TryFrom<&SpotBeacon> for Beacon {
+    type Error = Error;
+
+    fn try_from(beacon: &SpotBeacon) -> Result<Self> {
+        Ok(Beacon::new(
+            (&beacon.gps).try_into()?,
+            beacon.signature.to_be_bytes().to_vec(),
+        ))
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct SpotCellAttach {
+    pub attach_counter: u32,
+    pub gps: SpotGps,
+    pub from_scan: u32,
+    pub delay: u32,
+    pub cell_id: u32,
+    pub fcn: u16,
+    pub rsrp: i32,
+    pub rsrq: i32,
+    /// As in `MapperAttachResult`, 0 for none through 5 for no network service
+    pub result: i32,
+}
+
+impl From<&CellAttach> for SpotCellAttach {
+    fn from(attach: &CellAttach) -> Self {
+        let proto = MapperCbrsAttachV1::from(*attach);
+        let AttachCandidate {
+            from_scan,
+            delay,
+            cell_id,
+            fcn,
+            rsrp,
+            rsrq,
+        } = attach.candidate;
+        Self {
+            attach_counter: attach.attach_counter,
+            gps: (&attach.gps).into(),
+            from_scan,
+            delay,
+            cell_id,
+            fcn,
+            rsrp,
+            rsrq,
+            result: proto.result,
+        }
+    }
+}
+
+impl TryFrom<&SpotCellAttach> for CellAttach {
+    type Error = Error;
+
+    fn try_from(attach: &SpotCellAttach) -> Result<Self> {
+        CellAttach::try_from(MapperCbrsAttachV1 {
+            attach_counter: attach.attach_counter,
+            gps: Some(Gps::try_from(&attach.gps)?.into()),
+            candidate: Some(MapperCbrsAttachCandidate {
+                from_scan: attach.from_scan,
+                delay: attach.delay,
+                fcn: attach.fcn.into(),
+                cid: attach.cell_id,
+                rsrp: attach.rsrp,
+                rsrq: attach.rsrq,
+            }),
+            result: attach.result,
+        })
+    }
+}
+
+fn status(result: Result) -> SpotStatus {
+    match result {
+        Ok(()) => SpotStatus::Ok,
+        Err(error) => (&error).into(),
+    }
+}
+
+/// # Safety
+///
+/// `input` must be null or valid for reads of `len` bytes
+unsafe fn input<'a>(input: *const u8, len: usize) -> Option<&'a [u8]> {
+    (!input.is_null()).then(|| slice::from_raw_parts(input, len))
+}
+
+/// # Safety
+///
+/// `out` must be null or valid for writes of `out_len` bytes, `written` null or valid for a
+/// write
+unsafe fn write_out(bytes: &[u8], out: *mut u8, out_len: usize, written: *mut usize) -> SpotStatus {
+    if out.is_null() || written.is_null() {
+        return SpotStatus::NullPointer;
+    }
+    *written = bytes.len();
+    if out_len < bytes.len() {
+        return SpotStatus::BufferTooSmall;
+    }
+    slice::from_raw_parts_mut(out, bytes.len()).copy_from_slice(bytes);
+    SpotStatus::Ok
+}
+
+/// Packs `beacon` into its `SPOT_BEACON_FRAME_SIZE` byte frame
+///
+/// # Safety
+///
+/// `beacon` must be null or point to a `SpotBeacon`, `out` be null or valid for writes of
+/// `out_len` bytes and `written` null or valid for a write.
+#[no_mangle]
+pub unsafe extern "C" fn spot_beacon_encode(
+    beacon: *const SpotBeacon,
+    mode: u32,
+    out: *mut u8,
+    out_len: usize,
+    written: *mut usize,
+) -> SpotStatus {
+    let Some(beacon) = beacon.as_ref() else {
+        return SpotStatus::NullPointer;
+    };
+    let Some(mode) = encode_mode(mode) else {
+        return SpotStatus::InvalidValue;
+    };
+    match Beacon::try_from(beacon).and_then(|beacon| beacon.to_lora_bytes_with_mode(mode)) {
+        Ok(frame) => write_out(&frame, out, out_len, written),
+        Err(error) => (&error).into(),
+    }
+}
+
+/// Unpacks the beacon frame at the front of `frame`, writing the bytes it used to `used`. What
+/// follows, usually the signature, is left to the caller.
+///
+/// # Safety
+///
+/// `frame` must be null or valid for reads of `frame_len` bytes, `beacon` and `used` null or
+/// valid for a write.
+#[no_mangle]
+pub unsafe extern "C" fn spot_beacon_decode(
+    frame: *const u8,
+    frame_len: usize,
+    beacon: *mut SpotBeacon,
+    used: *mut usize,
+) -> SpotStatus {
+    let (Some(frame), false, false) = (input(frame, frame_len), beacon.is_null(), used.is_null())
+    else {
+        return SpotStatus::NullPointer;
+    };
+    match Beacon::from_lora_slice(frame) {
+        Ok((decoded, size)) => {
+            *beacon = (&decoded).into();
+            *used = size;
+            SpotStatus::Ok
+        }
+        Err(error) => (&error).into(),
+    }
+}
+
+/// Packs `attach` into its `SPOT_CELL_ATTACH_FRAME_SIZE` byte frame
+///
+/// # Safety
+///
+/// As `spot_beacon_encode`
+#[no_mangle]
+pub unsafe extern "C" fn spot_cell_attach_encode(
+    attach: *const SpotCellAttach,
+    mode: u32,
+    out: *mut u8,
+    out_len: usize,
+    written: *mut usize,
+) -> SpotStatus {
+    let Some(attach) = attach.as_ref() else {
+        return SpotStatus::NullPointer;
+    };
+    let Some(mode) = encode_mode(mode) else {
+        return SpotStatus::InvalidValue;
+    };
+    match CellAttach::try_from(attach).and_then(|attach| attach.to_lora_bytes_with_mode(mode)) {
+        Ok(frame) => write_out(&frame, out, out_len, written),
+        Err(error) => (&error).into(),
+    }
+}
+
+/// Unpacks the attach frame at the front of `frame`, as `spot_beacon_decode`
+///
+/// # Safety
+///
+/// As `spot_beacon_decode`
+#[no_mangle]
+pub unsafe extern "C" fn spot_cell_attach_decode(
+    frame: *const u8,
+    frame_len: usize,
+    attach: *mut SpotCellAttach,
+    used: *mut usize,
+) -> SpotStatus {
+    let (Some(frame), false, false) = (input(frame, frame_len), attach.is_null(), used.is_null())
+    else {
+        return SpotStatus::NullPointer;
+    };
+    match CellAttach::from_lora_slice(frame) {
+        Ok((decoded, size)) => {
+            *attach = (&decoded).into();
+            *used = size;
+            SpotStatus::Ok
+        }
+        Err(error) => (&error).into(),
+    }
+}
+
+/// Verifies `signature`, a tail as sent after a frame, over `frame` for the binary helium
+/// `pubkey`
+///
+/// # Safety
+///
+/// Each pointer must be null or valid for reads of the length following it
+#[no_mangle]
+pub unsafe extern "C" fn spot_lora_verify(
+    pubkey: *const u8,
+    pubkey_len: usize,
+    frame: *const u8,
+    frame_len: usize,
+    signature: *const u8,
+    signature_len: usize,
+) -> SpotStatus {
+    let (Some(pubkey), Some(frame), Some(signature)) = (
+        input(pubkey, pubkey_len),
+        input(frame, frame_len),
+        input(signature, signature_len),
+    ) else {
+        return SpotStatus::NullPointer;
+    };
+    let Ok(pubkey) = PublicKey::from_bytes(pubkey) else {
+        return SpotStatus::InvalidPubkey;
+    };
+    status(verify_lora_frame(&pubkey, frame, signature))
+}
+
+/// Strips the 2 byte DER sequence header, which the receiver adds back, from a signature
+///
+/// # Safety
+///
+/// `der` must be null or valid for reads of `der_len` bytes, `out` null or valid for writes of
+/// `out_len` bytes and `written` null or valid for a write.
+#[no_mangle]
+pub unsafe extern "C" fn spot_signature_der_to_lora(
+    der: *const u8,
+    der_len: usize,
+    out: *mut u8,
+    out_len: usize,
+    written: *mut usize,
+) -> SpotStatus {
+    let Some(der) = input(der, der_len) else {
+        return SpotStatus::NullPointer;
+    };
+    match der {
+        [0x30, len, tail @ ..] if usize::from(*len) == tail.len() => {
+            write_out(tail, out, out_len, written)
+        }
+        _ => SpotStatus::InvalidSignature,
+    }
+}
+
+/// Adds back the DER sequence header to a signature tail received after a frame
+///
+/// # Safety
+///
+/// As `spot_signature_der_to_lora`
+#[no_mangle]
+pub unsafe extern "C" fn spot_signature_lora_to_der(
+    signature: *const u8,
+    signature_len: usize,
+    out: *mut u8,
+    out_len: usize,
+    written: *mut usize,
+) -> SpotStatus {
+    let Some(signature) = input(signature, signature_len) else {
+        return SpotStatus::NullPointer;
+    };
+    let Ok(len) = u8::try_from(signature.len()) else {
+        return SpotStatus::InvalidSignature;
+    };
+    let mut der = vec![0x30, len];
+    der.extend_from_slice(signature);
+    write_out(&der, out, out_len, written)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        keys::{self, KeyTrait},
+        sign_lora_frame, CellAttachResult, CellScanResult,
+    };
+    use std::ptr;
+
+    #[test]
+    fn beacon_roundtrip() {
+        let beacon = SpotBeacon::from(&Beacon::new(Gps::rounded(), vec![0xAB, 0xCD]));
+        let (mut frame, mut written) = ([0; 64], 0);
+        let status = unsafe {
+            spot_beacon_encode(
+                &beacon,
+                SpotEncodeMode::Strict as u32,
+                frame.as_mut_ptr(),
+                8,
+                &mut written,
+            )
+        };
+        assert_eq!(
+            (status, written),
+            (SpotStatus::BufferTooSmall, SPOT_BEACON_FRAME_SIZE)
+        );
+        let status = unsafe {
+            spot_beacon_encode(
+                &beacon,
+                SpotEncodeMode::Strict as u32,
+                frame.as_mut_ptr(),
+                frame.len(),
+                &mut written,
+            )
+        };
+        assert_eq!(status, SpotStatus::Ok);
+        assert_eq!(
+            frame[..written],
+            Beacon::try_from(&beacon).unwrap().to_lora_bytes()
+        );
+
+        let (mut decoded, mut used) = (SpotBeacon::default(), 0);
+        let status =
+            unsafe { spot_beacon_decode(frame.as_ptr(), written, &mut decoded, &mut used) };
+        assert_eq!((status, used, decoded), (SpotStatus::Ok, written, beacon));
+        let status = unsafe { spot_beacon_decode(frame.as_ptr(), 3, &mut decoded, &mut used) };
+        assert_eq!(status, SpotStatus::InvalidFrame);
+        let status = unsafe { spot_beacon_decode(ptr::null(), 3, &mut decoded, &mut used) };
+        assert_eq!(status, SpotStatus::NullPointer);
+    }
+
+    #[test]
+    fn invalid_encode_mode() {
+        let beacon = SpotBeacon::from(&Beacon::new(Gps::rounded(), vec![0xAB, 0xCD]));
+        let (mut frame, mut written) = ([0; 64], 0);
+        let status = unsafe {
+            spot_beacon_encode(&beacon, 7, frame.as_mut_ptr(), frame.len(), &mut written)
+        };
+        assert_eq!((status, written), (SpotStatus::InvalidValue, 0));
+    }
+
+    #[test]
+    fn signed_attach_frame() {
+        let key = keys::file::File::create_key().unwrap();
+        let attach = SpotCellAttach::from(&CellAttach {
+            attach_counter: 7,
+            gps: Gps::rounded(),
+            candidate: CellScanResult::random().into(),
+            result: CellAttachResult::Connected,
+            failure_cause: None,
+            sim: None,
+        });
+        let (mut frame, mut written) = ([0; SPOT_CELL_ATTACH_FRAME_SIZE], 0);
+        let status = unsafe {
+            spot_cell_attach_encode(
+                &attach,
+                SpotEncodeMode::Strict as u32,
+                frame.as_mut_ptr(),
+                frame.len(),
+                &mut written,
+            )
+        };
+        assert_eq!((status, written), (SpotStatus::Ok, frame.len()));
+        let (mut decoded, mut used) = (SpotCellAttach::default(), 0);
+        let status =
+            unsafe { spot_cell_attach_decode(frame.as_ptr(), written, &mut decoded, &mut used) };
+        assert_eq!((status, decoded), (SpotStatus::Ok, attach));
+
+        // a secure element would sign the frame and hand back DER
+        let tail = sign_lora_frame(&key, &frame).unwrap();
+        let (mut der, mut der_len) = ([0; 80], 0);
+        let status = unsafe {
+            spot_signature_lora_to_der(
+                tail.as_ptr(),
+                tail.len(),
+                der.as_mut_ptr(),
+                80,
+                &mut der_len,
+            )
+        };
+        assert_eq!(status, SpotStatus::Ok);
+        let (mut stripped, mut stripped_len) = ([0; 80], 0);
+        let status = unsafe {
+            spot_signature_der_to_lora(
+                der.as_ptr(),
+                der_len,
+                stripped.as_mut_ptr(),
+                80,
+                &mut stripped_len,
+            )
+        };
+        assert_eq!(
+            (status, &stripped[..stripped_len]),
+            (SpotStatus::Ok, &tail[..])
+        );
+
+        let pubkey = key.pubkey().unwrap().to_vec();
+        let verify = |frame: &[u8]| unsafe {
+            spot_lora_verify(
+                pubkey.as_ptr(),
+                pubkey.len(),
+                frame.as_ptr(),
+                frame.len(),
+                tail.as_ptr(),
+                tail.len(),
+            )
+        };
+        assert_eq!(verify(&frame), SpotStatus::Ok);
+        frame[0] ^= 1;
+        assert_eq!(verify(&frame), SpotStatus::InvalidSignature);
+    }
+}
@@ -0,0 +1,175 @@
+//! Fleet visibility: when each device was first and last seen and how many reports of each kind
+//! it sent. `DeviceIndex` serializes with serde, keyed by b58 pubkey, so services can persist it
+//! in whatever format they already use and merge indexes built by separate workers.
+use super::{DateTime, Deserialize, Message, PayloadKind, PublicKey, Serialize, Utc};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceRecord {
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub counts: BTreeMap<PayloadKind, u64>,
+}
+
+impl DeviceRecord {
+    fn new(at: DateTime<Utc>) -> Self {
+        Self {
+            first_seen: at,
+            last_seen: at,
+            counts: BTreeMap::new(),
+        }
+    }
+
+    pub fn count(&self, kind: PayloadKind) -> u64 {
+        self.counts.get(&kind).copied().unwrap_or_default()
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    fn merge(&mut self, other: &DeviceRecord) {
+        self.first_seen = self.first_seen.min(other.first_seen);
+        self.last_seen = self.last_seen.max(other.last_seen);
+        for (kind, count) in &other.counts {
+            *self.counts.entry(*kind).or_default() += count;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct DeviceIndex {
+    /// keyed by b58 pubkey
+    devices: BTreeMap<String, DeviceRecord>,
+}
+
+impl DeviceIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts a report of `kind` seen at `at`, which may be out of order
+    pub fn record(&mut self, pubkey: &PublicKey, kind: PayloadKind, at: DateTime<Utc>) {
+        let record = self
+            .devices
+            .entry(pubkey.to_string())
+            .or_insert_with(|| DeviceRecord::new(at));
+        record.first_seen = record.first_seen.min(at);
+        record.last_seen = record.last_seen.max(at);
+        *record.counts.entry(kind).or_default() += 1;
+    }
+
+    /// Records a message at its reception time, or its fix time when it has no `IngestMeta`
+    pub fn record_message(&mut self, msg: &Message) {
+        let at = msg
+            .ingest_meta
+            .as_ref()
+            .map_or(msg.payload.gps().timestamp, |meta| meta.received_at);
+        self.record(&msg.pubkey, msg.payload.kind(), at);
+    }
+
+    /// Adds the counts of `other`, eg: built by another worker
+    pub fn merge(&mut self, other: &DeviceIndex) {
+        for (pubkey, record) in &other.devices {
+            match self.devices.get_mut(pubkey) {
+                Some(existing) => existing.merge(record),
+                None => {
+                    self.devices.insert(pubkey.clone(), record.clone());
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, pubkey: &PublicKey) -> Option<&DeviceRecord> {
+        self.devices.get(&pubkey.to_string())
+    }
+
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    /// Records by b58 pubkey
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &DeviceRecord)> {
+        self.devices
+            .iter()
+            .map(|(pubkey, record)| (pubkey.as_str(), record))
+    }
+
+    /// Devices seen at or after `since`
+    pub fn active_since(
+        &self,
+        since: DateTime<Utc>,
+    ) -> impl Iterator<Item = (&str, &DeviceRecord)> {
+        self.iter()
+            .filter(move |(_, record)| record.last_seen >= since)
+    }
+
+    /// Devices not seen since before `cutoff`
+    pub fn silent_since(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> impl Iterator<Item = (&str, &DeviceRecord)> {
+        self.iter()
+            .filter(move |(_, record)| record.last_seen < cutoff)
+    }
+
+    /// Devices first seen at or after `since`
+    pub fn new_since(&self, since: DateTime<Utc>) -> impl Iterator<Item = (&str, &DeviceRecord)> {
+        self.iter()
+            .filter(move |(_, record)| record.first_seen >= since)
+    }
+
+    /// Reports of `kind` over the whole fleet
+    pub fn total(&self, kind: PayloadKind) -> u64 {
+        self.devices.values().map(|record| record.count(kind)).sum()
+    }
+
+    /// Stops tracking devices not seen since before `cutoff`
+    pub fn forget_before(&mut self, cutoff: DateTime<Utc>) {
+        self.devices.retain(|_, record| record.last_seen >= cutoff);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::keys::{self, KeyTrait};
+    use chrono::Duration;
+
+    #[test]
+    fn tracks_and_merges_devices() {
+        let (first, second) = (
+            keys::file::File::create_key().unwrap().pubkey().unwrap(),
+            keys::file::File::create_key().unwrap().pubkey().unwrap(),
+        );
+        let start = crate::Gps::rounded().timestamp;
+        let mut index = DeviceIndex::new();
+        index.record(&first, PayloadKind::Beacon, start + Duration::hours(1));
+        index.record(&first, PayloadKind::Beacon, start);
+        index.record(&first, PayloadKind::Gps, start + Duration::minutes(5));
+
+        let mut other = DeviceIndex::new();
+        other.record(&first, PayloadKind::Beacon, start + Duration::days(1));
+        other.record(&second, PayloadKind::Gps, start + Duration::days(1));
+        index.merge(&other);
+
+        let record = index.get(&first).unwrap();
+        assert_eq!(
+            (record.first_seen, record.last_seen),
+            (start, start + Duration::days(1))
+        );
+        assert_eq!((record.count(PayloadKind::Beacon), record.total()), (3, 4));
+        assert_eq!(index.total(PayloadKind::Gps), 2);
+        assert_eq!(index.new_since(start + Duration::hours(2)).count(), 1);
+        assert_eq!(index.silent_since(start + Duration::days(1)).count(), 0);
+
+        let json = serde_json::to_string(&index).unwrap();
+        assert!(json.contains(&format!("\"{first}\"")));
+        assert_eq!(serde_json::from_str::<DeviceIndex>(&json).unwrap(), index);
+    }
+}
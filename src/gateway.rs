@@ -0,0 +1,190 @@
+//! Mapping of packet forwarder gateway EUIs to Helium public keys, so that a `LoraGw` can be
+//! built from uplink metadata.
+use super::{
+    DateTime, Deserialize, Error, FrequencyHz, H3Index, LoraGw, PublicKey, Result, Serialize, Utc,
+};
+use helium_proto::DataRate;
+use rust_decimal::Decimal;
+use std::{collections::HashMap, future::Future};
+
+/// 64-bit gateway EUI as reported by packet forwarders
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Eui(pub u64);
+
+/// EUIs are serialized as their hex string
+impl Serialize for Eui {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        crate::serde_helpers::display_fromstr::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Eui {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        crate::serde_helpers::display_fromstr::deserialize(deserializer)
+    }
+}
+
+impl std::fmt::Display for Eui {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl std::str::FromStr for Eui {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.trim_start_matches("0x").replace([':', '-'], "");
+        Ok(Eui(u64::from_str_radix(&s, 16)?))
+    }
+}
+
+pub trait GatewayResolver {
+    fn resolve(&self, eui: Eui) -> Option<PublicKey>;
+}
+
+/// Resolvers backed by a remote service implement this instead of `GatewayResolver`. Every
+/// `GatewayResolver` is also an `AsyncGatewayResolver`.
+pub trait AsyncGatewayResolver {
+    fn resolve(&self, eui: Eui) -> impl Future<Output = Option<PublicKey>> + Send;
+}
+
+impl<T: GatewayResolver + Sync> AsyncGatewayResolver for T {
+    fn resolve(&self, eui: Eui) -> impl Future<Output = Option<PublicKey>> + Send {
+        std::future::ready(GatewayResolver::resolve(self, eui))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryResolver {
+    gateways: HashMap<Eui, PublicKey>,
+}
+
+impl InMemoryResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, eui: Eui, pubkey: PublicKey) -> Option<PublicKey> {
+        self.gateways.insert(eui, pubkey)
+    }
+
+    pub fn remove(&mut self, eui: Eui) -> Option<PublicKey> {
+        self.gateways.remove(&eui)
+    }
+
+    pub fn len(&self) -> usize {
+        self.gateways.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.gateways.is_empty()
+    }
+}
+
+impl FromIterator<(Eui, PublicKey)> for InMemoryResolver {
+    fn from_iter<I: IntoIterator<Item = (Eui, PublicKey)>>(iter: I) -> Self {
+        Self {
+            gateways: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl GatewayResolver for InMemoryResolver {
+    fn resolve(&self, eui: Eui) -> Option<PublicKey> {
+        self.gateways.get(&eui).cloned()
+    }
+}
+
+/// Receive metadata for a single gateway, as reported alongside an uplink
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GatewayUplink {
+    pub eui: Eui,
+    pub h3_cell: H3Index,
+    #[serde(with = "crate::serde_helpers::decimal::scale1")]
+    pub snr: Decimal,
+    #[serde(with = "crate::serde_helpers::decimal::scale2")]
+    pub rssi: Decimal,
+    pub frequency: FrequencyHz,
+    #[serde(with = "crate::serde_helpers::data_rate")]
+    pub data_rate: DataRate,
+    /// Packet forwarder receive time, when the gateway has a GPS or NTP synced clock
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub received_at: Option<DateTime<Utc>>,
+}
+
+impl GatewayUplink {
+    fn into_lora_gw(self, pubkey: Option<PublicKey>) -> Result<LoraGw> {
+        let pubkey = pubkey.ok_or(Error::UnknownGatewayEui(self.eui.0))?;
+        Ok(LoraGw {
+            pubkey,
+            h3_cell: self.h3_cell,
+            snr: self.snr,
+            rssi: self.rssi,
+            frequency: self.frequency,
+            data_rate: self.data_rate,
+            received_at: self.received_at,
+        })
+    }
+}
+
+impl LoraGw {
+    pub fn from_uplink<R: GatewayResolver>(resolver: &R, uplink: GatewayUplink) -> Result<Self> {
+        let pubkey = GatewayResolver::resolve(resolver, uplink.eui);
+        uplink.into_lora_gw(pubkey)
+    }
+
+    pub async fn from_uplink_async<R: AsyncGatewayResolver>(
+        resolver: &R,
+        uplink: GatewayUplink,
+    ) -> Result<Self> {
+        let pubkey = AsyncGatewayResolver::resolve(resolver, uplink.eui).await;
+        uplink.into_lora_gw(pubkey)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::keys::{self, KeyTrait};
+
+    fn uplink(eui: Eui) -> GatewayUplink {
+        GatewayUplink {
+            eui,
+            h3_cell: H3Index::new(0x8a1fb46622dffff).unwrap(),
+            snr: Decimal::new(55, 1),
+            rssi: Decimal::new(-110, 0),
+            frequency: FrequencyHz::from_khz(904_300),
+            data_rate: DataRate::Sf10bw125,
+            received_at: None,
+        }
+    }
+
+    #[test]
+    fn uplink_to_lora_gw() {
+        let pubkey = keys::file::File::create_key().unwrap().pubkey().unwrap();
+        let eui: Eui = "0xAA555A0000000101".parse().unwrap();
+        let resolver: InMemoryResolver = [(eui, pubkey.clone())].into_iter().collect();
+
+        let lora_gw = LoraGw::from_uplink(&resolver, uplink(eui)).unwrap();
+        assert_eq!(lora_gw.pubkey, pubkey);
+        assert!(matches!(
+            LoraGw::from_uplink(&resolver, uplink(Eui(1))),
+            Err(Error::UnknownGatewayEui(1))
+        ));
+    }
+
+    #[test]
+    fn eui_display_roundtrip() {
+        let eui = Eui(0xAA555A0000000101);
+        assert_eq!(eui.to_string(), "aa555a0000000101");
+        assert_eq!(eui, eui.to_string().parse::<Eui>().unwrap());
+        assert_eq!(eui, "AA:55:5A:00:00:00:01:01".parse::<Eui>().unwrap());
+        assert_eq!(serde_json::to_string(&eui).unwrap(), "\"aa555a0000000101\"");
+    }
+}
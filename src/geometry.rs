@@ -0,0 +1,63 @@
+//! Conversions to the `geo` crate, for polygon operations on fixes and H3 cells. Coordinates are
+//! `f64` degrees with x the longitude and y the latitude, as `geo` expects.
+use super::Gps;
+use geo::{Coord, LineString, MultiPolygon, Point, Polygon};
+use h3o::{CellIndex, LatLng};
+use rust_decimal::prelude::ToPrimitive;
+
+impl From<&Gps> for Point {
+    fn from(gps: &Gps) -> Self {
+        Point::new(
+            gps.lon.to_f64().unwrap_or(f64::NAN),
+            gps.lat.to_f64().unwrap_or(f64::NAN),
+        )
+    }
+}
+
+impl From<Gps> for Point {
+    fn from(gps: Gps) -> Self {
+        Point::from(&gps)
+    }
+}
+
+fn coord(latlng: LatLng) -> Coord {
+    Coord {
+        x: latlng.lng(),
+        y: latlng.lat(),
+    }
+}
+
+/// Boundary of `cell` as a closed exterior ring. Cells crossing the antimeridian are not split.
+pub fn cell_polygon(cell: CellIndex) -> Polygon {
+    let ring: Vec<Coord> = cell.boundary().iter().copied().map(coord).collect();
+    // `Polygon::new` closes the ring
+    Polygon::new(LineString::new(ring), Vec::new())
+}
+
+/// One polygon per cell, not dissolved
+pub fn cells_multi_polygon(cells: impl IntoIterator<Item = CellIndex>) -> MultiPolygon {
+    cells.into_iter().map(cell_polygon).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geo::Contains;
+    use h3o::Resolution;
+
+    #[test]
+    fn fix_inside_its_cell() {
+        let gps = Gps::rounded();
+        let point = Point::from(gps);
+        assert!((point.x() - 120.12345).abs() < 1e-9 && (point.y() + 50.12345).abs() < 1e-9);
+
+        let cell = gps.to_h3_cell(Resolution::Nine).unwrap();
+        let polygon = cell_polygon(cell);
+        assert!(polygon.exterior().is_closed());
+        assert!(polygon.contains(&point));
+
+        let neighbors = cells_multi_polygon(cell.grid_disk::<Vec<_>>(1));
+        assert_eq!(neighbors.0.len(), 7);
+        assert!(neighbors.contains(&point));
+    }
+}
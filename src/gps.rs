@@ -1,4 +1,5 @@
 use super::*;
+use crate::epoch::Epoch;
 use helium_proto::{mapper_gps, MapperGps};
 use rust_decimal::Decimal;
 
@@ -9,26 +10,132 @@ pub struct Gps {
     /// UTC of position fix
     pub timestamp: DateTime<Utc>,
     /// Latitude in degrees
+    #[serde(with = "crate::serde_helpers::decimal::scale5")]
     pub lat: Decimal,
     /// Longitude in degrees
+    #[serde(with = "crate::serde_helpers::decimal::scale5")]
     pub lon: Decimal,
     /// Horizontal dilution of position
+    #[serde(with = "crate::serde_helpers::decimal::scale2")]
     pub hdop: Decimal,
     /// Height of geoid (mean sea level) above WGS84 ellipsoid
+    #[serde(with = "crate::serde_helpers::decimal::scale2")]
     pub altitude: Decimal,
     /// Number of satellites in use
     pub num_sats: u8,
     /// Speed over ground (SoG), km/h
+    #[serde(with = "crate::serde_helpers::decimal::scale2")]
     pub speed: Decimal,
+    /// Estimated horizontal accuracy in meters, when the receiver reports it (eg: uBlox hAcc).
+    /// The proto extension carries it in cm, the `BEACON_V5` layout in m.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "crate::serde_helpers::decimal::scale2::option"
+    )]
+    pub h_acc_m: Option<Decimal>,
+    /// Estimated vertical accuracy in meters, when the receiver reports it (eg: uBlox vAcc)
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "crate::serde_helpers::decimal::scale2::option"
+    )]
+    pub v_acc_m: Option<Decimal>,
+    /// Jamming indicator of the receiver, eg: uBlox UBX-MON-RF. This and `spoofing` travel in the
+    /// proto extension, the LoRa frames leave both out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jamming: Option<JammingState>,
+    /// Spoofing indicator of the receiver, eg: uBlox UBX-NAV-STATUS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spoofing: Option<SpoofingState>,
 }
 
+/// Ordered from the least to the most severe
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JammingState {
+    Ok,
+    Warning,
+    Critical,
+}
+
+impl JammingState {
+    /// From the `flags` of a UBX-MON-RF block, `jammingState` in bits 0-1. `None` when the
+    /// receiver reports it as unknown or disabled.
+    pub fn from_ubx_mon_rf(flags: u8) -> Option<Self> {
+        match flags & 0x03 {
+            1 => Some(Self::Ok),
+            2 => Some(Self::Warning),
+            3 => Some(Self::Critical),
+            _ => None,
+        }
+    }
+
+    /// The `jammingState` value `from_ubx_mon_rf` reads as `self`
+    pub fn ubx_value(self) -> u8 {
+        match self {
+            Self::Ok => 1,
+            Self::Warning => 2,
+            Self::Critical => 3,
+        }
+    }
+}
+
+/// Ordered from the least to the most severe
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpoofingState {
+    NotIndicated,
+    Indicated,
+    /// Multiple spoofing indications
+    Multiple,
+}
+
+impl SpoofingState {
+    /// From `flags2` of UBX-NAV-STATUS, `spoofDetState` in bits 3-4. `None` when the receiver
+    /// reports it as unknown or deactivated.
+    pub fn from_ubx_nav_status(flags2: u8) -> Option<Self> {
+        match (flags2 >> 3) & 0x03 {
+            1 => Some(Self::NotIndicated),
+            2 => Some(Self::Indicated),
+            3 => Some(Self::Multiple),
+            _ => None,
+        }
+    }
+
+    /// The `spoofDetState` value `from_ubx_nav_status` reads as `self`
+    pub fn ubx_value(self) -> u8 {
+        match self {
+            Self::NotIndicated => 1,
+            Self::Indicated => 2,
+            Self::Multiple => 3,
+        }
+    }
+}
+
+/// Fixes reporting a horizontal accuracy worse than this are not considered locked
+pub const MAX_LOCKED_H_ACC_M: Decimal = Decimal::from_parts(100, 0, 0, false, 0);
+
+#[cfg(feature = "h3")]
 pub use h3o::Resolution;
 
 impl Gps {
     pub fn is_locked(&self) -> bool {
-        self.num_sats >= 3 && self.hdop > ZERO_DECIMAL
+        self.num_sats >= 3
+            && self.hdop > ZERO_DECIMAL
+            && !matches!(self.h_acc_m, Some(h_acc) if h_acc > MAX_LOCKED_H_ACC_M)
+    }
+
+    /// Horizontal accuracy in meters, as reported by the receiver or else estimated from HDOP
+    pub fn horizontal_accuracy_m(&self) -> f64 {
+        use rust_decimal::prelude::ToPrimitive;
+        match self.h_acc_m {
+            Some(h_acc) => h_acc.to_f64().unwrap_or(f64::INFINITY),
+            None => self.hdop.to_f64().unwrap_or(f64::INFINITY) * crate::GNSS_UERE_M,
+        }
     }
 
+    #[cfg(feature = "h3")]
     pub fn to_h3_cell(&self, r: h3o::Resolution) -> Result<h3o::CellIndex> {
         use rust_decimal::prelude::ToPrimitive;
         match (self.lat.to_f64(), self.lon.to_f64()) {
@@ -54,6 +161,10 @@ impl Gps {
             altitude: Decimal::new(rng.gen_range(-10_600..8_500), 2),
             num_sats: rng.gen_range(0..12),
             speed: Decimal::new(rng.gen_range(0..50_00), 2),
+            h_acc_m: None,
+            v_acc_m: None,
+            jamming: None,
+            spoofing: None,
         }
     }
 
@@ -68,10 +179,99 @@ impl Gps {
             altitude: Decimal::new(9_25, 2),
             num_sats: 5,
             speed: Decimal::new(50_50, 2),
+            h_acc_m: None,
+            v_acc_m: None,
+            jamming: None,
+            spoofing: None,
         }
     }
 }
 
+impl Gps {
+    /// `lat,lon Nsats hdopX`, shared by the Display impls of the payloads
+    pub(crate) fn fmt_fix(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{},{} {}sats hdop{}",
+            self.lat, self.lon, self.num_sats, self.hdop
+        )
+    }
+}
+
+/// A fix in the units shared by the LoRa frames of the payloads
+pub(crate) struct LoraFix {
+    pub time: u32,
+    pub lat: u32,
+    pub lon: u32,
+    pub hdop: u16,
+    pub alt: u16,
+    pub speed: u16,
+    pub num_sats: u8,
+}
+
+impl LoraFix {
+    pub(crate) fn dump(&self) -> Vec<crate::FieldDump> {
+        use crate::FieldDump;
+        use latlon::Unit;
+        vec![
+            FieldDump::new(
+                "time",
+                self.time,
+                time::from_lora_units(self.time).to_rfc3339(),
+            ),
+            FieldDump::new(
+                "lat",
+                self.lat,
+                latlon::from_lora_units(Unit::Lat(self.lat)),
+            ),
+            FieldDump::new(
+                "lon",
+                self.lon,
+                latlon::from_lora_units(Unit::Lon(self.lon)),
+            ),
+            FieldDump::new("hdop", self.hdop, hdop::from_units(self.hdop.into())),
+            FieldDump::new("alt", self.alt, altitude::from_lora_units(self.alt.into())),
+            FieldDump::new(
+                "speed",
+                self.speed,
+                speed::from_lora_units(self.speed.into()),
+            ),
+            FieldDump::new("num_sats", self.num_sats, self.num_sats),
+        ]
+    }
+}
+
+impl Gps {
+    /// Saturates or rejects, per `mode`: times outside 2023-01-01 plus 30 bits of seconds, HDOP
+    /// above 10.23, altitudes outside -110 m to 145.75 m, speeds above 127.75 km/h and more than
+    /// 15 satellites. Coordinates only fall out of range when they aren't valid degrees.
+    pub(crate) fn to_lora_fix(&self, mode: EncodeMode) -> Result<LoraFix> {
+        self.to_lora_fix_in(&Epoch::GENESIS, mode)
+    }
+
+    /// Same as `to_lora_fix` with time counted from `epoch`
+    pub(crate) fn to_lora_fix_in(&self, epoch: &Epoch, mode: EncodeMode) -> Result<LoraFix> {
+        use latlon::Degrees;
+        Ok(LoraFix {
+            time: time::to_lora_units_in(self.timestamp, epoch, mode)?,
+            lat: latlon::to_lora_units(Degrees::Lat(self.lat), mode)?,
+            lon: latlon::to_lora_units(Degrees::Lon(self.lon), mode)?,
+            hdop: mode.fit_scaled("hdop", hdop::scaled(self.hdop), 10)? as u16,
+            alt: altitude::to_lora_units(self.altitude, mode)? as u16,
+            speed: speed::to_lora_units(self.speed, mode)? as u16,
+            num_sats: mode.fit("num_sats", self.num_sats.into(), 4)? as u8,
+        })
+    }
+}
+
+impl std::fmt::Display for Gps {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Gps{")?;
+        self.fmt_fix(f)?;
+        f.write_str("}")
+    }
+}
+
 impl From<Gps> for helium_proto::MapperGpsV1 {
     fn from(gps_data: Gps) -> helium_proto::MapperGpsV1 {
         helium_proto::MapperGpsV1 {
@@ -86,18 +286,77 @@ impl From<Gps> for helium_proto::MapperGpsV1 {
     }
 }
 
-impl From<helium_proto::MapperGpsV1> for Gps {
-    fn from(gps_proto: helium_proto::MapperGpsV1) -> Gps {
-        Gps {
-            timestamp: time::from_proto_units(gps_proto.timestamp),
+impl TryFrom<helium_proto::MapperGpsV1> for Gps {
+    type Error = Error;
+
+    fn try_from(gps_proto: helium_proto::MapperGpsV1) -> Result<Gps> {
+        Ok(Gps {
+            timestamp: time::from_proto_units(gps_proto.timestamp)?,
             lat: latlon::from_proto_units(gps_proto.lat),
             lon: latlon::from_proto_units(gps_proto.lon),
             hdop: hdop::from_units(gps_proto.hdop),
             altitude: altitude::from_proto_units(gps_proto.altitude),
             num_sats: gps_proto.num_sats as u8,
             speed: speed::from_proto_units(gps_proto.speed),
-        }
+            h_acc_m: None,
+            v_acc_m: None,
+            jamming: None,
+            spoofing: None,
+        })
+    }
+}
+
+impl Gps {
+    /// The accuracies and interference states, in the extension of the payload carrying the fix.
+    /// Negative accuracies fail with `Error::OutOfRange`, accuracies beyond the field saturate.
+    pub(crate) fn to_proto_ext(&self) -> Result<Option<crate::proto_ext::GpsExtV1>> {
+        use rust_decimal::prelude::ToPrimitive;
+        let cm = |field: &'static str, m: Decimal| -> Result<u32> {
+            let cm = m.checked_mul(Decimal::ONE_HUNDRED).map(|cm| cm.round());
+            if m.is_sign_negative() && !m.is_zero() {
+                return Err(Error::OutOfRange {
+                    field,
+                    value: cm.and_then(|cm| cm.to_i128()).unwrap_or(i128::MIN),
+                });
+            }
+            Ok(cm.and_then(|cm| cm.to_u32()).unwrap_or(u32::MAX))
+        };
+        let ext = crate::proto_ext::GpsExtV1 {
+            h_acc_cm: self.h_acc_m.map(|m| cm("h_acc_m", m)).transpose()?,
+            v_acc_cm: self.v_acc_m.map(|m| cm("v_acc_m", m)).transpose()?,
+            jamming: self.jamming.map(|state| state.ubx_value().into()),
+            spoofing: self.spoofing.map(|state| state.ubx_value().into()),
+        };
+        Ok((ext != Default::default()).then_some(ext))
     }
+
+    pub(crate) fn set_proto_ext(&mut self, ext: &crate::proto_ext::GpsExtV1) -> Result<()> {
+        let m = |cm: u32| Decimal::new(cm.into(), 2);
+        self.h_acc_m = ext.h_acc_cm.map(m);
+        self.v_acc_m = ext.v_acc_cm.map(m);
+        self.jamming = ubx_state("jamming", ext.jamming, JammingState::from_ubx_mon_rf)?;
+        self.spoofing = ubx_state("spoofing", ext.spoofing, |value| {
+            SpoofingState::from_ubx_nav_status(value << 3)
+        })?;
+        Ok(())
+    }
+}
+
+/// A state of the proto extension, where unknown is left out rather than sent as 0
+fn ubx_state<T>(
+    field: &'static str,
+    value: Option<u32>,
+    from_ubx: impl Fn(u8) -> Option<T>,
+) -> Result<Option<T>> {
+    value
+        .map(|value| match value {
+            1..=3 => Ok(from_ubx(value as u8).expect("1 to 3 are all states")),
+            _ => Err(Error::OutOfRange {
+                field,
+                value: value.into(),
+            }),
+        })
+        .transpose()
 }
 
 impl TryFrom<MapperGps> for Gps {
@@ -105,13 +364,19 @@ impl TryFrom<MapperGps> for Gps {
 
     fn try_from(proto: MapperGps) -> Result<Self> {
         if let Some(mapper_gps::Version::GpsV1(proto)) = proto.version {
-            Ok(proto.into())
+            proto.try_into()
         } else {
             Err(Error::ProtoHasNone("version"))
         }
     }
 }
 
+impl From<Gps> for Payload {
+    fn from(gps: Gps) -> Self {
+        Payload::Gps(gps)
+    }
+}
+
 impl From<Gps> for mapper_payload::Message {
     fn from(gps: Gps) -> Self {
         mapper_payload::Message::Gps(MapperGps {
@@ -120,13 +385,121 @@ impl From<Gps> for mapper_payload::Message {
     }
 }
 
+/// The fix at `at` along a time ordered `track`, interpolated linearly between the fixes around
+/// it. HDOP, accuracies and interference indicators take the worse of the two fixes, satellites
+/// the fewer. `None` when `at` is outside the track. Tracks crossing the antimeridian are not
+/// handled.
+pub fn interpolate(track: &[Gps], at: DateTime<Utc>) -> Option<Gps> {
+    let after = track.partition_point(|fix| fix.timestamp <= at);
+    let before = *track.get(after.checked_sub(1)?)?;
+    if before.timestamp == at {
+        return Some(before);
+    }
+    let after = *track.get(after)?;
+    // receivers can report fixes less than a millisecond apart
+    let span = (after.timestamp - before.timestamp).num_microseconds()?;
+    if span <= 0 {
+        return Some(before);
+    }
+    let frac = Decimal::from((at - before.timestamp).num_microseconds()?) / Decimal::from(span);
+    let lerp = |a: Decimal, b: Decimal, dp| (a + (b - a) * frac).round_dp(dp);
+    let worse = |a: Option<Decimal>, b: Option<Decimal>| a.max(b);
+    Some(Gps {
+        timestamp: at,
+        lat: lerp(before.lat, after.lat, 5),
+        lon: lerp(before.lon, after.lon, 5),
+        hdop: before.hdop.max(after.hdop),
+        altitude: lerp(before.altitude, after.altitude, 2),
+        num_sats: before.num_sats.min(after.num_sats),
+        speed: lerp(before.speed, after.speed, 2),
+        h_acc_m: worse(before.h_acc_m, after.h_acc_m),
+        v_acc_m: worse(before.v_acc_m, after.v_acc_m),
+        jamming: before.jamming.max(after.jamming),
+        spoofing: before.spoofing.max(after.spoofing),
+    })
+}
+
+const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+/// Simplifies a time ordered series of fixes with the Ramer–Douglas–Peucker algorithm, keeping
+/// only the fixes needed to reproduce the trajectory within `epsilon_m` meters.
+///
+/// Distances are synchronized euclidean distances: each fix is compared with the position
+/// interpolated *at its timestamp* along the simplified segment rather than with the closest
+/// point on it. Fixes where the vehicle sped up, slowed down or stopped are therefore kept even
+/// when the path is straight, so speeds derived from the simplified series stay faithful.
+///
+/// The first and last fixes are always kept.
+pub fn simplify(fixes: &[Gps], epsilon_m: f64) -> Vec<Gps> {
+    if fixes.len() < 3 {
+        return fixes.to_vec();
+    }
+    let points: Vec<(f64, f64, f64)> = fixes.iter().map(to_local_meters).collect();
+    let mut keep = vec![false; fixes.len()];
+    keep[0] = true;
+    keep[fixes.len() - 1] = true;
+
+    let mut segments = vec![(0, fixes.len() - 1)];
+    while let Some((first, last)) = segments.pop() {
+        let mut max_distance = 0.0;
+        let mut max_index = first;
+        for (i, point) in points.iter().enumerate().take(last).skip(first + 1) {
+            let distance = synchronized_distance(points[first], points[last], *point);
+            if distance > max_distance {
+                max_distance = distance;
+                max_index = i;
+            }
+        }
+        if max_distance > epsilon_m {
+            keep[max_index] = true;
+            segments.push((first, max_index));
+            segments.push((max_index, last));
+        }
+    }
+
+    fixes
+        .iter()
+        .zip(keep)
+        .filter_map(|(fix, keep)| keep.then_some(*fix))
+        .collect()
+}
+
+/// Equirectangular projection, accurate enough for the few km covered between fixes.
+/// Returns (x meters, y meters, seconds)
+fn to_local_meters(gps: &Gps) -> (f64, f64, f64) {
+    use rust_decimal::prelude::ToPrimitive;
+    let lat = gps.lat.to_f64().unwrap_or_default().to_radians();
+    let lon = gps.lon.to_f64().unwrap_or_default().to_radians();
+    let t = gps.timestamp.timestamp_millis() as f64 / 1000.0;
+    (EARTH_RADIUS_M * lon * lat.cos(), EARTH_RADIUS_M * lat, t)
+}
+
+fn synchronized_distance(start: (f64, f64, f64), end: (f64, f64, f64), p: (f64, f64, f64)) -> f64 {
+    let dt = end.2 - start.2;
+    let ratio = if dt > 0.0 { (p.2 - start.2) / dt } else { 0.0 };
+    let x = start.0 + (end.0 - start.0) * ratio;
+    let y = start.1 + (end.1 - start.1) * ratio;
+    ((p.0 - x).powi(2) + (p.1 - y).powi(2)).sqrt()
+}
+
 pub mod hdop {
     use super::*;
 
     pub fn to_units(hdop: Decimal) -> u32 {
-        let multiplier = Decimal::new(100, 0);
-        let scaled = hdop.checked_mul(multiplier).unwrap().round();
-        scaled.to_string().parse::<u32>().unwrap()
+        to_units_with(hdop, Rounding::HalfEven)
+    }
+
+    /// Saturates values beyond the u32 range of the proto field
+    pub fn to_units_with(hdop: Decimal, rounding: Rounding) -> u32 {
+        to_units_with_mode(hdop, rounding, EncodeMode::Saturating).expect("saturating encode")
+    }
+
+    pub fn to_units_with_mode(hdop: Decimal, rounding: Rounding, mode: EncodeMode) -> Result<u32> {
+        mode.fit_u32("hdop", rounding.apply(scaled(hdop)))
+    }
+
+    pub(crate) fn scaled(hdop: Decimal) -> Decimal {
+        hdop.checked_mul(Decimal::new(100, 0)).unwrap()
     }
 
     pub(crate) fn from_units(hdop: u32) -> Decimal {
@@ -137,28 +510,61 @@ pub mod hdop {
 pub mod time {
     use super::*;
     // time for 2023-01-01 00:00:00 UTC
-    const REFERENCE: i64 = 1672531200;
+    pub(crate) const REFERENCE: i64 = 1672531200;
 
-    pub(crate) fn to_lora_units(datetime: DateTime<Utc>) -> u32 {
-        (datetime.timestamp() - REFERENCE) as u32
+    pub(crate) const LORA_BITS: u32 = 30;
+
+    /// Seconds since the reference, which give the LoRa frames 2023-01-01 through 2057-01-09
+    pub(crate) fn to_lora_units(datetime: DateTime<Utc>, mode: EncodeMode) -> Result<u32> {
+        to_lora_units_in(datetime, &Epoch::GENESIS, mode)
+    }
+
+    /// Seconds since the start of `epoch`
+    pub(crate) fn to_lora_units_in(
+        datetime: DateTime<Utc>,
+        epoch: &Epoch,
+        mode: EncodeMode,
+    ) -> Result<u32> {
+        let offset = i128::from(datetime.timestamp()) - i128::from(epoch.reference());
+        Ok(mode.fit("time", offset, LORA_BITS)? as u32)
+    }
+
+    pub fn try_to_lora_units(datetime: DateTime<Utc>) -> Result<u32> {
+        to_lora_units(datetime, EncodeMode::Strict)
+            .map_err(|_| Error::TimestampOutOfRange(datetime.timestamp()))
     }
 
-    pub(crate) fn from_lora_units(timestamp: u32) -> DateTime<Utc> {
+    /// Every u32 lands well inside chrono's range, so unlike the proto units this can't fail
+    pub fn from_lora_units(timestamp: u32) -> DateTime<Utc> {
+        from_lora_units_in(timestamp, &Epoch::GENESIS)
+    }
+
+    /// `Epoch::new` only admits epochs whose whole range chrono can represent
+    pub(crate) fn from_lora_units_in(timestamp: u32, epoch: &Epoch) -> DateTime<Utc> {
         DateTime::<Utc>::from_utc(
-            NaiveDateTime::from_timestamp_opt(timestamp as i64 + REFERENCE, 0).unwrap(),
+            NaiveDateTime::from_timestamp_opt(timestamp as i64 + epoch.reference(), 0)
+                .expect("u32 lora time in range"),
             Utc,
         )
     }
 
+    /// Leap seconds (chrono nanoseconds >= 1e9) fold into the second they extend. Times before
+    /// the unix epoch saturate to 0.
     pub fn to_proto_units(datetime: DateTime<Utc>) -> u64 {
-        datetime.timestamp() as u64
+        datetime.timestamp().max(0) as u64
     }
 
-    pub fn from_proto_units(timestamp: u64) -> DateTime<Utc> {
-        DateTime::<Utc>::from_utc(
-            NaiveDateTime::from_timestamp_opt(timestamp as i64, 0).unwrap(),
-            Utc,
-        )
+    pub fn try_to_proto_units(datetime: DateTime<Utc>) -> Result<u64> {
+        u64::try_from(datetime.timestamp())
+            .map_err(|_| Error::TimestampOutOfRange(datetime.timestamp()))
+    }
+
+    /// Rejects timestamps that chrono can't represent rather than panicking on hostile input
+    pub fn from_proto_units(timestamp: u64) -> Result<DateTime<Utc>> {
+        let seconds = i64::try_from(timestamp).map_err(|_| Error::TimestampOutOfRange(i64::MAX))?;
+        NaiveDateTime::from_timestamp_opt(seconds, 0)
+            .map(|naive| DateTime::<Utc>::from_utc(naive, Utc))
+            .ok_or(Error::TimestampOutOfRange(seconds))
     }
 
     #[cfg(test)]
@@ -169,7 +575,7 @@ pub mod time {
         #[test]
         fn time_to_lora_units() {
             let datetime = Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 5).unwrap();
-            assert_eq!(to_lora_units(datetime), 5);
+            assert_eq!(to_lora_units(datetime, EncodeMode::Strict).unwrap(), 5);
         }
 
         #[test]
@@ -187,7 +593,55 @@ pub mod time {
         #[test]
         fn time_from_proto_units() {
             let datetime = Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 5).unwrap();
-            assert_eq!(from_proto_units(1672531205), datetime);
+            assert_eq!(from_proto_units(1672531205).unwrap(), datetime);
+        }
+
+        #[test]
+        fn time_proto_units_out_of_range() {
+            assert!(matches!(
+                from_proto_units(u64::MAX),
+                Err(Error::TimestampOutOfRange(_))
+            ));
+            assert!(matches!(
+                from_proto_units(i64::MAX as u64),
+                Err(Error::TimestampOutOfRange(_))
+            ));
+            let before_epoch = Utc.with_ymd_and_hms(1969, 12, 31, 23, 59, 59).unwrap();
+            assert_eq!(to_proto_units(before_epoch), 0);
+            assert!(try_to_proto_units(before_epoch).is_err());
+        }
+
+        #[test]
+        fn time_lora_units_boundaries() {
+            let max_units = (1 << LORA_BITS) - 1;
+            let max = from_lora_units(max_units);
+            let after_max = max + chrono::Duration::seconds(1);
+            assert_eq!(to_lora_units(max, EncodeMode::Strict).unwrap(), max_units);
+            assert_eq!(
+                to_lora_units(after_max, EncodeMode::Saturating).unwrap(),
+                max_units
+            );
+            assert!(try_to_lora_units(after_max).is_err());
+
+            // before the reference would wrap to a date far in the future if cast
+            let before_reference = Utc.with_ymd_and_hms(2022, 12, 31, 23, 59, 59).unwrap();
+            assert_eq!(
+                to_lora_units(before_reference, EncodeMode::Saturating).unwrap(),
+                0
+            );
+            assert!(matches!(
+                try_to_lora_units(before_reference),
+                Err(Error::TimestampOutOfRange(1672531199))
+            ));
+        }
+
+        #[test]
+        fn time_leap_second_folds_into_previous() {
+            use chrono::Timelike;
+            let last_second = Utc.with_ymd_and_hms(2016, 12, 31, 23, 59, 59).unwrap();
+            let leap = last_second.with_nanosecond(1_500_000_000).unwrap();
+            assert_eq!(to_proto_units(leap), to_proto_units(last_second));
+            assert_eq!(from_proto_units(to_proto_units(leap)).unwrap(), last_second);
         }
     }
 }
@@ -208,14 +662,13 @@ pub mod latlon {
         Lon(u32),
     }
 
-    pub(crate) fn to_lora_units(coordinate: Degrees) -> u32 {
-        let offset_degrees = match coordinate {
-            Degrees::Lat(lat) => lat + LAT_OFFSET,
-            Degrees::Lon(lon) => lon + LON_OFFSET,
+    pub(crate) fn to_lora_units(coordinate: Degrees, mode: EncodeMode) -> Result<u32> {
+        let (field, offset_degrees, bits) = match coordinate {
+            Degrees::Lat(lat) => ("lat", lat + LAT_OFFSET, 25),
+            Degrees::Lon(lon) => ("lon", lon + LON_OFFSET, 26),
         };
-        let multiplier = Decimal::new(100000, 0);
-        let scaled = offset_degrees.checked_mul(multiplier).unwrap().round();
-        scaled.to_string().parse::<u32>().unwrap()
+        let scaled = offset_degrees.checked_mul(Decimal::new(100000, 0)).unwrap();
+        Ok(mode.fit_scaled(field, scaled, bits)? as u32)
     }
 
     pub(crate) fn from_lora_units(unit: Unit) -> Decimal {
@@ -227,10 +680,41 @@ pub mod latlon {
         }
     }
 
+    /// In 1e-4 degree steps (11.1 m), 4 bits narrower than `to_lora_units`
+    pub(crate) fn to_coarse_lora_units(coordinate: Degrees, mode: EncodeMode) -> Result<u32> {
+        let (field, offset_degrees, bits) = match coordinate {
+            Degrees::Lat(lat) => ("lat", lat + LAT_OFFSET, 21),
+            Degrees::Lon(lon) => ("lon", lon + LON_OFFSET, 22),
+        };
+        let scaled = offset_degrees.checked_mul(Decimal::new(10000, 0)).unwrap();
+        Ok(mode.fit_scaled(field, scaled, bits)? as u32)
+    }
+
+    pub(crate) fn from_coarse_lora_units(unit: Unit) -> Decimal {
+        match unit {
+            Unit::Lat(lat) => Decimal::new(lat.into(), 4) - LAT_OFFSET,
+            Unit::Lon(lon) => Decimal::new(lon.into(), 4) - LON_OFFSET,
+        }
+    }
+
     pub fn to_proto_units(coordinate: Decimal) -> i32 {
+        to_proto_units_with(coordinate, Rounding::HalfEven)
+    }
+
+    /// Saturates values beyond the i32 range of the proto field
+    pub fn to_proto_units_with(coordinate: Decimal, rounding: Rounding) -> i32 {
+        to_proto_units_with_mode(coordinate, rounding, EncodeMode::Saturating)
+            .expect("saturating encode")
+    }
+
+    pub fn to_proto_units_with_mode(
+        coordinate: Decimal,
+        rounding: Rounding,
+        mode: EncodeMode,
+    ) -> Result<i32> {
         let multiplier = Decimal::new(100000, 0);
-        let scaled = coordinate.checked_mul(multiplier).unwrap().round();
-        scaled.to_string().parse::<i32>().unwrap()
+        let scaled = rounding.apply(coordinate.checked_mul(multiplier).unwrap());
+        mode.fit_i32("latlon", scaled)
     }
 
     pub fn from_proto_units(unit: i32) -> Decimal {
@@ -257,7 +741,7 @@ pub mod latlon {
             let mut rng = rand::thread_rng();
             let random_lat = rng.gen_range(-90_00000..90_00000);
             let lat = Decimal::new(random_lat, 5);
-            let units = to_lora_units(Degrees::Lat(lat));
+            let units = to_lora_units(Degrees::Lat(lat), EncodeMode::Strict).unwrap();
             let degrees = from_lora_units(Unit::Lat(units));
             assert_eq!(lat, degrees);
         }
@@ -267,7 +751,7 @@ pub mod latlon {
             let mut rng = rand::thread_rng();
             let random_lon = rng.gen_range(-180_00000..180_00000);
             let lon = Decimal::new(random_lon, 5);
-            let units = to_lora_units(Degrees::Lon(lon));
+            let units = to_lora_units(Degrees::Lon(lon), EncodeMode::Strict).unwrap();
             let degrees = from_lora_units(Unit::Lon(units));
             assert_eq!(lon, degrees);
         }
@@ -293,13 +777,11 @@ pub mod altitude {
     #[allow(clippy::zero_prefixed_literal, clippy::inconsistent_digit_grouping)]
     const ALTITUDE_PROTO_SCALAR: Decimal = Decimal::from_parts(1, 0, 0, false, 2);
 
-    pub(crate) fn to_lora_units(altitude: Decimal) -> u32 {
-        let offset_altitude = altitude + ALTITUDE_OFFSET;
-        let scaled = offset_altitude
+    pub(crate) fn to_lora_units(altitude: Decimal, mode: EncodeMode) -> Result<u32> {
+        let scaled = (altitude + ALTITUDE_OFFSET)
             .checked_div(ALTITUDE_LORA_SCALAR)
-            .unwrap()
-            .round();
-        scaled.to_string().parse::<u32>().unwrap()
+            .unwrap();
+        Ok(mode.fit_scaled("altitude", scaled, 10)? as u32)
     }
 
     pub(crate) fn from_lora_units(altitude: u32) -> Decimal {
@@ -309,8 +791,22 @@ pub mod altitude {
     }
 
     pub fn to_proto_units(altitude: Decimal) -> i32 {
-        let scaled = altitude.checked_div(ALTITUDE_PROTO_SCALAR).unwrap().round();
-        scaled.to_string().parse::<i32>().unwrap()
+        to_proto_units_with(altitude, Rounding::HalfEven)
+    }
+
+    /// Saturates values beyond the i32 range of the proto field
+    pub fn to_proto_units_with(altitude: Decimal, rounding: Rounding) -> i32 {
+        to_proto_units_with_mode(altitude, rounding, EncodeMode::Saturating)
+            .expect("saturating encode")
+    }
+
+    pub fn to_proto_units_with_mode(
+        altitude: Decimal,
+        rounding: Rounding,
+        mode: EncodeMode,
+    ) -> Result<i32> {
+        let scaled = rounding.apply(altitude.checked_div(ALTITUDE_PROTO_SCALAR).unwrap());
+        mode.fit_i32("altitude", scaled)
     }
 
     pub fn from_proto_units(altitude: i32) -> Decimal {
@@ -328,11 +824,34 @@ pub mod altitude {
             assert_eq!(ALTITUDE_OFFSET.to_string(), "110.00");
         }
 
+        #[test]
+        fn proto_units_out_of_range() {
+            let above = Decimal::new(30_000_000, 0);
+            assert!(matches!(
+                to_proto_units_with_mode(above, Rounding::HalfEven, EncodeMode::Strict),
+                Err(Error::OutOfRange {
+                    field: "altitude",
+                    value: 3_000_000_000
+                })
+            ));
+            assert_eq!(to_proto_units(above), i32::MAX);
+            assert_eq!(to_proto_units(-above), i32::MIN);
+            assert_eq!(
+                to_proto_units_with_mode(
+                    Decimal::new(-10_125, 3),
+                    Rounding::HalfEven,
+                    EncodeMode::Strict
+                )
+                .unwrap(),
+                -1012
+            );
+        }
+
         #[test]
         fn altitude_lower_limit_roundtrip_lora() {
             let altitude = Decimal::new(-110_00, 2);
             assert_eq!(altitude.to_string(), "-110.00");
-            let units = to_lora_units(altitude);
+            let units = to_lora_units(altitude, EncodeMode::Strict).unwrap();
             assert_eq!(0, units);
             let altitude = from_lora_units(units);
             assert_eq!(altitude.to_string(), "-110.00");
@@ -342,7 +861,7 @@ pub mod altitude {
         fn altitude_zero_roundtrip_lora() {
             let altitude = Decimal::new(0, 2);
             assert_eq!(altitude.to_string(), "0.00");
-            let units = to_lora_units(altitude);
+            let units = to_lora_units(altitude, EncodeMode::Strict).unwrap();
             assert_eq!(110_00 / 25, units);
             let altitude = from_lora_units(units);
             assert_eq!(altitude.to_string(), "0.00");
@@ -352,7 +871,7 @@ pub mod altitude {
         fn altitude_round_down_lora() {
             let altitude = Decimal::new(10_12, 2);
             assert_eq!(altitude.to_string(), "10.12");
-            let altitude = from_lora_units(to_lora_units(altitude));
+            let altitude = from_lora_units(to_lora_units(altitude, EncodeMode::Strict).unwrap());
             assert_eq!(altitude.to_string(), "10.00");
         }
 
@@ -360,7 +879,7 @@ pub mod altitude {
         fn altitude_round_up_lora() {
             let altitude = Decimal::new(10_21, 2);
             assert_eq!(altitude.to_string(), "10.21");
-            let altitude = from_lora_units(to_lora_units(altitude));
+            let altitude = from_lora_units(to_lora_units(altitude, EncodeMode::Strict).unwrap());
             assert_eq!(altitude.to_string(), "10.25");
         }
     }
@@ -372,9 +891,9 @@ pub mod speed {
     const SPEED_LORA_SCALAR: Decimal = Decimal::from_parts(0_25, 0, 0, false, 2);
     const SPEED_PROTO_SCALAR: Decimal = Decimal::from_parts(1, 0, 0, false, 2);
 
-    pub(crate) fn to_lora_units(speed: Decimal) -> u32 {
-        let scaled = speed.checked_div(SPEED_LORA_SCALAR).unwrap().round();
-        scaled.to_string().parse::<u32>().unwrap()
+    pub(crate) fn to_lora_units(speed: Decimal, mode: EncodeMode) -> Result<u32> {
+        let scaled = speed.checked_div(SPEED_LORA_SCALAR).unwrap();
+        Ok(mode.fit_scaled("speed", scaled, 9)? as u32)
     }
 
     pub(crate) fn from_lora_units(speed: u32) -> Decimal {
@@ -383,8 +902,22 @@ pub mod speed {
     }
 
     pub fn to_proto_units(speed: Decimal) -> u32 {
-        let scaled = speed.checked_div(SPEED_PROTO_SCALAR).unwrap().round();
-        scaled.to_string().parse::<u32>().unwrap()
+        to_proto_units_with(speed, Rounding::HalfEven)
+    }
+
+    /// Saturates values beyond the u32 range of the proto field
+    pub fn to_proto_units_with(speed: Decimal, rounding: Rounding) -> u32 {
+        to_proto_units_with_mode(speed, rounding, EncodeMode::Saturating)
+            .expect("saturating encode")
+    }
+
+    pub fn to_proto_units_with_mode(
+        speed: Decimal,
+        rounding: Rounding,
+        mode: EncodeMode,
+    ) -> Result<u32> {
+        let scaled = rounding.apply(speed.checked_div(SPEED_PROTO_SCALAR).unwrap());
+        mode.fit_u32("speed", scaled)
     }
 
     pub fn from_proto_units(speed: u32) -> Decimal {
@@ -396,11 +929,25 @@ pub mod speed {
     mod test {
         use super::*;
 
+        #[test]
+        fn proto_units_out_of_range() {
+            let negative = Decimal::new(-1, 0);
+            assert!(matches!(
+                to_proto_units_with_mode(negative, Rounding::HalfEven, EncodeMode::Strict),
+                Err(Error::OutOfRange {
+                    field: "speed",
+                    value: -100
+                })
+            ));
+            assert_eq!(to_proto_units(negative), 0);
+            assert_eq!(to_proto_units(Decimal::new(5_000_000_000, 0)), u32::MAX);
+        }
+
         #[test]
         fn speed_upper_limit_roundtrip_lora() {
             let speed = Decimal::new(80_00, 2);
             assert_eq!(speed.to_string(), "80.00");
-            let units = to_lora_units(speed);
+            let units = to_lora_units(speed, EncodeMode::Strict).unwrap();
             assert_eq!(80_00 / 25, units);
             let speed = from_lora_units(units);
             assert_eq!(speed.to_string(), "80.00");
@@ -410,7 +957,7 @@ pub mod speed {
         fn speed_round_down_lora() {
             let altitude = Decimal::new(20_12, 2);
             assert_eq!(altitude.to_string(), "20.12");
-            let altitude = from_lora_units(to_lora_units(altitude));
+            let altitude = from_lora_units(to_lora_units(altitude, EncodeMode::Strict).unwrap());
             assert_eq!(altitude.to_string(), "20.00");
         }
 
@@ -418,7 +965,7 @@ pub mod speed {
         fn speed_round_up_lora() {
             let altitude = Decimal::new(20_13, 2);
             assert_eq!(altitude.to_string(), "20.13");
-            let altitude = from_lora_units(to_lora_units(altitude));
+            let altitude = from_lora_units(to_lora_units(altitude, EncodeMode::Strict).unwrap());
             assert_eq!(altitude.to_string(), "20.25");
         }
     }
@@ -429,15 +976,92 @@ mod test {
     use super::*;
     use helium_proto::Message;
 
+    fn fix(seconds: i64, lat: i64) -> Gps {
+        Gps {
+            timestamp: Utc.timestamp_opt(1672531200 + seconds, 0).unwrap(),
+            lat: Decimal::new(lat, 5),
+            ..Gps::rounded()
+        }
+    }
+
+    #[test]
+    fn interpolate_between_fixes() {
+        let track = [fix(0, 0), fix(10, 1_000)];
+        let at = |seconds| fix(seconds, 0).timestamp;
+        let mid = interpolate(&track, at(4)).unwrap();
+        assert_eq!(mid.timestamp, at(4));
+        assert_eq!(mid.lat, track[0].lat + Decimal::new(400, 5));
+        assert_eq!(interpolate(&track, at(10)), Some(track[1]));
+        assert_eq!(interpolate(&track, at(11)), None);
+        assert_eq!(interpolate(&track, at(-1)), None);
+        assert_eq!(interpolate(&[], at(0)), None);
+
+        let mut close = track;
+        close[1].timestamp = close[0].timestamp + chrono::Duration::nanoseconds(500);
+        let between = close[0].timestamp + chrono::Duration::nanoseconds(200);
+        assert_eq!(interpolate(&close, between), Some(close[0]));
+    }
+
+    #[test]
+    fn simplify_constant_speed_line() {
+        // ~1.11 m per second heading north
+        let fixes: Vec<Gps> = (0..60).map(|i| fix(i, i)).collect();
+        let simplified = simplify(&fixes, 1.0);
+        assert_eq!(simplified, vec![fixes[0], fixes[59]]);
+    }
+
+    #[test]
+    fn simplify_preserves_stop() {
+        // drive north for 30 s, stand still for 30 s: a straight path but a speed change
+        let fixes: Vec<Gps> = (0..60).map(|i| fix(i, i.min(30))).collect();
+        let simplified = simplify(&fixes, 1.0);
+        assert_eq!(simplified, vec![fixes[0], fixes[30], fixes[59]]);
+    }
+
     #[test]
     fn gps_roundtrip_proto() {
         let gps = Gps::rounded();
         let proto: helium_proto::MapperGpsV1 = gps.clone().into();
         let mut proto_bytes = Vec::new();
         proto.encode(&mut proto_bytes).unwrap();
-        let gps_returned = helium_proto::MapperGpsV1::decode(proto_bytes.as_slice())
+        let gps_returned: Gps = helium_proto::MapperGpsV1::decode(proto_bytes.as_slice())
             .unwrap()
-            .into();
+            .try_into()
+            .unwrap();
         assert_eq!(gps, gps_returned);
     }
+
+    #[test]
+    fn accuracy_limits_lock() {
+        let mut gps = Gps::rounded();
+        assert!(gps.is_locked());
+        assert!((gps.horizontal_accuracy_m() - 45.25).abs() < 1e-9);
+        gps.h_acc_m = Some(Decimal::new(3_5, 1));
+        assert!(gps.is_locked());
+        assert_eq!(gps.horizontal_accuracy_m(), 3.5);
+        gps.h_acc_m = Some(Decimal::new(250, 0));
+        assert!(!gps.is_locked());
+    }
+
+    #[test]
+    fn accuracy_proto_ext_bounds() {
+        let mut gps = Gps::rounded();
+        gps.h_acc_m = Some(Decimal::new(-1_5, 1));
+        assert!(matches!(
+            gps.to_proto_ext(),
+            Err(Error::OutOfRange {
+                field: "h_acc_m",
+                value: -150
+            })
+        ));
+        gps.h_acc_m = Some(Decimal::ZERO);
+        gps.v_acc_m = Some(Decimal::MAX);
+        let ext = gps.to_proto_ext().unwrap().unwrap();
+        assert_eq!((ext.h_acc_cm, ext.v_acc_cm), (Some(0), Some(u32::MAX)));
+        gps.v_acc_m = Some(Decimal::new(50_000_000, 0));
+        assert_eq!(
+            gps.to_proto_ext().unwrap().unwrap().v_acc_cm,
+            Some(u32::MAX)
+        );
+    }
 }
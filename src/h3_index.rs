@@ -0,0 +1,106 @@
+//! H3 cell indexes as the protos carry them, eg: where a witnessing gateway is asserted. Builds
+//! without the `h3` feature check the bit layout of the index; with it the index must also be a
+//! cell h3o accepts, and converts to and from `h3o::CellIndex`.
+use super::{Deserialize, Error, Result, Serialize};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "u64", into = "u64")]
+pub struct H3Index(u64);
+
+impl H3Index {
+    const MAX_BASE_CELL: u64 = 121;
+
+    /// Fails with `Error::InvalidH3Index` unless `index` is an H3 cell
+    pub fn new(index: u64) -> Result<Self> {
+        if !Self::is_cell(index) {
+            return Err(Error::InvalidH3Index(index));
+        }
+        #[cfg(feature = "h3")]
+        h3o::CellIndex::try_from(index).map_err(|_| Error::InvalidH3Index(index))?;
+        Ok(Self(index))
+    }
+
+    fn is_cell(index: u64) -> bool {
+        let bits = |offset: u32, len: u32| (index >> offset) & ((1 << len) - 1);
+        let resolution = bits(52, 4) as u32;
+        bits(63, 1) == 0
+            && bits(59, 4) == 1
+            && bits(56, 3) == 0
+            && bits(45, 7) <= Self::MAX_BASE_CELL
+            && (1..=15).all(|digit| {
+                let value = bits((15 - digit) * 3, 3);
+                // digits past the resolution are all ones, the others can't be
+                (digit > resolution) == (value == 7)
+            })
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0
+    }
+
+    #[cfg(feature = "h3")]
+    pub fn cell(&self) -> h3o::CellIndex {
+        h3o::CellIndex::try_from(self.0).expect("checked on construction")
+    }
+}
+
+impl TryFrom<u64> for H3Index {
+    type Error = Error;
+
+    fn try_from(index: u64) -> Result<Self> {
+        Self::new(index)
+    }
+}
+
+impl From<H3Index> for u64 {
+    fn from(index: H3Index) -> Self {
+        index.0
+    }
+}
+
+#[cfg(feature = "h3")]
+impl From<h3o::CellIndex> for H3Index {
+    fn from(cell: h3o::CellIndex) -> Self {
+        Self(cell.into())
+    }
+}
+
+#[cfg(feature = "h3")]
+impl From<H3Index> for h3o::CellIndex {
+    fn from(index: H3Index) -> Self {
+        index.cell()
+    }
+}
+
+/// Lower case hex, as h3 indexes are usually written
+impl std::fmt::Display for H3Index {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:x}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn checks_bit_layout() {
+        let index = H3Index::new(0x8a1fb46622dffff).unwrap();
+        assert_eq!(index.to_string(), "8a1fb46622dffff");
+        assert_eq!(u64::from(index.cell()), index.get());
+        for invalid in [
+            0,
+            // a digit past the resolution that isn't 7
+            0x8a1fb46622dfffe,
+            // mode 2, a directed edge
+            0x10a1fb46622dffff,
+            // base cell 122
+            0x80f5fffffffffff,
+        ] {
+            assert!(
+                matches!(H3Index::new(invalid), Err(Error::InvalidH3Index(i)) if i == invalid),
+                "{invalid:x}"
+            );
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use super::{Message, Result, SignedBytes};
+use sha2::{Digest, Sha256};
+pub use uuid::Uuid;
+
+/// Namespace of message ids. Never change it: ids must stay stable across versions.
+pub const MESSAGE_ID_NAMESPACE: Uuid = Uuid::from_u128(0xd747a5d3_4bb6_48da_8498_579d8ef9d3c3);
+
+impl Message {
+    /// UUIDv5 identifying the message, named by the pubkey bytes followed by the SHA-256 of the
+    /// signed payload encoding. The payload carries the fix timestamp and the attach or scan
+    /// counter, so every report of a device gets its own id while re-delivered copies, with
+    /// different witnesses or ingest metadata, share one. The payload extension is signed, so
+    /// it takes part as well.
+    pub fn id(&self) -> Result<Uuid> {
+        let signed_bytes = SignedBytes::from_payload(&self.payload)?;
+        let mut name = self.pubkey.to_vec();
+        name.extend_from_slice(&Sha256::digest(signed_bytes.as_bytes()));
+        Ok(Uuid::new_v5(&MESSAGE_ID_NAMESPACE, &name))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{keys, EnvelopeSig, Gps, Payload};
+
+    #[test]
+    fn id_ignores_delivery_details() {
+        let key = keys::file::File::create_key().unwrap();
+        let msg = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap();
+        let id = msg.id().unwrap();
+        assert_eq!(id.get_version_num(), 5);
+
+        let mut witnessed = msg.clone().with_ingest_meta(crate::IngestMeta::now());
+        witnessed.signature = EnvelopeSig::default();
+        assert_eq!(witnessed.id().unwrap(), id);
+
+        let mut later = Gps::rounded();
+        later.timestamp += chrono::Duration::seconds(1);
+        let other = Message::from_payload_signed(&key, Payload::Gps(later)).unwrap();
+        assert_ne!(other.id().unwrap(), id);
+
+        let other_key = keys::file::File::create_key().unwrap();
+        let other_device = Message::from_payload_signed(&other_key, msg.payload).unwrap();
+        assert_ne!(other_device.id().unwrap(), id);
+    }
+}
@@ -0,0 +1,230 @@
+//! InfluxDB line protocol export. Every message becomes one point, except scans which get one
+//! point per scanned cell, tagged with the device pubkey, payload kind and coverage h3 cell.
+use super::{Gps, Message, Payload, ResolutionPolicy, Result};
+use chrono::{DateTime, Utc};
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Float(f64),
+    Int(i64),
+    Bool(bool),
+    Str(String),
+}
+
+impl From<Decimal> for FieldValue {
+    fn from(value: Decimal) -> Self {
+        FieldValue::Float(value.to_f64().unwrap_or(f64::NAN))
+    }
+}
+
+impl std::fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FieldValue::Float(value) => write!(f, "{value}"),
+            FieldValue::Int(value) => write!(f, "{value}i"),
+            FieldValue::Bool(value) => write!(f, "{value}"),
+            FieldValue::Str(value) => {
+                write!(
+                    f,
+                    "\"{}\"",
+                    value.replace('\\', "\\\\").replace('"', "\\\"")
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Point {
+    pub measurement: &'static str,
+    pub tags: Vec<(&'static str, String)>,
+    pub fields: Vec<(&'static str, FieldValue)>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Point {
+    fn new(measurement: &'static str, timestamp: DateTime<Utc>) -> Self {
+        Self {
+            measurement,
+            tags: vec![],
+            fields: vec![],
+            timestamp,
+        }
+    }
+
+    fn tag(mut self, key: &'static str, value: impl ToString) -> Self {
+        self.tags.push((key, value.to_string()));
+        self
+    }
+
+    fn field(mut self, key: &'static str, value: impl Into<FieldValue>) -> Self {
+        self.fields.push((key, value.into()));
+        self
+    }
+
+    fn int(self, key: &'static str, value: impl Into<i64>) -> Self {
+        self.field(key, FieldValue::Int(value.into()))
+    }
+
+    fn gps_fields(self, gps: &Gps) -> Self {
+        self.field("lat", gps.lat)
+            .field("lon", gps.lon)
+            .field("hdop", gps.hdop)
+            .field("altitude", gps.altitude)
+            .field("speed", gps.speed)
+            .int("num_sats", gps.num_sats)
+    }
+}
+
+/// Escapes commas, spaces and, for tags, equal signs
+fn escape(s: &str, equals: bool) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c == ',' || c == ' ' || (equals && c == '=') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl std::fmt::Display for Point {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&escape(self.measurement, false))?;
+        for (key, value) in &self.tags {
+            write!(f, ",{}={}", escape(key, true), escape(value, true))?;
+        }
+        for (i, (key, value)) in self.fields.iter().enumerate() {
+            let separator = if i == 0 { ' ' } else { ',' };
+            write!(f, "{separator}{}={value}", escape(key, true))?;
+        }
+        let nanos = self.timestamp.timestamp_nanos_opt().unwrap_or_default();
+        write!(f, " {nanos}")
+    }
+}
+
+impl Message {
+    pub fn to_influx_points(&self) -> Result<Vec<Point>> {
+        self.to_influx_points_with(&ResolutionPolicy::default())
+    }
+
+    pub fn to_influx_points_with(&self, policy: &ResolutionPolicy) -> Result<Vec<Point>> {
+        let gps = self.payload.gps();
+        let base = |measurement| -> Result<Point> {
+            Ok(Point::new(measurement, gps.timestamp)
+                .tag("pubkey", &self.pubkey)
+                .tag("kind", self.kind())
+                .tag("h3", gps.to_h3_cell(policy.coverage)?)
+                .int("witnesses", self.lora_gws.len() as i64))
+        };
+        Ok(match &self.payload {
+            Payload::Gps(gps) => vec![base("mapper_gps")?.gps_fields(gps)],
+            Payload::Position(position) => vec![base("mapper_position")?
+                .tag("method", format!("{:?}", position.estimate().method))
+                .gps_fields(position.gps())],
+            #[cfg(feature = "beacon")]
+            Payload::Beacon(beacon) => vec![base("mapper_beacon")?.gps_fields(&beacon.gps)],
+            #[cfg(feature = "cell")]
+            Payload::CellAttach(attach) => vec![base("mapper_attach")?
+                .tag("result", format!("{:?}", attach.result))
+                .gps_fields(&attach.gps)
+                .int("attach_counter", attach.attach_counter)
+                .int("cell_id", attach.candidate.cell_id)
+                .int("fcn", attach.candidate.fcn)
+                .int("rsrp", attach.candidate.rsrp)
+                .int("rsrq", attach.candidate.rsrq)
+                .int("delay", attach.candidate.delay)],
+            #[cfg(feature = "cell")]
+            Payload::CellScan(scan) => scan
+                .results
+                .iter()
+                .map(|result| {
+                    Ok(base("mapper_scan")?
+                        .tag("earfcn", result.earfcn)
+                        .int("scan_counter", scan.scan_counter)
+                        .field("cell_id", FieldValue::Int(result.cell_id as i64))
+                        .int("pci", result.physical_cell_id.get())
+                        .int("rsrp", result.rsrp)
+                        .int("rsrq", result.rsrq)
+                        .field("lte", FieldValue::Bool(result.lte)))
+                })
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+/// Groups lines into write bodies of at most `max_lines` lines or `max_bytes` bytes
+#[derive(Debug)]
+pub struct LineBatcher {
+    max_lines: usize,
+    max_bytes: usize,
+    body: String,
+    lines: usize,
+}
+
+impl LineBatcher {
+    pub fn new(max_lines: usize, max_bytes: usize) -> Self {
+        Self {
+            max_lines,
+            max_bytes,
+            body: String::new(),
+            lines: 0,
+        }
+    }
+
+    /// Adds a point, returning the previous body if the point didn't fit alongside it
+    pub fn push(&mut self, point: &Point) -> Option<String> {
+        let line = point.to_string();
+        let full = self.lines > 0
+            && (self.lines >= self.max_lines || self.body.len() + line.len() + 1 > self.max_bytes);
+        let batch = if full { self.flush() } else { None };
+        self.body.push_str(&line);
+        self.body.push('\n');
+        self.lines += 1;
+        batch
+    }
+
+    /// Returns the pending body, if any
+    pub fn flush(&mut self) -> Option<String> {
+        if self.lines == 0 {
+            return None;
+        }
+        self.lines = 0;
+        Some(std::mem::take(&mut self.body))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::keys;
+
+    #[test]
+    fn gps_line() {
+        let key = keys::file::File::create_key().unwrap();
+        let msg = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap();
+        let points = msg.to_influx_points().unwrap();
+        assert_eq!(points.len(), 1);
+        let line = points[0].to_string();
+        assert!(line.starts_with(&format!("mapper_gps,pubkey={},kind=gps,h3=", msg.pubkey)));
+        assert!(line.contains(" witnesses=0i,lat=-50.12345,lon=120.12345,"));
+        assert!(line.ends_with(" 1672531205000000000"));
+    }
+
+    #[test]
+    fn escaping_and_batches() {
+        let point = Point::new("m", DateTime::<Utc>::default())
+            .tag("a tag", "x,y=z")
+            .field("s", FieldValue::Str("say \"hi\"".into()));
+        assert_eq!(point.to_string(), r#"m,a\ tag=x\,y\=z s="say \"hi\"" 0"#);
+
+        let mut batcher = LineBatcher::new(2, 1024);
+        assert_eq!(batcher.push(&point), None);
+        assert_eq!(batcher.push(&point), None);
+        let batch = batcher.push(&point).unwrap();
+        assert_eq!(batch.lines().count(), 2);
+        assert_eq!(batcher.flush().unwrap().lines().count(), 1);
+        assert_eq!(batcher.flush(), None);
+    }
+}
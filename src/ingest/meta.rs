@@ -0,0 +1,143 @@
+use crate::{DateTime, Deserialize, Message, Priority, Serialize, Utc};
+
+/// Server side metadata about how a message was received. It is not part of the signed payload
+/// and is never encoded into a MapperMsg.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IngestMeta {
+    pub received_at: DateTime<Utc>,
+    /// Number of gateways that forwarded the message
+    pub gateway_count: u32,
+    /// Identifier of the ingest node that received the message
+    pub ingest_node: Option<String>,
+    /// Triage priority, overriding the payload's default priority
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<Priority>,
+    /// Logical network the message belongs to (eg: prod, staging, a partner fleet)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network_id: Option<String>,
+}
+
+impl IngestMeta {
+    pub fn new(received_at: DateTime<Utc>) -> Self {
+        Self {
+            received_at,
+            gateway_count: 0,
+            ingest_node: None,
+            priority: None,
+            network_id: None,
+        }
+    }
+
+    pub fn now() -> Self {
+        Self::new(Utc::now())
+    }
+
+    pub fn with_gateway_count(mut self, gateway_count: u32) -> Self {
+        self.gateway_count = gateway_count;
+        self
+    }
+
+    pub fn with_ingest_node(mut self, ingest_node: impl Into<String>) -> Self {
+        self.ingest_node = Some(ingest_node.into());
+        self
+    }
+
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    pub fn with_network_id(mut self, network_id: impl Into<String>) -> Self {
+        self.network_id = Some(network_id.into());
+        self
+    }
+}
+
+impl Message {
+    pub fn with_ingest_meta(mut self, ingest_meta: IngestMeta) -> Self {
+        self.ingest_meta = Some(ingest_meta);
+        self
+    }
+
+    /// Priority recorded at ingest, or the payload's default
+    pub fn priority(&self) -> Priority {
+        self.ingest_meta
+            .as_ref()
+            .and_then(|meta| meta.priority)
+            .unwrap_or_else(|| self.payload.default_priority())
+    }
+
+    /// Network recorded at ingest, if any
+    pub fn network_id(&self) -> Option<&str> {
+        self.ingest_meta
+            .as_ref()
+            .and_then(|meta| meta.network_id.as_deref())
+    }
+
+    pub fn is_in_network(&self, network_id: &str) -> bool {
+        self.network_id() == Some(network_id)
+    }
+
+    /// Time between the fix of the payload and the reception by the server
+    pub fn ingest_latency(&self) -> Option<chrono::Duration> {
+        self.ingest_meta
+            .as_ref()
+            .map(|meta| meta.received_at - self.payload.gps().timestamp)
+    }
+
+    /// Time between the fix of the payload and `now`. Negative for fixes dated in the future.
+    pub fn age(&self, now: DateTime<Utc>) -> chrono::Duration {
+        now - self.payload.gps().timestamp
+    }
+
+    /// Whether the message was older than `ttl` when received, or at `now` if it carries no
+    /// ingest metadata
+    pub fn is_expired(&self, ttl: chrono::Duration, now: DateTime<Utc>) -> bool {
+        self.age_at_reception(now) > ttl
+    }
+
+    pub(super) fn age_at_reception(&self, now: DateTime<Utc>) -> chrono::Duration {
+        self.age(
+            self.ingest_meta
+                .as_ref()
+                .map_or(now, |meta| meta.received_at),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{keys, Gps, MapperMsg, Payload};
+
+    #[test]
+    fn meta_is_not_encoded() {
+        let key = keys::file::File::create_key().unwrap();
+        let gps = Gps::rounded();
+        let meta = IngestMeta::new(gps.timestamp + chrono::Duration::seconds(3))
+            .with_gateway_count(2)
+            .with_ingest_node("ingest-1");
+        let msg = Message::from_payload_signed(&key, Payload::Gps(gps))
+            .unwrap()
+            .with_ingest_meta(meta.clone());
+        assert_eq!(msg.ingest_latency(), Some(chrono::Duration::seconds(3)));
+        assert_eq!(msg.priority(), Priority::Normal);
+        let urgent = msg
+            .clone()
+            .with_ingest_meta(meta.clone().with_priority(Priority::Urgent));
+        assert_eq!(urgent.priority(), Priority::Urgent);
+
+        let decoded = Message::try_from_with_signature_verification(
+            MapperMsg::try_from(msg.clone()).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(decoded.ingest_meta, None);
+        assert_eq!(decoded.with_ingest_meta(meta), msg);
+
+        let json = serde_json::to_string(&msg.ingest_meta).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Option<IngestMeta>>(&json).unwrap(),
+            msg.ingest_meta
+        );
+    }
+}
@@ -0,0 +1,352 @@
+//! Plumbing shared by ingest services: decode a MapperMsg, verify its signature, validate it
+//! against a policy and hand it to a handler.
+use super::{
+    AllowAllDevices, DecodeLimits, DeviceRegistry, Error, ExtendedMsg, InProcessVerifier, Message,
+    Result, VerifierBackend,
+};
+use bytes::Bytes;
+use helium_crypto::Network;
+use std::{collections::HashSet, future::Future};
+
+mod meta;
+pub use meta::IngestMeta;
+
+#[cfg(feature = "ingest-server")]
+pub mod server;
+
+/// Checks applied to messages whose signature has been verified
+pub trait Policy {
+    /// Returns `Error::PolicyRejected` if the message should not be ingested
+    fn validate(&self, msg: &Message) -> Result;
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct AcceptAll;
+
+impl Policy for AcceptAll {
+    fn validate(&self, _msg: &Message) -> Result {
+        Ok(())
+    }
+}
+
+impl<F: Fn(&Message) -> Result> Policy for F {
+    fn validate(&self, msg: &Message) -> Result {
+        self(msg)
+    }
+}
+
+/// Only admits messages tagged with one of the allowed networks
+#[derive(Debug, Default, Clone)]
+pub struct NetworkPolicy {
+    pub allowed: HashSet<String>,
+    /// Whether messages without a network id are admitted
+    pub allow_untagged: bool,
+}
+
+impl NetworkPolicy {
+    pub fn new<I: IntoIterator<Item = S>, S: Into<String>>(allowed: I) -> Self {
+        Self {
+            allowed: allowed.into_iter().map(Into::into).collect(),
+            allow_untagged: false,
+        }
+    }
+
+    pub fn allow_untagged(mut self, allow_untagged: bool) -> Self {
+        self.allow_untagged = allow_untagged;
+        self
+    }
+}
+
+impl Policy for NetworkPolicy {
+    fn validate(&self, msg: &Message) -> Result {
+        match msg.network_id() {
+            Some(network_id) if self.allowed.contains(network_id) => Ok(()),
+            None if self.allow_untagged => Ok(()),
+            Some(network_id) => Err(Error::PolicyRejected(format!(
+                "network {network_id} not allowed"
+            ))),
+            None => Err(Error::PolicyRejected("untagged network".to_string())),
+        }
+    }
+}
+
+/// Only admits messages signed by keys of the expected network, eg: to keep TestNet devices out
+/// of MainNet ingest
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KeyNetworkPolicy {
+    pub expected: Network,
+}
+
+impl KeyNetworkPolicy {
+    pub fn new(expected: Network) -> Self {
+        Self { expected }
+    }
+}
+
+impl Policy for KeyNetworkPolicy {
+    fn validate(&self, msg: &Message) -> Result {
+        let network = msg.pubkey.network;
+        if network == self.expected {
+            Ok(())
+        } else {
+            Err(Error::PolicyRejected(format!(
+                "key network {network:?} not allowed, expected {:?}",
+                self.expected
+            )))
+        }
+    }
+}
+
+/// Rejects messages received more than `ttl` after their fix with `Error::Expired`, so that
+/// stale store-and-forward deliveries can be told apart from invalid messages. The reception
+/// time is taken from `IngestMeta::received_at`, or the current time when there is none.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TtlPolicy {
+    pub ttl: chrono::Duration,
+}
+
+impl TtlPolicy {
+    pub fn new(ttl: chrono::Duration) -> Self {
+        Self { ttl }
+    }
+}
+
+impl Policy for TtlPolicy {
+    fn validate(&self, msg: &Message) -> Result {
+        let age = msg.age_at_reception(chrono::Utc::now());
+        if age > self.ttl {
+            Err(Error::Expired {
+                age_s: age.num_seconds(),
+                ttl_s: self.ttl.num_seconds(),
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Receives every message that passed verification and policy validation
+pub trait MessageHandler {
+    fn handle(&self, msg: Message) -> impl Future<Output = Result> + Send;
+}
+
+pub struct Ingestor<P = AcceptAll, V = InProcessVerifier, R = AllowAllDevices> {
+    pub policy: P,
+    pub verifier: V,
+    pub registry: R,
+    /// Applied to every message before and after its signature is verified
+    pub limits: DecodeLimits,
+}
+
+impl Default for Ingestor {
+    fn default() -> Self {
+        Self::new(AcceptAll)
+    }
+}
+
+impl<P: Policy> Ingestor<P> {
+    pub fn new(policy: P) -> Self {
+        Self {
+            policy,
+            verifier: InProcessVerifier,
+            registry: AllowAllDevices,
+            limits: DecodeLimits::DEFAULT,
+        }
+    }
+}
+
+impl<P: Policy, V: VerifierBackend, R: DeviceRegistry> Ingestor<P, V, R> {
+    pub fn with_verifier<V2: VerifierBackend>(self, verifier: V2) -> Ingestor<P, V2, R> {
+        Ingestor {
+            policy: self.policy,
+            verifier,
+            registry: self.registry,
+            limits: self.limits,
+        }
+    }
+
+    pub fn with_registry<R2: DeviceRegistry>(self, registry: R2) -> Ingestor<P, V, R2> {
+        Ingestor {
+            policy: self.policy,
+            verifier: self.verifier,
+            registry,
+            limits: self.limits,
+        }
+    }
+
+    pub fn with_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Checks the device, then decodes, verifies and validates a message
+    pub fn ingest(&self, msg: impl Into<ExtendedMsg>) -> Result<Message> {
+        let msg =
+            Message::try_from_with_registry(msg, &self.verifier, &self.registry, &self.limits)?;
+        self.policy.validate(&msg)?;
+        Ok(msg)
+    }
+
+    /// Same as `ingest` for an encoded MapperMsg held as `Bytes`, see `Message::decode_from_bytes`
+    pub fn ingest_bytes(&self, bytes: Bytes) -> Result<Message> {
+        self.ingest(ExtendedMsg::decode(bytes)?)
+    }
+
+    /// Ingests a message and passes it on to the handler
+    pub async fn ingest_into<H: MessageHandler>(
+        &self,
+        msg: impl Into<ExtendedMsg>,
+        handler: &H,
+    ) -> Result {
+        let msg = self.ingest(msg)?;
+        handler.handle(msg).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{keys, Gps, MapperMsg, Payload, ProtoMessage};
+
+    #[test]
+    fn policy_rejects() {
+        let key = keys::file::File::create_key().unwrap();
+        let msg: MapperMsg = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded()))
+            .unwrap()
+            .try_into()
+            .unwrap();
+
+        assert!(Ingestor::default().ingest(msg.clone()).is_ok());
+        let encoded = Bytes::from(msg.encode_to_vec());
+        assert!(Ingestor::default().ingest_bytes(encoded).is_ok());
+
+        let locked_only = Ingestor::new(|msg: &Message| {
+            if msg.payload.gps().is_locked() {
+                Ok(())
+            } else {
+                Err(Error::PolicyRejected("gps not locked".into()))
+            }
+        });
+        assert!(locked_only.ingest(msg.clone()).is_ok());
+
+        let reject_all = Ingestor::new(|_: &Message| Err(Error::PolicyRejected("closed".into())));
+        assert!(matches!(
+            reject_all.ingest(msg),
+            Err(Error::PolicyRejected(_))
+        ));
+    }
+
+    #[test]
+    fn extension_survives_ingest_from_bytes() {
+        let key = keys::file::File::create_key().unwrap();
+        let mut gps = Gps::rounded();
+        gps.h_acc_m = Some(rust_decimal::Decimal::new(3_25, 2));
+        let msg = Message::from_payload_signed(&key, Payload::Gps(gps)).unwrap();
+        assert!(matches!(
+            MapperMsg::try_from(msg.clone()),
+            Err(Error::PayloadExtensionDropped(_))
+        ));
+
+        let mut encoded = Vec::new();
+        msg.encode_to(&mut encoded).unwrap();
+        let ingested = Ingestor::default()
+            .ingest_bytes(Bytes::from(encoded))
+            .unwrap();
+        assert_eq!(ingested, msg);
+        assert_eq!(
+            Ingestor::default()
+                .ingest(ExtendedMsg::try_from(msg.clone()).unwrap())
+                .unwrap(),
+            msg
+        );
+    }
+
+    #[test]
+    fn limits_apply() {
+        let key = keys::file::File::create_key().unwrap();
+        let msg: MapperMsg = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded()))
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let strict =
+            Ingestor::default().with_limits(DecodeLimits::DEFAULT.with_max_signature_len(1));
+        assert!(matches!(
+            strict.ingest(msg),
+            Err(Error::SignatureTooLong { max: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn network_policy() {
+        let key = keys::file::File::create_key().unwrap();
+        let msg = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap();
+        let staging = msg
+            .clone()
+            .with_ingest_meta(IngestMeta::now().with_network_id("staging"));
+        assert!(staging.is_in_network("staging"));
+
+        let prod_only = NetworkPolicy::new(["prod"]);
+        assert!(matches!(
+            prod_only.validate(&staging),
+            Err(Error::PolicyRejected(_))
+        ));
+        assert!(prod_only.validate(&msg).is_err());
+        assert!(prod_only
+            .clone()
+            .allow_untagged(true)
+            .validate(&msg)
+            .is_ok());
+        assert!(NetworkPolicy::new(["prod", "staging"])
+            .validate(&staging)
+            .is_ok());
+    }
+
+    #[test]
+    fn ttl_policy_marks_stale_messages_expired() {
+        let key = keys::file::File::create_key().unwrap();
+        let gps = Gps::rounded();
+        let msg = Message::from_payload_signed(&key, Payload::Gps(gps)).unwrap();
+        let week = chrono::Duration::days(7);
+        let received = |after| {
+            msg.clone()
+                .with_ingest_meta(IngestMeta::new(gps.timestamp + after))
+        };
+
+        let policy = TtlPolicy::new(chrono::Duration::days(1));
+        assert!(policy
+            .validate(&received(chrono::Duration::hours(1)))
+            .is_ok());
+        let stale = received(week);
+        assert!(matches!(
+            policy.validate(&stale),
+            Err(Error::Expired {
+                age_s: 604_800,
+                ttl_s: 86_400
+            })
+        ));
+        assert!(stale.is_expired(policy.ttl, chrono::Utc::now()));
+        assert_eq!(msg.age(gps.timestamp + week), week);
+        // without metadata the current time is used, and 2023 is long gone
+        assert!(policy.validate(&msg).is_err());
+    }
+
+    #[test]
+    fn key_network_policy() {
+        let key = keys::file::File::create_key_with(helium_crypto::KeyTag {
+            network: Network::TestNet,
+            key_type: helium_crypto::KeyType::EccCompact,
+        })
+        .unwrap();
+        let msg: MapperMsg = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded()))
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert!(Ingestor::new(KeyNetworkPolicy::new(Network::TestNet))
+            .ingest(msg.clone())
+            .is_ok());
+        assert!(matches!(
+            Ingestor::new(KeyNetworkPolicy::new(Network::MainNet)).ingest(msg),
+            Err(Error::PolicyRejected(_))
+        ));
+    }
+}
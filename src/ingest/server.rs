@@ -0,0 +1,111 @@
+//! Skeleton for tonic based ingest servers. The service handler of an ingestor delegates to
+//! `IngestServer::submit`, which performs decode, signature verification and policy validation
+//! before calling the user provided `MessageHandler`.
+//!
+//! Submissions are taken as the encoded MapperMsg rather than the `MapperMsg` the generated
+//! helium-proto service decodes: prost drops the payload extension, which the signature covers,
+//! so serve the method with `BytesCodec` for messages that have one to verify.
+use super::{Ingestor, MessageHandler, Policy};
+use crate::{AllowAllDevices, DeviceRegistry, Error, VerifierBackend};
+use bytes::{Buf, Bytes};
+use tonic::{
+    codec::{Codec, DecodeBuf, Decoder, EncodeBuf, Encoder},
+    Request, Response, Status,
+};
+
+pub struct IngestServer<H, P, V, R = AllowAllDevices> {
+    ingestor: Ingestor<P, V, R>,
+    handler: H,
+}
+
+impl<H, P, V, R> IngestServer<H, P, V, R>
+where
+    H: MessageHandler + Send + Sync + 'static,
+    P: Policy + Send + Sync + 'static,
+    V: VerifierBackend + Send + Sync + 'static,
+    R: DeviceRegistry + Send + Sync + 'static,
+{
+    pub fn new(ingestor: Ingestor<P, V, R>, handler: H) -> Self {
+        Self { ingestor, handler }
+    }
+
+    /// Handles a submission of an encoded MapperMsg, replying with the default response on
+    /// success
+    pub async fn submit<Resp: Default>(
+        &self,
+        request: Request<Bytes>,
+    ) -> std::result::Result<Response<Resp>, Status> {
+        let msg = self
+            .ingestor
+            .ingest_bytes(request.into_inner())
+            .map_err(to_status)?;
+        self.handler.handle(msg).await.map_err(to_status)?;
+        Ok(Response::new(Resp::default()))
+    }
+
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+}
+
+/// Passes request bodies through as the bytes received and encodes responses with prost
+#[derive(Debug, Clone, Copy)]
+pub struct BytesCodec<Resp>(std::marker::PhantomData<Resp>);
+
+impl<Resp> Default for BytesCodec<Resp> {
+    fn default() -> Self {
+        Self(std::marker::PhantomData)
+    }
+}
+
+impl<Resp: prost::Message + Send + 'static> Codec for BytesCodec<Resp> {
+    type Encode = Resp;
+    type Decode = Bytes;
+    type Encoder = ProstEncoder<Resp>;
+    type Decoder = BytesDecoder;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        ProstEncoder(std::marker::PhantomData)
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        BytesDecoder
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ProstEncoder<Resp>(std::marker::PhantomData<Resp>);
+
+impl<Resp: prost::Message> Encoder for ProstEncoder<Resp> {
+    type Item = Resp;
+    type Error = Status;
+
+    fn encode(&mut self, item: Resp, dst: &mut EncodeBuf<'_>) -> std::result::Result<(), Status> {
+        item.encode(dst)
+            .map_err(|error| Status::internal(error.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BytesDecoder;
+
+impl Decoder for BytesDecoder {
+    type Item = Bytes;
+    type Error = Status;
+
+    fn decode(&mut self, src: &mut DecodeBuf<'_>) -> std::result::Result<Option<Bytes>, Status> {
+        Ok(Some(src.copy_to_bytes(src.remaining())))
+    }
+}
+
+/// Maps crate errors to the gRPC status reported to the submitter
+pub fn to_status(error: Error) -> Status {
+    match error {
+        Error::PolicyRejected(_) | Error::DeviceRejected(_) => {
+            Status::permission_denied(error.to_string())
+        }
+        Error::SignatureVerification { .. } => Status::unauthenticated(error.to_string()),
+        Error::VerifierBackend(_) | Error::Handler(_) => Status::internal(error.to_string()),
+        _ => Status::invalid_argument(error.to_string()),
+    }
+}
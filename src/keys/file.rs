@@ -1,4 +1,5 @@
 use super::KeyTrait;
+use crate::SignedBytes;
 use helium_crypto::{KeyTag, KeyType, Network};
 
 use rand::rngs::OsRng;
@@ -9,6 +10,12 @@ use std::{
     sync::Arc,
 };
 
+/// Key created when none is asked for explicitly
+pub const DEFAULT_KEY_TAG: KeyTag = KeyTag {
+    network: Network::MainNet,
+    key_type: KeyType::EccCompact,
+};
+
 #[derive(Clone)]
 pub struct File {
     pub keypair: Arc<helium_crypto::Keypair>,
@@ -24,9 +31,12 @@ pub enum Error {
     IoKeypairRead(std::io::Error),
     #[error("io error writing keypair: {0}")]
     IoKeypairWrite(std::io::Error),
+    #[error("keypair is {found:?}, expected {expected:?}")]
+    UnexpectedKeyTag { expected: KeyTag, found: KeyTag },
 }
 
 impl File {
+    /// Loads a keypair of any type, creating a `DEFAULT_KEY_TAG` one if the file is empty
     pub fn load(path: &Path) -> Result<File, Error> {
         let data = fs::read(path).map_err(Error::IoKeypairRead)?;
         if data.is_empty() {
@@ -36,6 +46,27 @@ impl File {
         }
     }
 
+    /// Loads a keypair that must have `key_tag`, creating one if the file is empty
+    pub fn load_with(path: &Path, key_tag: KeyTag) -> Result<File, Error> {
+        let data = fs::read(path).map_err(Error::IoKeypairRead)?;
+        if data.is_empty() {
+            return Self::create_and_save_key_with(path, key_tag);
+        }
+        let file: File = helium_crypto::Keypair::try_from(&data[..])?.into();
+        let found = file.key_tag();
+        if found != key_tag {
+            return Err(Error::UnexpectedKeyTag {
+                expected: key_tag,
+                found,
+            });
+        }
+        Ok(file)
+    }
+
+    pub fn key_tag(&self) -> KeyTag {
+        self.keypair.key_tag()
+    }
+
     fn save(keypair: &helium_crypto::Keypair, path: &Path) -> Result<(), Error> {
         if let Some(parent) = path::PathBuf::from(path).parent() {
             fs::create_dir_all(parent).map_err(Error::IoKeypairWrite)?;
@@ -45,25 +76,21 @@ impl File {
     }
 
     pub fn create_key() -> Result<File, Error> {
-        Ok(helium_crypto::Keypair::generate(
-            KeyTag {
-                network: Network::MainNet,
-                key_type: KeyType::Secp256k1,
-            },
-            &mut OsRng,
-        )
-        .into())
+        Self::create_key_with(DEFAULT_KEY_TAG)
+    }
+
+    pub fn create_key_with(key_tag: KeyTag) -> Result<File, Error> {
+        Ok(helium_crypto::Keypair::generate(key_tag, &mut OsRng).into())
     }
 
     pub fn create_and_save_key(path: &path::Path) -> Result<File, Error> {
-        let file = Self::create_key()?;
-        Self::save(&file.keypair, path)?;
-        Ok(file)
+        Self::create_and_save_key_with(path, DEFAULT_KEY_TAG)
     }
 
-    pub fn sign(&self, msg: &[u8]) -> Result<Vec<u8>, Error> {
-        use helium_crypto::Sign;
-        Ok(self.keypair.sign(msg)?)
+    pub fn create_and_save_key_with(path: &path::Path, key_tag: KeyTag) -> Result<File, Error> {
+        let file = Self::create_key_with(key_tag)?;
+        Self::save(&file.keypair, path)?;
+        Ok(file)
     }
 }
 
@@ -82,8 +109,32 @@ impl KeyTrait for File {
         Ok(self.keypair.public_key().clone())
     }
 
-    fn sign(&self, msg: &[u8]) -> Result<Vec<u8>, Self::Error> {
+    fn sign_bytes(&self, msg: &SignedBytes) -> Result<Vec<u8>, Self::Error> {
         use helium_crypto::Sign;
-        Ok(self.keypair.sign(msg)?)
+        Ok(self.keypair.sign(msg.as_bytes())?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn key_tag_selection() {
+        assert_eq!(File::create_key().unwrap().key_tag(), DEFAULT_KEY_TAG);
+        let testnet = KeyTag {
+            network: Network::TestNet,
+            key_type: KeyType::Secp256k1,
+        };
+        let path = std::env::temp_dir().join(format!("spot-messages-key-{}", std::process::id()));
+        fs::write(&path, []).unwrap();
+        let created = File::load_with(&path, testnet).unwrap();
+        assert_eq!(created.key_tag(), testnet);
+        assert_eq!(File::load(&path).unwrap().key_tag(), testnet);
+        assert!(matches!(
+            File::load_with(&path, DEFAULT_KEY_TAG),
+            Err(Error::UnexpectedKeyTag { .. })
+        ));
+        fs::remove_file(path).unwrap();
     }
 }
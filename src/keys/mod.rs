@@ -1,3 +1,4 @@
+use crate::SignedBytes;
 use std::result::Result;
 
 pub mod file;
@@ -5,5 +6,8 @@ pub mod file;
 pub trait KeyTrait {
     type Error: core::fmt::Debug + core::fmt::Display;
     fn pubkey(&self) -> Result<helium_crypto::public_key::PublicKey, Self::Error>;
-    fn sign(&self, msg: &[u8]) -> Result<Vec<u8>, Self::Error>;
+
+    /// Signs the canonical encoding of a message. There is no raw byte signing so a key can't
+    /// produce a signature over bytes the verifier will never reconstruct.
+    fn sign_bytes(&self, msg: &SignedBytes) -> Result<Vec<u8>, Self::Error>;
 }
@@ -0,0 +1,155 @@
+use super::{Deserialize, Error, Message, Payload, Serialize};
+use std::collections::HashMap;
+
+/// Fieldless discriminant of `Payload`, for routing messages without matching on the payload.
+/// Every kind exists whatever the enabled features, since kinds are also wire names.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadKind {
+    CellAttach,
+    CellScan,
+    Beacon,
+    Gps,
+    Position,
+}
+
+impl PayloadKind {
+    pub const ALL: [PayloadKind; 5] = [
+        PayloadKind::CellAttach,
+        PayloadKind::CellScan,
+        PayloadKind::Beacon,
+        PayloadKind::Gps,
+        PayloadKind::Position,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PayloadKind::CellAttach => "cell_attach",
+            PayloadKind::CellScan => "cell_scan",
+            PayloadKind::Beacon => "beacon",
+            PayloadKind::Gps => "gps",
+            PayloadKind::Position => "position",
+        }
+    }
+}
+
+impl std::fmt::Display for PayloadKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for PayloadKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        PayloadKind::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == s)
+            .ok_or_else(|| Error::UnexpectedPayloadKindStr(s.into()))
+    }
+}
+
+impl Payload {
+    pub fn kind(&self) -> PayloadKind {
+        match self {
+            #[cfg(feature = "cell")]
+            Payload::CellAttach(_) => PayloadKind::CellAttach,
+            #[cfg(feature = "cell")]
+            Payload::CellScan(_) => PayloadKind::CellScan,
+            #[cfg(feature = "beacon")]
+            Payload::Beacon(_) => PayloadKind::Beacon,
+            Payload::Gps(_) => PayloadKind::Gps,
+            Payload::Position(_) => PayloadKind::Position,
+        }
+    }
+}
+
+impl Message {
+    pub fn kind(&self) -> PayloadKind {
+        self.payload.kind()
+    }
+}
+
+/// Anything that can be routed by its payload kind
+pub trait HasPayloadKind {
+    fn payload_kind(&self) -> PayloadKind;
+}
+
+impl HasPayloadKind for Payload {
+    fn payload_kind(&self) -> PayloadKind {
+        self.kind()
+    }
+}
+
+impl HasPayloadKind for Message {
+    fn payload_kind(&self) -> PayloadKind {
+        self.kind()
+    }
+}
+
+impl<T: HasPayloadKind> HasPayloadKind for &T {
+    fn payload_kind(&self) -> PayloadKind {
+        T::payload_kind(self)
+    }
+}
+
+pub trait PayloadKindIterExt: Iterator + Sized
+where
+    Self::Item: HasPayloadKind,
+{
+    /// Keeps only the items of the given kind
+    fn of_kind(self, kind: PayloadKind) -> impl Iterator<Item = Self::Item> {
+        self.filter(move |item| item.payload_kind() == kind)
+    }
+
+    /// Splits the items into one Vec per kind. Kinds that were not seen are absent.
+    fn partition_by_kind(self) -> HashMap<PayloadKind, Vec<Self::Item>> {
+        let mut partitions: HashMap<PayloadKind, Vec<Self::Item>> = HashMap::new();
+        for item in self {
+            partitions
+                .entry(item.payload_kind())
+                .or_default()
+                .push(item);
+        }
+        partitions
+    }
+}
+
+impl<I> PayloadKindIterExt for I
+where
+    I: Iterator,
+    I::Item: HasPayloadKind,
+{
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Beacon, CellScan, Gps};
+
+    #[test]
+    fn kind_str_roundtrip() {
+        for kind in PayloadKind::ALL {
+            assert_eq!(kind, kind.to_string().parse::<PayloadKind>().unwrap());
+        }
+        assert!("telemetry".parse::<PayloadKind>().is_err());
+    }
+
+    #[test]
+    fn partition_payloads() {
+        let payloads = vec![
+            Payload::Gps(Gps::rounded()),
+            Payload::CellScan(CellScan::random()),
+            Payload::Beacon(Beacon::new(Gps::rounded(), vec![0xAB, 0xCD])),
+            Payload::Gps(Gps::rounded()),
+        ];
+        assert_eq!(payloads.iter().of_kind(PayloadKind::Gps).count(), 2);
+
+        let partitions = payloads.into_iter().partition_by_kind();
+        assert_eq!(partitions[&PayloadKind::Gps].len(), 2);
+        assert_eq!(partitions[&PayloadKind::CellScan].len(), 1);
+        assert_eq!(partitions[&PayloadKind::Beacon].len(), 1);
+        assert!(!partitions.contains_key(&PayloadKind::CellAttach));
+    }
+}
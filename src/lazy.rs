@@ -0,0 +1,188 @@
+//! Decode now, verify later. `LazyVerified` keeps the signed bytes captured at decode time, so
+//! that verifying a deferred or sampled message doesn't encode its payload again, and only hands
+//! out the message once verified, as a `Verified`. Decoded from bytes, it keeps the payload bytes
+//! as received, so a payload with fields this crate doesn't know still verifies.
+use super::{
+    session::{Linkage, LinkageValidator},
+    Deserialize, Error, ExtendedMsg, InProcessVerifier, MapperMsg, Message, Result, Serialize,
+    SignedBytes, UnverifiedMsg, VerifierBackend,
+};
+
+/// A decoded message whose signature has not been checked yet
+#[derive(Debug, Clone, PartialEq)]
+pub struct LazyVerified<T> {
+    inner: T,
+    signed_bytes: SignedBytes,
+}
+
+/// A message whose signature was checked
+#[derive(Debug, Clone, PartialEq)]
+pub struct Verified<T>(T);
+
+/// Findings of `LazyVerified::verify_with_report` that don't fail verification but should
+/// lower trust in the message
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerificationReport {
+    /// Set when a `LinkageValidator` ran on a CellAttach
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub linkage: Option<Linkage>,
+}
+
+impl VerificationReport {
+    pub fn is_clean(&self) -> bool {
+        !matches!(self.linkage, Some(Linkage::Dangling { .. }))
+    }
+}
+
+impl LazyVerified<Message> {
+    /// Decodes a MapperMsg without verifying it, keeping its payload bytes for verification
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let signed_bytes =
+            SignedBytes::from_received_payload(ExtendedMsg::received_payload_bytes(bytes)?);
+        Ok(Self {
+            inner: ExtendedMsg::decode(bytes)?.try_into()?,
+            signed_bytes,
+        })
+    }
+
+    pub fn verify(self) -> Result<Verified<Message>> {
+        self.verify_with(&InProcessVerifier)
+    }
+
+    pub fn verify_with<V: VerifierBackend + ?Sized>(
+        self,
+        verifier: &V,
+    ) -> Result<Verified<Message>> {
+        verifier.verify(
+            &self.inner.pubkey,
+            &self.signed_bytes,
+            self.inner.signature.as_bytes(),
+        )?;
+        Ok(Verified(self.inner))
+    }
+
+    /// Verifies the signature, then runs the optional checks. Only the signature and session
+    /// store errors fail; the checks' findings are in the report.
+    pub fn verify_with_report<V: VerifierBackend + ?Sized>(
+        self,
+        verifier: &V,
+        linkage: Option<&LinkageValidator<'_>>,
+    ) -> Result<(Verified<Message>, VerificationReport)> {
+        let verified = self.verify_with(verifier)?;
+        let report = VerificationReport {
+            linkage: match linkage {
+                Some(validator) => validator.check(&verified)?,
+                None => None,
+            },
+        };
+        Ok((verified, report))
+    }
+}
+
+impl<T> LazyVerified<T> {
+    /// The message as decoded, for pipelines that skip verification of this one. Nothing it
+    /// carries can be trusted.
+    pub fn peek_unverified(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn into_unverified(self) -> T {
+        self.inner
+    }
+
+    /// The bytes the signature covers
+    pub fn signed_bytes(&self) -> &SignedBytes {
+        &self.signed_bytes
+    }
+}
+
+/// Decodes under `DecodeLimits::DEFAULT`, without verifying the signature
+impl TryFrom<MapperMsg> for LazyVerified<Message> {
+    type Error = Error;
+
+    fn try_from(value: MapperMsg) -> Result<Self> {
+        ExtendedMsg::from(value).try_into()
+    }
+}
+
+/// Without the received bytes the signed bytes are the encoding of the decoded payload, which
+/// leaves out fields unknown to helium-proto. Use `LazyVerified::decode` to keep them.
+impl TryFrom<ExtendedMsg> for LazyVerified<Message> {
+    type Error = Error;
+
+    fn try_from(value: ExtendedMsg) -> Result<Self> {
+        let unverified = UnverifiedMsg::try_from(value)?;
+        let signed_bytes = unverified.signed_bytes();
+        Ok(Self {
+            inner: unverified.into_message()?,
+            signed_bytes,
+        })
+    }
+}
+
+impl<T> Verified<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> std::ops::Deref for Verified<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{keys, EnvelopeSig, Gps, Payload, ProtoMessage};
+
+    #[test]
+    fn verifies_on_demand() {
+        let key = keys::file::File::create_key().unwrap();
+        let msg = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap();
+        let mut bytes = Vec::new();
+        msg.encode_to(&mut bytes).unwrap();
+
+        let lazy = LazyVerified::decode(&bytes).unwrap();
+        assert_eq!(lazy.peek_unverified(), &msg);
+        assert_eq!(
+            lazy.signed_bytes(),
+            &SignedBytes::from_payload(&msg.payload).unwrap()
+        );
+        let verified = lazy.verify().unwrap();
+        assert_eq!(verified.payload, msg.payload);
+
+        let mut forged = msg;
+        forged.signature = EnvelopeSig::from(vec![0; 64]);
+        let lazy = LazyVerified::try_from(MapperMsg::try_from(forged).unwrap()).unwrap();
+        assert!(lazy.verify().is_err());
+    }
+
+    #[test]
+    fn verifies_over_received_payload_bytes() {
+        use crate::keys::KeyTrait;
+        let key = keys::file::File::create_key().unwrap();
+        let mut msg = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap();
+        // a field added upstream, 99: varint 1, in a payload signed by newer firmware
+        let unknown = [0x98, 0x06, 0x01];
+        let mut payload = msg.payload.to_proto().encode_to_vec();
+        payload.extend_from_slice(&unknown);
+        msg.signature = key
+            .sign_bytes(&SignedBytes::from_received_payload(payload))
+            .unwrap()
+            .into();
+        let mut bytes = Vec::new();
+        msg.encode_to(&mut bytes).unwrap();
+        // msg_v1 { payload { 99: 1 } }, merged into the message by decoders
+        bytes.extend_from_slice(&[0x0A, 0x05, 0x0A, 0x03]);
+        bytes.extend_from_slice(&unknown);
+
+        assert!(Message::decode_from_with_signature_verification(&bytes).is_err());
+        let lazy = LazyVerified::decode(&bytes).unwrap();
+        assert_eq!(lazy.peek_unverified(), &msg);
+        assert_eq!(lazy.verify().unwrap().into_inner(), msg);
+    }
+}
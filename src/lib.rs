@@ -1,5 +1,42 @@
+//! Messages exchanged with spotmapper devices, in their proto and LoRa encodings.
+//!
+//! A mapper signs each payload with its key. Over the internet the payload travels as a signed
+//! proto `Message`:
+//!
+//! ```
+//! use spot_messages::{keys, Gps, Message, Payload, ProtoMessage};
+//!
+//! let key = keys::file::File::create_key().unwrap();
+//! let msg = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap();
+//! let bytes = msg.to_proto().encode_to_vec();
+//!
+//! let received = Message::decode_from_with_signature_verification(&bytes).unwrap();
+//! assert_eq!(received, msg);
+//! ```
+//!
+//! Over LoRa it travels as a fixed layout frame followed by the signature over it, verified
+//! against the pubkey the device was onboarded with:
+//!
+//! ```
+//! # #[cfg(feature = "beacon")] {
+//! use spot_messages::{keys::{self, KeyTrait}, Beacon, Gps, LoraDecode, LoraEncode};
+//!
+//! let key = keys::file::File::create_key().unwrap();
+//! let beacon = Beacon::new(Gps::rounded(), vec![0xAB, 0xCD]);
+//! let frame = beacon.to_lora_bytes_with_signature(&key).unwrap();
+//!
+//! let pubkey = key.pubkey().unwrap();
+//! let received = Beacon::from_lora_slice_with_verified_signature(&pubkey, &frame).unwrap();
+//! assert_eq!(received.gps, beacon.gps);
+//! # }
+//! ```
+//!
+//! `Gps::rounded`, a fix the LoRa frames carry exactly, and the `random` constructors of the
+//! payloads serve as fixtures. See `examples/` for complete workflows, eg: `attach_lifecycle`
+//! from scan to attach statistics.
+use bytes::{Buf, BufMut, Bytes};
 use chrono::{prelude::*, DateTime, NaiveDateTime};
-pub use helium_proto::{self, EncodeError, Message as ProtoMessage};
+pub use helium_proto::{self, DecodeError, EncodeError, Message as ProtoMessage};
 use serde::{Deserialize, Serialize};
 
 use helium_proto::{mapper_payload, MapperMsg, MapperMsgV1};
@@ -7,45 +44,265 @@ use helium_proto::{mapper_payload, MapperMsg, MapperMsgV1};
 pub use helium_crypto;
 use helium_crypto::{public_key::PublicKey, Verify};
 
+#[cfg(feature = "cell")]
 mod cell_attach;
+#[cfg(feature = "cell")]
 pub use cell_attach::*;
 
 pub mod gps;
-pub use gps::Gps;
+pub use gps::{Gps, JammingState, SpoofingState};
 
+pub mod nmea;
+
+pub mod ubx;
+
+#[cfg(feature = "cell")]
 mod cell_scan;
+#[cfg(feature = "cell")]
 pub use cell_scan::*;
 
+mod position;
+pub use position::*;
+
+#[cfg(feature = "cell")]
+mod plmn;
+#[cfg(feature = "cell")]
+pub use plmn::{Plmn, PlmnPacking};
+
+#[cfg(feature = "cell")]
+mod sim;
+#[cfg(feature = "cell")]
+pub use sim::*;
+
+#[cfg(feature = "csv")]
+pub mod scan_csv;
+
 pub mod keys;
 
+pub mod region;
+
+pub mod config;
+
+pub mod capabilities;
+
+#[cfg(feature = "h3")]
+pub mod resolution;
+#[cfg(feature = "h3")]
+pub use resolution::{CellRole, ResolutionPolicy};
+
+#[cfg(feature = "geo")]
+pub mod geometry;
+
+#[cfg(feature = "h3")]
+mod cell_at;
+#[cfg(feature = "h3")]
+pub use cell_at::{CellAt, CoverageCell, DedupeCell};
+
+mod h3_index;
+pub use h3_index::H3Index;
+
 mod lora_gw;
 pub use lora_gw::*;
 
+mod witnesses;
+pub use witnesses::Witnesses;
+#[cfg(feature = "h3")]
+pub use witnesses::{Diversity, SelfWitness, SELF_WITNESS_DISTANCE_M};
+
+pub mod attribution;
+
+pub mod eligibility;
+
+pub mod anomaly;
+
+pub mod fleet;
+
+pub mod sanity;
+
+mod rounding;
+pub use rounding::Rounding;
+
+mod data_rate;
+pub use data_rate::DataRateExt;
+
+pub mod gateway;
+
 mod lora_payload;
-pub use lora_payload::IntoFromLoraPayload;
+pub use lora_payload::{
+    sign_lora_frame, verify_lora_frame, DumpFields, EncodeMode, FieldDump, IntoFromLoraPayload,
+    LoraDecode, LoraEncode,
+};
 
 mod ports;
 pub use ports::*;
 
+#[cfg(feature = "beacon")]
 mod beacon;
+#[cfg(feature = "beacon")]
 pub use beacon::*;
 
+#[cfg(feature = "beacon")]
+pub mod burst;
+
+#[cfg(feature = "beacon-mac")]
+pub mod beacon_mac;
+
+mod signed_bytes;
+pub use signed_bytes::SignedBytes;
+
+pub mod proto_ext;
+pub use proto_ext::ExtendedMsg;
+
+mod signatures;
+pub use signatures::{EnvelopeSig, TruncatedDeviceSig};
+
+mod limits;
+pub use limits::DecodeLimits;
+
+pub mod verifier;
+pub use verifier::{InProcessVerifier, VerifierBackend, VerifyRequest};
+
+mod lazy;
+pub use lazy::{LazyVerified, VerificationReport, Verified};
+
+pub mod device_registry;
+pub use device_registry::{AllowAllDevices, DeviceRegistry};
+
+pub mod ingest;
+pub use ingest::IngestMeta;
+
+pub mod quarantine;
+
+pub mod export;
+
+#[cfg(feature = "h3")]
+pub mod redaction;
+
+pub mod compression;
+
+#[cfg(feature = "raw-dump")]
+pub mod raw_dump;
+
+pub mod pipeline;
+
+#[cfg(all(feature = "cell", feature = "h3"))]
+pub mod analytics;
+
+pub mod propagation;
+
+#[cfg(feature = "cell")]
+pub mod compat;
+
+pub mod satellite;
+
+pub mod epoch;
+
+pub mod clock;
+
+pub mod proto_version;
+
+#[cfg(feature = "proto-json")]
+pub mod proto_json;
+
+#[cfg(feature = "msgpack")]
+pub mod msgpack;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+pub mod registry;
+
+#[cfg(feature = "beacon")]
+pub mod selftest;
+
+pub mod planner;
+
+#[cfg(feature = "cell")]
+pub mod workflow;
+
+#[cfg(feature = "cell")]
+pub mod counter_store;
+
+pub mod session;
+
+#[cfg(feature = "influx")]
+pub mod influx;
+
+#[cfg(feature = "ws")]
+pub mod ws;
+
+mod kind;
+pub use kind::*;
+
+mod priority;
+pub use priority::Priority;
+
+mod unsigned;
+pub use unsigned::Unsigned;
+
+mod id;
+pub use id::{Uuid, MESSAGE_ID_NAMESPACE};
+
+mod serde_helpers;
+
+mod error_report;
+pub use error_report::ErrorReport;
+
 pub type Result<T = ()> = std::result::Result<T, Error>;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Payload {
+    #[cfg(feature = "cell")]
     CellAttach(CellAttach),
+    #[cfg(feature = "cell")]
     CellScan(CellScan),
+    #[cfg(feature = "beacon")]
     Beacon(Beacon),
     Gps(Gps),
+    Position(Position),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub payload: Payload,
-    pub signature: Vec<u8>,
+    pub signature: EnvelopeSig,
+    #[serde(with = "serde_helpers::pubkey")]
     pub pubkey: PublicKey,
-    pub lora_gws: Vec<LoraGw>,
+    pub lora_gws: Witnesses,
+    /// Server side metadata, not covered by the signature
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ingest_meta: Option<ingest::IngestMeta>,
+    /// The encoded MapperMsg as received, when decoded with `Message::decode_retaining`. See
+    /// `Message::forward_bytes`.
+    #[serde(skip)]
+    original_bytes: Option<Retained>,
+}
+
+/// The retained bytes are how the message arrived rather than part of its value
+impl PartialEq for Message {
+    fn eq(&self, other: &Self) -> bool {
+        self.payload == other.payload
+            && self.signature == other.signature
+            && self.pubkey == other.pubkey
+            && self.lora_gws == other.lora_gws
+            && self.ingest_meta == other.ingest_meta
+    }
+}
+
+/// Bytes a message was decoded from, with the digest of its own encoding at the time so that
+/// later changes to the message can be told
+#[derive(Debug, Clone)]
+struct Retained {
+    bytes: Bytes,
+    encoding_digest: [u8; 32],
+}
+
+impl Retained {
+    fn digest(encoding: &[u8]) -> [u8; 32] {
+        use sha2::Digest;
+        sha2::Sha256::digest(encoding).into()
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -54,6 +311,14 @@ pub enum Error {
     ParseInt(#[from] std::num::ParseIntError),
     #[error("unexpected attach result str: {0}")]
     UnexpectedAttachResultStr(String),
+    #[error("unexpected payload kind str: {0}")]
+    UnexpectedPayloadKindStr(String),
+    #[error("expected {expected} payload, found {found}")]
+    UnexpectedPayloadKind {
+        expected: PayloadKind,
+        found: PayloadKind,
+    },
+    #[cfg(feature = "h3")]
     #[error("h3o: {0}")]
     H3oInvalidLatLong(#[from] h3o::error::InvalidLatLng),
     #[error("invalid attach result value: {value}")]
@@ -75,14 +340,306 @@ pub enum Error {
     },
     #[error("helium proto encode error: {0}")]
     HeliumProtoEncode(#[from] EncodeError),
+    #[error("helium proto decode error: {0}")]
+    HeliumProtoDecode(#[from] DecodeError),
     #[error("key error: {0}")]
     Key(String), // String avoids making all of these API require the KeyTrait definition
-    #[error("invalid vec size for parsing payload \"{payload}\": {size}")]
-    InvalidVecForParsingLoraPayload { payload: &'static str, size: usize },
+    #[error("verifier backend error: {0}")]
+    VerifierBackend(String),
+    #[error("rejected by policy: {0}")]
+    PolicyRejected(String),
+    #[error("message handler error: {0}")]
+    Handler(String),
+    #[error("invalid vec size for parsing payload \"{payload}\": {size}, expected {expected}")]
+    InvalidVecForParsingLoraPayload {
+        payload: &'static str,
+        size: usize,
+        expected: usize,
+    },
+    #[cfg(feature = "h3")]
     #[error("h3o: {0}")]
     H3oInvalidCellIndex(#[from] h3o::error::InvalidCellIndex),
+    #[error("invalid h3 cell index: {0:x}")]
+    InvalidH3Index(u64),
     #[error("invalid datarate: {0}")]
     InvalidDatarate(i32),
+    #[error("no pubkey known for gateway eui {0:016x}")]
+    UnknownGatewayEui(u64),
+    #[error("invalid datarate DR{dr} for region {region}")]
+    InvalidRegionalDatarate { region: region::Region, dr: u8 },
+    #[error("unexpected region str: {0}")]
+    UnexpectedRegionStr(String),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid quarantine frame: {0}")]
+    InvalidQuarantineFrame(&'static str),
+    #[error("attach delay {delay} exceeds {max}")]
+    InvalidAttachDelay { delay: u32, max: u32 },
+    #[error("candidate cell {cell_id} on earfcn {earfcn} is not in scan {from_scan}")]
+    CandidateNotInScan {
+        from_scan: u32,
+        cell_id: u64,
+        earfcn: u32,
+    },
+    #[error("value {value} out of range for field \"{field}\"")]
+    OutOfRange { field: &'static str, value: i128 },
+    #[error("invalid csv line: {0}")]
+    InvalidCsvLine(String),
+    #[error("missing required csv field \"{0}\"")]
+    MissingCsvField(&'static str),
+    #[cfg(feature = "csv")]
+    #[error("csv: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("unknown earfcn: {0}")]
+    UnknownEarfcn(u32),
+    #[error("position estimate is not a gnss fix: {0:?}")]
+    NotGnssPosition(PositionMethod),
+    #[error("timestamp out of range: {0}")]
+    TimestampOutOfRange(i64),
+    #[error("device rejected by registry: {0}")]
+    DeviceRejected(String),
+    #[error("invalid imsi")]
+    InvalidImsi,
+    #[error("no lora data rate for sf{spreading_factor} bw{bandwidth_khz}")]
+    InvalidLoraModulation {
+        spreading_factor: u8,
+        bandwidth_khz: u32,
+    },
+    #[error("{count} witnesses exceed the max of {max}")]
+    TooManyWitnesses { count: usize, max: usize },
+    #[error("invalid physical cell id: {0}")]
+    InvalidPci(u64),
+    #[error("invalid bandwidth, expected kHz: {0}")]
+    InvalidBandwidth(u32),
+    #[error("invalid frequency: {0} MHz")]
+    InvalidFrequency(rust_decimal::Decimal),
+    #[error("frequency {frequency} is outside of {region}")]
+    FrequencyOutOfRegion {
+        frequency: FrequencyHz,
+        region: region::Region,
+    },
+    #[error("no {kind} encoding in the {profile:?} payload profile")]
+    UnsupportedInProfile {
+        profile: satellite::PayloadProfile,
+        kind: PayloadKind,
+    },
+    #[error("no encoding fits in {max_bytes} bytes with the required fields")]
+    NoEncodingWithinBudget { max_bytes: usize },
+    #[cfg(feature = "cell")]
+    #[error("{event} is not valid in the {state:?} state")]
+    InvalidTransition {
+        state: workflow::WorkflowState,
+        event: &'static str,
+    },
+    #[error("invalid counter file: {0:?}")]
+    InvalidCounterFile(String),
+    #[error("{0} payloads are not compiled in")]
+    PayloadKindNotCompiled(PayloadKind),
+    #[error("{count} scan results exceed the max of {max}")]
+    TooManyScanResults { count: usize, max: usize },
+    #[error("signature of {len} bytes exceeds the max of {max}")]
+    SignatureTooLong { len: usize, max: usize },
+    #[error("unknown epoch id: {0}")]
+    UnknownEpoch(u8),
+    #[error("epoch id {0} is already registered for another epoch")]
+    EpochConflict(u8),
+    #[error("unsupported {payload} payload version: {version}")]
+    UnsupportedPayloadVersion { payload: &'static str, version: u8 },
+    #[error("{payload} field \"{field}\" at bit {bit_offset} (byte {}) has invalid raw value {raw}", .bit_offset / 8)]
+    InvalidLoraField {
+        payload: &'static str,
+        field: &'static str,
+        bit_offset: usize,
+        raw: u64,
+    },
+    #[error("scan result {index}: {source}")]
+    InvalidScanResult { index: usize, source: Box<Error> },
+    #[error("witness {index}: {source}")]
+    InvalidWitness { index: usize, source: Box<Error> },
+    #[error("self-test failed: {0}")]
+    SelfTestFailed(&'static str),
+    #[error("invalid plmn: {0}")]
+    InvalidPlmn(String),
+    #[error("expected message {expected}, found {found}")]
+    MessageMismatch { expected: Uuid, found: Uuid },
+    #[error("invalid config: {0}")]
+    InvalidConfig(&'static str),
+    #[error("config patch is based on revision {found}, config is at {expected}")]
+    ConfigRevisionMismatch { expected: u32, found: u32 },
+    #[error("message expired: {age_s}s old, ttl is {ttl_s}s")]
+    Expired { age_s: i64, ttl_s: i64 },
+    #[error("unknown export column: {0}")]
+    UnknownExportColumn(String),
+    #[error("unsupported export format: {0}")]
+    UnsupportedExportFormat(String),
+    #[error("invalid checkpoint: {0}")]
+    InvalidCheckpoint(&'static str),
+    #[error("invalid feed filter: {0}")]
+    InvalidFeedFilter(String),
+    #[error("beacon mac does not match")]
+    InvalidBeaconMac,
+    /// `None` if the header is cut short
+    #[error("unsupported compression codec: {0:?}")]
+    UnsupportedCodec(Option<u8>),
+    /// Also raised for known fields in a non canonical encoding, which would not re-encode to
+    /// the same bytes either
+    #[error("message has unknown proto fields: {len} bytes, {known_len} known")]
+    UnknownProtoFields { len: usize, known_len: usize },
+    #[error("cell at resolution {found}, expected {expected}")]
+    WrongResolution { expected: u8, found: u8 },
+    #[error("invalid nmea sentence: {0}")]
+    InvalidNmea(&'static str),
+    #[error("invalid ubx frame: {0}")]
+    InvalidUbx(&'static str),
+    #[error("invalid session file: {0:?}")]
+    InvalidSessionFile(String),
+    #[error("beacon {index} of a burst of {len}")]
+    InvalidBurst { index: u8, len: u8 },
+    #[error("invalid pseudonym: {0:?}")]
+    InvalidPseudonym(String),
+    #[error("raw modem dump of {0} bytes")]
+    RawDumpTooLong(usize),
+    #[error("no layout registered for firmware {firmware} on port {port}")]
+    UnknownFirmwareLayout { firmware: String, port: u8 },
+    #[error("{payload} frame looks like v{found} but firmware {firmware} sends v{expected}")]
+    FirmwareLayoutMismatch {
+        firmware: String,
+        payload: &'static str,
+        expected: u8,
+        found: u8,
+    },
+    #[error("invalid proto json: {0}")]
+    InvalidProtoJson(String),
+    #[error("{0} payload has an extension a plain MapperMsg cannot carry")]
+    PayloadExtensionDropped(PayloadKind),
+    #[cfg(any(feature = "ws", feature = "proto-json"))]
+    #[error("json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[cfg(feature = "ws")]
+    #[error("websocket: {0}")]
+    WebSocket(#[from] tokio_tungstenite::tungstenite::Error),
+    #[cfg(feature = "msgpack")]
+    #[error("msgpack encode: {0}")]
+    MsgpackEncode(#[from] rmp_serde::encode::Error),
+    #[cfg(feature = "msgpack")]
+    #[error("msgpack decode: {0}")]
+    MsgpackDecode(#[from] rmp_serde::decode::Error),
+    #[cfg(feature = "arrow")]
+    #[error("arrow: {0}")]
+    Arrow(#[from] arrow_schema::ArrowError),
+    #[cfg(feature = "parquet")]
+    #[error("parquet: {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+}
+
+/// Lets identity conversions such as `Payload: TryFrom<Payload>` flow through `?`
+impl From<std::convert::Infallible> for Error {
+    fn from(infallible: std::convert::Infallible) -> Self {
+        match infallible {}
+    }
+}
+
+impl Error {
+    /// Name of the variant, stable across releases so that it can be persisted
+    pub fn variant_name(&self) -> &'static str {
+        match self {
+            Error::ParseInt(_) => "ParseInt",
+            Error::UnexpectedAttachResultStr(_) => "UnexpectedAttachResultStr",
+            Error::UnexpectedPayloadKindStr(_) => "UnexpectedPayloadKindStr",
+            Error::UnexpectedPayloadKind { .. } => "UnexpectedPayloadKind",
+            #[cfg(feature = "h3")]
+            Error::H3oInvalidLatLong(_) => "H3oInvalidLatLong",
+            Error::InvalidAttachResultInt { .. } => "InvalidAttachResultInt",
+            Error::ProtoHasNone(_) => "ProtoHasNone",
+            Error::DecimalCouldNotMapToFloat { .. } => "DecimalCouldNotMapToFloat",
+            Error::PubkeyParse { .. } => "PubkeyParse",
+            Error::SignatureVerification { .. } => "SignatureVerification",
+            Error::HeliumProtoEncode(_) => "HeliumProtoEncode",
+            Error::HeliumProtoDecode(_) => "HeliumProtoDecode",
+            Error::Key(_) => "Key",
+            Error::VerifierBackend(_) => "VerifierBackend",
+            Error::PolicyRejected(_) => "PolicyRejected",
+            Error::Handler(_) => "Handler",
+            Error::InvalidVecForParsingLoraPayload { .. } => "InvalidVecForParsingLoraPayload",
+            #[cfg(feature = "h3")]
+            Error::H3oInvalidCellIndex(_) => "H3oInvalidCellIndex",
+            Error::InvalidH3Index(_) => "InvalidH3Index",
+            Error::InvalidDatarate(_) => "InvalidDatarate",
+            Error::UnknownGatewayEui(_) => "UnknownGatewayEui",
+            Error::InvalidRegionalDatarate { .. } => "InvalidRegionalDatarate",
+            Error::UnexpectedRegionStr(_) => "UnexpectedRegionStr",
+            Error::Io(_) => "Io",
+            Error::InvalidQuarantineFrame(_) => "InvalidQuarantineFrame",
+            Error::InvalidAttachDelay { .. } => "InvalidAttachDelay",
+            Error::CandidateNotInScan { .. } => "CandidateNotInScan",
+            Error::OutOfRange { .. } => "OutOfRange",
+            Error::InvalidCsvLine(_) => "InvalidCsvLine",
+            Error::MissingCsvField(_) => "MissingCsvField",
+            #[cfg(feature = "csv")]
+            Error::Csv(_) => "Csv",
+            Error::UnknownEarfcn(_) => "UnknownEarfcn",
+            Error::NotGnssPosition(_) => "NotGnssPosition",
+            Error::TimestampOutOfRange(_) => "TimestampOutOfRange",
+            Error::DeviceRejected(_) => "DeviceRejected",
+            Error::InvalidImsi => "InvalidImsi",
+            Error::InvalidLoraModulation { .. } => "InvalidLoraModulation",
+            Error::TooManyWitnesses { .. } => "TooManyWitnesses",
+            Error::PayloadKindNotCompiled(_) => "PayloadKindNotCompiled",
+            Error::InvalidFrequency(_) => "InvalidFrequency",
+            Error::FrequencyOutOfRegion { .. } => "FrequencyOutOfRegion",
+            Error::UnsupportedInProfile { .. } => "UnsupportedInProfile",
+            Error::NoEncodingWithinBudget { .. } => "NoEncodingWithinBudget",
+            #[cfg(feature = "cell")]
+            Error::InvalidTransition { .. } => "InvalidTransition",
+            Error::InvalidCounterFile(_) => "InvalidCounterFile",
+            Error::InvalidPci(_) => "InvalidPci",
+            Error::InvalidBandwidth(_) => "InvalidBandwidth",
+            Error::TooManyScanResults { .. } => "TooManyScanResults",
+            Error::SignatureTooLong { .. } => "SignatureTooLong",
+            Error::UnknownEpoch(_) => "UnknownEpoch",
+            Error::EpochConflict(_) => "EpochConflict",
+            Error::UnsupportedPayloadVersion { .. } => "UnsupportedPayloadVersion",
+            Error::InvalidLoraField { .. } => "InvalidLoraField",
+            Error::InvalidScanResult { .. } => "InvalidScanResult",
+            Error::InvalidWitness { .. } => "InvalidWitness",
+            Error::SelfTestFailed(_) => "SelfTestFailed",
+            Error::InvalidPlmn(_) => "InvalidPlmn",
+            Error::MessageMismatch { .. } => "MessageMismatch",
+            Error::InvalidConfig(_) => "InvalidConfig",
+            Error::ConfigRevisionMismatch { .. } => "ConfigRevisionMismatch",
+            Error::Expired { .. } => "Expired",
+            Error::UnknownExportColumn(_) => "UnknownExportColumn",
+            Error::UnsupportedExportFormat(_) => "UnsupportedExportFormat",
+            Error::InvalidCheckpoint(_) => "InvalidCheckpoint",
+            Error::InvalidFeedFilter(_) => "InvalidFeedFilter",
+            Error::InvalidBeaconMac => "InvalidBeaconMac",
+            Error::UnsupportedCodec(_) => "UnsupportedCodec",
+            Error::UnknownProtoFields { .. } => "UnknownProtoFields",
+            Error::WrongResolution { .. } => "WrongResolution",
+            Error::InvalidNmea(_) => "InvalidNmea",
+            Error::InvalidUbx(_) => "InvalidUbx",
+            Error::InvalidSessionFile(_) => "InvalidSessionFile",
+            Error::InvalidBurst { .. } => "InvalidBurst",
+            Error::InvalidPseudonym(_) => "InvalidPseudonym",
+            Error::RawDumpTooLong(_) => "RawDumpTooLong",
+            Error::UnknownFirmwareLayout { .. } => "UnknownFirmwareLayout",
+            Error::FirmwareLayoutMismatch { .. } => "FirmwareLayoutMismatch",
+            Error::InvalidProtoJson(_) => "InvalidProtoJson",
+            Error::PayloadExtensionDropped(_) => "PayloadExtensionDropped",
+            #[cfg(any(feature = "ws", feature = "proto-json"))]
+            Error::Json(_) => "Json",
+            #[cfg(feature = "ws")]
+            Error::WebSocket(_) => "WebSocket",
+            #[cfg(feature = "msgpack")]
+            Error::MsgpackEncode(_) => "MsgpackEncode",
+            #[cfg(feature = "msgpack")]
+            Error::MsgpackDecode(_) => "MsgpackDecode",
+            #[cfg(feature = "arrow")]
+            Error::Arrow(_) => "Arrow",
+            #[cfg(feature = "parquet")]
+            Error::Parquet(_) => "Parquet",
+        }
+    }
 }
 
 impl TryFrom<mapper_payload::Message> for Payload {
@@ -90,9 +647,24 @@ impl TryFrom<mapper_payload::Message> for Payload {
 
     fn try_from(value: mapper_payload::Message) -> std::result::Result<Self, Self::Error> {
         match value {
+            #[cfg(feature = "beacon")]
             mapper_payload::Message::Beacon(beacon) => Ok(Payload::Beacon(beacon.try_into()?)),
+            #[cfg(feature = "cell")]
             mapper_payload::Message::Attach(attach) => Ok(Payload::CellAttach(attach.try_into()?)),
+            #[cfg(feature = "cell")]
             mapper_payload::Message::Scan(scan) => Ok(Payload::CellScan(scan.try_into()?)),
+            #[cfg(not(feature = "beacon"))]
+            mapper_payload::Message::Beacon(_) => {
+                Err(Error::PayloadKindNotCompiled(PayloadKind::Beacon))
+            }
+            #[cfg(not(feature = "cell"))]
+            mapper_payload::Message::Attach(_) => {
+                Err(Error::PayloadKindNotCompiled(PayloadKind::CellAttach))
+            }
+            #[cfg(not(feature = "cell"))]
+            mapper_payload::Message::Scan(_) => {
+                Err(Error::PayloadKindNotCompiled(PayloadKind::CellScan))
+            }
             mapper_payload::Message::Gps(gps) => Ok(Payload::Gps(gps.try_into()?)),
         }
     }
@@ -101,14 +673,229 @@ impl TryFrom<mapper_payload::Message> for Payload {
 impl From<Payload> for mapper_payload::Message {
     fn from(payload: Payload) -> Self {
         match payload {
+            #[cfg(feature = "beacon")]
             Payload::Beacon(beacon) => beacon.into(),
+            #[cfg(feature = "cell")]
             Payload::CellAttach(attach) => attach.into(),
+            #[cfg(feature = "cell")]
             Payload::CellScan(scan) => scan.into(),
             Payload::Gps(gps) => gps.into(),
+            Payload::Position(position) => position.gps.into(),
         }
     }
 }
 
+impl std::fmt::Display for Payload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            #[cfg(feature = "cell")]
+            Payload::CellAttach(attach) => std::fmt::Display::fmt(attach, f),
+            #[cfg(feature = "cell")]
+            Payload::CellScan(scan) => std::fmt::Display::fmt(scan, f),
+            #[cfg(feature = "beacon")]
+            Payload::Beacon(beacon) => std::fmt::Display::fmt(beacon, f),
+            Payload::Gps(gps) => std::fmt::Display::fmt(gps, f),
+            Payload::Position(position) => std::fmt::Display::fmt(position, f),
+        }
+    }
+}
+
+impl std::fmt::Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Message{{{} {} witnesses={}}}",
+            short_pubkey(&self.pubkey),
+            self.payload,
+            self.lora_gws.len()
+        )
+    }
+}
+
+/// Decodes a MapperMsg and its extension that re-encode to exactly `bytes`
+fn strict_proto(bytes: &[u8]) -> Result<ExtendedMsg> {
+    let msg = ExtendedMsg::decode(bytes)?;
+    let reencoded = msg.encode_to_vec();
+    if reencoded != bytes {
+        return Err(Error::UnknownProtoFields {
+            len: bytes.len(),
+            known_len: reencoded.len(),
+        });
+    }
+    Ok(msg)
+}
+
+/// First and last 6 characters of the b58 encoding of a pubkey, for logs
+pub(crate) fn short_pubkey(pubkey: &PublicKey) -> String {
+    let b58 = pubkey.to_string();
+    if b58.len() <= 14 {
+        b58
+    } else {
+        format!("{}..{}", &b58[..6], &b58[b58.len() - 6..])
+    }
+}
+
+impl Payload {
+    /// Every payload carries the fix it was taken at
+    pub fn gps(&self) -> &Gps {
+        match self {
+            #[cfg(feature = "cell")]
+            Payload::CellAttach(attach) => &attach.gps,
+            #[cfg(feature = "cell")]
+            Payload::CellScan(scan) => &scan.gps,
+            #[cfg(feature = "beacon")]
+            Payload::Beacon(beacon) => &beacon.gps,
+            Payload::Gps(gps) => gps,
+            Payload::Position(position) => position.gps(),
+        }
+    }
+
+    fn gps_mut(&mut self) -> &mut Gps {
+        match self {
+            #[cfg(feature = "cell")]
+            Payload::CellAttach(attach) => &mut attach.gps,
+            #[cfg(feature = "cell")]
+            Payload::CellScan(scan) => &mut scan.gps,
+            #[cfg(feature = "beacon")]
+            Payload::Beacon(beacon) => &mut beacon.gps,
+            Payload::Gps(gps) => gps,
+            Payload::Position(position) => &mut position.gps,
+        }
+    }
+
+    /// What the payload carries beyond its helium-proto message, `None` if nothing. Fails on
+    /// values the extension can't hold, eg: a negative accuracy. See `proto_ext`.
+    pub fn to_proto_ext(&self) -> Result<Option<proto_ext::PayloadExtV1>> {
+        let ext = proto_ext::PayloadExtV1 {
+            gps: self.gps().to_proto_ext()?,
+            beacon: match self {
+                #[cfg(feature = "beacon")]
+                Payload::Beacon(beacon) => beacon.to_proto_ext(),
+                _ => None,
+            },
+            attach: match self {
+                #[cfg(feature = "cell")]
+                Payload::CellAttach(attach) => attach.to_proto_ext(),
+                _ => None,
+            },
+            scan: match self {
+                #[cfg(feature = "cell")]
+                Payload::CellScan(scan) => scan.to_proto_ext(),
+                _ => None,
+            },
+            position: match self {
+                Payload::Position(position) => Some(position.to_proto_ext()),
+                _ => None,
+            },
+        };
+        Ok((ext != proto_ext::PayloadExtV1::default()).then_some(ext))
+    }
+
+    /// The payload with the fields of its decoded extension set
+    pub fn with_proto_ext(mut self, ext: proto_ext::PayloadExtV1) -> Result<Self> {
+        if let Some(gps) = ext.gps {
+            self.gps_mut().set_proto_ext(&gps)?;
+        }
+        if let Some(ext) = ext.beacon {
+            match &mut self {
+                #[cfg(feature = "beacon")]
+                Payload::Beacon(beacon) => beacon.set_proto_ext(&ext)?,
+                other => {
+                    return Err(Error::UnexpectedPayloadKind {
+                        expected: PayloadKind::Beacon,
+                        found: other.kind(),
+                    })
+                }
+            }
+        }
+        if let Some(ext) = ext.attach {
+            match &mut self {
+                #[cfg(feature = "cell")]
+                Payload::CellAttach(attach) => attach.set_proto_ext(&ext)?,
+                other => {
+                    return Err(Error::UnexpectedPayloadKind {
+                        expected: PayloadKind::CellAttach,
+                        found: other.kind(),
+                    })
+                }
+            }
+        }
+        if let Some(ext) = ext.scan {
+            match &mut self {
+                #[cfg(feature = "cell")]
+                Payload::CellScan(scan) => scan.set_proto_ext(&ext)?,
+                other => {
+                    return Err(Error::UnexpectedPayloadKind {
+                        expected: PayloadKind::CellScan,
+                        found: other.kind(),
+                    })
+                }
+            }
+        }
+        // last, so that the fix it is read from is complete
+        if let Some(ext) = ext.position {
+            self = match self {
+                Payload::Gps(gps) => Payload::Position(Position::from_proto_ext(&gps, &ext)?),
+                other => {
+                    return Err(Error::UnexpectedPayloadKind {
+                        expected: PayloadKind::Gps,
+                        found: other.kind(),
+                    })
+                }
+            };
+        }
+        Ok(self)
+    }
+
+    /// Builds the proto representation of the payload without consuming or cloning the whole
+    /// payload first. The extension is left out, see `to_proto_ext`.
+    pub fn to_proto(&self) -> helium_proto::MapperPayload {
+        helium_proto::MapperPayload {
+            message: Some(match self {
+                #[cfg(feature = "beacon")]
+                Payload::Beacon(beacon) => beacon.into(),
+                #[cfg(feature = "cell")]
+                Payload::CellAttach(attach) => (*attach).into(),
+                #[cfg(feature = "cell")]
+                Payload::CellScan(scan) => scan.into(),
+                Payload::Gps(gps) => (*gps).into(),
+                Payload::Position(position) => position.gps.into(),
+            }),
+        }
+    }
+}
+
+macro_rules! payload_try_from {
+    ($($variant:ident),*) => {
+        $(impl TryFrom<Payload> for $variant {
+            type Error = Error;
+            fn try_from(payload: Payload) -> Result<Self> {
+                // with a single payload kind compiled in the fallback arm is unreachable
+                #[allow(unreachable_patterns)]
+                match payload {
+                    Payload::$variant(inner) => Ok(inner),
+                    other => Err(Error::UnexpectedPayloadKind {
+                        expected: PayloadKind::$variant,
+                        found: other.kind(),
+                    }),
+                }
+            }
+        })*
+    };
+}
+
+#[cfg(feature = "cell")]
+payload_try_from!(CellAttach, CellScan);
+#[cfg(feature = "beacon")]
+payload_try_from!(Beacon);
+payload_try_from!(Gps, Position);
+
+impl From<&Payload> for helium_proto::MapperPayload {
+    fn from(payload: &Payload) -> Self {
+        payload.to_proto()
+    }
+}
+
 impl TryFrom<MapperMsg> for Message {
     type Error = Error;
 
@@ -120,14 +907,30 @@ impl TryFrom<MapperMsg> for Message {
     }
 }
 
-impl From<Message> for MapperMsg {
-    fn from(value: Message) -> Self {
-        MapperMsg {
+impl TryFrom<ExtendedMsg> for Message {
+    type Error = Error;
+
+    fn try_from(value: ExtendedMsg) -> Result<Self> {
+        UnverifiedMsg::try_from(value)?.into_message()
+    }
+}
+
+/// Fails with `Error::PayloadExtensionDropped` if the payload has an extension, which a plain
+/// MapperMsg cannot carry and without which the signature no longer verifies. Convert to an
+/// `ExtendedMsg` to keep it.
+impl TryFrom<Message> for MapperMsg {
+    type Error = Error;
+
+    fn try_from(value: Message) -> Result<Self> {
+        if value.payload.to_proto_ext()?.is_some() {
+            return Err(Error::PayloadExtensionDropped(value.payload.kind()));
+        }
+        Ok(MapperMsg {
             version: Some(helium_proto::mapper_msg::Version::MsgV1(MapperMsgV1 {
                 payload: Some(helium_proto::MapperPayload {
-                    message: Some(value.payload.try_into().unwrap()),
+                    message: Some(value.payload.into()),
                 }),
-                signature: value.signature,
+                signature: value.signature.into(),
                 pubkey: value.pubkey.to_vec(),
                 lora_gws: value
                     .lora_gws
@@ -135,71 +938,364 @@ impl From<Message> for MapperMsg {
                     .map(|lora_gw| lora_gw.into())
                     .collect(),
             })),
-        }
+        })
+    }
+}
+
+impl TryFrom<Message> for ExtendedMsg {
+    type Error = Error;
+
+    fn try_from(value: Message) -> Result<Self> {
+        value.to_extended_proto()
     }
 }
 
 impl Message {
+    /// Builds the proto representation of the message without consuming it. The payload
+    /// extension is left out, so a payload that has one no longer verifies: encode with
+    /// `encode_to` or `to_extended_proto` to keep it.
+    pub fn to_proto(&self) -> MapperMsg {
+        MapperMsg {
+            version: Some(helium_proto::mapper_msg::Version::MsgV1(MapperMsgV1 {
+                payload: Some(self.payload.to_proto()),
+                signature: self.signature.as_bytes().to_vec(),
+                pubkey: self.pubkey.to_vec(),
+                lora_gws: self
+                    .lora_gws
+                    .iter()
+                    .map(|lora_gw| lora_gw.clone().into())
+                    .collect(),
+            })),
+        }
+    }
+
+    /// The proto representation of the message with its payload extension
+    pub fn to_extended_proto(&self) -> Result<ExtendedMsg> {
+        Ok(ExtendedMsg {
+            msg: self.to_proto(),
+            ext: self.payload.to_proto_ext()?,
+        })
+    }
+
+    /// Encodes the message as a MapperMsg into `buf`
+    pub fn encode_to(&self, buf: &mut impl BufMut) -> Result {
+        self.to_extended_proto()?.encode(buf)
+    }
+
+    /// Encodes the message as a MapperMsg prefixed by its varint encoded length
+    pub fn encode_length_delimited_to(&self, buf: &mut impl BufMut) -> Result {
+        self.to_extended_proto()?.encode_length_delimited(buf)
+    }
+
+    /// Decodes a MapperMsg without verifying its signature
+    pub fn decode_from(bytes: &[u8]) -> Result<Self> {
+        ExtendedMsg::decode(bytes)?.try_into()
+    }
+
+    pub fn decode_from_with_signature_verification(bytes: &[u8]) -> Result<Self> {
+        Self::try_from_with_signature_verification(ExtendedMsg::decode(bytes)?)
+    }
+
+    /// Same as `decode_from`, but fails with `Error::UnknownProtoFields` if the bytes hold
+    /// anything the crate's helium-proto doesn't know. prost drops unknown fields, so such a
+    /// message would re-encode differently, and its signature, made over fields added upstream,
+    /// would not verify. Use `decode_retaining` to relay these messages untouched instead.
+    pub fn decode_from_strict(bytes: &[u8]) -> Result<Self> {
+        strict_proto(bytes)?.try_into()
+    }
+
+    pub fn decode_from_strict_with_signature_verification(bytes: &[u8]) -> Result<Self> {
+        Self::try_from_with_signature_verification(strict_proto(bytes)?)
+    }
+
+    /// Same as `decode_from` for a buffer already held as `Bytes`, eg: by a gRPC server. prost
+    /// reads the buffer in place, but the bytes fields of helium-proto are `Vec<u8>`, so the
+    /// pubkey, signature and witness keys are still copied out.
+    pub fn decode_from_bytes(bytes: Bytes) -> Result<Self> {
+        ExtendedMsg::decode(bytes)?.try_into()
+    }
+
+    pub fn decode_from_bytes_with_signature_verification(bytes: Bytes) -> Result<Self> {
+        Self::try_from_with_signature_verification(ExtendedMsg::decode(bytes)?)
+    }
+
+    /// Same as `decode_from`, keeping `bytes` for `forward_bytes` without copying them
+    pub fn decode_retaining(bytes: Bytes) -> Result<Self> {
+        Self::decode_from_bytes(bytes.clone())?.retaining(bytes)
+    }
+
+    /// Same as `decode_from_with_signature_verification`, keeping `bytes` for `forward_bytes`
+    pub fn decode_retaining_with_signature_verification(bytes: Bytes) -> Result<Self> {
+        Self::decode_from_bytes_with_signature_verification(bytes.clone())?.retaining(bytes)
+    }
+
+    fn retaining(mut self, bytes: Bytes) -> Result<Self> {
+        let mut buf = Vec::new();
+        self.encode_to(&mut buf)?;
+        self.original_bytes = Some(Retained {
+            bytes,
+            encoding_digest: Retained::digest(&buf),
+        });
+        Ok(self)
+    }
+
+    /// The bytes to relay: exactly those received if they were retained and the message still
+    /// encodes as it did when decoded, else a fresh encoding. Changes are told by encoding the
+    /// message again, so edits of the public fields are caught as well.
+    pub fn forward_bytes(&self) -> Result<Bytes> {
+        let mut buf = Vec::new();
+        self.encode_to(&mut buf)?;
+        match &self.original_bytes {
+            Some(retained) if retained.encoding_digest == Retained::digest(&buf) => {
+                Ok(retained.bytes.clone())
+            }
+            _ => Ok(buf.into()),
+        }
+    }
+
+    /// Decodes one length-delimited MapperMsg, advancing `buf` past it so that consecutive
+    /// frames can be read from the same buffer. The signature is not verified.
+    pub fn decode_length_delimited_from(buf: &mut impl Buf) -> Result<Self> {
+        ExtendedMsg::decode_length_delimited(buf)?.try_into()
+    }
+
+    pub fn decode_length_delimited_from_with_signature_verification(
+        buf: &mut impl Buf,
+    ) -> Result<Self> {
+        Self::try_from_with_signature_verification(ExtendedMsg::decode_length_delimited(buf)?)
+    }
+
     pub fn from_payload_signed<K: keys::KeyTrait>(
         key: &K,
         payload: Payload,
     ) -> std::result::Result<Self, Error> {
         let mut payload_bytes = Vec::new();
-        let payload_proto = helium_proto::MapperPayload {
-            message: Some(payload.clone().into()),
-        };
-        payload_proto.encode(&mut payload_bytes)?;
+        Self::from_payload_signed_with_buf(key, payload, &mut payload_bytes)
+    }
+
+    /// Same as `from_payload_signed` but encodes the payload into a caller-provided buffer so
+    /// that it may be reused across calls. The buffer is cleared before encoding.
+    pub fn from_payload_signed_with_buf<K: keys::KeyTrait>(
+        key: &K,
+        payload: Payload,
+        buf: &mut Vec<u8>,
+    ) -> std::result::Result<Self, Error> {
+        let signed_bytes = SignedBytes::encode_payload(&payload, std::mem::take(buf))?;
         let signature = key
-            .sign(&payload_bytes)
+            .sign_bytes(&signed_bytes)
             .map_err(|e| Error::Key(e.to_string()))?;
+        *buf = signed_bytes.into_inner();
         Ok(Message {
             payload,
-            signature,
+            signature: signature.into(),
             pubkey: key.pubkey().map_err(|e| Error::Key(e.to_string()))?,
             // this field is left blank because it is not used in the mapper
-            lora_gws: vec![],
+            lora_gws: Witnesses::new(),
+            ingest_meta: None,
+            original_bytes: None,
         })
     }
 
-    pub fn try_from_with_signature_verification(value: MapperMsg) -> Result<Self> {
+    /// Decodes the message without verifying its signature, accepting up to `max_witnesses`
+    /// instead of `Witnesses::DEFAULT_MAX`
+    pub fn try_from_with_max_witnesses(value: MapperMsg, max_witnesses: usize) -> Result<Self> {
+        let limits = DecodeLimits::default().with_max_witnesses(max_witnesses);
+        Self::try_from_with_limits(value, &limits)
+    }
+
+    /// Decodes the message without verifying its signature, under `limits` instead of
+    /// `DecodeLimits::DEFAULT`
+    pub fn try_from_with_limits(
+        value: impl Into<ExtendedMsg>,
+        limits: &DecodeLimits,
+    ) -> Result<Self> {
+        UnverifiedMsg::try_from(value.into())?.into_message_with(limits, Payload::try_from)
+    }
+
+    /// A plain `MapperMsg` has no payload extension, so the signature of a payload that had one
+    /// fails. Verify an `ExtendedMsg`, or the bytes with `decode_from_with_signature_verification`.
+    pub fn try_from_with_signature_verification(value: impl Into<ExtendedMsg>) -> Result<Self> {
+        Self::try_from_with_verifier(value, &InProcessVerifier)
+    }
+
+    /// Decodes the message, verifying its signature with the given backend
+    pub fn try_from_with_verifier<V: VerifierBackend + ?Sized>(
+        value: impl Into<ExtendedMsg>,
+        verifier: &V,
+    ) -> Result<Self> {
+        Self::try_from_with_registry(value, verifier, &AllowAllDevices, &DecodeLimits::DEFAULT)
+    }
+
+    /// Decodes the message under `limits`, checking its pubkey against the registry before
+    /// verifying the signature with the given backend
+    pub fn try_from_with_registry<V: VerifierBackend + ?Sized, R: DeviceRegistry + ?Sized>(
+        value: impl Into<ExtendedMsg>,
+        verifier: &V,
+        registry: &R,
+        limits: &DecodeLimits,
+    ) -> Result<Self> {
+        let unverified = UnverifiedMsg::try_from(value.into())?;
+        unverified.check_limits(limits)?;
+        registry.check(&unverified.pubkey)?;
+        verifier.verify(
+            &unverified.pubkey,
+            &unverified.signed_bytes(),
+            &unverified.signature,
+        )?;
+        unverified.into_message_with(limits, Payload::try_from)
+    }
+
+    /// Decodes all messages and verifies their signatures with a single call to
+    /// `VerifierBackend::verify_batch`. Results are returned in the order of `values`.
+    pub fn try_batch_from_with_verifier<M: Into<ExtendedMsg>, V: VerifierBackend + ?Sized>(
+        values: Vec<M>,
+        verifier: &V,
+    ) -> Vec<Result<Self>> {
+        Self::try_batch_from_with_limits(values, verifier, &DecodeLimits::DEFAULT)
+    }
+
+    /// Same as `try_batch_from_with_verifier` under `limits`. Messages beyond them fail on their
+    /// own and are left out of the batch handed to the backend.
+    pub fn try_batch_from_with_limits<M: Into<ExtendedMsg>, V: VerifierBackend + ?Sized>(
+        values: Vec<M>,
+        verifier: &V,
+        limits: &DecodeLimits,
+    ) -> Vec<Result<Self>> {
+        let decoded: Vec<Result<UnverifiedMsg>> = values
+            .into_iter()
+            .map(|value| {
+                let unverified = UnverifiedMsg::try_from(value.into())?;
+                unverified.check_limits(limits)?;
+                Ok(unverified)
+            })
+            .collect();
+        let signed_bytes: Vec<Option<SignedBytes>> = decoded
+            .iter()
+            .map(|d| d.as_ref().ok().map(UnverifiedMsg::signed_bytes))
+            .collect();
+        let requests: Vec<VerifyRequest> = decoded
+            .iter()
+            .zip(&signed_bytes)
+            .filter_map(|(decoded, msg)| match (decoded, msg) {
+                (Ok(decoded), Some(msg)) => Some(VerifyRequest {
+                    pubkey: &decoded.pubkey,
+                    msg,
+                    signature: &decoded.signature,
+                }),
+                _ => None,
+            })
+            .collect();
+        let mut verified = verifier.verify_batch(&requests).into_iter();
+
+        decoded
+            .into_iter()
+            .map(|decoded| {
+                let decoded = decoded?;
+                verified.next().unwrap_or_else(|| {
+                    Err(Error::VerifierBackend(
+                        "batch returned fewer results than requests".into(),
+                    ))
+                })?;
+                decoded.into_message_with(limits, Payload::try_from)
+            })
+            .collect()
+    }
+}
+
+/// A decoded MapperMsgV1 whose signature has not been checked yet
+struct UnverifiedMsg {
+    payload: mapper_payload::Message,
+    ext: Option<proto_ext::PayloadExtV1>,
+    pubkey: PublicKey,
+    signature: Vec<u8>,
+    lora_gws: Vec<helium_proto::LoraGw>,
+}
+
+impl TryFrom<MapperMsg> for UnverifiedMsg {
+    type Error = Error;
+
+    fn try_from(value: MapperMsg) -> Result<Self> {
         match value.version {
-            Some(helium_proto::mapper_msg::Version::MsgV1(msg)) => Self::inner_try_from(msg, true),
+            Some(helium_proto::mapper_msg::Version::MsgV1(msg)) => msg.try_into(),
             _ => Err(Error::ProtoHasNone("version")),
         }
     }
+}
+
+impl TryFrom<ExtendedMsg> for UnverifiedMsg {
+    type Error = Error;
+
+    fn try_from(value: ExtendedMsg) -> Result<Self> {
+        Ok(Self {
+            ext: value.ext,
+            ..Self::try_from(value.msg)?
+        })
+    }
+}
 
-    /// with_verification flag will verify the signature of the message
-    fn inner_try_from(value: MapperMsgV1, with_verification: bool) -> Result<Self> {
+impl TryFrom<MapperMsgV1> for UnverifiedMsg {
+    type Error = Error;
+
+    fn try_from(value: MapperMsgV1) -> Result<Self> {
         let payload = value.payload.ok_or(Error::ProtoHasNone("payload"))?;
         let payload = payload.message.ok_or(Error::ProtoHasNone("message"))?;
         let pubkey = PublicKey::from_bytes(&value.pubkey).map_err(|error| Error::PubkeyParse {
             error,
             bytes: value.pubkey,
         })?;
-
-        if with_verification {
-            let mut payload_bytes = Vec::new();
-            payload.encode(&mut payload_bytes);
-            pubkey
-                .verify(&payload_bytes, &value.signature)
-                .map_err(|_| Error::SignatureVerification {
-                    pubkey: Box::new(pubkey.clone()),
-                    msg: payload_bytes,
-                    signature: value.signature.clone(),
-                })?;
-        }
-
-        let payload = payload.try_into()?;
-
         Ok(Self {
             payload,
-            signature: value.signature,
+            ext: None,
             pubkey,
-            lora_gws: value
+            signature: value.signature,
+            lora_gws: value.lora_gws,
+        })
+    }
+}
+
+impl UnverifiedMsg {
+    fn signed_bytes(&self) -> SignedBytes {
+        SignedBytes::from_proto_payload_with_ext(&self.payload, self.ext.as_ref())
+    }
+
+    /// Checked before the signature is verified and again before conversion
+    fn check_limits(&self, limits: &DecodeLimits) -> Result {
+        limits.check_signature_len(self.signature.len())?;
+        limits.check_witnesses(self.lora_gws.len())?;
+        limits.check_payload(&self.payload, self.ext.as_ref())
+    }
+
+    fn into_message(self) -> Result<Message> {
+        self.into_message_with(&DecodeLimits::DEFAULT, Payload::try_from)
+    }
+
+    fn into_message_with(
+        self,
+        limits: &DecodeLimits,
+        decode_payload: impl FnOnce(mapper_payload::Message) -> Result<Payload>,
+    ) -> Result<Message> {
+        self.check_limits(limits)?;
+        let payload = decode_payload(self.payload)?;
+        Ok(Message {
+            payload: match self.ext {
+                Some(ext) => payload.with_proto_ext(ext)?,
+                None => payload,
+            },
+            signature: self.signature.into(),
+            pubkey: self.pubkey,
+            lora_gws: self
                 .lora_gws
                 .into_iter()
-                .map(|v| v.try_into())
+                .enumerate()
+                .map(|(index, v)| {
+                    v.try_into().map_err(|source| Error::InvalidWitness {
+                        index,
+                        source: Box::new(source),
+                    })
+                })
                 .collect::<Result<_>>()?,
+            ingest_meta: None,
+            original_bytes: None,
         })
     }
 }
@@ -211,21 +1307,7 @@ impl TryFrom<MapperMsgV1> for Message {
     type Error = Error;
 
     fn try_from(value: MapperMsgV1) -> std::result::Result<Self, Self::Error> {
-        Self::inner_try_from(value, false)
-    }
-}
-
-fn mapper_msg_with_payload(payload: mapper_payload::Message) -> MapperMsg {
-    use helium_proto::{mapper_msg, MapperPayload};
-    MapperMsg {
-        version: Some(mapper_msg::Version::MsgV1(MapperMsgV1 {
-            pubkey: vec![0; 32],
-            payload: Some(MapperPayload {
-                message: Some(payload),
-            }),
-            signature: vec![0; 64],
-            lora_gws: vec![],
-        })),
+        UnverifiedMsg::try_from(value)?.into_message()
     }
 }
 
@@ -243,4 +1325,119 @@ mod test {
         let msg_rx = Message::try_from_with_signature_verification(proto_msg).unwrap();
         assert_eq!(msg, msg_rx);
     }
+
+    #[test]
+    fn retained_bytes_are_forwarded_as_is() {
+        let key = keys::file::File::create_key().unwrap();
+        let msg = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap();
+        let mut encoded = Vec::new();
+        msg.encode_to(&mut encoded).unwrap();
+        // a field unknown to this version of the proto, which re-encoding would drop
+        encoded.extend_from_slice(&[0xf8, 0x07, 0x01]);
+        let received = Bytes::from(encoded);
+
+        let decoded =
+            Message::decode_retaining_with_signature_verification(received.clone()).unwrap();
+        assert_eq!(decoded.payload, msg.payload);
+        let forwarded = decoded.forward_bytes().unwrap();
+        assert_eq!(forwarded, received);
+        assert_eq!(forwarded.as_ptr(), received.as_ptr());
+        assert_ne!(msg.forward_bytes().unwrap(), received);
+        assert!(decoded.clone().without_witnesses().original_bytes.is_none());
+        assert_eq!(decoded, Message::decode_from(&received).unwrap());
+
+        // changes to the public fields are caught as well
+        let mut edited = decoded.clone();
+        edited.lora_gws.push(LoraGw::random());
+        let mut fresh = Vec::new();
+        edited.encode_to(&mut fresh).unwrap();
+        assert_eq!(edited.forward_bytes().unwrap(), fresh);
+        edited.lora_gws.strip();
+        assert_eq!(edited.forward_bytes().unwrap(), received);
+    }
+
+    #[test]
+    fn display_shortens_pubkey() {
+        let key = keys::file::File::create_key().unwrap();
+        let msg = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap();
+        let b58 = msg.pubkey.to_string();
+        assert_eq!(
+            msg.to_string(),
+            format!(
+                "Message{{{}..{} Gps{{-50.12345,120.12345 5sats hdop9.05}} witnesses=0}}",
+                &b58[..6],
+                &b58[b58.len() - 6..]
+            )
+        );
+    }
+
+    #[test]
+    fn message_json_roundtrip() {
+        let key = keys::file::File::create_key().unwrap();
+        let mut msg =
+            Message::from_payload_signed(&key, Payload::CellScan(CellScan::random())).unwrap();
+        msg.lora_gws.push(LoraGw::random());
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains(&format!("\"{}\"", msg.pubkey)));
+        assert!(json.contains("\"SF10BW125\""));
+        assert_eq!(msg, serde_json::from_str::<Message>(&json).unwrap());
+    }
+
+    #[test]
+    fn to_proto_matches_owned_conversion() {
+        let payload = Payload::CellScan(CellScan::random());
+        let owned = helium_proto::MapperPayload {
+            message: Some(payload.clone().into()),
+        };
+        assert_eq!(payload.to_proto(), owned);
+    }
+
+    #[test]
+    fn encode_decode_length_delimited_stream() {
+        let key = keys::file::File::create_key().unwrap();
+        let msgs: Vec<Message> = (0..3)
+            .map(|_| {
+                Message::from_payload_signed(&key, Payload::CellScan(CellScan::random())).unwrap()
+            })
+            .collect();
+        let mut buf = Vec::new();
+        for msg in &msgs {
+            msg.encode_length_delimited_to(&mut buf).unwrap();
+        }
+        let mut reader = buf.as_slice();
+        for msg in &msgs {
+            let decoded =
+                Message::decode_length_delimited_from_with_signature_verification(&mut reader)
+                    .unwrap();
+            assert_eq!(msg, &decoded);
+        }
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn encode_to_matches_owned_conversion() {
+        let key = keys::file::File::create_key().unwrap();
+        let msg = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap();
+        let mut buf = Vec::new();
+        msg.encode_to(&mut buf).unwrap();
+        assert_eq!(
+            buf,
+            MapperMsg::try_from(msg.clone()).unwrap().encode_to_vec()
+        );
+        assert_eq!(msg, Message::decode_from(&buf).unwrap());
+    }
+
+    #[test]
+    fn sign_with_reused_buf() {
+        let key = keys::file::File::create_key().unwrap();
+        let mut buf = Vec::new();
+        for _ in 0..2 {
+            let payload = Payload::CellScan(CellScan::random());
+            let msg = Message::from_payload_signed_with_buf(&key, payload, &mut buf).unwrap();
+            assert_eq!(buf, msg.payload.to_proto().encode_to_vec());
+            let proto_msg: MapperMsg = msg.clone().try_into().unwrap();
+            let msg_rx = Message::try_from_with_signature_verification(proto_msg).unwrap();
+            assert_eq!(msg, msg_rx);
+        }
+    }
 }
@@ -0,0 +1,140 @@
+//! Bounds on the collections a decoded MapperMsg may carry. Prost has already allocated the proto
+//! by the time these apply, so ingest should also bound the frame size; the limits keep hostile
+//! messages from being converted, verified and passed on.
+use super::{
+    mapper_payload, proto_ext::PayloadExtV1, Deserialize, Error, Result, Serialize, Witnesses,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DecodeLimits {
+    pub max_scan_results: usize,
+    pub max_witnesses: usize,
+    pub max_signature_len: usize,
+}
+
+impl DecodeLimits {
+    pub const DEFAULT: Self = Self {
+        max_scan_results: 128,
+        max_witnesses: Witnesses::DEFAULT_MAX,
+        // DER encoded ECDSA signatures are at most 72 bytes and ed25519 ones 64
+        max_signature_len: 128,
+    };
+
+    pub fn with_max_scan_results(mut self, max_scan_results: usize) -> Self {
+        self.max_scan_results = max_scan_results;
+        self
+    }
+
+    pub fn with_max_witnesses(mut self, max_witnesses: usize) -> Self {
+        self.max_witnesses = max_witnesses;
+        self
+    }
+
+    pub fn with_max_signature_len(mut self, max_signature_len: usize) -> Self {
+        self.max_signature_len = max_signature_len;
+        self
+    }
+
+    pub fn check_signature_len(&self, len: usize) -> Result {
+        if len > self.max_signature_len {
+            Err(Error::SignatureTooLong {
+                len,
+                max: self.max_signature_len,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn check_scan_results(&self, count: usize) -> Result {
+        if count > self.max_scan_results {
+            Err(Error::TooManyScanResults {
+                count,
+                max: self.max_scan_results,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn check_witnesses(&self, count: usize) -> Result {
+        Witnesses::check_count(count, self.max_witnesses)
+    }
+
+    /// Checks the collections of a proto payload and its extension before they are converted.
+    /// The extension's scan times are one per scan result, so they share its limit.
+    pub fn check_payload(
+        &self,
+        payload: &mapper_payload::Message,
+        ext: Option<&PayloadExtV1>,
+    ) -> Result {
+        if let Some(scan) = ext.and_then(|ext| ext.scan.as_ref()) {
+            self.check_scan_results(scan.observed_at_ms.len())?;
+        }
+        match payload {
+            mapper_payload::Message::Scan(helium_proto::MapperScan {
+                version: Some(helium_proto::mapper_scan::Version::ScanV1(scan)),
+            }) => self.check_scan_results(scan.results.len()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        keys,
+        proto_ext::{PayloadExtV1, ScanExtV1},
+        CellScan, ExtendedMsg, MapperMsg, Message, Payload,
+    };
+
+    #[test]
+    fn limits_are_enforced_on_decode() {
+        let key = keys::file::File::create_key().unwrap();
+        let mut scan = CellScan::random();
+        while scan.results.len() < 4 {
+            scan.results.push(crate::CellScanResult::random());
+        }
+        let msg: MapperMsg = Message::from_payload_signed(&key, Payload::CellScan(scan))
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert!(Message::try_from(msg.clone()).is_ok());
+
+        let few_results = DecodeLimits::default().with_max_scan_results(3);
+        assert!(matches!(
+            Message::try_from_with_limits(msg.clone(), &few_results),
+            Err(Error::TooManyScanResults { max: 3, .. })
+        ));
+        let short_signature = DecodeLimits::default().with_max_signature_len(8);
+        assert!(matches!(
+            Message::try_from_with_limits(msg.clone(), &short_signature),
+            Err(Error::SignatureTooLong { max: 8, .. })
+        ));
+
+        // the extension's repeated fields are bounded too, before they are matched to results
+        let oversized_ext = ExtendedMsg {
+            msg,
+            ext: Some(PayloadExtV1 {
+                scan: Some(ScanExtV1 {
+                    observed_at_ms: vec![0; 4096],
+                }),
+                ..Default::default()
+            }),
+        };
+        assert!(matches!(
+            Message::try_from_with_limits(oversized_ext, &DecodeLimits::default()),
+            Err(Error::TooManyScanResults {
+                count: 4096,
+                max: 128
+            })
+        ));
+    }
+}
@@ -1,15 +1,272 @@
-use super::{Error, PublicKey, Result};
+#[cfg(feature = "h3")]
+use super::Gps;
+use super::{
+    propagation::PathLossModel, region::Region, short_pubkey, DataRateExt, DateTime, Deserialize,
+    EncodeMode, Error, H3Index, PublicKey, Result, Rounding, Serialize, Utc,
+};
 use helium_proto::DataRate;
 use rust_decimal::Decimal;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct LoraGw {
+    #[serde(with = "crate::serde_helpers::pubkey")]
     pub pubkey: PublicKey,
-    pub h3_cell: h3o::CellIndex,
+    pub h3_cell: H3Index,
+    #[serde(with = "crate::serde_helpers::decimal::scale1")]
     pub snr: Decimal,
+    #[serde(with = "crate::serde_helpers::decimal::scale2")]
     pub rssi: Decimal,
-    pub frequency: Decimal,
+    pub frequency: FrequencyHz,
+    #[serde(with = "crate::serde_helpers::data_rate")]
     pub data_rate: DataRate,
+    /// When the gateway received the uplink, if it reported it. It is not part of the proto and
+    /// is lost when encoding.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub received_at: Option<DateTime<Utc>>,
+}
+
+/// Uplink frequency in Hz. It serializes as an integer number of Hz; decimal MHz, as written by
+/// earlier versions (eg: "904.300"), is still accepted when deserializing. Integers below 1 MHz
+/// are rejected rather than read as Hz, since they can only be MHz written without a fraction.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(transparent)]
+pub struct FrequencyHz(pub u64);
+
+impl FrequencyHz {
+    pub const fn from_khz(khz: u64) -> Self {
+        Self(khz * 1_000)
+    }
+
+    pub const fn from_mhz(mhz: u64) -> Self {
+        Self(mhz * 1_000_000)
+    }
+
+    /// Fails if `mhz` is negative or not a whole number of Hz
+    pub fn from_mhz_decimal(mhz: Decimal) -> Result<Self> {
+        use rust_decimal::prelude::ToPrimitive;
+        let hz = mhz * Decimal::from(1_000_000);
+        hz.fract()
+            .is_zero()
+            .then(|| hz.to_u64())
+            .flatten()
+            .map(Self)
+            .ok_or(Error::InvalidFrequency(mhz))
+    }
+
+    pub fn as_mhz(&self) -> f64 {
+        self.0 as f64 / 1e6
+    }
+
+    pub fn as_mhz_decimal(&self) -> Decimal {
+        Decimal::from(self.0) / Decimal::from(1_000_000)
+    }
+}
+
+impl std::fmt::Display for FrequencyHz {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}MHz", self.as_mhz_decimal().normalize())
+    }
+}
+
+impl<'de> Deserialize<'de> for FrequencyHz {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Hz(u64),
+            LegacyMhz(Decimal),
+        }
+        match Repr::deserialize(deserializer)? {
+            Repr::Hz(hz) if hz < Self::from_mhz(1).0 => Err(serde::de::Error::custom(format!(
+                "frequency {hz} is below 1MHz, write MHz as a decimal string"
+            ))),
+            Repr::Hz(hz) => Ok(Self(hz)),
+            Repr::LegacyMhz(mhz) => Self::from_mhz_decimal(mhz).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+impl std::fmt::Display for LoraGw {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "LoraGw{{{} snr{} rssi{} {} {}}}",
+            short_pubkey(&self.pubkey),
+            self.snr,
+            self.rssi,
+            self.frequency,
+            self.data_rate.as_str_name()
+        )
+    }
+}
+
+/// Assumptions about the uplink used by `LoraGw::plausible_for_with`
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkBudget {
+    /// EIRP of the mapper, in dBm
+    pub tx_power_dbm: f64,
+    /// Receiver noise figure of the gateway, in dB
+    pub noise_figure_db: f64,
+    pub path_loss: PathLossModel,
+}
+
+impl Default for LinkBudget {
+    fn default() -> Self {
+        Self {
+            tx_power_dbm: 20.0,
+            noise_figure_db: 6.0,
+            path_loss: PathLossModel::HataSuburban {
+                base_height_m: 10.0,
+                mobile_height_m: 1.5,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImplausibleReason {
+    /// Received stronger than the path loss to the asserted location allows
+    RssiTooStrong { expected_dbm: f64, excess_db: f64 },
+    /// Received below the sensitivity of the spreading factor
+    BelowSensitivity {
+        sensitivity_dbm: f64,
+        excess_db: f64,
+    },
+    /// SNR below the demodulation floor of the spreading factor
+    SnrBelowFloor { floor_db: f64, excess_db: f64 },
+}
+
+impl ImplausibleReason {
+    pub fn excess_db(&self) -> f64 {
+        match self {
+            ImplausibleReason::RssiTooStrong { excess_db, .. }
+            | ImplausibleReason::BelowSensitivity { excess_db, .. }
+            | ImplausibleReason::SnrBelowFloor { excess_db, .. } => *excess_db,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Plausibility {
+    /// Distance from the fix to the centroid of the gateway's h3 cell
+    pub distance_m: f64,
+    /// 1.0 when nothing is out of bounds, falling towards 0.0 as the worst excess grows
+    pub score: f64,
+    pub reasons: Vec<ImplausibleReason>,
+}
+
+impl Plausibility {
+    pub fn is_plausible(&self) -> bool {
+        self.reasons.is_empty()
+    }
+}
+
+/// Distances are clamped so a fix inside the gateway's cell doesn't blow up the path loss
+#[cfg(feature = "h3")]
+const MIN_DISTANCE_M: f64 = 10.0;
+
+/// The distance to the gateway is taken from its h3 cell, so this needs the `h3` feature
+#[cfg(feature = "h3")]
+impl LoraGw {
+    /// Checks whether this gateway could have heard a mapper at `gps` with the reported RSSI and
+    /// SNR, allowing `margin_db` of slack on every check.
+    pub fn plausible_for(&self, gps: &Gps, margin_db: f64) -> Result<Plausibility> {
+        self.plausible_for_with(gps, margin_db, &LinkBudget::default())
+    }
+
+    pub fn plausible_for_with(
+        &self,
+        gps: &Gps,
+        margin_db: f64,
+        budget: &LinkBudget,
+    ) -> Result<Plausibility> {
+        use rust_decimal::prelude::ToPrimitive;
+        let to_f64 = |decimal: Decimal| {
+            decimal
+                .to_f64()
+                .ok_or(Error::DecimalCouldNotMapToFloat { decimal })
+        };
+        let fix = h3o::LatLng::new(to_f64(gps.lat)?, to_f64(gps.lon)?)?;
+        let distance_m = h3o::LatLng::from(self.h3_cell.cell()).distance_m(fix);
+        let rssi = to_f64(self.rssi)?;
+        let snr = to_f64(self.snr)?;
+        let frequency_mhz = self.frequency.as_mhz();
+
+        let mut reasons = Vec::new();
+        let expected_dbm = budget.tx_power_dbm
+            - budget
+                .path_loss
+                .path_loss_db(frequency_mhz, distance_m.max(MIN_DISTANCE_M));
+        if rssi > expected_dbm + margin_db {
+            reasons.push(ImplausibleReason::RssiTooStrong {
+                expected_dbm,
+                excess_db: rssi - expected_dbm - margin_db,
+            });
+        }
+        if let (Some(spreading_factor), Some(bandwidth_khz)) = (
+            self.data_rate.spreading_factor(),
+            self.data_rate.bandwidth_khz(),
+        ) {
+            let floor_db = demodulation_floor_db(spreading_factor);
+            let sensitivity_dbm = -174.0
+                + 10.0 * (bandwidth_khz as f64 * 1000.0).log10()
+                + budget.noise_figure_db
+                + floor_db;
+            if rssi < sensitivity_dbm - margin_db {
+                reasons.push(ImplausibleReason::BelowSensitivity {
+                    sensitivity_dbm,
+                    excess_db: sensitivity_dbm - margin_db - rssi,
+                });
+            }
+            if snr < floor_db - margin_db {
+                reasons.push(ImplausibleReason::SnrBelowFloor {
+                    floor_db,
+                    excess_db: floor_db - margin_db - snr,
+                });
+            }
+        }
+        let worst = reasons
+            .iter()
+            .map(ImplausibleReason::excess_db)
+            .fold(0.0, f64::max);
+        Ok(Plausibility {
+            distance_m,
+            score: 10f64.powf(-worst / 20.0),
+            reasons,
+        })
+    }
+}
+
+impl LoraGw {
+    /// Fails if the gateway reported a frequency outside of the region's band
+    pub fn check_region(&self, region: Region) -> Result {
+        region.check_frequency(self.frequency)
+    }
+
+    /// A gateway with a fresh pubkey that heard an SF10BW125 uplink on 904.3 MHz at 5.5 dB SNR and
+    /// -110 dBm RSSI, for tests to override with struct update syntax
+    pub fn random() -> Self {
+        use crate::keys::{self, KeyTrait};
+        let key = keys::file::File::create_key().expect("random key");
+        Self {
+            pubkey: key.pubkey().expect("pubkey of random key"),
+            h3_cell: H3Index::new(0x8a1fb46622dffff).expect("valid cell"),
+            snr: Decimal::new(55, 1),
+            rssi: Decimal::new(-110, 0),
+            frequency: FrequencyHz::from_khz(904_300),
+            data_rate: DataRate::Sf10bw125,
+            received_at: None,
+        }
+    }
+}
+
+/// Minimum SNR the LoRa demodulator needs at each spreading factor
+#[cfg(feature = "h3")]
+fn demodulation_floor_db(spreading_factor: u8) -> f64 {
+    -2.5 * (spreading_factor as f64 - 4.0)
 }
 
 impl TryFrom<helium_proto::LoraGw> for LoraGw {
@@ -22,12 +279,13 @@ impl TryFrom<helium_proto::LoraGw> for LoraGw {
                     bytes: value.pubkey,
                 }
             })?,
-            h3_cell: h3o::CellIndex::try_from(value.h3_cell)?,
+            h3_cell: H3Index::new(value.h3_cell)?,
             snr: snr::from_proto_units(value.snr),
             rssi: rssi::from_proto_units(value.rssi),
             frequency: frequency::from_proto_units(value.frequency),
             data_rate: DataRate::from_i32(value.data_rate)
                 .ok_or(Error::InvalidDatarate(value.data_rate))?,
+            received_at: None,
         })
     }
 }
@@ -45,20 +303,42 @@ impl From<LoraGw> for helium_proto::LoraGw {
     }
 }
 
+/// SNR is carried in the proto as a signed int32 of 0.1 dB, so the negative values common at
+/// the edge of coverage (down to -20 dB) roundtrip as is.
 pub mod snr {
     use super::*;
 
     const SNR_PROTO_SCALAR: Decimal = Decimal::from_parts(1, 0, 0, false, 1);
 
     pub fn to_proto_units(snr: Decimal) -> i32 {
-        let scaled = snr.checked_div(SNR_PROTO_SCALAR).unwrap().round();
-        scaled.to_string().parse::<i32>().unwrap()
+        to_proto_units_with(snr, Rounding::HalfEven)
+    }
+
+    /// Saturates values beyond the i32 range of the proto field
+    pub fn to_proto_units_with(snr: Decimal, rounding: Rounding) -> i32 {
+        to_proto_units_with_mode(snr, rounding, EncodeMode::Saturating).expect("saturating encode")
+    }
+
+    pub fn to_proto_units_with_mode(
+        snr: Decimal,
+        rounding: Rounding,
+        mode: EncodeMode,
+    ) -> Result<i32> {
+        let scaled = rounding.apply(snr.checked_div(SNR_PROTO_SCALAR).unwrap());
+        mode.fit_i32("snr", scaled)
     }
 
     pub fn from_proto_units(snr: i32) -> Decimal {
         let snr_unscaled = Decimal::new(snr.into(), 0);
         snr_unscaled.checked_mul(SNR_PROTO_SCALAR).unwrap()
     }
+
+    /// For records exported by tools that read the field as a uint32, where a negative SNR shows
+    /// up as a value close to `u32::MAX`. On the wire both decode the same, so only such exports
+    /// need this.
+    pub fn from_legacy_u32_units(snr: u32) -> Decimal {
+        from_proto_units(snr as i32)
+    }
 }
 
 pub mod rssi {
@@ -67,8 +347,21 @@ pub mod rssi {
     const RSSI_PROTO_SCALAR: Decimal = Decimal::from_parts(1, 0, 0, false, 2);
 
     pub fn to_proto_units(rssi: Decimal) -> i32 {
-        let scaled = rssi.checked_div(RSSI_PROTO_SCALAR).unwrap().round();
-        scaled.to_string().parse::<i32>().unwrap()
+        to_proto_units_with(rssi, Rounding::HalfEven)
+    }
+
+    /// Saturates values beyond the i32 range of the proto field
+    pub fn to_proto_units_with(rssi: Decimal, rounding: Rounding) -> i32 {
+        to_proto_units_with_mode(rssi, rounding, EncodeMode::Saturating).expect("saturating encode")
+    }
+
+    pub fn to_proto_units_with_mode(
+        rssi: Decimal,
+        rounding: Rounding,
+        mode: EncodeMode,
+    ) -> Result<i32> {
+        let scaled = rounding.apply(rssi.checked_div(RSSI_PROTO_SCALAR).unwrap());
+        mode.fit_i32("rssi", scaled)
     }
 
     pub fn from_proto_units(rssi: i32) -> Decimal {
@@ -77,23 +370,143 @@ pub mod rssi {
     }
 }
 
+/// The proto carries the frequency as a uint32 of kHz, which fits any sub-GHz or 2.4 GHz
+/// channel. LoRa channel plans are on 100 kHz steps, so nothing is lost.
 pub mod frequency {
     use super::*;
 
-    const FREQUENCY_PROTO_SCALAR: Decimal = Decimal::from_parts(1, 0, 0, false, 3);
+    /// Rounds to the nearest kHz, saturating at `u32::MAX` kHz
+    pub fn to_proto_units(frequency: FrequencyHz) -> u32 {
+        let khz = frequency.0.saturating_add(500) / 1_000;
+        u32::try_from(khz).unwrap_or(u32::MAX)
+    }
+
+    pub fn from_proto_units(frequency: u32) -> FrequencyHz {
+        FrequencyHz::from_khz(frequency.into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn gateway_at(gps: &Gps, rssi: Decimal, snr: Decimal) -> LoraGw {
+        LoraGw {
+            h3_cell: gps.to_h3_cell(h3o::Resolution::Twelve).unwrap().into(),
+            snr,
+            rssi,
+            frequency: FrequencyHz::from_khz(903_900),
+            ..LoraGw::random()
+        }
+    }
+
+    #[test]
+    fn negative_snr_roundtrip() {
+        use helium_proto::Message;
+        let gateway = gateway_at(
+            &Gps::rounded(),
+            Decimal::new(-1205, 1),
+            Decimal::new(-205, 1),
+        );
+        let proto = helium_proto::LoraGw::from(gateway.clone());
+        assert_eq!(proto.snr, -205);
+        let decoded = helium_proto::LoraGw::decode(proto.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(LoraGw::try_from(decoded).unwrap(), gateway);
+
+        assert_eq!(
+            snr::from_legacy_u32_units(-205i32 as u32),
+            Decimal::new(-205, 1)
+        );
+        assert_eq!(snr::from_legacy_u32_units(55), Decimal::new(55, 1));
+    }
+
+    #[test]
+    fn snr_and_rssi_proto_units_out_of_range() {
+        let huge = Decimal::new(1_000_000_000, 0);
+        assert!(matches!(
+            snr::to_proto_units_with_mode(huge, Rounding::HalfEven, EncodeMode::Strict),
+            Err(Error::OutOfRange {
+                field: "snr",
+                value: 10_000_000_000
+            })
+        ));
+        assert!(matches!(
+            rssi::to_proto_units_with_mode(-huge, Rounding::HalfEven, EncodeMode::Strict),
+            Err(Error::OutOfRange { field: "rssi", .. })
+        ));
+        assert_eq!(snr::to_proto_units(huge), i32::MAX);
+        assert_eq!(rssi::to_proto_units(-huge), i32::MIN);
+        assert_eq!(
+            rssi::to_proto_units_with_mode(
+                Decimal::new(-1205, 1),
+                Rounding::HalfEven,
+                EncodeMode::Strict
+            )
+            .unwrap(),
+            -12050
+        );
+    }
+
+    #[test]
+    fn frequency_units_and_region() {
+        let frequency = FrequencyHz::from_khz(904_300);
+        assert_eq!(frequency.0, 904_300_000);
+        assert_eq!(frequency.to_string(), "904.3MHz");
+        assert_eq!(frequency::to_proto_units(frequency), 904_300);
+        assert_eq!(frequency::from_proto_units(904_300), frequency);
+        assert_eq!(frequency::to_proto_units(FrequencyHz(u64::MAX)), u32::MAX);
+        assert_eq!(serde_json::to_string(&frequency).unwrap(), "904300000");
+        assert_eq!(
+            serde_json::from_str::<FrequencyHz>("\"904.300\"").unwrap(),
+            frequency
+        );
+        assert!(serde_json::from_str::<FrequencyHz>("\"-1\"").is_err());
+        // a legacy whole MHz integer is not read as Hz
+        assert!(serde_json::from_str::<FrequencyHz>("915").is_err());
+        assert_eq!(
+            serde_json::from_str::<FrequencyHz>("\"915\"").unwrap(),
+            FrequencyHz::from_mhz(915)
+        );
+        assert_eq!(
+            serde_json::from_str::<FrequencyHz>("915000000").unwrap(),
+            FrequencyHz::from_mhz(915)
+        );
+
+        let gateway = gateway_at(&Gps::rounded(), Decimal::new(-90, 0), Decimal::new(5, 0));
+        assert!(gateway.check_region(Region::US915).is_ok());
+        assert!(matches!(
+            gateway.check_region(Region::EU868),
+            Err(Error::FrequencyOutOfRegion {
+                region: Region::EU868,
+                ..
+            })
+        ));
+    }
 
-    pub fn to_proto_units(frequency: Decimal) -> u32 {
-        let scaled = frequency
-            .checked_div(FREQUENCY_PROTO_SCALAR)
-            .unwrap()
-            .round();
-        scaled.to_string().parse::<u32>().unwrap()
+    #[test]
+    fn plausible_link() {
+        let gps = Gps::rounded();
+        let gateway = gateway_at(&gps, Decimal::new(-90, 0), Decimal::new(5, 0));
+        let plausibility = gateway.plausible_for(&gps, 3.0).unwrap();
+        assert!(plausibility.is_plausible());
+        assert_eq!(plausibility.score, 1.0);
     }
 
-    pub fn from_proto_units(frequency: u32) -> Decimal {
-        let frequency_unscaled = Decimal::new(frequency.into(), 0);
-        frequency_unscaled
-            .checked_mul(FREQUENCY_PROTO_SCALAR)
-            .unwrap()
+    #[test]
+    fn too_strong_and_below_floor() {
+        let near = Gps::rounded();
+        let mut far = near;
+        far.lat += Decimal::ONE;
+        let gateway = gateway_at(&far, Decimal::new(-40, 0), Decimal::new(-30, 0));
+        let plausibility = gateway.plausible_for(&near, 3.0).unwrap();
+        assert!(plausibility.distance_m > 100_000.0);
+        assert!(matches!(
+            plausibility.reasons[..],
+            [
+                ImplausibleReason::RssiTooStrong { .. },
+                ImplausibleReason::SnrBelowFloor { .. }
+            ]
+        ));
+        assert!(plausibility.score < 0.01);
     }
 }
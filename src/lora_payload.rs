@@ -1,52 +1,204 @@
-use super::{keys::KeyTrait, Error, PublicKey, Result, Verify};
+use super::{keys::KeyTrait, Deserialize, Error, PublicKey, Result, Serialize, SignedBytes};
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use std::borrow::Cow;
 
+/// What to do with a field that doesn't fit its LoRa encoding, eg: an HDOP above 10.23 or an
+/// RSRP below -150 dBm.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EncodeMode {
+    /// Reject the payload with `Error::OutOfRange`
+    Strict,
+    /// Clamp the field to the nearest value it can carry, keeping the report
+    #[default]
+    Saturating,
+}
+
+impl EncodeMode {
+    /// Fits `value` into an unsigned field `bits` wide
+    pub(crate) fn fit(self, field: &'static str, value: i128, bits: u32) -> Result<u64> {
+        Ok(self.fit_range(field, value, 0, (1i128 << bits) - 1)? as u64)
+    }
+
+    /// Same as `fit` for a value already scaled to LoRa units, rounding it half to even first
+    pub(crate) fn fit_scaled(self, field: &'static str, scaled: Decimal, bits: u32) -> Result<u64> {
+        self.fit(field, saturating_i128(scaled.round()), bits)
+    }
+
+    /// Fits a value scaled to proto units and already rounded into an int32 field
+    pub(crate) fn fit_i32(self, field: &'static str, scaled: Decimal) -> Result<i32> {
+        let value = saturating_i128(scaled);
+        Ok(self.fit_range(field, value, i32::MIN.into(), i32::MAX.into())? as i32)
+    }
+
+    /// Fits a value scaled to proto units and already rounded into a uint32 field
+    pub(crate) fn fit_u32(self, field: &'static str, scaled: Decimal) -> Result<u32> {
+        Ok(self.fit(field, saturating_i128(scaled), 32)? as u32)
+    }
+
+    fn fit_range(self, field: &'static str, value: i128, min: i128, max: i128) -> Result<i128> {
+        match self {
+            _ if (min..=max).contains(&value) => Ok(value),
+            EncodeMode::Strict => Err(Error::OutOfRange { field, value }),
+            EncodeMode::Saturating => Ok(value.clamp(min, max)),
+        }
+    }
+}
+
+/// The integer part of `value`, saturating at the bounds of `i128`
+fn saturating_i128(value: Decimal) -> i128 {
+    value.to_i128().unwrap_or(if value.is_sign_negative() {
+        i128::MIN
+    } else {
+        i128::MAX
+    })
+}
+
+/// Encodes a payload into its LoRa frame. Fixed size payloads use `[u8; N]` as their `Bytes`,
+/// variable length ones a `Vec<u8>`.
+pub trait LoraEncode {
+    type Bytes: AsRef<[u8]>;
+
+    fn to_lora_bytes_with_mode(&self, mode: EncodeMode) -> Result<Self::Bytes>;
+
+    /// Encodes with `EncodeMode::Saturating`, which can't fail
+    fn to_lora_bytes(&self) -> Self::Bytes {
+        self.to_lora_bytes_with_mode(EncodeMode::Saturating)
+            .expect("saturating encode")
+    }
+
+    /// The frame followed by the signature over it, minus the DER header
+    fn to_lora_bytes_with_signature<K: KeyTrait + ?Sized>(&self, key: &K) -> Result<Vec<u8>> {
+        self.to_lora_bytes_with_signature_and_mode(key, EncodeMode::Saturating)
+    }
+
+    fn to_lora_bytes_with_signature_and_mode<K: KeyTrait + ?Sized>(
+        &self,
+        key: &K,
+        mode: EncodeMode,
+    ) -> Result<Vec<u8>> {
+        let bytes = self.to_lora_bytes_with_mode(mode)?;
+        let mut frame = bytes.as_ref().to_vec();
+        frame.extend_from_slice(&sign_lora_frame(key, bytes.as_ref())?);
+        Ok(frame)
+    }
+}
+
+/// Decodes a payload from the front of a LoRa frame
+pub trait LoraDecode: Sized {
+    /// Returns the payload together with the number of bytes it used, leaving whatever trails it
+    /// (a signature, the next payload of a stream) to the caller.
+    fn from_lora_slice(bytes: &[u8]) -> Result<(Self, usize)>;
+
+    fn from_lora_slice_with_verified_signature(pubkey: &PublicKey, bytes: &[u8]) -> Result<Self> {
+        let (payload, used) = Self::from_lora_slice(bytes)?;
+        verify_lora_frame(pubkey, &bytes[..used], &bytes[used..])?;
+        Ok(payload)
+    }
+}
+
+/// Signs a LoRa frame. ECDSA signatures lose the first two bytes of their DER encoding since the
+/// receiver can infer them, Ed25519 signatures are raw and kept whole.
+/// `LoraEncode::to_lora_bytes_with_signature` appends this to the frame; this is for backhauls
+/// that carry the signature apart from the frame.
+pub fn sign_lora_frame<K: KeyTrait + ?Sized>(key: &K, frame: &[u8]) -> Result<Vec<u8>> {
+    let pubkey = key.pubkey().map_err(|e| Error::Key(e.to_string()))?;
+    let mut signature = key
+        .sign_bytes(&SignedBytes::from_lora_frame(frame))
+        .map_err(|e| Error::Key(e.to_string()))?;
+    if !is_der_signed(&pubkey) {
+        return Ok(signature);
+    }
+    if signature.first() != Some(&0x30) {
+        return Err(Error::Key("expected a DER encoded signature".to_string()));
+    }
+    Ok(signature.split_off(2))
+}
+
+/// Verifies the output of `sign_lora_frame` over `frame`
+pub fn verify_lora_frame(pubkey: &PublicKey, frame: &[u8], signature_tail: &[u8]) -> Result {
+    let frame = SignedBytes::from_lora_frame(frame);
+    if !is_der_signed(pubkey) {
+        return frame.verify(pubkey, signature_tail);
+    }
+    // add back in the first two bytes of the signature
+    let mut signature = vec![0x30, signature_tail.len() as u8];
+    signature.extend_from_slice(signature_tail);
+    frame.verify(pubkey, &signature)
+}
+
+/// Whether the key signs with DER encoded ECDSA, rather than Ed25519
+fn is_der_signed(pubkey: &PublicKey) -> bool {
+    pubkey.key_tag().key_type != helium_crypto::KeyType::Ed25519
+}
+
+/// Splits a fixed size frame off the front of `bytes` for `LoraDecode` implementations
+pub(crate) fn split_fixed<const N: usize>(label: &'static str, bytes: &[u8]) -> Result<[u8; N]> {
+    bytes
+        .get(..N)
+        .and_then(|frame| frame.try_into().ok())
+        .ok_or(Error::InvalidVecForParsingLoraPayload {
+            payload: label,
+            size: bytes.len(),
+            expected: N,
+        })
+}
+
+/// The original by-value, fixed size codec, kept for existing callers. New code should use
+/// `LoraEncode` and `LoraDecode`, which Beacon and CellAttach also implement.
 pub trait IntoFromLoraPayload<const N: usize> {
     fn into_lora_bytes_with_signature<K: KeyTrait>(self, key: &K) -> Result<Vec<u8>>
     where
         Self: Sized,
     {
         let bytes = self.into_lora_bytes();
-        let signature = key.sign(&bytes).map_err(|e| Error::Key(e.to_string()))?;
-        // remove the first two bytes because we can infer them later
-        let mut bytes = bytes.to_vec();
-        bytes.append(&mut signature[2..].to_vec());
-        Ok(bytes)
+        let mut frame = bytes.to_vec();
+        frame.append(&mut sign_lora_frame(key, &bytes)?);
+        Ok(frame)
     }
 
     fn from_lora_vec_with_verified_signature(pubkey: &PublicKey, vec: Vec<u8>) -> Result<Self>
     where
         Self: Sized,
     {
-        let size = vec.len();
-        if size < N {
-            return Err(Error::InvalidVecForParsingLoraPayload {
-                payload: Self::label(),
-                size,
-            });
-        }
-        let bytes: [u8; N] =
-            vec[..N]
-                .try_into()
-                .map_err(|_| Error::InvalidVecForParsingLoraPayload {
-                    payload: Self::label(),
-                    size,
-                })?;
-
-        let signature_bytes = &vec[N..];
-        // add back in the first two bytes of the signature
-        let mut signature = vec![0x30, signature_bytes.len() as u8];
-        signature.append(&mut signature_bytes.to_vec());
-        pubkey
-            .verify(&bytes, &signature)
-            .map_err(|_| Error::SignatureVerification {
-                pubkey: Box::new(pubkey.clone()),
-                msg: bytes.to_vec(),
-                signature: signature.to_vec(),
-            })?;
-
-        Ok(Self::from_lora_bytes(bytes))
+        let bytes: [u8; N] = split_fixed(Self::label(), &vec)?;
+        verify_lora_frame(pubkey, &bytes, &vec[N..])?;
+        Self::from_lora_bytes(bytes)
     }
     fn into_lora_bytes(self) -> [u8; N];
-    fn from_lora_bytes(bytes: [u8; N]) -> Self;
+    /// Fails on frames with field values the payload has no variant for
+    fn from_lora_bytes(bytes: [u8; N]) -> Result<Self>
+    where
+        Self: Sized;
     fn label() -> &'static str;
 }
+
+/// One field of a packed frame: its raw bits and what they decode to. The name is borrowed
+/// when dumped and owned when deserialized.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldDump {
+    pub name: Cow<'static, str>,
+    pub raw: u64,
+    pub decoded: String,
+}
+
+impl FieldDump {
+    pub(crate) fn new(name: &'static str, raw: impl Into<u64>, decoded: impl ToString) -> Self {
+        Self {
+            name: Cow::Borrowed(name),
+            raw: raw.into(),
+            decoded: decoded.to_string(),
+        }
+    }
+}
+
+/// Field by field view of a payload's LoRa frame, for debug tooling and error messages
+pub trait DumpFields: LoraEncode {
+    /// Dumps a frame as received, without validating it against the payload
+    fn dump_lora_frame(bytes: &[u8]) -> Result<Vec<FieldDump>>;
+
+    /// Dumps the frame this payload encodes to, as saturated by `EncodeMode::Saturating`
+    fn dump_fields(&self) -> Vec<FieldDump> {
+        Self::dump_lora_frame(self.to_lora_bytes().as_ref()).expect("dump of encoded frame")
+    }
+}
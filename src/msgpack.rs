@@ -0,0 +1,26 @@
+//! MessagePack encoding of our serde shapes, for local transports between edge processes. The
+//! encoding is `rmp_serde::to_vec_named`: structs are maps keyed by field name, as in JSON, so
+//! that decoders tolerate the optional fields skipped when unset. Positional (array) encoding
+//! would not survive those skipped fields and is not offered.
+use super::{Message, Payload, Result};
+
+impl Message {
+    pub fn to_msgpack(&self) -> Result<Vec<u8>> {
+        Ok(rmp_serde::to_vec_named(self)?)
+    }
+
+    /// Like JSON, the signature is not verified
+    pub fn from_msgpack(bytes: &[u8]) -> Result<Self> {
+        Ok(rmp_serde::from_slice(bytes)?)
+    }
+}
+
+impl Payload {
+    pub fn to_msgpack(&self) -> Result<Vec<u8>> {
+        Ok(rmp_serde::to_vec_named(self)?)
+    }
+
+    pub fn from_msgpack(bytes: &[u8]) -> Result<Self> {
+        Ok(rmp_serde::from_slice(bytes)?)
+    }
+}
@@ -0,0 +1,364 @@
+//! Builds `Gps` fixes from the NMEA 0183 output of a GNSS module. A fix is emitted once a GGA
+//! (quality, satellites, HDOP, altitude) and an RMC (date, speed) of the same time were read.
+//!
+//! `Mode::Strict` rejects any sentence off the standard. `Mode::Lenient` is meant for cheap
+//! modules and recovers what it can sentence by sentence: any talker (GN, GP, GQ...), missing
+//! checksums, extra or missing trailing fields, garbage before the `$`, and speed or altitude
+//! left empty, which are taken as 0. Sentences with a bad checksum or without a usable position
+//! are always dropped. `NmeaStats` counts what happened to every line.
+use super::{Error, Gps, Result};
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeZone, Utc};
+use rust_decimal::Decimal;
+
+const STANDARD_TALKERS: [&str; 6] = ["GP", "GL", "GA", "GB", "BD", "GN"];
+
+const KNOTS_TO_KMH: Decimal = Decimal::from_parts(1852, 0, 0, false, 3);
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    Strict,
+    #[default]
+    Lenient,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct NmeaStats {
+    /// Sentences read without recovery
+    pub parsed: u64,
+    /// Sentences read thanks to `Mode::Lenient`
+    pub recovered: u64,
+    /// Sentences not understood, or without a position
+    pub dropped: u64,
+    /// Well formed sentences of a type the parser doesn't use, eg: GSV
+    pub ignored: u64,
+    /// Fixes emitted
+    pub fixes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Gga {
+    time: NaiveTime,
+    lat: Decimal,
+    lon: Decimal,
+    num_sats: u8,
+    hdop: Decimal,
+    altitude: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Rmc {
+    timestamp: DateTime<Utc>,
+    speed: Decimal,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct NmeaParser {
+    mode: Mode,
+    stats: NmeaStats,
+    gga: Option<Gga>,
+    rmc: Option<Rmc>,
+}
+
+impl NmeaParser {
+    pub fn new(mode: Mode) -> Self {
+        Self {
+            mode,
+            ..Default::default()
+        }
+    }
+
+    pub fn stats(&self) -> NmeaStats {
+        self.stats
+    }
+
+    /// Reads one line, returning the fix it completes if any. Malformed lines fail in
+    /// `Mode::Strict` and are counted and skipped in `Mode::Lenient`.
+    pub fn push_line(&mut self, line: &str) -> Result<Option<Gps>> {
+        let mut recovered = false;
+        let parsed = self.parse(line, &mut recovered);
+        match parsed {
+            Ok(Some(sentence)) => {
+                if recovered {
+                    self.stats.recovered += 1;
+                } else {
+                    self.stats.parsed += 1;
+                }
+                Ok(self.combine(sentence))
+            }
+            Ok(None) => {
+                self.stats.ignored += 1;
+                Ok(None)
+            }
+            Err(reason) => {
+                self.stats.dropped += 1;
+                match self.mode {
+                    Mode::Strict => Err(Error::InvalidNmea(reason)),
+                    Mode::Lenient => Ok(None),
+                }
+            }
+        }
+    }
+
+    /// Fixes of every line of `text`, skipping malformed lines whatever the mode
+    pub fn push_text(&mut self, text: &str) -> Vec<Gps> {
+        text.lines()
+            .filter_map(|line| self.push_line(line).ok().flatten())
+            .collect()
+    }
+
+    fn combine(&mut self, sentence: Sentence) -> Option<Gps> {
+        match sentence {
+            Sentence::Gga(gga) => self.gga = Some(gga),
+            Sentence::Rmc(rmc) => self.rmc = Some(rmc),
+        }
+        let (gga, rmc) = (self.gga.as_ref()?, self.rmc.as_ref()?);
+        if gga.time != rmc.timestamp.time() {
+            return None;
+        }
+        let fix = Gps {
+            timestamp: rmc.timestamp,
+            lat: gga.lat,
+            lon: gga.lon,
+            hdop: gga.hdop,
+            altitude: gga.altitude,
+            num_sats: gga.num_sats,
+            speed: rmc.speed,
+            h_acc_m: None,
+            v_acc_m: None,
+            jamming: None,
+            spoofing: None,
+        };
+        self.gga = None;
+        self.rmc = None;
+        self.stats.fixes += 1;
+        Some(fix)
+    }
+
+    /// `Ok(None)` for sentence types that are not used
+    fn parse(
+        &self,
+        line: &str,
+        recovered: &mut bool,
+    ) -> std::result::Result<Option<Sentence>, &'static str> {
+        let lenient = self.mode == Mode::Lenient;
+        let mut line = line.trim();
+        if !line.starts_with('$') {
+            let start = line.find('$').filter(|_| lenient).ok_or("no leading $")?;
+            line = &line[start..];
+            *recovered = true;
+        }
+        let body = match line[1..].split_once('*') {
+            Some((body, checksum)) => {
+                let expected =
+                    u8::from_str_radix(checksum.trim(), 16).map_err(|_| "bad checksum")?;
+                if body.bytes().fold(0, |sum, byte| sum ^ byte) != expected {
+                    return Err("checksum mismatch");
+                }
+                body
+            }
+            None if lenient => {
+                *recovered = true;
+                &line[1..]
+            }
+            None => return Err("no checksum"),
+        };
+        let mut fields = body.split(',');
+        let address = fields.next().unwrap_or_default();
+        if address.len() != 5 || !address.is_ascii() {
+            return Err("bad address");
+        }
+        let (talker, kind) = address.split_at(2);
+        if !STANDARD_TALKERS.contains(&talker) {
+            if !lenient || !talker.bytes().all(|byte| byte.is_ascii_uppercase()) {
+                return Err("unknown talker");
+            }
+            *recovered = true;
+        }
+        let fields: Vec<&str> = fields.collect();
+        let mut fields = Fields {
+            fields,
+            lenient,
+            recovered,
+        };
+        match kind {
+            "GGA" => fields.gga().map(|gga| Some(Sentence::Gga(gga))),
+            "RMC" => fields.rmc().map(|rmc| Some(Sentence::Rmc(rmc))),
+            _ => Ok(None),
+        }
+    }
+}
+
+enum Sentence {
+    Gga(Gga),
+    Rmc(Rmc),
+}
+
+struct Fields<'a, 'r> {
+    fields: Vec<&'a str>,
+    lenient: bool,
+    recovered: &'r mut bool,
+}
+
+impl Fields<'_, '_> {
+    /// Strict mode wants exactly one of `counts` fields
+    fn check_count(&mut self, counts: &[usize]) -> std::result::Result<(), &'static str> {
+        if counts.contains(&self.fields.len()) {
+            Ok(())
+        } else if self.lenient {
+            *self.recovered = true;
+            Ok(())
+        } else {
+            Err("wrong field count")
+        }
+    }
+
+    fn get(&self, index: usize) -> Option<&str> {
+        self.fields
+            .get(index)
+            .copied()
+            .filter(|field| !field.is_empty())
+    }
+
+    fn required(&self, index: usize) -> std::result::Result<&str, &'static str> {
+        self.get(index).ok_or("missing field")
+    }
+
+    /// Missing values are 0 in lenient mode
+    fn defaulted(&mut self, index: usize) -> std::result::Result<Decimal, &'static str> {
+        match self.get(index) {
+            Some(field) => decimal(field),
+            None if self.lenient => {
+                *self.recovered = true;
+                Ok(Decimal::ZERO)
+            }
+            None => Err("missing field"),
+        }
+    }
+
+    fn coordinate(&self, index: usize) -> std::result::Result<Decimal, &'static str> {
+        let raw = decimal(self.required(index)?)?;
+        let degrees = (raw / Decimal::ONE_HUNDRED).trunc();
+        let minutes = raw - degrees * Decimal::ONE_HUNDRED;
+        let value = (degrees + minutes / Decimal::from(60)).round_dp(7);
+        match self.required(index + 1)? {
+            "N" | "E" => Ok(value),
+            "S" | "W" => Ok(-value),
+            _ => Err("bad hemisphere"),
+        }
+    }
+
+    fn gga(&mut self) -> std::result::Result<Gga, &'static str> {
+        self.check_count(&[14])?;
+        if self.get(5).unwrap_or("0") == "0" {
+            return Err("no fix");
+        }
+        Ok(Gga {
+            time: time(self.required(0)?)?,
+            lat: self.coordinate(1)?,
+            lon: self.coordinate(3)?,
+            num_sats: self.required(6)?.parse().map_err(|_| "bad satellites")?,
+            hdop: decimal(self.required(7)?)?,
+            altitude: self.defaulted(8)?,
+        })
+    }
+
+    fn rmc(&mut self) -> std::result::Result<Rmc, &'static str> {
+        self.check_count(&[11, 12, 13])?;
+        if self.required(1)? != "A" {
+            return Err("no fix");
+        }
+        let date = self.required(8)?;
+        let date = (date.len() == 6 && date.bytes().all(|byte| byte.is_ascii_digit()))
+            .then(|| {
+                let part = |range: std::ops::Range<usize>| date[range].parse::<u32>().ok();
+                NaiveDate::from_ymd_opt(2000 + part(4..6)? as i32, part(2..4)?, part(0..2)?)
+            })
+            .flatten()
+            .ok_or("bad date")?;
+        let time = time(self.required(0)?)?;
+        Ok(Rmc {
+            timestamp: Utc.from_utc_datetime(&date.and_time(time)),
+            speed: (self.defaulted(6)? * KNOTS_TO_KMH).round_dp(2),
+        })
+    }
+}
+
+fn decimal(field: &str) -> std::result::Result<Decimal, &'static str> {
+    field.parse().map_err(|_| "bad number")
+}
+
+/// `hhmmss` with optional fractional seconds
+fn time(field: &str) -> std::result::Result<NaiveTime, &'static str> {
+    let (hms, fraction) = field.split_once('.').unwrap_or((field, ""));
+    if hms.len() != 6 || !hms.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err("bad time");
+    }
+    let part = |range: std::ops::Range<usize>| hms[range].parse::<u32>().unwrap_or_default();
+    let nanos = if fraction.is_empty() {
+        0
+    } else {
+        let digits: String = fraction
+            .chars()
+            .chain("000000000".chars())
+            .take(9)
+            .collect();
+        digits.parse().map_err(|_| "bad time")?
+    };
+    NaiveTime::from_hms_nano_opt(part(0..2), part(2..4), part(4..6), nanos).ok_or("bad time")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sentence(body: &str) -> String {
+        let checksum = body.bytes().fold(0, |sum, byte| sum ^ byte);
+        format!("${body}*{checksum:02X}")
+    }
+
+    #[test]
+    fn strict_and_lenient_fixes() {
+        let gga = sentence("GPGGA,000005.00,5007.407,S,12007.407,E,1,05,9.05,9.25,M,0.0,M,,");
+        let rmc = sentence("GPRMC,000005.00,A,5007.407,S,12007.407,E,27.27,0.0,010123,,,A");
+        let mut strict = NmeaParser::new(Mode::Strict);
+        assert_eq!(strict.push_line(&gga).unwrap(), None);
+        let fix = strict.push_line(&rmc).unwrap().unwrap();
+        assert_eq!(fix.timestamp, Gps::rounded().timestamp);
+        assert_eq!(fix.lat.round_dp(5), Gps::rounded().lat);
+        assert_eq!((fix.hdop, fix.num_sats), (Decimal::new(905, 2), 5));
+        assert_eq!(fix.speed, Decimal::new(50_50, 2));
+        // six bytes, but not six digits
+        let bad_date =
+            sentence("GPRMC,000005.00,A,5007.407,S,12007.407,E,27.27,0.0,0\u{e9}123,,,A");
+        assert!(strict.push_line(&bad_date).is_err());
+
+        // GN talker, no checksum, noise before the $, empty speed and a chopped trailer
+        let sloppy = [
+            "\u{0}\u{0}$GNGGA,000005.00,5007.407,S,12007.407,E,1,05,9.05,,M".to_string(),
+            "$GQRMC,000005.00,A,5007.407,S,12007.407,E,,0.0,010123".to_string(),
+        ];
+        assert!(strict.push_line(&sloppy[0]).is_err());
+        let mut lenient = NmeaParser::new(Mode::Lenient);
+        let fixes = lenient.push_text(&sloppy.join("\r\n"));
+        assert_eq!(fixes.len(), 1);
+        assert_eq!(
+            (fixes[0].altitude, fixes[0].speed),
+            (Decimal::ZERO, Decimal::ZERO)
+        );
+
+        let mut corrupted = gga;
+        corrupted.replace_range(10..11, "9");
+        assert_eq!(lenient.push_line(&corrupted).unwrap(), None);
+        assert_eq!(lenient.push_line(&sentence("GPGSV,1,1,00")).unwrap(), None);
+        assert_eq!(
+            lenient.stats(),
+            NmeaStats {
+                parsed: 0,
+                recovered: 2,
+                dropped: 1,
+                ignored: 1,
+                fixes: 1
+            }
+        );
+    }
+}
@@ -0,0 +1,281 @@
+//! Building blocks for the usual firmware dataflow: GPS, modem and radio tasks exchange values
+//! over bounded channels and the radio task drains all producers into a transmit queue that
+//! sends payloads by `Priority` (by default failed attaches, then beacons, then telemetry).
+//!
+//! Capacity is bounded everywhere. When the transmit queue is full for a priority,
+//! `FanIn` stops pulling from the producer that is blocked, so the producer's channel fills up
+//! and its `send` blocks (or `try_send` fails) instead of memory growing.
+use super::{Payload, Priority};
+pub use std::sync::mpsc::Receiver;
+use std::{
+    collections::VecDeque,
+    sync::mpsc::{self, RecvTimeoutError, SyncSender, TryRecvError, TrySendError},
+    time::Duration,
+};
+
+/// Why a value could not be handed over
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Backpressure<T> {
+    /// The receiving side is full, the value is returned
+    Full(T),
+    /// The receiving side is gone, the value is returned
+    Disconnected(T),
+}
+
+impl<T> Backpressure<T> {
+    pub fn into_inner(self) -> T {
+        match self {
+            Backpressure::Full(t) | Backpressure::Disconnected(t) => t,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Sender<T> {
+    inner: SyncSender<T>,
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Sender<T> {
+    /// Blocks while the channel is full
+    pub fn send(&self, value: T) -> Result<(), Backpressure<T>> {
+        self.inner
+            .send(value)
+            .map_err(|e| Backpressure::Disconnected(e.0))
+    }
+
+    pub fn try_send(&self, value: T) -> Result<(), Backpressure<T>> {
+        self.inner.try_send(value).map_err(|e| match e {
+            TrySendError::Full(t) => Backpressure::Full(t),
+            TrySendError::Disconnected(t) => Backpressure::Disconnected(t),
+        })
+    }
+}
+
+/// Bounded channel between two tasks
+pub fn bounded<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let (inner, receiver) = mpsc::sync_channel(capacity);
+    (Sender { inner }, receiver)
+}
+
+/// Payloads waiting for the radio, served by `Priority` and FIFO within a priority
+#[derive(Debug, Clone)]
+pub struct TransmitQueue {
+    queues: [VecDeque<(Payload, Priority)>; 4],
+    capacity_per_priority: usize,
+}
+
+impl TransmitQueue {
+    pub fn new(capacity_per_priority: usize) -> Self {
+        Self {
+            queues: Default::default(),
+            capacity_per_priority,
+        }
+    }
+
+    /// Queues the payload at its default priority
+    pub fn push(&mut self, payload: Payload) -> Result<(), Backpressure<Payload>> {
+        let priority = payload.default_priority();
+        self.push_with_priority(payload, priority)
+    }
+
+    pub fn push_with_priority(
+        &mut self,
+        payload: Payload,
+        priority: Priority,
+    ) -> Result<(), Backpressure<Payload>> {
+        let queue = &mut self.queues[priority.index()];
+        if queue.len() >= self.capacity_per_priority {
+            return Err(Backpressure::Full(payload));
+        }
+        queue.push_back((payload, priority));
+        Ok(())
+    }
+
+    /// Next payload to transmit
+    pub fn pop(&mut self) -> Option<Payload> {
+        self.pop_with_priority().map(|(payload, _)| payload)
+    }
+
+    pub fn pop_with_priority(&mut self) -> Option<(Payload, Priority)> {
+        self.queues.iter_mut().find_map(|queue| queue.pop_front())
+    }
+
+    pub fn len(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Fan-in of several producers into a `TransmitQueue`
+#[derive(Debug, Default)]
+pub struct FanIn {
+    inputs: Vec<Input>,
+}
+
+#[derive(Debug)]
+struct Input {
+    receiver: Receiver<Payload>,
+    /// value taken from the channel that did not fit in the queue yet
+    pending: Option<Payload>,
+    disconnected: bool,
+}
+
+impl FanIn {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, receiver: Receiver<Payload>) {
+        self.inputs.push(Input {
+            receiver,
+            pending: None,
+            disconnected: false,
+        });
+    }
+
+    /// Moves everything that fits from the producers into the queue without blocking and
+    /// returns how many payloads were moved
+    pub fn drain_into(&mut self, queue: &mut TransmitQueue) -> usize {
+        let mut moved = 0;
+        for input in &mut self.inputs {
+            loop {
+                let payload = match input.pending.take() {
+                    Some(payload) => payload,
+                    None => match input.receiver.try_recv() {
+                        Ok(payload) => payload,
+                        Err(TryRecvError::Empty) => break,
+                        Err(TryRecvError::Disconnected) => {
+                            input.disconnected = true;
+                            break;
+                        }
+                    },
+                };
+                match queue.push(payload) {
+                    Ok(()) => moved += 1,
+                    Err(backpressure) => {
+                        input.pending = Some(backpressure.into_inner());
+                        break;
+                    }
+                }
+            }
+        }
+        moved
+    }
+
+    /// Waits up to `timeout` on the first producer when nothing is available, then drains
+    pub fn wait_and_drain_into(&mut self, queue: &mut TransmitQueue, timeout: Duration) -> usize {
+        let moved = self.drain_into(queue);
+        if moved > 0 || !queue.is_empty() {
+            return moved;
+        }
+        if let Some(input) = self.inputs.iter_mut().find(|i| !i.disconnected) {
+            match input.receiver.recv_timeout(timeout) {
+                Ok(payload) => input.pending = Some(payload),
+                Err(RecvTimeoutError::Disconnected) => input.disconnected = true,
+                Err(RecvTimeoutError::Timeout) => (),
+            }
+        }
+        self.drain_into(queue)
+    }
+
+    /// true once every producer is gone and nothing is pending
+    pub fn is_finished(&self) -> bool {
+        self.inputs
+            .iter()
+            .all(|i| i.disconnected && i.pending.is_none())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Beacon, CellScan, Gps, PayloadKind};
+
+    #[test]
+    fn beacons_go_first() {
+        let (gps_tx, gps_rx) = bounded(4);
+        let (radio_tx, radio_rx) = bounded(4);
+        let mut fan_in = FanIn::new();
+        fan_in.add(gps_rx);
+        fan_in.add(radio_rx);
+
+        gps_tx.send(Payload::Gps(Gps::rounded())).unwrap();
+        gps_tx.send(Payload::CellScan(CellScan::random())).unwrap();
+        radio_tx
+            .send(Payload::Beacon(Beacon::new(
+                Gps::rounded(),
+                vec![0xAB, 0xCD],
+            )))
+            .unwrap();
+
+        let mut queue = TransmitQueue::new(8);
+        assert_eq!(fan_in.drain_into(&mut queue), 3);
+        let kinds: Vec<PayloadKind> = std::iter::from_fn(|| queue.pop())
+            .map(|p| p.kind())
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![PayloadKind::Beacon, PayloadKind::Gps, PayloadKind::CellScan]
+        );
+
+        drop(gps_tx);
+        drop(radio_tx);
+        fan_in.drain_into(&mut queue);
+        assert!(fan_in.is_finished());
+    }
+
+    #[test]
+    fn priority_override() {
+        let mut queue = TransmitQueue::new(2);
+        queue
+            .push(Payload::Beacon(Beacon::new(
+                Gps::rounded(),
+                vec![0xAB, 0xCD],
+            )))
+            .unwrap();
+        queue
+            .push_with_priority(Payload::Gps(Gps::rounded()), Priority::Urgent)
+            .unwrap();
+        assert_eq!(
+            queue
+                .pop_with_priority()
+                .map(|(p, priority)| (p.kind(), priority)),
+            Some((PayloadKind::Gps, Priority::Urgent))
+        );
+        assert_eq!(queue.pop().map(|p| p.kind()), Some(PayloadKind::Beacon));
+    }
+
+    #[test]
+    fn full_queue_pushes_back_on_producer() {
+        let (tx, rx) = bounded(1);
+        let mut fan_in = FanIn::new();
+        fan_in.add(rx);
+        let mut queue = TransmitQueue::new(1);
+
+        tx.try_send(Payload::Gps(Gps::rounded())).unwrap();
+        assert_eq!(fan_in.drain_into(&mut queue), 1);
+        // held as pending by the fan-in since the queue is full
+        tx.try_send(Payload::Gps(Gps::rounded())).unwrap();
+        assert_eq!(fan_in.drain_into(&mut queue), 0);
+        // the channel fills up behind the pending value
+        tx.try_send(Payload::Gps(Gps::rounded())).unwrap();
+        assert!(matches!(
+            tx.try_send(Payload::Gps(Gps::rounded())),
+            Err(Backpressure::Full(_))
+        ));
+
+        assert!(queue.pop().is_some());
+        assert_eq!(fan_in.drain_into(&mut queue), 1);
+    }
+}
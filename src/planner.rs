@@ -0,0 +1,342 @@
+//! Picks how to frame a payload under a byte budget. Every variant that fits is decoded back and
+//! compared with the payload, so the reported losses are exactly what the receiver will see.
+#[cfg(feature = "beacon")]
+use super::Beacon;
+#[cfg(feature = "cell")]
+use super::CellAttach;
+use super::{
+    keys::KeyTrait, satellite::Satellite, Deserialize, EncodeMode, Error, Gps, LoraDecode,
+    LoraEncode, Message, Payload, Result, Serialize,
+};
+
+/// Framings in order of decreasing fidelity
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EncodingVariant {
+    /// Signed MapperMsg, as sent over IP backhauls
+    Proto,
+    /// Fixed LoRa frame followed by its signature
+    Lora,
+    /// Satellite frame followed by its signature, see `satellite`
+    Satellite,
+}
+
+impl EncodingVariant {
+    pub const ALL: [EncodingVariant; 3] = [
+        EncodingVariant::Proto,
+        EncodingVariant::Lora,
+        EncodingVariant::Satellite,
+    ];
+}
+
+/// Parts of a payload an encoding may lose
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    Timestamp,
+    Position,
+    Hdop,
+    Altitude,
+    Speed,
+    NumSats,
+    /// `Gps::h_acc_m` and `Gps::v_acc_m`
+    Accuracy,
+    BeaconSignature,
+    Candidate,
+    FailureCause,
+    Sim,
+    ScanResults,
+    /// `CellScanResult::observed_at`
+    ResultTimestamps,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Loss {
+    /// Carried with less precision (eg: coarser steps, a truncated signature)
+    Rounded(Field),
+    /// Not carried at all
+    Dropped(Field),
+    /// The weakest scan results were left out to fit
+    DroppedResults(usize),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncodingBudget {
+    pub max_bytes: usize,
+    /// Fields that must not be dropped. Rounding them is accepted.
+    pub required_fields: Vec<Field>,
+}
+
+impl EncodingBudget {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            required_fields: Vec::new(),
+        }
+    }
+
+    pub fn require(mut self, field: Field) -> Self {
+        self.required_fields.push(field);
+        self
+    }
+
+    fn allows(&self, losses: &[Loss]) -> bool {
+        losses.iter().all(|loss| match loss {
+            Loss::Dropped(field) => !self.required_fields.contains(field),
+            Loss::DroppedResults(_) => !self.required_fields.contains(&Field::ScanResults),
+            Loss::Rounded(_) => true,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncodingPlan {
+    pub variant: EncodingVariant,
+    /// The signed frame, ready to send
+    pub bytes: Vec<u8>,
+    pub losses: Vec<Loss>,
+}
+
+/// The highest fidelity variant whose signed frame fits `budget` without dropping a required
+/// field. Fails with `Error::NoEncodingWithinBudget` if there is none.
+pub fn plan<K: KeyTrait>(
+    payload: &Payload,
+    budget: &EncodingBudget,
+    key: &K,
+) -> Result<EncodingPlan> {
+    for variant in EncodingVariant::ALL {
+        let Some(plan) = encode(variant, payload, budget, key)? else {
+            continue;
+        };
+        if plan.bytes.len() <= budget.max_bytes && budget.allows(&plan.losses) {
+            return Ok(plan);
+        }
+    }
+    Err(Error::NoEncodingWithinBudget {
+        max_bytes: budget.max_bytes,
+    })
+}
+
+/// `None` if the variant has no encoding for the payload
+fn encode<K: KeyTrait>(
+    variant: EncodingVariant,
+    payload: &Payload,
+    budget: &EncodingBudget,
+    key: &K,
+) -> Result<Option<EncodingPlan>> {
+    let mode = EncodeMode::Saturating;
+    let mut losses = Vec::new();
+    let bytes = match (variant, payload) {
+        #[cfg(feature = "cell")]
+        (EncodingVariant::Proto, Payload::CellScan(scan)) => {
+            let mut scan = scan.clone();
+            scan.results.sort_by(|a, b| b.rsrp.cmp(&a.rsrp));
+            let total = scan.results.len();
+            loop {
+                let bytes = proto_bytes(key, Payload::CellScan(scan.clone()))?;
+                if bytes.len() <= budget.max_bytes || scan.results.is_empty() {
+                    if scan.results.len() < total {
+                        losses.push(Loss::DroppedResults(total - scan.results.len()));
+                    }
+                    // the extension carries them in ms
+                    let sub_ms = |at: chrono::DateTime<chrono::Utc>| {
+                        at.timestamp_subsec_nanos() % 1_000_000 != 0
+                    };
+                    if scan
+                        .results
+                        .iter()
+                        .any(|r| r.observed_at.is_some_and(sub_ms))
+                    {
+                        losses.push(Loss::Rounded(Field::ResultTimestamps));
+                    }
+                    break bytes;
+                }
+                scan.results.pop();
+            }
+        }
+        (EncodingVariant::Proto, _) => {
+            let bytes = proto_bytes(key, payload.clone())?;
+            let decoded = Message::decode_from(&bytes)?.payload;
+            payload_losses(payload, &decoded, &mut losses);
+            bytes
+        }
+        #[cfg(feature = "beacon")]
+        (EncodingVariant::Lora, Payload::Beacon(beacon)) => {
+            let bytes = beacon.to_lora_bytes_with_signature_and_mode(key, mode)?;
+            let (decoded, _) = Beacon::from_lora_slice(&bytes)?;
+            payload_losses(payload, &Payload::Beacon(decoded), &mut losses);
+            bytes
+        }
+        #[cfg(feature = "cell")]
+        (EncodingVariant::Lora, Payload::CellAttach(attach)) => {
+            let bytes = attach.to_lora_bytes_with_signature_and_mode(key, mode)?;
+            let (decoded, _) = CellAttach::from_lora_slice(&bytes)?;
+            payload_losses(payload, &Payload::CellAttach(decoded), &mut losses);
+            bytes
+        }
+        #[cfg(feature = "beacon")]
+        (EncodingVariant::Satellite, Payload::Beacon(beacon)) => {
+            let bytes =
+                Satellite(beacon.clone()).to_lora_bytes_with_signature_and_mode(key, mode)?;
+            let (Satellite(decoded), _) = Satellite::<Beacon>::from_lora_slice(&bytes)?;
+            payload_losses(payload, &Payload::Beacon(decoded), &mut losses);
+            satellite_drops(&beacon.gps, &mut losses);
+            bytes
+        }
+        (EncodingVariant::Satellite, Payload::Gps(gps)) => {
+            let bytes = Satellite(*gps).to_lora_bytes_with_signature_and_mode(key, mode)?;
+            let (Satellite(decoded), _) = Satellite::<Gps>::from_lora_slice(&bytes)?;
+            payload_losses(payload, &Payload::Gps(decoded), &mut losses);
+            satellite_drops(gps, &mut losses);
+            bytes
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(EncodingPlan {
+        variant,
+        bytes,
+        losses,
+    }))
+}
+
+fn proto_bytes<K: KeyTrait>(key: &K, payload: Payload) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    Message::from_payload_signed(key, payload)?.encode_to(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn payload_losses(original: &Payload, decoded: &Payload, losses: &mut Vec<Loss>) {
+    gps_losses(original.gps(), decoded.gps(), losses);
+    let mut check = |field, same: bool, dropped: bool| {
+        if !same {
+            losses.push(if dropped {
+                Loss::Dropped(field)
+            } else {
+                Loss::Rounded(field)
+            })
+        }
+    };
+    match (original, decoded) {
+        #[cfg(feature = "beacon")]
+        (Payload::Beacon(original), Payload::Beacon(decoded)) => check(
+            Field::BeaconSignature,
+            original.signature == decoded.signature,
+            decoded.signature.is_empty(),
+        ),
+        #[cfg(feature = "cell")]
+        (Payload::CellAttach(original), Payload::CellAttach(decoded)) => {
+            check(
+                Field::Candidate,
+                original.candidate == decoded.candidate,
+                false,
+            );
+            check(
+                Field::FailureCause,
+                original.failure_cause == decoded.failure_cause,
+                decoded.failure_cause.is_none(),
+            );
+            check(
+                Field::Sim,
+                original.sim == decoded.sim,
+                decoded.sim.is_none(),
+            );
+        }
+        _ => (),
+    }
+}
+
+fn gps_losses(original: &Gps, decoded: &Gps, losses: &mut Vec<Loss>) {
+    let mut rounded = |field, same: bool| {
+        if !same {
+            losses.push(Loss::Rounded(field))
+        }
+    };
+    rounded(Field::Timestamp, original.timestamp == decoded.timestamp);
+    rounded(
+        Field::Position,
+        original.lat == decoded.lat && original.lon == decoded.lon,
+    );
+    rounded(Field::Hdop, original.hdop == decoded.hdop);
+    rounded(Field::Altitude, original.altitude == decoded.altitude);
+    rounded(Field::Speed, original.speed == decoded.speed);
+    rounded(Field::NumSats, original.num_sats == decoded.num_sats);
+    if (original.h_acc_m, original.v_acc_m) != (decoded.h_acc_m, decoded.v_acc_m) {
+        let dropped = (original.h_acc_m.is_some() && decoded.h_acc_m.is_none())
+            || (original.v_acc_m.is_some() && decoded.v_acc_m.is_none());
+        losses.push(if dropped {
+            Loss::Dropped(Field::Accuracy)
+        } else {
+            Loss::Rounded(Field::Accuracy)
+        });
+    }
+}
+
+/// The satellite frames don't carry these at all, whatever the diff says
+fn satellite_drops(gps: &Gps, losses: &mut Vec<Loss>) {
+    losses.retain(|loss| {
+        !matches!(
+            loss,
+            Loss::Rounded(Field::Altitude | Field::Speed | Field::NumSats)
+        )
+    });
+    let zero = Gps::default();
+    for (field, carried) in [
+        (Field::Altitude, gps.altitude != zero.altitude),
+        (Field::Speed, gps.speed != zero.speed),
+        (Field::NumSats, gps.num_sats != zero.num_sats),
+    ] {
+        if carried {
+            losses.push(Loss::Dropped(field));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{keys, CellScan};
+
+    #[test]
+    fn picks_highest_fidelity_that_fits() {
+        let key = keys::file::File::create_key().unwrap();
+        let beacon = Payload::Beacon(Beacon::new(Gps::rounded(), vec![0xAB; 64]));
+
+        let proto = plan(&beacon, &EncodingBudget::new(1024), &key).unwrap();
+        assert_eq!(
+            (proto.variant, proto.losses.len()),
+            (EncodingVariant::Proto, 0)
+        );
+
+        let lora = plan(&beacon, &EncodingBudget::new(100), &key).unwrap();
+        assert_eq!(lora.variant, EncodingVariant::Lora);
+        assert!(lora.losses.contains(&Loss::Rounded(Field::BeaconSignature)));
+
+        let satellite = plan(&beacon, &EncodingBudget::new(83), &key).unwrap();
+        assert_eq!(satellite.variant, EncodingVariant::Satellite);
+        assert!(satellite.losses.contains(&Loss::Dropped(Field::Altitude)));
+
+        let budget = EncodingBudget::new(83).require(Field::Altitude);
+        assert!(matches!(
+            plan(&beacon, &budget, &key),
+            Err(Error::NoEncodingWithinBudget { max_bytes: 83 })
+        ));
+    }
+
+    #[test]
+    fn drops_weakest_scan_results() {
+        let key = keys::file::File::create_key().unwrap();
+        let mut scan = CellScan::random();
+        while scan.results.len() < 20 {
+            scan.results.push(crate::CellScanResult::random());
+        }
+        let strongest = scan.results.iter().map(|r| r.rsrp).max().unwrap();
+        let plan = plan(&Payload::CellScan(scan), &EncodingBudget::new(400), &key).unwrap();
+        assert!(plan.bytes.len() <= 400);
+        assert!(matches!(plan.losses[..], [Loss::DroppedResults(n)] if n > 0));
+        let Payload::CellScan(sent) = Message::decode_from(&plan.bytes).unwrap().payload else {
+            panic!("not a scan");
+        };
+        assert_eq!(sent.results[0].rsrp, strongest);
+    }
+}
@@ -0,0 +1,240 @@
+//! PLMN (MCC-MNC) packing for the proto `plmn` field. MNCs are 2 or 3 digits and those are
+//! different networks (eg: 01 vs 001), which the original `(mcc << 16) | mnc` packing can't tell
+//! apart. With `PlmnPacking::Bcd`, PLMNs with a known MNC length are packed as the 3 BCD octets of
+//! 3GPP TS 24.008 10.5.1.13 with bit 31 set:
+//!
+//! ```text
+//! bit 31 | 30..24 | MCC2 MCC1 | MNC3 MCC3 | MNC2 MNC1
+//!   1    |   0    |  octet 1  |  octet 2  |  octet 3
+//! ```
+//!
+//! where MNC3 is 0xF for 2 digit MNCs. Values without bit 31 are the original packing, which
+//! never sets it since MCCs stop at 999, and decode with an unknown MNC length. The original
+//! packing stays the default until every consumer of the proto decodes both.
+use super::{Deserialize, Error, Result, Serialize};
+
+const BCD_FLAG: u32 = 1 << 31;
+const FILLER: u8 = 0xF;
+
+/// How `Plmn::to_proto_with` packs the proto `plmn` field. Decoding takes either.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlmnPacking {
+    /// `(mcc << 16) | mnc`, dropping the MNC length
+    #[default]
+    Legacy,
+    /// TS 24.008 BCD with bit 31 set when the MNC length is known, `Legacy` otherwise
+    Bcd,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Plmn {
+    pub mcc: u16,
+    pub mnc: u16,
+    /// 2 or 3, None when decoded from the original packing
+    pub mnc_digits: Option<u8>,
+}
+
+impl Plmn {
+    pub fn new(mcc: u16, mnc: u16, mnc_digits: u8) -> Result<Self> {
+        let plmn = Self {
+            mcc,
+            mnc,
+            mnc_digits: Some(mnc_digits),
+        };
+        let valid = mcc <= 999
+            && match mnc_digits {
+                2 => mnc <= 99,
+                3 => mnc <= 999,
+                _ => false,
+            };
+        if valid {
+            Ok(plmn)
+        } else {
+            Err(Error::InvalidPlmn(plmn.to_string()))
+        }
+    }
+
+    /// The 3 octets of TS 24.008, or None if the MNC length is unknown
+    pub fn to_bcd(&self) -> Option<[u8; 3]> {
+        let mnc_digits = self.mnc_digits?;
+        let [mcc1, mcc2, mcc3] = digits(self.mcc);
+        let [mnc1, mnc2, mnc3] = match mnc_digits {
+            2 => {
+                let [_, mnc1, mnc2] = digits(self.mnc);
+                [mnc1, mnc2, FILLER]
+            }
+            _ => digits(self.mnc),
+        };
+        Some([mcc2 << 4 | mcc1, mnc3 << 4 | mcc3, mnc2 << 4 | mnc1])
+    }
+
+    pub fn from_bcd(octets: [u8; 3]) -> Result<Self> {
+        let nibbles = [
+            octets[0] & 0x0F,
+            octets[0] >> 4,
+            octets[1] & 0x0F,
+            octets[2] & 0x0F,
+            octets[2] >> 4,
+            octets[1] >> 4,
+        ];
+        let invalid = || Error::InvalidPlmn(format!("{octets:02x?}"));
+        let [mcc1, mcc2, mcc3, mnc1, mnc2, mnc3] = nibbles;
+        if nibbles[..5].iter().any(|digit| *digit > 9) || (mnc3 > 9 && mnc3 != FILLER) {
+            return Err(invalid());
+        }
+        let mcc = u16::from(mcc1) * 100 + u16::from(mcc2) * 10 + u16::from(mcc3);
+        let mnc = u16::from(mnc1) * 10 + u16::from(mnc2);
+        match mnc3 {
+            FILLER => Self::new(mcc, mnc, 2),
+            _ => Self::new(mcc, mnc * 10 + u16::from(mnc3), 3),
+        }
+    }
+
+    /// The original packing, see `to_proto_with` for BCD
+    pub fn to_proto(&self) -> u32 {
+        self.to_proto_with(PlmnPacking::default())
+    }
+
+    pub fn to_proto_with(&self, packing: PlmnPacking) -> u32 {
+        match (packing, self.to_bcd()) {
+            (PlmnPacking::Bcd, Some([o1, o2, o3])) => {
+                BCD_FLAG | u32::from_be_bytes([0, o1, o2, o3])
+            }
+            _ => (u32::from(self.mcc) << 16) | u32::from(self.mnc),
+        }
+    }
+
+    pub fn from_proto(plmn: u32) -> Result<Self> {
+        if plmn & BCD_FLAG == 0 {
+            return Ok(Self {
+                mcc: (plmn >> 16) as u16,
+                mnc: plmn as u16,
+                mnc_digits: None,
+            });
+        }
+        let [flag, o1, o2, o3] = plmn.to_be_bytes();
+        if flag != 0x80 {
+            return Err(Error::InvalidPlmn(format!("{plmn:#010x}")));
+        }
+        Self::from_bcd([o1, o2, o3])
+    }
+}
+
+/// Hundreds, tens and units
+fn digits(value: u16) -> [u8; 3] {
+    [
+        (value / 100 % 10) as u8,
+        (value / 10 % 10) as u8,
+        (value % 10) as u8,
+    ]
+}
+
+/// MNC length implied by how it was written: "001" or "01" say it, "1" or "10" don't
+pub(crate) fn mnc_digits_written(mnc: &str) -> Option<u8> {
+    match mnc.trim() {
+        s if s.len() == 3 => Some(3),
+        s if s.len() == 2 && s.starts_with('0') => Some(2),
+        _ => None,
+    }
+}
+
+/// `mcc-mnc`, with the MNC zero padded to its length when known, eg: 315-010
+impl std::fmt::Display for Plmn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.mnc_digits {
+            Some(width) => write!(
+                f,
+                "{:03}-{:0width$}",
+                self.mcc,
+                self.mnc,
+                width = usize::from(width)
+            ),
+            None => write!(f, "{:03}-{}", self.mcc, self.mnc),
+        }
+    }
+}
+
+impl std::str::FromStr for Plmn {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidPlmn(s.into());
+        let (mcc, mnc) = s.trim().split_once(['-', '_']).ok_or_else(invalid)?;
+        if mcc.len() != 3 || !(2..=3).contains(&mnc.len()) {
+            return Err(invalid());
+        }
+        let mcc = mcc.parse().map_err(|_| invalid())?;
+        let mnc_digits = mnc.len() as u8;
+        Self::new(mcc, mnc.parse().map_err(|_| invalid())?, mnc_digits)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const REAL_WORLD: [(&str, [u8; 3]); 12] = [
+        ("302-220", [0x03, 0x02, 0x22]),
+        ("315-010", [0x13, 0x05, 0x10]),
+        ("310-260", [0x13, 0x00, 0x62]),
+        ("310-410", [0x13, 0x00, 0x14]),
+        ("311-480", [0x13, 0x01, 0x84]),
+        ("262-01", [0x62, 0xF2, 0x10]),
+        ("234-15", [0x32, 0xF4, 0x51]),
+        ("208-01", [0x02, 0xF8, 0x10]),
+        ("460-00", [0x64, 0xF0, 0x00]),
+        ("440-10", [0x44, 0xF0, 0x01]),
+        ("505-01", [0x05, 0xF5, 0x10]),
+        ("001-01", [0x00, 0xF1, 0x10]),
+    ];
+
+    #[test]
+    fn real_world_plmns() {
+        for (s, bcd) in REAL_WORLD {
+            let plmn: Plmn = s.parse().unwrap();
+            assert_eq!(plmn.to_string(), s);
+            assert_eq!(plmn.to_bcd(), Some(bcd), "{s}");
+            assert_eq!(Plmn::from_bcd(bcd).unwrap(), plmn);
+            let bcd = plmn.to_proto_with(PlmnPacking::Bcd);
+            assert_eq!(Plmn::from_proto(bcd).unwrap(), plmn);
+        }
+        let cbrs = Plmn::new(crate::CBRS_MCC, crate::CBRS_MNC, 3).unwrap();
+        assert_eq!(cbrs.to_string(), "315-010");
+        assert_eq!(cbrs.to_proto_with(PlmnPacking::Bcd), 0x8013_0510);
+    }
+
+    #[test]
+    fn legacy_packing_is_the_default() {
+        let cbrs = Plmn::new(crate::CBRS_MCC, crate::CBRS_MNC, 3).unwrap();
+        assert_eq!(cbrs.to_proto(), (315 << 16) | 10);
+        assert_eq!(cbrs.to_proto_with(PlmnPacking::Legacy), cbrs.to_proto());
+        let decoded = Plmn::from_proto(cbrs.to_proto()).unwrap();
+        assert_eq!(
+            (decoded.mcc, decoded.mnc, decoded.mnc_digits),
+            (315, 10, None)
+        );
+    }
+
+    #[test]
+    fn mnc_length_is_kept() {
+        let two: Plmn = "310-01".parse().unwrap();
+        let three: Plmn = "310-001".parse().unwrap();
+        assert_eq!((two.mcc, two.mnc), (three.mcc, three.mnc));
+        let bcd = |plmn: Plmn| plmn.to_proto_with(PlmnPacking::Bcd);
+        assert_ne!(bcd(two), bcd(three));
+        assert_ne!(Plmn::from_proto(bcd(two)).unwrap(), three);
+
+        // the original packing still decodes, without a length
+        let legacy = Plmn::from_proto((315 << 16) | 10).unwrap();
+        assert_eq!((legacy.mcc, legacy.mnc, legacy.mnc_digits), (315, 10, None));
+        assert_eq!(legacy.to_proto(), (315 << 16) | 10);
+
+        assert!(Plmn::new(310, 100, 2).is_err());
+        assert!(Plmn::from_bcd([0x13, 0x00, 0x6A]).is_err());
+        assert!(Plmn::from_proto(0xC013_0062).is_err());
+        assert!("31-01".parse::<Plmn>().is_err());
+        assert_eq!(mnc_digits_written("010"), Some(3));
+        assert_eq!(mnc_digits_written("10"), None);
+    }
+}
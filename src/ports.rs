@@ -1,2 +1,6 @@
 pub const ATTACH_PORT: u8 = 0x01;
 pub const BEACON_PORT: u8 = 0x10;
+/// Downlinks carrying a `config::ConfigPatch`
+pub const CONFIG_PORT: u8 = 0x20;
+/// Uplinks carrying a `capabilities::Capabilities`
+pub const CAPABILITIES_PORT: u8 = 0x21;
@@ -0,0 +1,312 @@
+//! Positions from sources other than the GNSS receiver. As a payload, a `Position`, they travel
+//! as the `MapperGpsV1` of the fix derived from the estimate, which is all that decoders of the
+//! plain helium-proto messages see; the proto extension adds the method and the error ellipse.
+use super::{gps, proto_ext::PositionExtV1, Deserialize, Error, Gps, Result, Serialize};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+
+/// User equivalent range error assumed when deriving an accuracy from HDOP
+pub const GNSS_UERE_M: f64 = 5.0;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PositionMethod {
+    Gnss,
+    RssiMultilateration,
+    /// LTE observed time difference of arrival
+    Otdoa,
+    WifiRtt,
+}
+
+impl From<PositionMethod> for u32 {
+    fn from(method: PositionMethod) -> Self {
+        match method {
+            PositionMethod::Gnss => 1,
+            PositionMethod::RssiMultilateration => 2,
+            PositionMethod::Otdoa => 3,
+            PositionMethod::WifiRtt => 4,
+        }
+    }
+}
+
+impl TryFrom<u32> for PositionMethod {
+    type Error = Error;
+
+    fn try_from(value: u32) -> Result<Self> {
+        Ok(match value {
+            1 => PositionMethod::Gnss,
+            2 => PositionMethod::RssiMultilateration,
+            3 => PositionMethod::Otdoa,
+            4 => PositionMethod::WifiRtt,
+            _ => {
+                return Err(Error::OutOfRange {
+                    field: "method",
+                    value: value.into(),
+                })
+            }
+        })
+    }
+}
+
+/// 1-sigma horizontal error ellipse
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Uncertainty {
+    pub semi_major_m: f64,
+    pub semi_minor_m: f64,
+    /// Orientation of the semi-major axis, in degrees clockwise from north
+    pub orientation_deg: f64,
+}
+
+impl Uncertainty {
+    pub fn circular(radius_m: f64) -> Self {
+        Self {
+            semi_major_m: radius_m,
+            semi_minor_m: radius_m,
+            orientation_deg: 0.0,
+        }
+    }
+
+    /// Error ellipse of an east/north covariance matrix, in m²
+    pub fn from_covariance(var_east: f64, var_north: f64, cov_east_north: f64) -> Self {
+        let mean = (var_east + var_north) / 2.0;
+        let spread = (((var_east - var_north) / 2.0).powi(2) + cov_east_north.powi(2)).sqrt();
+        // angle of the major axis from east, counter clockwise
+        let theta = 0.5 * (2.0 * cov_east_north).atan2(var_east - var_north);
+        Self {
+            semi_major_m: (mean + spread).max(0.0).sqrt(),
+            semi_minor_m: (mean - spread).max(0.0).sqrt(),
+            orientation_deg: (90.0 - theta.to_degrees()).rem_euclid(180.0),
+        }
+    }
+
+    /// Radius of the circle with the same area as the ellipse
+    pub fn horizontal_m(&self) -> f64 {
+        (self.semi_major_m * self.semi_minor_m).sqrt()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PositionEstimate {
+    pub timestamp: DateTime<Utc>,
+    pub method: PositionMethod,
+    /// Latitude in degrees
+    #[serde(with = "crate::serde_helpers::decimal::scale5")]
+    pub lat: Decimal,
+    /// Longitude in degrees
+    #[serde(with = "crate::serde_helpers::decimal::scale5")]
+    pub lon: Decimal,
+    /// Height above mean sea level, if the method resolves it
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "crate::serde_helpers::decimal::scale2::option"
+    )]
+    pub altitude: Option<Decimal>,
+    pub uncertainty: Uncertainty,
+}
+
+impl PositionEstimate {
+    #[cfg(feature = "h3")]
+    pub fn to_h3_cell(&self, r: h3o::Resolution) -> Result<h3o::CellIndex> {
+        use rust_decimal::prelude::ToPrimitive;
+        match (self.lat.to_f64(), self.lon.to_f64()) {
+            (Some(lat), Some(lon)) => Ok(h3o::LatLng::new(lat, lon)?.to_cell(r)),
+            (None, _) => Err(Error::DecimalCouldNotMapToFloat { decimal: self.lat }),
+            (_, None) => Err(Error::DecimalCouldNotMapToFloat { decimal: self.lon }),
+        }
+    }
+}
+
+impl From<&Gps> for PositionEstimate {
+    fn from(gps: &Gps) -> Self {
+        Self {
+            timestamp: gps.timestamp,
+            method: PositionMethod::Gnss,
+            lat: gps.lat,
+            lon: gps.lon,
+            altitude: Some(gps.altitude),
+            uncertainty: Uncertainty::circular(gps.horizontal_accuracy_m()),
+        }
+    }
+}
+
+impl TryFrom<&PositionEstimate> for Gps {
+    type Error = Error;
+    /// Only GNSS estimates are fixes; everything else lacks satellites and a dilution figure
+    fn try_from(estimate: &PositionEstimate) -> Result<Self> {
+        if estimate.method != PositionMethod::Gnss {
+            return Err(Error::NotGnssPosition(estimate.method));
+        }
+        Ok(derived_fix(estimate))
+    }
+}
+
+/// The estimate as a fix without satellites, with an HDOP and accuracy from its uncertainty
+fn derived_fix(estimate: &PositionEstimate) -> Gps {
+    let h_acc_m = estimate.uncertainty.horizontal_m();
+    let hdop = Decimal::try_from(h_acc_m / GNSS_UERE_M)
+        .unwrap_or(gps::ZERO_DECIMAL)
+        .round_dp(2);
+    Gps {
+        timestamp: estimate.timestamp,
+        lat: estimate.lat,
+        lon: estimate.lon,
+        hdop,
+        altitude: estimate.altitude.unwrap_or_default(),
+        num_sats: 0,
+        speed: gps::ZERO_DECIMAL,
+        h_acc_m: Decimal::try_from(h_acc_m)
+            .ok()
+            .map(|h_acc| h_acc.round_dp(2)),
+        v_acc_m: None,
+        jamming: None,
+        spoofing: None,
+    }
+}
+
+/// A `PositionEstimate` as a payload, of any method. `Payload::gps` gives the fix derived from
+/// the estimate, so that everything reading payloads by their fix handles positions too.
+///
+/// The time, position and altitude travel at the precision of `MapperGpsV1`.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "PositionEstimate", into = "PositionEstimate")]
+pub struct Position {
+    estimate: PositionEstimate,
+    pub(crate) gps: Gps,
+}
+
+impl Position {
+    pub fn new(estimate: PositionEstimate) -> Self {
+        Self {
+            gps: derived_fix(&estimate),
+            estimate,
+        }
+    }
+
+    pub fn estimate(&self) -> &PositionEstimate {
+        &self.estimate
+    }
+
+    pub fn gps(&self) -> &Gps {
+        &self.gps
+    }
+
+    /// The method and error ellipse, in the extension of the payload carrying the position
+    pub(crate) fn to_proto_ext(&self) -> PositionExtV1 {
+        let Uncertainty {
+            semi_major_m,
+            semi_minor_m,
+            orientation_deg,
+        } = self.estimate.uncertainty;
+        PositionExtV1 {
+            method: self.estimate.method.into(),
+            semi_major_m,
+            semi_minor_m,
+            orientation_deg,
+            has_altitude: self.estimate.altitude.is_some(),
+        }
+    }
+
+    /// The position a payload carries: the fix of its `MapperGpsV1` with its extension
+    pub(crate) fn from_proto_ext(gps: &Gps, ext: &PositionExtV1) -> Result<Self> {
+        Ok(Self::new(PositionEstimate {
+            timestamp: gps.timestamp,
+            method: ext.method.try_into()?,
+            lat: gps.lat,
+            lon: gps.lon,
+            altitude: ext.has_altitude.then_some(gps.altitude),
+            uncertainty: Uncertainty {
+                semi_major_m: ext.semi_major_m,
+                semi_minor_m: ext.semi_minor_m,
+                orientation_deg: ext.orientation_deg,
+            },
+        }))
+    }
+}
+
+impl From<PositionEstimate> for Position {
+    fn from(estimate: PositionEstimate) -> Self {
+        Self::new(estimate)
+    }
+}
+
+impl From<Position> for PositionEstimate {
+    fn from(position: Position) -> Self {
+        position.estimate
+    }
+}
+
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Position{{{:?} ", self.estimate.method)?;
+        self.gps.fmt_fix(f)?;
+        write!(f, " ±{:.1}m}}", self.estimate.uncertainty.horizontal_m())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn covariance_ellipse() {
+        let north_south = Uncertainty::from_covariance(4.0, 16.0, 0.0);
+        assert!((north_south.semi_major_m - 4.0).abs() < 1e-9);
+        assert!((north_south.semi_minor_m - 2.0).abs() < 1e-9);
+        assert!(north_south.orientation_deg.abs() < 1e-9);
+
+        let east_west = Uncertainty::from_covariance(16.0, 4.0, 0.0);
+        assert!((east_west.orientation_deg - 90.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn from_gps_and_json() {
+        let gps = Gps::rounded();
+        let estimate = PositionEstimate::from(&gps);
+        assert_eq!(estimate.method, PositionMethod::Gnss);
+        assert!((estimate.uncertainty.horizontal_m() - 45.25).abs() < 1e-9);
+        assert_eq!(Gps::try_from(&estimate).unwrap().hdop, gps.hdop);
+
+        let json = serde_json::to_string(&estimate).unwrap();
+        assert!(json.contains("\"gnss\""));
+        assert_eq!(estimate, serde_json::from_str(&json).unwrap());
+
+        let otdoa = PositionEstimate {
+            method: PositionMethod::Otdoa,
+            ..estimate
+        };
+        assert!(Gps::try_from(&otdoa).is_err());
+    }
+
+    #[test]
+    fn position_payload_proto() {
+        let key = crate::keys::file::File::create_key().unwrap();
+        let gps = Gps::rounded();
+        let position = Position::new(PositionEstimate {
+            method: PositionMethod::Otdoa,
+            altitude: None,
+            uncertainty: Uncertainty {
+                semi_major_m: 60.0,
+                semi_minor_m: 15.0,
+                orientation_deg: 30.0,
+            },
+            ..PositionEstimate::from(&gps)
+        });
+        assert_eq!(position.gps().hdop, Decimal::new(6_00, 2));
+        assert_eq!(position.gps().num_sats, 0);
+
+        let payload = crate::Payload::Position(position);
+        let msg = crate::Message::from_payload_signed(&key, payload.clone()).unwrap();
+        let mut bytes = Vec::new();
+        msg.encode_to(&mut bytes).unwrap();
+        let received = crate::Message::decode_from_with_signature_verification(&bytes).unwrap();
+        assert_eq!(received.payload, payload);
+        // plain helium-proto decoders see the derived fix
+        let plain = crate::Message::try_from(msg.to_proto()).unwrap();
+        assert_eq!(plain.kind(), crate::PayloadKind::Gps);
+        assert_eq!(plain.payload.gps().hdop, position.gps().hdop);
+
+        let json = serde_json::to_string(&payload).unwrap();
+        assert_eq!(payload, serde_json::from_str(&json).unwrap());
+    }
+}
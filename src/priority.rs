@@ -0,0 +1,88 @@
+#[cfg(feature = "cell")]
+use super::CellAttachResult;
+use super::{Deserialize, Payload, Serialize};
+
+/// How urgently a payload should be transmitted and triaged. Ordered from most to least
+/// urgent, so sorting ascending puts urgent payloads first.
+#[derive(
+    Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    Urgent,
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 4] = [
+        Priority::Urgent,
+        Priority::High,
+        Priority::Normal,
+        Priority::Low,
+    ];
+
+    /// As it serializes, eg: "urgent"
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Urgent => "urgent",
+            Priority::High => "high",
+            Priority::Normal => "normal",
+            Priority::Low => "low",
+        }
+    }
+
+    pub(crate) fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+impl Payload {
+    /// Priority used when none is given explicitly: failed attaches are urgent, beacons go ahead
+    /// of routine telemetry
+    pub fn default_priority(&self) -> Priority {
+        match self {
+            #[cfg(feature = "cell")]
+            Payload::CellAttach(attach) if attach.result != CellAttachResult::Connected => {
+                Priority::Urgent
+            }
+            #[cfg(feature = "beacon")]
+            Payload::Beacon(_) => Priority::High,
+            #[cfg(feature = "cell")]
+            Payload::CellAttach(_) | Payload::CellScan(_) => Priority::Normal,
+            Payload::Gps(_) | Payload::Position(_) => Priority::Normal,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{AttachCandidate, Beacon, CellAttach, CellScanResult, Gps};
+
+    #[test]
+    fn default_priorities() {
+        let mut attach = CellAttach {
+            attach_counter: 1,
+            gps: Gps::rounded(),
+            candidate: AttachCandidate::from(CellScanResult::random()),
+            result: CellAttachResult::Connected,
+            failure_cause: None,
+            sim: None,
+        };
+        assert_eq!(
+            Payload::CellAttach(attach).default_priority(),
+            Priority::Normal
+        );
+        attach.result = CellAttachResult::NoConnection;
+        assert_eq!(
+            Payload::CellAttach(attach).default_priority(),
+            Priority::Urgent
+        );
+        let beacon = Payload::Beacon(Beacon::new(Gps::rounded(), vec![0xAB, 0xCD]));
+        assert_eq!(beacon.default_priority(), Priority::High);
+        assert!(Priority::Urgent < Priority::Low);
+    }
+}
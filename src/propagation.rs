@@ -0,0 +1,171 @@
+//! Path loss models for plausibility checks and coverage modeling. These are coarse estimates:
+//! Hata is only calibrated between 150 MHz and 1.5 GHz (2 GHz with the COST-231 extension) and
+//! is extrapolated above that, eg: for CBRS.
+#[cfg(feature = "cell")]
+use super::{AttachCandidate, CellScanResult};
+use super::{Deserialize, Error, Result, Serialize};
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PathLossModel {
+    FreeSpace,
+    /// Okumura-Hata for suburban areas
+    HataSuburban {
+        base_height_m: f64,
+        mobile_height_m: f64,
+    },
+}
+
+impl Default for PathLossModel {
+    fn default() -> Self {
+        PathLossModel::HataSuburban {
+            base_height_m: 30.0,
+            mobile_height_m: 1.5,
+        }
+    }
+}
+
+impl PathLossModel {
+    /// Returns (A, B) such that path loss in dB is `A + B * log10(distance_km)`
+    fn coefficients(&self, frequency_mhz: f64) -> (f64, f64) {
+        let log_f = frequency_mhz.log10();
+        match *self {
+            PathLossModel::FreeSpace => (32.44 + 20.0 * log_f, 20.0),
+            PathLossModel::HataSuburban {
+                base_height_m,
+                mobile_height_m,
+            } => {
+                let log_hb = base_height_m.log10();
+                let a_hm = (1.1 * log_f - 0.7) * mobile_height_m - (1.56 * log_f - 0.8);
+                let urban = 69.55 + 26.16 * log_f - 13.82 * log_hb - a_hm;
+                let suburban = urban - 2.0 * (frequency_mhz / 28.0).log10().powi(2) - 5.4;
+                (suburban, 44.9 - 6.55 * log_hb)
+            }
+        }
+    }
+
+    pub fn path_loss_db(&self, frequency_mhz: f64, distance_m: f64) -> f64 {
+        let (a, b) = self.coefficients(frequency_mhz);
+        a + b * (distance_m / 1000.0).log10()
+    }
+
+    /// Inverse of `path_loss_db`
+    pub fn distance_m(&self, frequency_mhz: f64, path_loss_db: f64) -> f64 {
+        let (a, b) = self.coefficients(frequency_mhz);
+        1000.0 * 10f64.powf((path_loss_db - a) / b)
+    }
+}
+
+/// Model relating the RSRP measured by a mapper to its distance from the cell
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RsrpModel {
+    pub path_loss: PathLossModel,
+    /// Transmit power per reference signal resource element, including antenna gain
+    pub reference_signal_power_dbm: f64,
+}
+
+impl Default for RsrpModel {
+    fn default() -> Self {
+        Self {
+            path_loss: PathLossModel::default(),
+            reference_signal_power_dbm: 15.0,
+        }
+    }
+}
+
+impl RsrpModel {
+    pub fn estimate_distance_m(&self, rsrp: i32, earfcn: u32) -> Result<f64> {
+        let frequency_mhz = earfcn_to_downlink_mhz(earfcn).ok_or(Error::UnknownEarfcn(earfcn))?;
+        let path_loss = self.reference_signal_power_dbm - rsrp as f64;
+        Ok(self.path_loss.distance_m(frequency_mhz, path_loss))
+    }
+}
+
+/// (band, F_DL_low MHz, N_Offs-DL, last EARFCN of the band) from 3GPP TS 36.101 table 5.7.3-1
+const EARFCN_BANDS: [(u8, f64, u32, u32); 18] = [
+    (1, 2110.0, 0, 599),
+    (2, 1930.0, 600, 1199),
+    (3, 1805.0, 1200, 1949),
+    (4, 2110.0, 1950, 2399),
+    (5, 869.0, 2400, 2649),
+    (7, 2620.0, 2750, 3449),
+    (8, 925.0, 3450, 3799),
+    (12, 729.0, 5010, 5179),
+    (13, 746.0, 5180, 5279),
+    (14, 758.0, 5280, 5379),
+    (17, 734.0, 5730, 5849),
+    (20, 791.0, 6150, 6449),
+    (25, 1930.0, 8040, 8689),
+    (26, 859.0, 8690, 9039),
+    (41, 2496.0, 39650, 41589),
+    (48, 3550.0, 55240, 56739),
+    (66, 2110.0, 66436, 67335),
+    (71, 617.0, 68586, 68935),
+];
+
+/// E-UTRA band of a downlink EARFCN, for the bands in use by the mapping program
+pub fn earfcn_band(earfcn: u32) -> Option<u8> {
+    EARFCN_BANDS
+        .iter()
+        .find(|(_, _, first, last)| (*first..=*last).contains(&earfcn))
+        .map(|(band, ..)| *band)
+}
+
+/// Downlink carrier frequency of an EARFCN
+pub fn earfcn_to_downlink_mhz(earfcn: u32) -> Option<f64> {
+    EARFCN_BANDS
+        .iter()
+        .find(|(_, _, first, last)| (*first..=*last).contains(&earfcn))
+        .map(|(_, low_mhz, offset, _)| low_mhz + 0.1 * (earfcn - offset) as f64)
+}
+
+#[cfg(feature = "cell")]
+impl CellScanResult {
+    pub fn estimate_distance_m(&self, model: &RsrpModel) -> Result<f64> {
+        model.estimate_distance_m(self.rsrp, self.earfcn)
+    }
+}
+
+#[cfg(feature = "cell")]
+impl AttachCandidate {
+    pub fn estimate_distance_m(&self, model: &RsrpModel) -> Result<f64> {
+        model.estimate_distance_m(self.rsrp, self.fcn.into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cbrs_earfcn() {
+        assert_eq!(earfcn_band(55990), Some(48));
+        assert!((earfcn_to_downlink_mhz(55990).unwrap() - 3625.0).abs() < 1e-9);
+        assert_eq!(earfcn_to_downlink_mhz(60000), None);
+    }
+
+    #[test]
+    fn free_space_reference() {
+        // 1 km at 1 GHz is ~92.44 dB
+        let model = PathLossModel::FreeSpace;
+        assert!((model.path_loss_db(1000.0, 1000.0) - 92.44).abs() < 0.01);
+        assert!((model.distance_m(1000.0, 92.44) - 1000.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn hata_roundtrip_and_monotonic() {
+        let model = PathLossModel::default();
+        let near = model.path_loss_db(850.0, 500.0);
+        let far = model.path_loss_db(850.0, 5000.0);
+        assert!(far > near);
+        assert!((model.distance_m(850.0, far) - 5000.0).abs() < 1e-6);
+
+        let rsrp_model = RsrpModel::default();
+        let strong = rsrp_model.estimate_distance_m(-70, 55990).unwrap();
+        let weak = rsrp_model.estimate_distance_m(-110, 55990).unwrap();
+        assert!(weak > strong);
+        assert!(matches!(
+            rsrp_model.estimate_distance_m(-70, 60000),
+            Err(Error::UnknownEarfcn(60000))
+        ));
+    }
+}
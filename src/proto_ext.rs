@@ -0,0 +1,299 @@
+//! Fields the crate carries beyond helium-proto's mapper messages. The extension of a payload is
+//! field 1000 of its `MapperPayload`, a `PayloadExtV1` that decoders of the plain helium-proto
+//! messages skip as unknown. It is encoded after the helium-proto payload, in the signed bytes
+//! as in the MapperMsg, where it follows the message nested in a `MapperMsgV1` of its own:
+//! protobuf decoders merge repeated embedded messages, so both read as the one message.
+//!
+//! A payload with nothing to extend encodes without the field, to exactly the bytes, and so the
+//! signature, of the plain proto. Decoding a `MapperMsg` struct, eg: the one tonic hands out, has
+//! no extension to read; decode the bytes with `ExtendedMsg::decode` to keep it. For the same
+//! reason a `Message` whose payload has one does not convert to a plain `MapperMsg`.
+//!
+//! As with `raw_dump`, the prost these derive with must be the one helium-proto builds on.
+use super::{DecodeError, MapperMsg, ProtoMessage, Result};
+use bytes::{Buf, BufMut};
+
+/// What a payload carries beyond its helium-proto message, each part set only when it holds
+/// something
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PayloadExtV1 {
+    /// Of the fix every payload carries
+    #[prost(message, optional, tag = "1")]
+    pub gps: Option<GpsExtV1>,
+    /// Only on beacon payloads
+    #[prost(message, optional, tag = "2")]
+    pub beacon: Option<BeaconExtV1>,
+    /// Only on cell attach payloads
+    #[prost(message, optional, tag = "3")]
+    pub attach: Option<AttachExtV1>,
+    /// Only on cell scan payloads
+    #[prost(message, optional, tag = "4")]
+    pub scan: Option<ScanExtV1>,
+    /// Set on the gps payload a `Position` travels as, which it turns back into
+    #[prost(message, optional, tag = "5")]
+    pub position: Option<PositionExtV1>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GpsExtV1 {
+    /// `Gps::h_acc_m` in cm
+    #[prost(uint32, optional, tag = "1")]
+    pub h_acc_cm: Option<u32>,
+    /// `Gps::v_acc_m` in cm
+    #[prost(uint32, optional, tag = "2")]
+    pub v_acc_cm: Option<u32>,
+    /// `Gps::jamming` as UBX-MON-RF reports it: 1 ok, 2 warning, 3 critical
+    #[prost(uint32, optional, tag = "3")]
+    pub jamming: Option<u32>,
+    /// `Gps::spoofing` as UBX-NAV-STATUS reports it: 1 not indicated, 2 indicated, 3 multiple
+    #[prost(uint32, optional, tag = "4")]
+    pub spoofing: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct BeaconExtV1 {
+    /// `Beacon::tx_power_dbm`
+    #[prost(sint32, optional, tag = "1")]
+    pub tx_power_dbm: Option<i32>,
+    /// `Beacon::antenna_gain_dbi` in 0.01 dBi
+    #[prost(sint32, optional, tag = "2")]
+    pub antenna_gain_cdbi: Option<i32>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AttachExtV1 {
+    /// `CellAttach::failure_cause`
+    #[prost(oneof = "attach_ext_v1::FailureCause", tags = "1, 2, 3, 4")]
+    pub failure_cause: Option<attach_ext_v1::FailureCause>,
+    /// `CellAttach::sim`
+    #[prost(message, optional, tag = "5")]
+    pub sim: Option<SimInfoV1>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SimInfoV1 {
+    /// The 32 bytes of an `ImsiHash`
+    #[prost(bytes = "vec", optional, tag = "1")]
+    pub imsi_hash: Option<Vec<u8>>,
+    /// As in `attach_ext_v1::FailureCause::Sim`
+    #[prost(uint32, optional, tag = "2")]
+    pub sim_error: Option<u32>,
+    #[prost(bool, tag = "3")]
+    pub roaming: bool,
+}
+
+pub mod attach_ext_v1 {
+    #[derive(Clone, Copy, PartialEq, prost::Oneof)]
+    pub enum FailureCause {
+        /// The EMM cause code
+        #[prost(uint32, tag = "1")]
+        Emm(u32),
+        #[prost(bool, tag = "2")]
+        OperatorReject(bool),
+        /// The `SimError`: 1 not inserted, 2 PIN required, 3 PUK required, 4 failure
+        #[prost(uint32, tag = "3")]
+        Sim(u32),
+        #[prost(bool, tag = "4")]
+        Timeout(bool),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ScanExtV1 {
+    /// `CellScanResult::observed_at` of every result of the scan, in order, as ms since the
+    /// unix epoch. 0 for results whose time is unknown.
+    #[prost(uint64, repeated, tag = "1")]
+    pub observed_at_ms: Vec<u64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PositionExtV1 {
+    /// The `PositionMethod`: 1 GNSS, 2 RSSI multilateration, 3 OTDOA, 4 Wi-Fi RTT
+    #[prost(uint32, tag = "1")]
+    pub method: u32,
+    #[prost(double, tag = "2")]
+    pub semi_major_m: f64,
+    #[prost(double, tag = "3")]
+    pub semi_minor_m: f64,
+    #[prost(double, tag = "4")]
+    pub orientation_deg: f64,
+    /// Whether the altitude of the fix is the estimate's rather than a stand-in 0
+    #[prost(bool, tag = "5")]
+    pub has_altitude: bool,
+}
+
+/// A `MapperPayload` as far as its extension goes
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct MapperPayloadExt {
+    #[prost(message, optional, tag = "1000")]
+    pub ext: Option<PayloadExtV1>,
+}
+
+/// A `MapperMsg` as far as the extension of its payload goes: `msg_v1` and its `payload`
+#[derive(Clone, PartialEq, prost::Message)]
+struct MapperMsgExt {
+    #[prost(message, optional, tag = "1")]
+    msg_v1: Option<MapperMsgV1Ext>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct MapperMsgV1Ext {
+    #[prost(message, optional, tag = "1")]
+    payload: Option<MapperPayloadExt>,
+}
+
+/// A `MapperMsg` as far as the bytes of its payload go, keeping every occurrence of `msg_v1` and
+/// of its `payload` apart instead of merging them
+#[derive(Clone, PartialEq, prost::Message)]
+struct MapperMsgPayloadBytes {
+    #[prost(message, repeated, tag = "1")]
+    msg_v1: Vec<MapperMsgV1PayloadBytes>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct MapperMsgV1PayloadBytes {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    payload: Vec<Vec<u8>>,
+}
+
+const _: fn() = || {
+    fn helium_proto_message<M: ProtoMessage>() {}
+    helium_proto_message::<MapperMsgExt>();
+};
+
+/// A MapperMsg with the extension of its payload, as decoded from the same bytes. Converting a
+/// plain `MapperMsg` leaves the extension out.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExtendedMsg {
+    pub msg: MapperMsg,
+    pub ext: Option<PayloadExtV1>,
+}
+
+impl ExtendedMsg {
+    pub fn decode<B: Buf + Clone>(buf: B) -> Result<Self> {
+        let ext = MapperMsgExt::decode(buf.clone())?
+            .msg_v1
+            .and_then(|v1| v1.payload)
+            .and_then(|payload| payload.ext);
+        Ok(Self {
+            msg: MapperMsg::decode(buf)?,
+            ext,
+        })
+    }
+
+    /// The payload bytes as received: every occurrence of `msg_v1.payload` in order, ie: the
+    /// payload followed by its extension, as they were signed. Unlike an encoding of the decoded
+    /// payload these keep the fields helium-proto doesn't know.
+    pub(crate) fn received_payload_bytes(buf: impl Buf) -> Result<Vec<u8>> {
+        Ok(MapperMsgPayloadBytes::decode(buf)?
+            .msg_v1
+            .into_iter()
+            .flat_map(|v1| v1.payload)
+            .flatten()
+            .collect())
+    }
+
+    /// Decodes one length-delimited message, advancing `buf` past it
+    pub fn decode_length_delimited(buf: &mut impl Buf) -> Result<Self> {
+        let len = prost::decode_length_delimiter(&mut *buf)?;
+        if len > buf.remaining() {
+            return Err(DecodeError::new("buffer underflow").into());
+        }
+        Self::decode(buf.copy_to_bytes(len))
+    }
+
+    fn ext_msg(&self) -> Option<MapperMsgExt> {
+        Some(MapperMsgExt {
+            msg_v1: Some(MapperMsgV1Ext {
+                payload: Some(MapperPayloadExt {
+                    ext: Some(self.ext.clone()?),
+                }),
+            }),
+        })
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        self.msg.encoded_len() + self.ext_msg().map_or(0, |ext| ext.encoded_len())
+    }
+
+    /// The MapperMsg, followed by the extension if there is one
+    pub fn encode(&self, buf: &mut impl BufMut) -> Result {
+        self.msg.encode(buf)?;
+        if let Some(ext) = self.ext_msg() {
+            ext.encode(buf)?;
+        }
+        Ok(())
+    }
+
+    pub fn encode_length_delimited(&self, buf: &mut impl BufMut) -> Result {
+        prost::encode_length_delimiter(self.encoded_len(), buf)?;
+        self.encode(buf)
+    }
+
+    pub fn encode_to_vec(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode(&mut buf).expect("a vec grows as needed");
+        buf
+    }
+}
+
+impl From<MapperMsg> for ExtendedMsg {
+    fn from(msg: MapperMsg) -> Self {
+        Self { msg, ext: None }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{keys, Gps, Message, Payload};
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn extension_travels_after_the_plain_proto() {
+        let key = keys::file::File::create_key().unwrap();
+        let plain = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap();
+        let mut bytes = Vec::new();
+        plain.encode_to(&mut bytes).unwrap();
+        assert_eq!(bytes, plain.to_proto().encode_to_vec());
+
+        let mut gps = Gps::rounded();
+        gps.h_acc_m = Some(Decimal::new(3_25, 2));
+        gps.v_acc_m = Some(Decimal::new(7_5, 1));
+        let msg = Message::from_payload_signed(&key, Payload::Gps(gps)).unwrap();
+        let mut bytes = Vec::new();
+        msg.encode_to(&mut bytes).unwrap();
+        assert!(bytes.starts_with(&msg.to_proto().encode_to_vec()));
+        // plain helium-proto decoders skip it
+        assert_eq!(MapperMsg::decode(bytes.as_slice()).unwrap(), msg.to_proto());
+
+        let received = Message::decode_from_strict_with_signature_verification(&bytes).unwrap();
+        assert_eq!(received, msg);
+        // without the extension the signature no longer covers the payload
+        assert!(Message::try_from_with_signature_verification(msg.to_proto()).is_err());
+        assert!(matches!(
+            MapperMsg::try_from(msg.clone()),
+            Err(crate::Error::PayloadExtensionDropped(
+                crate::PayloadKind::Gps
+            ))
+        ));
+        assert_eq!(
+            ExtendedMsg::try_from(msg.clone()).unwrap(),
+            msg.to_extended_proto().unwrap()
+        );
+
+        let mut delimited = Vec::new();
+        msg.encode_length_delimited_to(&mut delimited).unwrap();
+        plain.encode_length_delimited_to(&mut delimited).unwrap();
+        let mut buf = delimited.as_slice();
+        assert_eq!(
+            Message::decode_length_delimited_from(&mut buf).unwrap(),
+            msg
+        );
+        assert_eq!(
+            Message::decode_length_delimited_from(&mut buf).unwrap(),
+            plain
+        );
+        assert!(buf.is_empty());
+    }
+}
@@ -0,0 +1,611 @@
+//! Proto3 canonical JSON of the mapper protos, for consumers that parse the standard mapping
+//! rather than our serde shapes. Fields use their lowerCamelCase JSON names, 64-bit integers are
+//! strings, bytes are base64 and enums are their value names. Output omits fields at their
+//! default value; input accepts either field name, numbers or strings for integers, enum
+//! numbers and `null` for defaults, and rejects unknown fields.
+//!
+//! helium-proto has no JSON support of its own, so the mapping is written out here and has to
+//! follow proto changes by hand. The payload extensions of `proto_ext` are not mapped, so payloads
+//! that have one fail with `Error::PayloadExtensionDropped` rather than lose it, and with it the
+//! signature of their message.
+use super::{Error, MapperMsg, MapperMsgV1, Message, Payload, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use helium_proto::{
+    mapper_attach, mapper_beacon, mapper_cbrs_attach_v1, mapper_gps, mapper_msg, mapper_payload,
+    mapper_scan, DataRate, LoraGw, MapperAttach, MapperBeacon, MapperBeaconV1, MapperCbrsAttachV1,
+    MapperCellScanResult, MapperCellScanV1, MapperGps, MapperGpsV1, MapperPayload, MapperScan,
+};
+use serde_json::{Map, Value};
+
+impl Message {
+    pub fn to_proto_json(&self) -> Result<String> {
+        self.payload.check_no_proto_ext()?;
+        Ok(mapper_msg_to_json(&self.to_proto()).to_string())
+    }
+
+    /// Verifies the signature over the payload as the JSON gives it
+    pub fn from_proto_json(json: &str) -> Result<Self> {
+        let msg = mapper_msg_from_json(&serde_json::from_str(json)?)?;
+        Message::try_from_with_signature_verification(msg)
+    }
+}
+
+impl Payload {
+    pub fn to_proto_json(&self) -> Result<String> {
+        self.check_no_proto_ext()?;
+        Ok(mapper_payload_to_json(&self.to_proto()).to_string())
+    }
+
+    fn check_no_proto_ext(&self) -> Result {
+        match self.to_proto_ext()? {
+            Some(_) => Err(Error::PayloadExtensionDropped(self.kind())),
+            None => Ok(()),
+        }
+    }
+
+    pub fn from_proto_json(json: &str) -> Result<Self> {
+        mapper_payload_from_json(&serde_json::from_str(json)?)?
+            .message
+            .ok_or(Error::ProtoHasNone("message"))?
+            .try_into()
+    }
+}
+
+pub fn mapper_msg_to_json(msg: &MapperMsg) -> Value {
+    let mut object = Object::default();
+    if let Some(mapper_msg::Version::MsgV1(v1)) = &msg.version {
+        object.message("msgV1", mapper_msg_v1_to_json(v1));
+    }
+    object.into()
+}
+
+pub fn mapper_msg_from_json(json: &Value) -> Result<MapperMsg> {
+    let object = Fields::new(json, "MapperMsg")?;
+    let version = object
+        .message("msg_v1", mapper_msg_v1_from_json)?
+        .map(mapper_msg::Version::MsgV1);
+    object.finish(&["msg_v1"])?;
+    Ok(MapperMsg { version })
+}
+
+fn mapper_msg_v1_to_json(v1: &MapperMsgV1) -> Value {
+    let mut object = Object::default();
+    if let Some(payload) = &v1.payload {
+        object.message("payload", mapper_payload_to_json(payload));
+    }
+    object.bytes("signature", &v1.signature);
+    object.bytes("pubkey", &v1.pubkey);
+    object.repeated("loraGws", v1.lora_gws.iter().map(lora_gw_to_json));
+    object.into()
+}
+
+fn mapper_msg_v1_from_json(json: &Value) -> Result<MapperMsgV1> {
+    let object = Fields::new(json, "MapperMsgV1")?;
+    let v1 = MapperMsgV1 {
+        payload: object.message("payload", mapper_payload_from_json)?,
+        signature: object.bytes("signature")?,
+        pubkey: object.bytes("pubkey")?,
+        lora_gws: object.repeated("lora_gws", lora_gw_from_json)?,
+    };
+    object.finish(&["payload", "signature", "pubkey", "lora_gws"])?;
+    Ok(v1)
+}
+
+pub fn mapper_payload_to_json(payload: &MapperPayload) -> Value {
+    let mut object = Object::default();
+    match &payload.message {
+        Some(mapper_payload::Message::Gps(gps)) => {
+            let mut version = Object::default();
+            if let Some(mapper_gps::Version::GpsV1(v1)) = &gps.version {
+                version.message("gpsV1", gps_v1_to_json(v1));
+            }
+            object.message("gps", version.into());
+        }
+        Some(mapper_payload::Message::Beacon(beacon)) => {
+            let mut version = Object::default();
+            if let Some(mapper_beacon::Version::BeaconV1(v1)) = &beacon.version {
+                let mut fields = Object::default();
+                if let Some(gps) = &v1.gps {
+                    fields.message("gps", gps_v1_to_json(gps));
+                }
+                fields.bytes("signature", &v1.signature);
+                version.message("beaconV1", fields.into());
+            }
+            object.message("beacon", version.into());
+        }
+        Some(mapper_payload::Message::Scan(scan)) => {
+            let mut version = Object::default();
+            if let Some(mapper_scan::Version::ScanV1(v1)) = &scan.version {
+                version.message("scanV1", scan_v1_to_json(v1));
+            }
+            object.message("scan", version.into());
+        }
+        Some(mapper_payload::Message::Attach(attach)) => {
+            let mut version = Object::default();
+            if let Some(mapper_attach::Version::AttachV1(v1)) = &attach.version {
+                version.message("attachV1", attach_v1_to_json(v1));
+            }
+            object.message("attach", version.into());
+        }
+        None => (),
+    }
+    object.into()
+}
+
+pub fn mapper_payload_from_json(json: &Value) -> Result<MapperPayload> {
+    use mapper_payload::Message as Oneof;
+    let object = Fields::new(json, "MapperPayload")?;
+    let gps = object.message("gps", |json| {
+        let object = Fields::new(json, "MapperGps")?;
+        let version = object
+            .message("gps_v1", gps_v1_from_json)?
+            .map(mapper_gps::Version::GpsV1);
+        object.finish(&["gps_v1"])?;
+        Ok(Oneof::Gps(MapperGps { version }))
+    })?;
+    let beacon = object.message("beacon", |json| {
+        let object = Fields::new(json, "MapperBeacon")?;
+        let version = object
+            .message("beacon_v1", |json| {
+                let object = Fields::new(json, "MapperBeaconV1")?;
+                let v1 = MapperBeaconV1 {
+                    gps: object.message("gps", gps_v1_from_json)?,
+                    signature: object.bytes("signature")?,
+                };
+                object.finish(&["gps", "signature"])?;
+                Ok(v1)
+            })?
+            .map(mapper_beacon::Version::BeaconV1);
+        object.finish(&["beacon_v1"])?;
+        Ok(Oneof::Beacon(MapperBeacon { version }))
+    })?;
+    let scan = object.message("scan", |json| {
+        let object = Fields::new(json, "MapperScan")?;
+        let version = object
+            .message("scan_v1", scan_v1_from_json)?
+            .map(mapper_scan::Version::ScanV1);
+        object.finish(&["scan_v1"])?;
+        Ok(Oneof::Scan(MapperScan { version }))
+    })?;
+    let attach = object.message("attach", |json| {
+        let object = Fields::new(json, "MapperAttach")?;
+        let version = object
+            .message("attach_v1", attach_v1_from_json)?
+            .map(mapper_attach::Version::AttachV1);
+        object.finish(&["attach_v1"])?;
+        Ok(Oneof::Attach(MapperAttach { version }))
+    })?;
+    object.finish(&["gps", "beacon", "scan", "attach"])?;
+    let mut set = [gps, beacon, scan, attach].into_iter().flatten();
+    let message = set.next();
+    if set.next().is_some() {
+        return Err(invalid(
+            "MapperPayload",
+            "more than one field of oneof message",
+        ));
+    }
+    Ok(MapperPayload { message })
+}
+
+fn gps_v1_to_json(gps: &MapperGpsV1) -> Value {
+    let mut object = Object::default();
+    object.uint64("timestamp", gps.timestamp);
+    object.number("lat", gps.lat);
+    object.number("lon", gps.lon);
+    object.number("hdop", gps.hdop);
+    object.number("altitude", gps.altitude);
+    object.number("numSats", gps.num_sats);
+    object.number("speed", gps.speed);
+    object.into()
+}
+
+fn gps_v1_from_json(json: &Value) -> Result<MapperGpsV1> {
+    let object = Fields::new(json, "MapperGpsV1")?;
+    let gps = MapperGpsV1 {
+        timestamp: object.integer("timestamp")?,
+        lat: object.integer("lat")?,
+        lon: object.integer("lon")?,
+        hdop: object.integer("hdop")?,
+        altitude: object.integer("altitude")?,
+        num_sats: object.integer("num_sats")?,
+        speed: object.integer("speed")?,
+    };
+    object.finish(&[
+        "timestamp",
+        "lat",
+        "lon",
+        "hdop",
+        "altitude",
+        "num_sats",
+        "speed",
+    ])?;
+    Ok(gps)
+}
+
+fn scan_v1_to_json(scan: &MapperCellScanV1) -> Value {
+    let mut object = Object::default();
+    object.number("scanCounter", scan.scan_counter);
+    if let Some(gps) = &scan.gps {
+        object.message("gps", gps_v1_to_json(gps));
+    }
+    object.repeated(
+        "results",
+        scan.results.iter().map(|result| {
+            let mut object = Object::default();
+            object.bool("lte", result.lte);
+            object.uint64("cid", result.cid);
+            object.number("plmn", result.plmn);
+            object.number("fcn", result.fcn);
+            object.number("pci", result.pci);
+            object.number("rsrp", result.rsrp);
+            object.number("rsrq", result.rsrq);
+            object.number("bandwidth", result.bandwidth);
+            object.into()
+        }),
+    );
+    object.into()
+}
+
+fn scan_v1_from_json(json: &Value) -> Result<MapperCellScanV1> {
+    let object = Fields::new(json, "MapperCellScanV1")?;
+    let scan = MapperCellScanV1 {
+        scan_counter: object.integer("scan_counter")?,
+        gps: object.message("gps", gps_v1_from_json)?,
+        results: object.repeated("results", |json| {
+            let object = Fields::new(json, "MapperCellScanResult")?;
+            let result = MapperCellScanResult {
+                lte: object.bool("lte")?,
+                cid: object.integer("cid")?,
+                plmn: object.integer("plmn")?,
+                fcn: object.integer("fcn")?,
+                pci: object.integer("pci")?,
+                rsrp: object.integer("rsrp")?,
+                rsrq: object.integer("rsrq")?,
+                bandwidth: object.integer("bandwidth")?,
+            };
+            object.finish(&[
+                "lte",
+                "cid",
+                "plmn",
+                "fcn",
+                "pci",
+                "rsrp",
+                "rsrq",
+                "bandwidth",
+            ])?;
+            Ok(result)
+        })?,
+    };
+    object.finish(&["scan_counter", "gps", "results"])?;
+    Ok(scan)
+}
+
+fn attach_v1_to_json(attach: &MapperCbrsAttachV1) -> Value {
+    use mapper_cbrs_attach_v1::MapperAttachResult;
+    let mut object = Object::default();
+    object.number("attachCounter", attach.attach_counter);
+    if let Some(gps) = &attach.gps {
+        object.message("gps", gps_v1_to_json(gps));
+    }
+    if let Some(candidate) = &attach.candidate {
+        let mut fields = Object::default();
+        fields.number("fromScan", candidate.from_scan);
+        fields.number("delay", candidate.delay);
+        fields.number("fcn", candidate.fcn);
+        fields.number("cid", candidate.cid);
+        fields.number("rsrp", candidate.rsrp);
+        fields.number("rsrq", candidate.rsrq);
+        object.message("candidate", fields.into());
+    }
+    object.enumeration(
+        "result",
+        attach.result,
+        MapperAttachResult::from_i32(attach.result).map(|result| result.as_str_name()),
+    );
+    object.into()
+}
+
+fn attach_v1_from_json(json: &Value) -> Result<MapperCbrsAttachV1> {
+    use mapper_cbrs_attach_v1::{MapperAttachResult, MapperCbrsAttachCandidate};
+    let object = Fields::new(json, "MapperCbrsAttachV1")?;
+    let attach = MapperCbrsAttachV1 {
+        attach_counter: object.integer("attach_counter")?,
+        gps: object.message("gps", gps_v1_from_json)?,
+        candidate: object.message("candidate", |json| {
+            let object = Fields::new(json, "MapperCbrsAttachCandidate")?;
+            let candidate = MapperCbrsAttachCandidate {
+                from_scan: object.integer("from_scan")?,
+                delay: object.integer("delay")?,
+                fcn: object.integer("fcn")?,
+                cid: object.integer("cid")?,
+                rsrp: object.integer("rsrp")?,
+                rsrq: object.integer("rsrq")?,
+            };
+            object.finish(&["from_scan", "delay", "fcn", "cid", "rsrp", "rsrq"])?;
+            Ok(candidate)
+        })?,
+        result: object.enumeration("result", |name| {
+            MapperAttachResult::from_str_name(name).map(|result| result as i32)
+        })?,
+    };
+    object.finish(&["attach_counter", "gps", "candidate", "result"])?;
+    Ok(attach)
+}
+
+fn lora_gw_to_json(lora_gw: &LoraGw) -> Value {
+    let mut object = Object::default();
+    object.bytes("pubkey", &lora_gw.pubkey);
+    object.uint64("h3Cell", lora_gw.h3_cell);
+    object.number("snr", lora_gw.snr);
+    object.number("rssi", lora_gw.rssi);
+    object.number("frequency", lora_gw.frequency);
+    object.enumeration(
+        "dataRate",
+        lora_gw.data_rate,
+        DataRate::from_i32(lora_gw.data_rate).map(|data_rate| data_rate.as_str_name()),
+    );
+    object.into()
+}
+
+fn lora_gw_from_json(json: &Value) -> Result<LoraGw> {
+    let object = Fields::new(json, "LoraGw")?;
+    let lora_gw = LoraGw {
+        pubkey: object.bytes("pubkey")?,
+        h3_cell: object.integer("h3_cell")?,
+        snr: object.integer("snr")?,
+        rssi: object.integer("rssi")?,
+        frequency: object.integer("frequency")?,
+        data_rate: object.enumeration("data_rate", |name| {
+            DataRate::from_str_name(name).map(|data_rate| data_rate as i32)
+        })?,
+    };
+    object.finish(&["pubkey", "h3_cell", "snr", "rssi", "frequency", "data_rate"])?;
+    Ok(lora_gw)
+}
+
+fn invalid(path: &str, reason: impl std::fmt::Display) -> Error {
+    Error::InvalidProtoJson(format!("{path}: {reason}"))
+}
+
+/// lowerCamelCase JSON name of a proto field
+fn json_name(field: &str) -> String {
+    let mut name = String::with_capacity(field.len());
+    let mut upper = false;
+    for c in field.chars() {
+        match c {
+            '_' => upper = true,
+            c if upper => {
+                name.push(c.to_ascii_uppercase());
+                upper = false;
+            }
+            c => name.push(c),
+        }
+    }
+    name
+}
+
+/// JSON object under construction, skipping default values
+#[derive(Default)]
+struct Object(Map<String, Value>);
+
+impl Object {
+    fn number(&mut self, name: &str, value: impl Into<i64>) {
+        let value = value.into();
+        if value != 0 {
+            self.0.insert(name.into(), value.into());
+        }
+    }
+
+    fn uint64(&mut self, name: &str, value: u64) {
+        if value != 0 {
+            self.0.insert(name.into(), value.to_string().into());
+        }
+    }
+
+    fn bool(&mut self, name: &str, value: bool) {
+        if value {
+            self.0.insert(name.into(), true.into());
+        }
+    }
+
+    fn bytes(&mut self, name: &str, value: &[u8]) {
+        if !value.is_empty() {
+            self.0.insert(name.into(), STANDARD.encode(value).into());
+        }
+    }
+
+    /// Unknown values are written as numbers
+    fn enumeration(&mut self, name: &str, value: i32, value_name: Option<&str>) {
+        match value_name {
+            _ if value == 0 => (),
+            Some(value_name) => {
+                self.0.insert(name.into(), value_name.into());
+            }
+            None => {
+                self.0.insert(name.into(), value.into());
+            }
+        }
+    }
+
+    /// Messages are written even when empty, as their presence is significant
+    fn message(&mut self, name: &str, value: Value) {
+        self.0.insert(name.into(), value);
+    }
+
+    fn repeated(&mut self, name: &str, values: impl Iterator<Item = Value>) {
+        let values: Vec<Value> = values.collect();
+        if !values.is_empty() {
+            self.0.insert(name.into(), values.into());
+        }
+    }
+}
+
+impl From<Object> for Value {
+    fn from(object: Object) -> Self {
+        Value::Object(object.0)
+    }
+}
+
+/// JSON object being parsed into the message `path`
+struct Fields<'a> {
+    path: &'static str,
+    map: &'a Map<String, Value>,
+}
+
+impl<'a> Fields<'a> {
+    fn new(json: &'a Value, path: &'static str) -> Result<Self> {
+        match json {
+            Value::Object(map) => Ok(Self { path, map }),
+            _ => Err(invalid(path, "expected an object")),
+        }
+    }
+
+    /// The value under the JSON or the proto name of `field`, `None` if absent or null
+    fn get(&self, field: &str) -> Result<Option<&'a Value>> {
+        let json = self.map.get(&json_name(field));
+        let proto = self.map.get(field).filter(|_| json_name(field) != field);
+        match (json, proto) {
+            (Some(_), Some(_)) => Err(invalid(self.path, format!("{field} is set twice"))),
+            (Some(value), None) | (None, Some(value)) => Ok(Some(value).filter(|v| !v.is_null())),
+            (None, None) => Ok(None),
+        }
+    }
+
+    fn integer<T: TryFrom<i128> + Default>(&self, field: &str) -> Result<T> {
+        let Some(value) = self.get(field)? else {
+            return Ok(T::default());
+        };
+        let parsed: Option<i128> = match value {
+            Value::Number(number) => number
+                .as_i64()
+                .map(i128::from)
+                .or(number.as_u64().map(i128::from))
+                .or(number
+                    .as_f64()
+                    .filter(|f| f.fract() == 0.0 && f.abs() < 2f64.powi(64))
+                    .map(|f| f as i128)),
+            Value::String(s) => s.parse().ok(),
+            _ => None,
+        };
+        parsed
+            .and_then(|parsed| T::try_from(parsed).ok())
+            .ok_or_else(|| invalid(self.path, format!("{field} is not a valid integer")))
+    }
+
+    fn bool(&self, field: &str) -> Result<bool> {
+        match self.get(field)? {
+            None => Ok(false),
+            Some(Value::Bool(value)) => Ok(*value),
+            Some(_) => Err(invalid(self.path, format!("{field} is not a bool"))),
+        }
+    }
+
+    fn bytes(&self, field: &str) -> Result<Vec<u8>> {
+        match self.get(field)? {
+            None => Ok(Vec::new()),
+            Some(Value::String(s)) => STANDARD
+                .decode(s)
+                .map_err(|err| invalid(self.path, format!("{field}: {err}"))),
+            Some(_) => Err(invalid(self.path, format!("{field} is not base64"))),
+        }
+    }
+
+    fn enumeration(&self, field: &str, from_name: impl Fn(&str) -> Option<i32>) -> Result<i32> {
+        match self.get(field)? {
+            Some(Value::String(name)) => from_name(name)
+                .ok_or_else(|| invalid(self.path, format!("{field} has no value {name}"))),
+            Some(_) => self.integer(field),
+            None => Ok(0),
+        }
+    }
+
+    fn message<T>(&self, field: &str, parse: impl Fn(&Value) -> Result<T>) -> Result<Option<T>> {
+        self.get(field)?.map(parse).transpose()
+    }
+
+    fn repeated<T>(&self, field: &str, parse: impl Fn(&Value) -> Result<T>) -> Result<Vec<T>> {
+        match self.get(field)? {
+            None => Ok(Vec::new()),
+            Some(Value::Array(values)) => values.iter().map(parse).collect(),
+            Some(_) => Err(invalid(self.path, format!("{field} is not an array"))),
+        }
+    }
+
+    /// Fails on fields other than `known`
+    fn finish(&self, known: &[&str]) -> Result {
+        match self.map.keys().find(|key| {
+            !known
+                .iter()
+                .any(|field| *field == key.as_str() || json_name(field) == key.as_str())
+        }) {
+            Some(key) => Err(invalid(self.path, format!("unknown field {key}"))),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{keys, Gps};
+
+    #[test]
+    fn canonical_gps_json() {
+        let key = keys::file::File::create_key().unwrap();
+        let msg = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap();
+        let json: Value = serde_json::from_str(&msg.to_proto_json().unwrap()).unwrap();
+        let gps = &json["msgV1"]["payload"]["gps"]["gpsV1"];
+        assert_eq!(gps["numSats"], 5);
+        assert!(gps["timestamp"].is_string());
+        assert!(json["msgV1"].get("loraGws").is_none());
+        assert_eq!(Message::from_proto_json(&json.to_string()).unwrap(), msg);
+        let mut tampered = json.clone();
+        tampered["msgV1"]["payload"]["gps"]["gpsV1"]["numSats"] = 6.into();
+        assert!(matches!(
+            Message::from_proto_json(&tampered.to_string()),
+            Err(Error::SignatureVerification { .. })
+        ));
+
+        // proto field names, numbers as strings and nulls are accepted too
+        let payload = r#"{"gps": {"gps_v1": {"timestamp": 1672531205, "lat": "-5012345",
+            "lon": 12012345, "hdop": 905, "altitude": 925, "num_sats": 5, "speed": 5050,
+            "speed_kmh": null}}}"#;
+        assert!(matches!(
+            Payload::from_proto_json(payload),
+            Err(Error::InvalidProtoJson(reason)) if reason.contains("speed_kmh")
+        ));
+        let payload = payload.replace(r#""speed_kmh": null"#, r#""numSats": null"#);
+        assert!(Payload::from_proto_json(&payload).is_err());
+        let payload = payload.replace(r#", "numSats": null"#, "");
+        assert_eq!(
+            Payload::from_proto_json(&payload).unwrap(),
+            Payload::Gps(Gps::rounded())
+        );
+    }
+
+    #[test]
+    fn extension_is_not_dropped() {
+        let key = keys::file::File::create_key().unwrap();
+        let mut gps = Gps::rounded();
+        gps.h_acc_m = Some(rust_decimal::Decimal::new(3_25, 2));
+        let msg = Message::from_payload_signed(&key, Payload::Gps(gps)).unwrap();
+        assert!(matches!(
+            msg.to_proto_json(),
+            Err(Error::PayloadExtensionDropped(crate::PayloadKind::Gps))
+        ));
+        assert!(msg.payload.to_proto_json().is_err());
+    }
+
+    #[cfg(feature = "cell")]
+    #[test]
+    fn scan_roundtrip() {
+        let key = keys::file::File::create_key().unwrap();
+        let mut msg =
+            Message::from_payload_signed(&key, Payload::CellScan(crate::CellScan::random()))
+                .unwrap();
+        msg.lora_gws.push(crate::LoraGw::random());
+        let json = msg.to_proto_json().unwrap();
+        assert!(json.contains(r#""dataRate":"SF10BW125""#), "{json}");
+        assert_eq!(Message::from_proto_json(&json).unwrap(), msg);
+    }
+}
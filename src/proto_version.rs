@@ -0,0 +1,137 @@
+//! Which helium-proto the crate was built against, as far as the wire is concerned. Upstream
+//! proto changes have altered our encoding silently before, so the fixed fixtures below are
+//! encoded and compared to the checked in golden bytes of every helium-proto source we support
+//! by `tests/proto_golden.rs`, and their digest is exposed for services to log and compare at
+//! runtime.
+use super::{Gps, MapperMsg, MapperMsgV1, Payload};
+use helium_proto::{mapper_msg, Message as ProtoMessage};
+use sha2::{Digest, Sha256};
+
+/// helium-proto revision the crate builds against. `tests/golden/versions` must list it with the
+/// golden bytes its encodings match.
+pub const HELIUM_PROTO_SOURCE: &str =
+    "https://github.com/helium/proto?branch=lthiery/mapper-service";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtoVersion {
+    pub source: &'static str,
+    /// SHA-256 over the names and encodings of `golden_fixtures`. Builds that encode the same
+    /// fixtures identically, ie: are wire compatible for them, share it.
+    pub wire_fingerprint: [u8; 32],
+}
+
+impl std::fmt::Display for ProtoVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} wire=", self.source)?;
+        self.wire_fingerprint[..8]
+            .iter()
+            .try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+pub fn proto_version() -> ProtoVersion {
+    let mut hasher = Sha256::new();
+    for (name, bytes) in golden_fixtures() {
+        hasher.update(name.as_bytes());
+        hasher.update((bytes.len() as u64).to_be_bytes());
+        hasher.update(&bytes);
+    }
+    ProtoVersion {
+        source: HELIUM_PROTO_SOURCE,
+        wire_fingerprint: hasher.finalize().into(),
+    }
+}
+
+/// Deterministic payloads and a MapperMsg covering every proto field we write, with their
+/// encodings. Payload kinds that are not compiled in are left out.
+pub fn golden_fixtures() -> Vec<(&'static str, Vec<u8>)> {
+    let gps = Gps::rounded();
+    let mut payloads: Vec<(&'static str, Payload)> = vec![("gps", Payload::Gps(gps))];
+    #[cfg(feature = "beacon")]
+    payloads.push((
+        "beacon",
+        Payload::Beacon(crate::Beacon::new(gps, vec![0xAB, 0xCD])),
+    ));
+    #[cfg(feature = "cell")]
+    {
+        use crate::{
+            AttachCandidate, BandwidthKhz, CellAttach, CellAttachResult, CellScan, CellScanResult,
+            Pci,
+        };
+        let result = CellScanResult {
+            mcc: crate::CBRS_MCC,
+            mnc: crate::CBRS_MNC,
+            mnc_digits: Some(3),
+            earfcn: 55990,
+            physical_cell_id: Pci::new(17).expect("valid pci"),
+            rsrp: -95,
+            rsrq: -11,
+            cell_id: 0x0099D << 8 | 0x01,
+            bandwidth: BandwidthKhz::new(20_000).expect("valid bandwidth"),
+            lte: true,
+            observed_at: None,
+        };
+        payloads.push((
+            "scan",
+            Payload::CellScan(CellScan {
+                scan_counter: 24,
+                gps,
+                results: vec![result],
+            }),
+        ));
+        payloads.push((
+            "attach",
+            Payload::CellAttach(CellAttach {
+                attach_counter: 7,
+                gps,
+                candidate: AttachCandidate {
+                    from_scan: 24,
+                    delay: 12,
+                    cell_id: result.cell_id,
+                    fcn: 55990,
+                    rsrp: -95,
+                    rsrq: -11,
+                },
+                result: CellAttachResult::Connected,
+                failure_cause: None,
+                sim: None,
+            }),
+        ));
+    }
+
+    let msg = MapperMsg {
+        version: Some(mapper_msg::Version::MsgV1(MapperMsgV1 {
+            payload: Some(Payload::Gps(gps).to_proto()),
+            signature: vec![0x30, 0x06, 0x02, 0x01, 0x01, 0x02, 0x01, 0x02],
+            pubkey: vec![0x00; 34],
+            lora_gws: vec![helium_proto::LoraGw {
+                pubkey: vec![0x01; 33],
+                h3_cell: 0x8a1fb46622dffff,
+                snr: -55,
+                rssi: -1100,
+                frequency: 904_300,
+                data_rate: helium_proto::DataRate::Sf10bw125.into(),
+            }],
+        })),
+    };
+
+    payloads
+        .into_iter()
+        .map(|(name, payload)| (name, payload.to_proto().encode_to_vec()))
+        .chain(std::iter::once(("msg", msg.encode_to_vec())))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fingerprint_is_deterministic() {
+        let version = proto_version();
+        assert_eq!(version, proto_version());
+        let shown = version.to_string();
+        let (_, wire) = shown.rsplit_once("wire=").unwrap();
+        assert_eq!(wire.len(), 16);
+    }
+}
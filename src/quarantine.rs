@@ -0,0 +1,310 @@
+//! On-disk format for messages that failed to decode or verify, kept for later forensics.
+//!
+//! A quarantine file is a plain concatenation of frames. All integers are big endian:
+//!
+//! | field         | size       |                                               |
+//! |---------------|------------|-----------------------------------------------|
+//! | magic         | 3          | `b"SPQ"`                                      |
+//! | version       | 1          | currently 2                                   |
+//! | received_at   | 8          | i64 milliseconds since the unix epoch         |
+//! | error variant | 2 + len    | `Error::variant_name`, utf-8                  |
+//! | error message | 4 + len    | `Error` display string, utf-8                 |
+//! | source        | 2 + len    | free form origin (ingest node, peer address) |
+//! | raw len       | 8          | u64 length of the bytes as received           |
+//! | raw           | 4 + len    | the bytes as received, up to `MAX_FIELD_LEN`  |
+//!
+//! Version 1 frames have no raw len and are still read.
+use super::{DateTime, Deserialize, Error, Result, Serialize, Utc};
+use chrono::TimeZone;
+use std::io::{Read, Write};
+
+const MAGIC: &[u8; 3] = b"SPQ";
+const VERSION: u8 = 2;
+const VERSION_WITHOUT_RAW_LEN: u8 = 1;
+
+/// Fields longer than this are taken for corruption rather than allocated. The writer keeps only
+/// this much of an oversize raw payload, and refuses other fields beyond it, so that every file
+/// it writes can be read back.
+pub const MAX_FIELD_LEN: usize = 1 << 20;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuarantineRecord {
+    pub received_at: DateTime<Utc>,
+    pub error_variant: String,
+    pub error_message: String,
+    pub source: String,
+    /// At most `MAX_FIELD_LEN` bytes of what was received, see `is_truncated`
+    pub raw: Vec<u8>,
+    /// Length of what was received, which is more than `raw` holds when it was truncated
+    #[serde(default)]
+    pub raw_len: u64,
+}
+
+impl QuarantineRecord {
+    pub fn new(raw: Vec<u8>, error: &Error, source: impl Into<String>) -> Self {
+        Self::new_at(raw, error, source, Utc::now())
+    }
+
+    pub fn new_at(
+        raw: Vec<u8>,
+        error: &Error,
+        source: impl Into<String>,
+        received_at: DateTime<Utc>,
+    ) -> Self {
+        let mut raw = raw;
+        let raw_len = raw.len() as u64;
+        raw.truncate(MAX_FIELD_LEN);
+        Self {
+            received_at,
+            error_variant: error.variant_name().to_string(),
+            error_message: error.to_string(),
+            source: source.into(),
+            raw,
+            raw_len,
+        }
+    }
+
+    /// True if only a prefix of the received bytes was kept
+    pub fn is_truncated(&self) -> bool {
+        self.raw_len > self.raw.len() as u64
+    }
+}
+
+pub struct QuarantineWriter<W> {
+    inner: W,
+}
+
+impl<W: Write> QuarantineWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    /// Raw payloads beyond `MAX_FIELD_LEN` are truncated, keeping their length
+    pub fn write(&mut self, record: &QuarantineRecord) -> Result {
+        let raw = &record.raw[..record.raw.len().min(MAX_FIELD_LEN)];
+        let raw_len = record.raw_len.max(record.raw.len() as u64);
+        let mut frame = Vec::with_capacity(40 + raw.len() + record.error_message.len());
+        frame.extend_from_slice(MAGIC);
+        frame.push(VERSION);
+        frame.extend_from_slice(&record.received_at.timestamp_millis().to_be_bytes());
+        push_u16_prefixed(&mut frame, record.error_variant.as_bytes())?;
+        push_u32_prefixed(&mut frame, record.error_message.as_bytes())?;
+        push_u16_prefixed(&mut frame, record.source.as_bytes())?;
+        frame.extend_from_slice(&raw_len.to_be_bytes());
+        push_u32_prefixed(&mut frame, raw)?;
+        self.inner.write_all(&frame)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result {
+        Ok(self.inner.flush()?)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+fn push_u16_prefixed(frame: &mut Vec<u8>, bytes: &[u8]) -> Result {
+    let len = u16::try_from(bytes.len())
+        .map_err(|_| Error::InvalidQuarantineFrame("field longer than u16::MAX"))?;
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(bytes);
+    Ok(())
+}
+
+fn push_u32_prefixed(frame: &mut Vec<u8>, bytes: &[u8]) -> Result {
+    if bytes.len() > MAX_FIELD_LEN {
+        return Err(Error::InvalidQuarantineFrame("field too long"));
+    }
+    let len = bytes.len() as u32;
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(bytes);
+    Ok(())
+}
+
+/// Iterates over the records of a quarantine file
+pub struct QuarantineReader<R> {
+    inner: R,
+}
+
+impl<R: Read> QuarantineReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner }
+    }
+
+    /// Returns `None` at a clean end of input, between frames
+    pub fn read_record(&mut self) -> Result<Option<QuarantineRecord>> {
+        let mut header = [0u8; 4];
+        let read = read_until_full(&mut self.inner, &mut header)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        if read < header.len() {
+            return Err(Error::InvalidQuarantineFrame("truncated header"));
+        }
+        if &header[..3] != MAGIC {
+            return Err(Error::InvalidQuarantineFrame("bad magic"));
+        }
+        let version = header[3];
+        if version != VERSION && version != VERSION_WITHOUT_RAW_LEN {
+            return Err(Error::InvalidQuarantineFrame("unsupported version"));
+        }
+        let mut millis = [0u8; 8];
+        self.inner.read_exact(&mut millis)?;
+        let received_at = Utc
+            .timestamp_millis_opt(i64::from_be_bytes(millis))
+            .single()
+            .ok_or(Error::InvalidQuarantineFrame("timestamp out of range"))?;
+        let error_variant = self.read_string(2)?;
+        let error_message = self.read_string(4)?;
+        let source = self.read_string(2)?;
+        let raw_len = match version {
+            VERSION_WITHOUT_RAW_LEN => None,
+            _ => {
+                let mut raw_len = [0u8; 8];
+                self.inner.read_exact(&mut raw_len)?;
+                Some(u64::from_be_bytes(raw_len))
+            }
+        };
+        let raw = self.read_prefixed(4)?;
+        Ok(Some(QuarantineRecord {
+            received_at,
+            error_variant,
+            error_message,
+            source,
+            raw_len: raw_len.unwrap_or(raw.len() as u64),
+            raw,
+        }))
+    }
+
+    fn read_prefixed(&mut self, prefix_len: usize) -> Result<Vec<u8>> {
+        let mut len = [0u8; 4];
+        self.inner.read_exact(&mut len[4 - prefix_len..])?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len > MAX_FIELD_LEN {
+            return Err(Error::InvalidQuarantineFrame("field too long"));
+        }
+        let mut bytes = vec![0u8; len];
+        self.inner.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn read_string(&mut self, prefix_len: usize) -> Result<String> {
+        String::from_utf8(self.read_prefixed(prefix_len)?)
+            .map_err(|_| Error::InvalidQuarantineFrame("field is not utf-8"))
+    }
+}
+
+impl<R: Read> Iterator for QuarantineReader<R> {
+    type Item = Result<QuarantineRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+/// Like `read_exact` but returns how many bytes were read instead of failing at end of input
+fn read_until_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Message;
+
+    #[test]
+    fn quarantine_roundtrip() {
+        let raw = vec![0x0a, 0x02, 0xff, 0xff];
+        let error = Message::decode_from(&raw).unwrap_err();
+        let received_at = Utc.timestamp_millis_opt(1672531205123).unwrap();
+        let records = vec![
+            QuarantineRecord::new_at(raw, &error, "ingest-1", received_at),
+            QuarantineRecord::new_at(
+                vec![],
+                &Error::ProtoHasNone("version"),
+                "ingest-2",
+                received_at,
+            ),
+        ];
+
+        let mut writer = QuarantineWriter::new(Vec::new());
+        for record in &records {
+            writer.write(record).unwrap();
+        }
+        let bytes = writer.into_inner();
+
+        let read: Vec<QuarantineRecord> = QuarantineReader::new(bytes.as_slice())
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(read, records);
+        assert_eq!(read[0].error_variant, error.variant_name());
+
+        let truncated = &bytes[..bytes.len() - 1];
+        let mut reader = QuarantineReader::new(truncated);
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().unwrap().is_err());
+
+        // a corrupted length of the last, empty, raw field must not be allocated
+        let mut huge = bytes.clone();
+        let at = huge.len() - 4;
+        huge[at..].copy_from_slice(&u32::MAX.to_be_bytes());
+        let mut reader = QuarantineReader::new(huge.as_slice());
+        assert!(reader.next().unwrap().is_ok());
+        assert!(matches!(
+            reader.next().unwrap(),
+            Err(Error::InvalidQuarantineFrame(_))
+        ));
+    }
+
+    #[test]
+    fn oversize_raw_is_truncated_not_refused() {
+        let raw = vec![0xAB; MAX_FIELD_LEN + 10];
+        let record = QuarantineRecord::new(raw, &Error::ProtoHasNone("version"), "ingest-1");
+        assert!(record.is_truncated());
+        assert_eq!(record.raw.len(), MAX_FIELD_LEN);
+        assert_eq!(record.raw_len, MAX_FIELD_LEN as u64 + 10);
+
+        // records built by hand are truncated by the writer
+        let by_hand = QuarantineRecord {
+            raw: vec![0xAB; MAX_FIELD_LEN + 10],
+            raw_len: 0,
+            ..record.clone()
+        };
+        let mut writer = QuarantineWriter::new(Vec::new());
+        writer.write(&record).unwrap();
+        writer.write(&by_hand).unwrap();
+        let read: Vec<QuarantineRecord> = QuarantineReader::new(writer.into_inner().as_slice())
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(read, [record.clone(), record]);
+    }
+
+    #[test]
+    fn reads_version_1_frames() {
+        let mut frame = MAGIC.to_vec();
+        frame.push(VERSION_WITHOUT_RAW_LEN);
+        frame.extend_from_slice(&1672531205123i64.to_be_bytes());
+        push_u16_prefixed(&mut frame, b"ProtoHasNone").unwrap();
+        push_u32_prefixed(&mut frame, b"proto has none: version").unwrap();
+        push_u16_prefixed(&mut frame, b"ingest-1").unwrap();
+        push_u32_prefixed(&mut frame, &[0x0a, 0x00]).unwrap();
+        let record = QuarantineReader::new(frame.as_slice())
+            .read_record()
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.raw, [0x0a, 0x00]);
+        assert_eq!(record.raw_len, 2);
+        assert!(!record.is_truncated());
+    }
+}
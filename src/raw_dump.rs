@@ -0,0 +1,225 @@
+//! Raw modem output, eg: the full text of an `AT+COPS=?` scan, shipped for offline analysis when
+//! the structured `CellScan` is not enough. Dumps are large, so they live behind the `raw-dump`
+//! feature, are zstd compressed and are capped at `MAX_DUMP_LEN` bytes of text, keeping the
+//! head of longer output.
+//!
+//! helium-proto has no message for them and they travel on their own rather than in a
+//! `MapperPayload`, as the `RawModemDumpV1` protobuf below; there is no LoRa frame.
+use super::{
+    compression::{self, Codec},
+    DateTime, Deserialize, Error, ProtoMessage, Result, Serialize, Utc,
+};
+use chrono::TimeZone;
+
+pub const MAX_DUMP_LEN: usize = 64 << 10;
+
+const ZSTD_LEVEL: i32 = 19;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentType {
+    /// Response to a network scan command, eg: `AT+COPS=?` or `AT+QSCAN`
+    AtScan,
+    /// Transcript of AT commands and responses
+    AtLog,
+    /// Tag of a newer firmware, kept as is
+    Other(OtherTag),
+}
+
+const AT_SCAN: u32 = 1;
+const AT_LOG: u32 = 2;
+
+impl From<u32> for ContentType {
+    fn from(tag: u32) -> Self {
+        match tag {
+            AT_SCAN => Self::AtScan,
+            AT_LOG => Self::AtLog,
+            tag => Self::Other(OtherTag(tag)),
+        }
+    }
+}
+
+impl From<ContentType> for u32 {
+    fn from(content_type: ContentType) -> Self {
+        match content_type {
+            ContentType::AtScan => AT_SCAN,
+            ContentType::AtLog => AT_LOG,
+            ContentType::Other(tag) => tag.0,
+        }
+    }
+}
+
+/// A content type tag this crate has no variant for. The tags of the other variants are
+/// rejected, as they would come back as those variants after a roundtrip.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "u32", into = "u32")]
+pub struct OtherTag(u32);
+
+impl OtherTag {
+    pub fn new(tag: u32) -> Result<Self> {
+        match ContentType::from(tag) {
+            ContentType::Other(other) => Ok(other),
+            _ => Err(Error::OutOfRange {
+                field: "content_type",
+                value: tag.into(),
+            }),
+        }
+    }
+
+    pub fn get(&self) -> u32 {
+        self.0
+    }
+}
+
+impl TryFrom<u32> for OtherTag {
+    type Error = Error;
+
+    fn try_from(tag: u32) -> Result<Self> {
+        Self::new(tag)
+    }
+}
+
+impl From<OtherTag> for u32 {
+    fn from(tag: OtherTag) -> Self {
+        tag.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RawModemDump {
+    pub captured_at: DateTime<Utc>,
+    pub content_type: ContentType,
+    /// The text, compressed by `compression::compress`
+    compressed: Vec<u8>,
+    /// Set when the text was cut at `MAX_DUMP_LEN`
+    pub truncated: bool,
+}
+
+/// Wire form of a `RawModemDump`. It derives with the crate's own prost, which has to be the one
+/// helium-proto builds on; the assertion below stops the build when they drift apart.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RawModemDumpV1 {
+    /// Seconds since the Unix epoch
+    #[prost(uint64, tag = "1")]
+    pub captured_at: u64,
+    #[prost(uint32, tag = "2")]
+    pub content_type: u32,
+    #[prost(bytes = "vec", tag = "3")]
+    pub compressed: Vec<u8>,
+    #[prost(bool, tag = "4")]
+    pub truncated: bool,
+}
+
+const _: fn() = || {
+    fn helium_proto_message<M: ProtoMessage>() {}
+    helium_proto_message::<RawModemDumpV1>();
+};
+
+impl RawModemDump {
+    pub fn new(content_type: ContentType, text: &str, captured_at: DateTime<Utc>) -> Result<Self> {
+        let mut end = text.len().min(MAX_DUMP_LEN);
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        Ok(Self {
+            captured_at,
+            content_type,
+            compressed: compression::compress(
+                &text.as_bytes()[..end],
+                Codec::Zstd { level: ZSTD_LEVEL },
+            )?,
+            truncated: end < text.len(),
+        })
+    }
+
+    /// Size of the dump on the wire, before the other proto fields
+    pub fn compressed_len(&self) -> usize {
+        self.compressed.len()
+    }
+
+    /// Invalid UTF-8, which modems do send, is replaced
+    pub fn text(&self) -> Result<String> {
+        let bytes = compression::decompress(&self.compressed)?;
+        if bytes.len() > MAX_DUMP_LEN {
+            return Err(Error::RawDumpTooLong(bytes.len()));
+        }
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    pub fn to_proto(&self) -> RawModemDumpV1 {
+        RawModemDumpV1 {
+            captured_at: self.captured_at.timestamp().max(0) as u64,
+            content_type: self.content_type.into(),
+            compressed: self.compressed.clone(),
+            truncated: self.truncated,
+        }
+    }
+
+    pub fn encode_to_vec(&self) -> Vec<u8> {
+        self.to_proto().encode_to_vec()
+    }
+
+    /// Checks that the dump decompresses within `MAX_DUMP_LEN`
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        RawModemDumpV1::decode(bytes)?.try_into()
+    }
+}
+
+impl TryFrom<RawModemDumpV1> for RawModemDump {
+    type Error = Error;
+
+    fn try_from(proto: RawModemDumpV1) -> Result<Self> {
+        let seconds = i64::try_from(proto.captured_at).unwrap_or(i64::MAX);
+        let captured_at = Utc
+            .timestamp_opt(seconds, 0)
+            .single()
+            .ok_or(Error::TimestampOutOfRange(seconds))?;
+        let dump = Self {
+            captured_at,
+            content_type: proto.content_type.into(),
+            compressed: proto.compressed,
+            truncated: proto.truncated,
+        };
+        dump.text()?;
+        Ok(dump)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn proto_roundtrip_and_cap() {
+        let captured_at = crate::Gps::rounded().timestamp;
+        let scan = "+COPS: (2,\"CBRS\",\"CBRS\",\"315010\",7),,(0,1,2,3,4),(0,1,2)\r\nOK\r\n";
+        let dump = RawModemDump::new(ContentType::AtScan, scan, captured_at).unwrap();
+        let decoded = RawModemDump::decode(&dump.encode_to_vec()).unwrap();
+        assert_eq!(decoded, dump);
+        assert_eq!(decoded.text().unwrap(), scan);
+        assert!(!decoded.truncated);
+
+        let long = scan.repeat(MAX_DUMP_LEN / scan.len() + 1);
+        let dump = RawModemDump::new(ContentType::AtLog, &long, captured_at).unwrap();
+        assert!(dump.truncated);
+        assert!(dump.compressed_len() < MAX_DUMP_LEN / 10);
+        assert_eq!(dump.text().unwrap(), long[..MAX_DUMP_LEN]);
+    }
+
+    #[test]
+    fn content_types_survive_roundtrip() {
+        for tag in [1, 2, 3, u32::MAX] {
+            let content_type = ContentType::from(tag);
+            assert_eq!(u32::from(content_type), tag);
+            let json = serde_json::to_string(&content_type).unwrap();
+            assert_eq!(
+                serde_json::from_str::<ContentType>(&json).unwrap(),
+                content_type
+            );
+        }
+        assert!(OtherTag::new(1).is_err());
+        assert!(OtherTag::new(2).is_err());
+        assert_eq!(OtherTag::new(3).unwrap().get(), 3);
+        assert!(serde_json::from_str::<ContentType>(r#"{"other":2}"#).is_err());
+    }
+}
@@ -0,0 +1,340 @@
+//! Anonymized reports for public coverage datasets. `Redactor` turns a `Message` into an
+//! `AnonymizedReport`: the device pubkey becomes a `Pseudonym`, an HMAC under the dataset key,
+//! so reports of a device can be joined within a dataset but not across datasets or back to
+//! the device. Fixes are snapped to the center of an H3 cell and times truncated, SIM info and
+//! beacon commitments are dropped, and gateways are reduced to a count. The `IngestMeta` is kept,
+//! with its reception time truncated like the fix.
+//!
+//! Reports serialize with serde, and with the `arrow` feature `to_record_batch` lays their
+//! common columns out as an Arrow record batch.
+use super::{
+    DateTime, Deserialize, Error, IngestMeta, Message, Payload, PayloadKind, Result, Serialize, Utc,
+};
+use chrono::TimeZone;
+use h3o::{CellIndex, LatLng, Resolution};
+use hmac::{Hmac, Mac};
+use rust_decimal::Decimal;
+use sha2::Sha256;
+
+const PSEUDONYM_SIZE: usize = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RedactionConfig {
+    /// Fixes are replaced by the center of their cell at this resolution
+    pub position_resolution: Resolution,
+    /// Timestamps are truncated to a multiple of this many seconds
+    pub time_granularity_s: u32,
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        Self {
+            position_resolution: Resolution::Nine,
+            time_granularity_s: 60,
+        }
+    }
+}
+
+/// Truncated HMAC-SHA256 of a device pubkey, shown as hex
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Pseudonym(pub [u8; PSEUDONYM_SIZE]);
+
+impl std::fmt::Display for Pseudonym {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+impl std::str::FromStr for Pseudonym {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || Error::InvalidPseudonym(s.to_string());
+        if s.len() != PSEUDONYM_SIZE * 2 || !s.is_ascii() {
+            return Err(invalid());
+        }
+        let mut bytes = [0; PSEUDONYM_SIZE];
+        for (byte, pair) in bytes.iter_mut().zip(s.as_bytes().chunks(2)) {
+            let pair = std::str::from_utf8(pair).map_err(|_| invalid())?;
+            *byte = u8::from_str_radix(pair, 16).map_err(|_| invalid())?;
+        }
+        Ok(Self(bytes))
+    }
+}
+
+impl Serialize for Pseudonym {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        crate::serde_helpers::display_fromstr::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Pseudonym {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        crate::serde_helpers::display_fromstr::deserialize(deserializer)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnonymizedReport {
+    pub device: Pseudonym,
+    pub kind: PayloadKind,
+    /// Cell of the fix at the configured resolution
+    pub h3_cell: CellIndex,
+    /// The payload with its fix snapped and truncated and identifying fields removed
+    pub payload: Payload,
+    pub witness_count: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ingest_meta: Option<IngestMeta>,
+}
+
+#[derive(Clone)]
+pub struct Redactor {
+    mac: Hmac<Sha256>,
+    config: RedactionConfig,
+}
+
+impl std::fmt::Debug for Redactor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Redactor")
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Redactor {
+    /// `dataset_key` should be random and kept secret, and differ between datasets
+    pub fn new(dataset_key: &[u8], config: RedactionConfig) -> Self {
+        Self {
+            mac: Hmac::new_from_slice(dataset_key).expect("hmac takes keys of any size"),
+            config,
+        }
+    }
+
+    pub fn pseudonym(&self, pubkey: &crate::PublicKey) -> Pseudonym {
+        let mut mac = self.mac.clone();
+        mac.update(&pubkey.to_vec());
+        let digest = mac.finalize().into_bytes();
+        Pseudonym(digest[..PSEUDONYM_SIZE].try_into().unwrap())
+    }
+
+    /// Fails if the fix has no H3 cell
+    pub fn redact(&self, msg: &Message) -> Result<AnonymizedReport> {
+        let gps = msg.payload.gps();
+        let h3_cell = gps.to_h3_cell(self.config.position_resolution)?;
+        let center = LatLng::from(h3_cell);
+        let snap = |degrees: f64| Decimal::try_from(degrees).unwrap_or_default().round_dp(5);
+        let granularity = i64::from(self.config.time_granularity_s.max(1));
+        let truncate = |time: DateTime<Utc>| {
+            let seconds = time.timestamp();
+            Utc.timestamp_opt(seconds - seconds.rem_euclid(granularity), 0)
+                .single()
+                .unwrap_or(time)
+        };
+        let redacted_gps = crate::Gps {
+            timestamp: truncate(gps.timestamp),
+            lat: snap(center.lat()),
+            lon: snap(center.lng()),
+            h_acc_m: None,
+            v_acc_m: None,
+            ..*gps
+        };
+        let payload = match &msg.payload {
+            #[cfg(feature = "cell")]
+            Payload::CellAttach(attach) => Payload::CellAttach(crate::CellAttach {
+                gps: redacted_gps,
+                sim: None,
+                ..*attach
+            }),
+            #[cfg(feature = "cell")]
+            Payload::CellScan(scan) => Payload::CellScan(crate::CellScan {
+                gps: redacted_gps,
+                ..scan.clone()
+            }),
+            #[cfg(feature = "beacon")]
+            Payload::Beacon(beacon) => Payload::Beacon(crate::Beacon {
+                gps: redacted_gps,
+                signature: Vec::new().into(),
+                ..beacon.clone()
+            }),
+            Payload::Gps(_) => Payload::Gps(redacted_gps),
+            Payload::Position(position) => {
+                Payload::Position(crate::Position::new(crate::PositionEstimate {
+                    timestamp: redacted_gps.timestamp,
+                    lat: redacted_gps.lat,
+                    lon: redacted_gps.lon,
+                    ..*position.estimate()
+                }))
+            }
+        };
+        Ok(AnonymizedReport {
+            device: self.pseudonym(&msg.pubkey),
+            kind: msg.payload.kind(),
+            h3_cell,
+            payload,
+            witness_count: msg.lora_gws.len(),
+            ingest_meta: msg.ingest_meta.as_ref().map(|meta| IngestMeta {
+                received_at: truncate(meta.received_at),
+                ..meta.clone()
+            }),
+        })
+    }
+}
+
+/// The columns common to every payload kind: device, kind, timestamp (ms, UTC), h3_cell, lat,
+/// lon and witness_count, followed by the `IngestMeta` ones: received_at (ms, UTC),
+/// gateway_count, ingest_node, priority and network_id. These are null for reports without
+/// an `IngestMeta`, and ingest_node, priority and network_id also where the meta has none.
+#[cfg(feature = "arrow")]
+pub fn to_record_batch(reports: &[AnonymizedReport]) -> Result<arrow_array::RecordBatch> {
+    use arrow_array::{
+        ArrayRef, Float64Array, RecordBatch, StringArray, TimestampMillisecondArray, UInt32Array,
+        UInt64Array,
+    };
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use rust_decimal::prelude::ToPrimitive;
+    use std::sync::Arc;
+
+    let schema = Schema::new(vec![
+        Field::new("device", DataType::Utf8, false),
+        Field::new("kind", DataType::Utf8, false),
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("h3_cell", DataType::UInt64, false),
+        Field::new("lat", DataType::Float64, false),
+        Field::new("lon", DataType::Float64, false),
+        Field::new("witness_count", DataType::UInt64, false),
+        Field::new(
+            "received_at",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            true,
+        ),
+        Field::new("gateway_count", DataType::UInt32, true),
+        Field::new("ingest_node", DataType::Utf8, true),
+        Field::new("priority", DataType::Utf8, true),
+        Field::new("network_id", DataType::Utf8, true),
+    ]);
+    let gps = || reports.iter().map(|report| report.payload.gps());
+    let meta = || reports.iter().map(|report| report.ingest_meta.as_ref());
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(
+            reports.iter().map(|report| report.device.to_string()),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            reports.iter().map(|report| report.kind.as_str()),
+        )),
+        Arc::new(
+            TimestampMillisecondArray::from_iter_values(
+                gps().map(|gps| gps.timestamp.timestamp_millis()),
+            )
+            .with_timezone("UTC"),
+        ),
+        Arc::new(UInt64Array::from_iter_values(
+            reports.iter().map(|report| u64::from(report.h3_cell)),
+        )),
+        Arc::new(Float64Array::from_iter_values(
+            gps().map(|gps| gps.lat.to_f64().unwrap_or(f64::NAN)),
+        )),
+        Arc::new(Float64Array::from_iter_values(
+            gps().map(|gps| gps.lon.to_f64().unwrap_or(f64::NAN)),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            reports.iter().map(|report| report.witness_count as u64),
+        )),
+        Arc::new(
+            TimestampMillisecondArray::from_iter(
+                meta().map(|meta| meta.map(|meta| meta.received_at.timestamp_millis())),
+            )
+            .with_timezone("UTC"),
+        ),
+        Arc::new(UInt32Array::from_iter(
+            meta().map(|meta| meta.map(|meta| meta.gateway_count)),
+        )),
+        Arc::new(StringArray::from_iter(
+            meta().map(|meta| meta.and_then(|meta| meta.ingest_node.as_deref())),
+        )),
+        Arc::new(StringArray::from_iter(meta().map(|meta| {
+            meta.and_then(|meta| meta.priority.as_ref().map(crate::Priority::as_str))
+        }))),
+        Arc::new(StringArray::from_iter(
+            meta().map(|meta| meta.and_then(|meta| meta.network_id.as_deref())),
+        )),
+    ];
+    Ok(RecordBatch::try_new(Arc::new(schema), columns)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{keys, Gps};
+
+    #[test]
+    fn strips_identity_and_precision() {
+        let key = keys::file::File::create_key().unwrap();
+        let msg = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap();
+        let redactor = Redactor::new(b"dataset 2023-01", RedactionConfig::default());
+        let report = redactor.redact(&msg).unwrap();
+
+        assert_eq!(report.device, redactor.pseudonym(&msg.pubkey));
+        let other = Redactor::new(b"dataset 2023-02", RedactionConfig::default());
+        assert_ne!(report.device, other.pseudonym(&msg.pubkey));
+        assert_eq!(
+            report.device,
+            report.device.to_string().parse::<Pseudonym>().unwrap()
+        );
+
+        let gps = report.payload.gps();
+        assert_eq!(gps.timestamp.timestamp() % 60, 0);
+        assert_eq!(gps.to_h3_cell(Resolution::Nine).unwrap(), report.h3_cell);
+        assert_ne!((gps.lat, gps.lon), (Gps::rounded().lat, Gps::rounded().lon));
+        let json = serde_json::to_string(&report).unwrap();
+        assert!(!json.contains(&msg.pubkey.to_string()));
+        assert_eq!(report.ingest_meta, None);
+    }
+
+    #[test]
+    fn keeps_ingest_meta_with_truncated_reception() {
+        let key = keys::file::File::create_key().unwrap();
+        let received_at = Gps::rounded().timestamp + chrono::Duration::seconds(75);
+        let msg = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded()))
+            .unwrap()
+            .with_ingest_meta(
+                IngestMeta::new(received_at)
+                    .with_gateway_count(3)
+                    .with_network_id("prod"),
+            );
+        let report = Redactor::new(b"dataset", RedactionConfig::default())
+            .redact(&msg)
+            .unwrap();
+        let meta = report.ingest_meta.clone().unwrap();
+        assert_eq!(meta.received_at.timestamp() % 60, 0);
+        assert_eq!(meta.gateway_count, 3);
+
+        #[cfg(feature = "arrow")]
+        {
+            use arrow_array::{Array, StringArray, UInt32Array};
+            let mut untagged = msg.clone();
+            untagged.ingest_meta = None;
+            let untagged = Redactor::new(b"dataset", RedactionConfig::default())
+                .redact(&untagged)
+                .unwrap();
+            let batch = to_record_batch(&[report, untagged]).unwrap();
+            let column = |name: &str| batch.column_by_name(name).unwrap().clone();
+            let gateways = column("gateway_count");
+            let gateways = gateways.as_any().downcast_ref::<UInt32Array>().unwrap();
+            assert_eq!((gateways.value(0), gateways.is_null(1)), (3, true));
+            let networks = column("network_id");
+            let networks = networks.as_any().downcast_ref::<StringArray>().unwrap();
+            assert_eq!(networks.value(0), "prod");
+            assert!(column("ingest_node").is_null(0));
+            assert!(column("received_at").is_null(1));
+        }
+    }
+}
@@ -0,0 +1,272 @@
+//! LoRaWAN regional parameters (RP002-1.0.3) needed to plan uplinks: data rates, maximum
+//! application payload per data rate and dwell time limits.
+use super::{Deserialize, Error, FrequencyHz, Result, Serialize};
+
+/// LoRaWAN MHDR + FHDR (without FOpts) + FPort + MIC
+pub const LORAWAN_OVERHEAD: usize = 13;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Region {
+    US915,
+    EU868,
+    AU915,
+    AS923,
+}
+
+/// Whether the 400 ms uplink dwell time limit is in effect. It always is in US915 and never is
+/// in EU868; in AU915 and AS923 it is signaled by the network.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DwellTime {
+    Limited,
+    Unlimited,
+}
+
+pub const DWELL_TIME_LIMIT_MS: f64 = 400.0;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DataRateParams {
+    pub spreading_factor: u8,
+    pub bandwidth_khz: u32,
+    /// Maximum FRMPayload size (N) in bytes, None if the data rate may not be used
+    pub max_payload: Option<u8>,
+}
+
+impl DataRateParams {
+    /// The proto DataRate reported by gateways for uplinks at this data rate
+    pub fn data_rate(&self) -> Result<helium_proto::DataRate> {
+        use crate::DataRateExt;
+        helium_proto::DataRate::from_sf_bw(self.spreading_factor, self.bandwidth_khz)
+    }
+}
+
+const fn dr(spreading_factor: u8, bandwidth_khz: u32, max_payload: Option<u8>) -> DataRateParams {
+    DataRateParams {
+        spreading_factor,
+        bandwidth_khz,
+        max_payload,
+    }
+}
+
+const US915: [DataRateParams; 5] = [
+    dr(10, 125, Some(11)),
+    dr(9, 125, Some(53)),
+    dr(8, 125, Some(125)),
+    dr(7, 125, Some(242)),
+    dr(8, 500, Some(242)),
+];
+
+const EU868: [DataRateParams; 7] = [
+    dr(12, 125, Some(51)),
+    dr(11, 125, Some(51)),
+    dr(10, 125, Some(51)),
+    dr(9, 125, Some(115)),
+    dr(8, 125, Some(222)),
+    dr(7, 125, Some(222)),
+    dr(7, 250, Some(222)),
+];
+
+const AU915: [DataRateParams; 7] = [
+    dr(12, 125, Some(51)),
+    dr(11, 125, Some(51)),
+    dr(10, 125, Some(51)),
+    dr(9, 125, Some(115)),
+    dr(8, 125, Some(222)),
+    dr(7, 125, Some(222)),
+    dr(8, 500, Some(222)),
+];
+
+const AU915_DWELL: [DataRateParams; 7] = [
+    dr(12, 125, None),
+    dr(11, 125, None),
+    dr(10, 125, Some(11)),
+    dr(9, 125, Some(53)),
+    dr(8, 125, Some(125)),
+    dr(7, 125, Some(242)),
+    dr(8, 500, Some(242)),
+];
+
+const AS923: [DataRateParams; 7] = [
+    dr(12, 125, Some(51)),
+    dr(11, 125, Some(51)),
+    dr(10, 125, Some(51)),
+    dr(9, 125, Some(115)),
+    dr(8, 125, Some(222)),
+    dr(7, 125, Some(222)),
+    dr(7, 250, Some(222)),
+];
+
+const AS923_DWELL: [DataRateParams; 7] = [
+    dr(12, 125, None),
+    dr(11, 125, None),
+    dr(10, 125, Some(11)),
+    dr(9, 125, Some(53)),
+    dr(8, 125, Some(125)),
+    dr(7, 125, Some(242)),
+    dr(7, 250, Some(242)),
+];
+
+impl Region {
+    pub const ALL: [Region; 4] = [Region::US915, Region::EU868, Region::AU915, Region::AS923];
+
+    /// Uplink data rate table, indexed by DR
+    pub fn data_rates(&self, dwell_time: DwellTime) -> &'static [DataRateParams] {
+        match (self, dwell_time) {
+            (Region::US915, _) => &US915,
+            (Region::EU868, _) => &EU868,
+            (Region::AU915, DwellTime::Unlimited) => &AU915,
+            (Region::AU915, DwellTime::Limited) => &AU915_DWELL,
+            (Region::AS923, DwellTime::Unlimited) => &AS923,
+            (Region::AS923, DwellTime::Limited) => &AS923_DWELL,
+        }
+    }
+
+    pub fn data_rate(&self, dr: u8, dwell_time: DwellTime) -> Result<DataRateParams> {
+        self.data_rates(dwell_time)
+            .get(dr as usize)
+            .copied()
+            .ok_or(Error::InvalidRegionalDatarate { region: *self, dr })
+    }
+
+    /// Dwell time in effect when the network does not say otherwise
+    pub fn default_dwell_time(&self) -> DwellTime {
+        match self {
+            Region::US915 => DwellTime::Limited,
+            Region::EU868 | Region::AU915 | Region::AS923 => DwellTime::Unlimited,
+        }
+    }
+
+    /// Band the region's channels must fall in
+    pub fn frequency_range(&self) -> std::ops::RangeInclusive<FrequencyHz> {
+        let (low, high) = match self {
+            Region::US915 => (902, 928),
+            Region::EU868 => (863, 870),
+            Region::AU915 | Region::AS923 => (915, 928),
+        };
+        FrequencyHz::from_mhz(low)..=FrequencyHz::from_mhz(high)
+    }
+
+    pub fn check_frequency(&self, frequency: FrequencyHz) -> Result {
+        if self.frequency_range().contains(&frequency) {
+            Ok(())
+        } else {
+            Err(Error::FrequencyOutOfRegion {
+                frequency,
+                region: *self,
+            })
+        }
+    }
+
+    /// Maximum fraction of time a device may transmit, if the region imposes one
+    pub fn duty_cycle(&self) -> Option<f64> {
+        match self {
+            Region::EU868 => Some(0.01),
+            Region::US915 | Region::AU915 | Region::AS923 => None,
+        }
+    }
+
+    pub fn max_payload(&self, dr: u8, dwell_time: DwellTime) -> Result<Option<u8>> {
+        Ok(self.data_rate(dr, dwell_time)?.max_payload)
+    }
+
+    /// Whether an application payload of `len` bytes may be sent at `dr`, honoring both the
+    /// regional maximum payload and the dwell time limit.
+    pub fn can_send(&self, dr: u8, dwell_time: DwellTime, len: usize) -> Result<bool> {
+        let params = self.data_rate(dr, dwell_time)?;
+        let fits = params.max_payload.is_some_and(|max| len <= max as usize);
+        let dwell_ok = match dwell_time {
+            DwellTime::Unlimited => true,
+            DwellTime::Limited => params.time_on_air_ms(len) <= DWELL_TIME_LIMIT_MS,
+        };
+        Ok(fits && dwell_ok)
+    }
+
+    /// The slowest (longest range) data rate that can carry `len` bytes
+    pub fn slowest_data_rate_for(&self, dwell_time: DwellTime, len: usize) -> Option<u8> {
+        (0..self.data_rates(dwell_time).len() as u8)
+            .find(|dr| self.can_send(*dr, dwell_time, len).unwrap_or(false))
+    }
+}
+
+impl DataRateParams {
+    /// Time on air in ms of an uplink carrying `len` application bytes, assuming an 8 symbol
+    /// preamble, explicit header, CRC on and coding rate 4/5
+    pub fn time_on_air_ms(&self, len: usize) -> f64 {
+        time_on_air_ms(
+            self.spreading_factor,
+            self.bandwidth_khz,
+            len + LORAWAN_OVERHEAD,
+        )
+    }
+}
+
+/// LoRa time on air in ms for a PHY payload of `phy_len` bytes (Semtech AN1200.13)
+pub fn time_on_air_ms(spreading_factor: u8, bandwidth_khz: u32, phy_len: usize) -> f64 {
+    const PREAMBLE_SYMBOLS: f64 = 8.0;
+    const CODING_RATE: f64 = 1.0;
+    let sf = spreading_factor as f64;
+    let symbol_ms = 2f64.powf(sf) / bandwidth_khz as f64;
+    // low data rate optimization is mandated above 16 ms symbols
+    let de = if symbol_ms >= 16.0 { 1.0 } else { 0.0 };
+    let numerator = 8.0 * phy_len as f64 - 4.0 * sf + 28.0 + 16.0;
+    let payload_symbols =
+        8.0 + ((numerator / (4.0 * (sf - 2.0 * de))).ceil() * (CODING_RATE + 4.0)).max(0.0);
+    (PREAMBLE_SYMBOLS + 4.25 + payload_symbols) * symbol_ms
+}
+
+impl std::fmt::Display for Region {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Region::US915 => "US915",
+            Region::EU868 => "EU868",
+            Region::AU915 => "AU915",
+            Region::AS923 => "AS923",
+        };
+        f.write_str(s)
+    }
+}
+
+impl std::str::FromStr for Region {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Region::ALL
+            .into_iter()
+            .find(|region| region.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| Error::UnexpectedRegionStr(s.into()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn us915_dr0_fits_dwell_time() {
+        // max payload at DR0 is chosen to just fit the 400 ms dwell time
+        let dr0 = Region::US915.data_rate(0, DwellTime::Limited).unwrap();
+        let airtime = dr0.time_on_air_ms(11);
+        assert!((airtime - 370.688).abs() < 0.01, "{airtime}");
+        assert!(Region::US915.can_send(0, DwellTime::Limited, 11).unwrap());
+        assert!(!Region::US915.can_send(0, DwellTime::Limited, 12).unwrap());
+        assert_eq!(dr0.data_rate().unwrap(), helium_proto::DataRate::Sf10bw125);
+    }
+
+    #[test]
+    fn dwell_time_disables_slow_data_rates() {
+        assert!(Region::AS923.can_send(0, DwellTime::Unlimited, 17).unwrap());
+        assert!(!Region::AS923.can_send(0, DwellTime::Limited, 17).unwrap());
+        assert_eq!(
+            Region::AS923.slowest_data_rate_for(DwellTime::Limited, 17),
+            Some(3)
+        );
+        assert!(Region::EU868.data_rate(7, DwellTime::Unlimited).is_err());
+    }
+
+    #[test]
+    fn region_str_roundtrip() {
+        for region in Region::ALL {
+            assert_eq!(region, region.to_string().parse::<Region>().unwrap());
+        }
+        assert_eq!(Region::EU868, "eu868".parse::<Region>().unwrap());
+    }
+}
@@ -0,0 +1,167 @@
+//! Which LoRa layouts each firmware sends, for fleets running several firmwares at once. Layout
+//! 0 is the unversioned frame; anything above is the version in an `epoch::Versioned` header.
+//!
+//! Besides answering ops questions, `FirmwareRegistry::decode` uses it to explain failures: when
+//! a frame doesn't decode in the layout its firmware should send but does in another one, the
+//! error says so instead of reporting a bad field.
+use super::{
+    epoch::{Epoch, EpochRegistry, Versioned, VersionedCodec},
+    Deserialize, Error, Result, Serialize,
+};
+use std::collections::BTreeMap;
+
+/// Layouts sent by a firmware, keyed by LoRa port
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FirmwareProfile {
+    pub layouts: BTreeMap<u8, u8>,
+}
+
+impl FirmwareProfile {
+    pub fn with_layout(mut self, port: u8, version: u8) -> Self {
+        self.layouts.insert(port, version);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FirmwareRegistry {
+    firmwares: BTreeMap<String, FirmwareProfile>,
+}
+
+impl FirmwareRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the profile previously registered for `firmware`, if any
+    pub fn insert(
+        &mut self,
+        firmware: impl Into<String>,
+        profile: FirmwareProfile,
+    ) -> Option<FirmwareProfile> {
+        self.firmwares.insert(firmware.into(), profile)
+    }
+
+    pub fn profile(&self, firmware: &str) -> Option<&FirmwareProfile> {
+        self.firmwares.get(firmware)
+    }
+
+    pub fn layout(&self, firmware: &str, port: u8) -> Option<u8> {
+        self.profile(firmware)?.layouts.get(&port).copied()
+    }
+
+    /// Firmwares sending `version` on `port`
+    pub fn firmwares_using(&self, port: u8, version: u8) -> impl Iterator<Item = &str> {
+        self.firmwares
+            .iter()
+            .filter(move |(_, profile)| profile.layouts.get(&port) == Some(&version))
+            .map(|(firmware, _)| firmware.as_str())
+    }
+
+    /// Decodes a frame received on `port` from a device running `firmware`, in the layout the
+    /// firmware is registered with. Returns the payload and the number of bytes it used.
+    pub fn decode<T: VersionedCodec>(
+        &self,
+        firmware: &str,
+        port: u8,
+        bytes: &[u8],
+        epochs: &EpochRegistry,
+    ) -> Result<(T, usize)> {
+        let expected = self
+            .layout(firmware, port)
+            .ok_or_else(|| Error::UnknownFirmwareLayout {
+                firmware: firmware.to_string(),
+                port,
+            })?;
+        let error = match decode_layout::<T>(expected, bytes, epochs) {
+            Ok(decoded) => return Ok(decoded),
+            Err(error) => error,
+        };
+        // the version in the header if the frame has one, else maybe an unversioned frame
+        let header = bytes.first().map(|header| header >> 4);
+        let found = header
+            .into_iter()
+            .chain((expected != 0).then_some(0))
+            .filter(|version| *version != expected)
+            .find(|version| decode_layout::<T>(*version, bytes, epochs).is_ok());
+        match found {
+            Some(found) => Err(Error::FirmwareLayoutMismatch {
+                firmware: firmware.to_string(),
+                payload: T::LABEL,
+                expected,
+                found,
+            }),
+            None => Err(error),
+        }
+    }
+}
+
+fn decode_layout<T: VersionedCodec>(
+    version: u8,
+    bytes: &[u8],
+    epochs: &EpochRegistry,
+) -> Result<(T, usize)> {
+    if version == 0 {
+        // the unversioned frame is the version 1 layout in the genesis epoch
+        return T::decode_versioned(bytes, 1, &Epoch::GENESIS);
+    }
+    let (versioned, used) = Versioned::<T>::from_lora_slice_with_registry(bytes, epochs)?;
+    if versioned.version() != version {
+        return Err(T::unsupported(versioned.version()));
+    }
+    Ok((versioned.payload, used))
+}
+
+#[cfg(all(test, feature = "beacon"))]
+mod test {
+    use super::*;
+    use crate::{Beacon, Gps, LoraEncode, BEACON_PORT, BEACON_V2};
+
+    #[test]
+    fn explains_layout_mismatch() {
+        let mut registry = FirmwareRegistry::new();
+        registry.insert(
+            "1.2.0",
+            FirmwareProfile::default().with_layout(BEACON_PORT, 0),
+        );
+        registry.insert(
+            "2.0.1",
+            FirmwareProfile::default().with_layout(BEACON_PORT, BEACON_V2),
+        );
+        assert_eq!(
+            registry.firmwares_using(BEACON_PORT, 0).collect::<Vec<_>>(),
+            ["1.2.0"]
+        );
+
+        let beacon = Beacon::new(Gps::rounded(), vec![0xAB, 0xCD]);
+        let epochs = EpochRegistry::default();
+        let v2 = Versioned::new(beacon.clone(), Epoch::GENESIS)
+            .with_version(BEACON_V2)
+            .unwrap()
+            .to_lora_bytes();
+        let (decoded, used) = registry
+            .decode::<Beacon>("2.0.1", BEACON_PORT, &v2, &epochs)
+            .unwrap();
+        assert_eq!((decoded, used), (beacon.clone(), v2.len()));
+
+        let v0 = beacon.to_lora_bytes();
+        let error = registry
+            .decode::<Beacon>("2.0.1", BEACON_PORT, &v0, &epochs)
+            .unwrap_err();
+        assert!(
+            matches!(
+                error,
+                Error::FirmwareLayoutMismatch {
+                    expected: BEACON_V2,
+                    found: 0,
+                    ..
+                }
+            ),
+            "{error}"
+        );
+        assert!(matches!(
+            registry.decode::<Beacon>("9.9.9", BEACON_PORT, &v0, &epochs),
+            Err(Error::UnknownFirmwareLayout { .. })
+        ));
+    }
+}
@@ -0,0 +1,166 @@
+//! H3 resolutions used by the mapping program. Consumers should go through these rather than
+//! picking a `Resolution` themselves, so that rewards and dedupe agree across services.
+use super::{CellAt, CoverageCell, DedupeCell, Deserialize, Error, Message, Result, Serialize};
+use crate::gps::{Gps, Resolution};
+use h3o::CellIndex;
+use helium_crypto::PublicKey;
+
+/// Resolution at which coverage is rewarded
+pub const COVERAGE_RESOLUTION: Resolution = Resolution::Eight;
+/// Resolution at which reports from the same place are considered duplicates
+pub const DEDUPE_RESOLUTION: Resolution = Resolution::Twelve;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResolutionPolicy {
+    pub coverage: Resolution,
+    pub dedupe: Resolution,
+}
+
+impl Default for ResolutionPolicy {
+    fn default() -> Self {
+        Self {
+            coverage: COVERAGE_RESOLUTION,
+            dedupe: DEDUPE_RESOLUTION,
+        }
+    }
+}
+
+impl ResolutionPolicy {
+    /// Fails with `Error::WrongResolution` if the policy credits coverage at another resolution
+    /// than `R`, so that a consumer built for res 8 cells is never handed res 9 ones
+    pub fn coverage_cell<const R: u8>(&self, gps: &Gps) -> Result<CellAt<R>> {
+        cell_at(self.coverage, gps)
+    }
+
+    /// Same as `coverage_cell` for the dedupe resolution
+    pub fn dedupe_cell<const R: u8>(&self, gps: &Gps) -> Result<CellAt<R>> {
+        cell_at(self.dedupe, gps)
+    }
+}
+
+fn cell_at<const R: u8>(resolution: Resolution, gps: &Gps) -> Result<CellAt<R>> {
+    if resolution != CellAt::<R>::resolution() {
+        return Err(Error::WrongResolution {
+            expected: R,
+            found: resolution.into(),
+        });
+    }
+    CellAt::from_gps(gps)
+}
+
+impl Message {
+    /// Cell credited for coverage under the default policy
+    pub fn reward_cell(&self) -> Result<CoverageCell> {
+        self.reward_cell_with(&ResolutionPolicy::default())
+    }
+
+    /// See `ResolutionPolicy::coverage_cell`
+    pub fn reward_cell_with<const R: u8>(&self, policy: &ResolutionPolicy) -> Result<CellAt<R>> {
+        policy.coverage_cell(self.payload.gps())
+    }
+
+    /// Cell used to spot duplicate reports under the default policy
+    pub fn dedupe_cell(&self) -> Result<DedupeCell> {
+        self.dedupe_cell_with(&ResolutionPolicy::default())
+    }
+
+    pub fn dedupe_cell_with<const R: u8>(&self, policy: &ResolutionPolicy) -> Result<CellAt<R>> {
+        policy.dedupe_cell(self.payload.gps())
+    }
+}
+
+/// Why a cell is implied by a message
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CellRole {
+    /// Where the mapper was
+    Mapper,
+    /// Where a witnessing gateway is asserted
+    Gateway(PublicKey),
+    /// Where a cell scan observed service, ie: the mapper's cell when the scan has results
+    ScanCoverage,
+}
+
+impl Message {
+    /// Every cell implied by the message, at `resolution`. Gateway cells asserted coarser than
+    /// `resolution` are yielded as asserted. The mapper cell is left out if the fix can't be
+    /// placed.
+    pub fn h3_cells(
+        &self,
+        resolution: Resolution,
+    ) -> impl Iterator<Item = (CellIndex, CellRole)> + '_ {
+        let mapper = self.payload.gps().to_h3_cell(resolution).ok();
+        let scan_coverage = match &self.payload {
+            #[cfg(feature = "cell")]
+            super::Payload::CellScan(scan) if !scan.results.is_empty() => mapper,
+            _ => None,
+        };
+        let gateways = self.lora_gws.iter().map(move |lora_gw| {
+            let asserted = lora_gw.h3_cell.cell();
+            let cell = asserted.parent(resolution).unwrap_or(asserted);
+            (cell, CellRole::Gateway(lora_gw.pubkey.clone()))
+        });
+        mapper
+            .map(|cell| (cell, CellRole::Mapper))
+            .into_iter()
+            .chain(scan_coverage.map(|cell| (cell, CellRole::ScanCoverage)))
+            .chain(gateways)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{keys, Payload};
+
+    #[test]
+    fn reward_cell_resolutions() {
+        let key = keys::file::File::create_key().unwrap();
+        let msg = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap();
+        let reward_cell = msg.reward_cell().unwrap();
+        let dedupe_cell = msg.dedupe_cell().unwrap();
+        assert_eq!(reward_cell.index().resolution(), COVERAGE_RESOLUTION);
+        assert_eq!(dedupe_cell.index().resolution(), DEDUPE_RESOLUTION);
+        assert_eq!(dedupe_cell.parent(), Some(reward_cell));
+
+        let finer = ResolutionPolicy {
+            coverage: Resolution::Nine,
+            ..ResolutionPolicy::default()
+        };
+        assert!(matches!(
+            msg.reward_cell_with::<8>(&finer),
+            Err(Error::WrongResolution {
+                expected: 8,
+                found: 9
+            })
+        ));
+        let cell: CellAt<9> = msg.reward_cell_with(&finer).unwrap();
+        assert_eq!(cell.parent(), Some(reward_cell));
+    }
+
+    #[test]
+    fn h3_cells_roles() {
+        use crate::{CellScan, LoraGw};
+
+        let key = keys::file::File::create_key().unwrap();
+        let mut msg =
+            Message::from_payload_signed(&key, Payload::CellScan(CellScan::random())).unwrap();
+        let gateway_cell = CellIndex::try_from(0x8a1fb46622dffff).unwrap();
+        msg.lora_gws.push(LoraGw {
+            h3_cell: gateway_cell.into(),
+            ..LoraGw::random()
+        });
+        let cells: Vec<_> = msg.h3_cells(COVERAGE_RESOLUTION).collect();
+        let mapper = msg.reward_cell().unwrap().index();
+        assert_eq!(
+            cells,
+            vec![
+                (mapper, CellRole::Mapper),
+                (mapper, CellRole::ScanCoverage),
+                (
+                    gateway_cell.parent(COVERAGE_RESOLUTION).unwrap(),
+                    CellRole::Gateway(key.pubkey().unwrap())
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use super::{Deserialize, Serialize};
+use rust_decimal::{Decimal, RoundingStrategy};
+
+/// How the unit converters turn a scaled `Decimal` into whole units. The proto converters take
+/// it through their `_with` variants; the plain ones, and the LoRa encoding, always use
+/// `HalfEven`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rounding {
+    /// Nearest, ties to even (banker's rounding): 112.5 → 112, 113.5 → 114
+    #[default]
+    HalfEven,
+    /// Nearest, ties away from zero: 112.5 → 113, -112.5 → -113
+    HalfAwayFromZero,
+    /// Toward negative infinity: -112.5 → -113
+    Floor,
+    /// Toward positive infinity: 112.1 → 113
+    Ceil,
+    /// Toward zero, dropping the fraction: -112.9 → -112
+    Truncate,
+}
+
+impl Rounding {
+    fn strategy(self) -> RoundingStrategy {
+        match self {
+            Rounding::HalfEven => RoundingStrategy::MidpointNearestEven,
+            Rounding::HalfAwayFromZero => RoundingStrategy::MidpointAwayFromZero,
+            Rounding::Floor => RoundingStrategy::ToNegativeInfinity,
+            Rounding::Ceil => RoundingStrategy::ToPositiveInfinity,
+            Rounding::Truncate => RoundingStrategy::ToZero,
+        }
+    }
+
+    /// Rounds `value` to a whole number
+    pub fn apply(self, value: Decimal) -> Decimal {
+        value.round_dp_with_strategy(0, self.strategy())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gps::{altitude, hdop};
+
+    #[test]
+    fn x_125_boundaries() {
+        let hdop_1_125 = Decimal::new(1_125, 3);
+        let altitude = Decimal::new(-10_125, 3);
+        let cases = [
+            (Rounding::HalfEven, 112, -1012),
+            (Rounding::HalfAwayFromZero, 113, -1013),
+            (Rounding::Floor, 112, -1013),
+            (Rounding::Ceil, 113, -1012),
+            (Rounding::Truncate, 112, -1012),
+        ];
+        for (rounding, hdop_units, altitude_units) in cases {
+            assert_eq!(
+                hdop::to_units_with(hdop_1_125, rounding),
+                hdop_units,
+                "{rounding:?}"
+            );
+            assert_eq!(
+                altitude::to_proto_units_with(altitude, rounding),
+                altitude_units,
+                "{rounding:?}"
+            );
+        }
+        // the plain converters are locked to half even
+        assert_eq!(hdop::to_units(hdop_1_125), 112);
+        assert_eq!(hdop::to_units(Decimal::new(1_135, 3)), 114);
+        assert_eq!(altitude::to_proto_units(altitude), -1012);
+    }
+}
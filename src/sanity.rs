@@ -0,0 +1,116 @@
+//! Plausibility checks across the independent sources of a message. A spoofed GNSS receiver can
+//! report a plausible position with a wrong time; the clocks of the gateways that heard the
+//! uplink and of the ingest node are not under the device's control.
+use super::{DateTime, Deserialize, Message, PublicKey, Serialize, Utc};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeConsistencyConfig {
+    /// Largest offset between the fix and a gateway receive time. Beacons are sent right after
+    /// the fix, so this only covers clock error.
+    pub max_gateway_offset_s: u32,
+    /// Largest offset between the fix and the ingest receive time, which also includes queueing
+    /// in the device and retries
+    pub max_ingest_offset_s: u32,
+}
+
+impl Default for TimeConsistencyConfig {
+    fn default() -> Self {
+        Self {
+            max_gateway_offset_s: 30,
+            max_ingest_offset_s: 600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TimeSource {
+    Gateway {
+        #[serde(with = "crate::serde_helpers::pubkey")]
+        pubkey: PublicKey,
+    },
+    Ingest,
+}
+
+/// A reference clock disagreeing with the fix time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TimeInconsistency {
+    pub source: TimeSource,
+    pub fix_time: DateTime<Utc>,
+    pub reference_time: DateTime<Utc>,
+    /// Reference minus fix time, negative for a fix in the future of the reference
+    pub offset_s: f64,
+}
+
+/// Compares the fix time of `msg` to the receive time of each gateway that reported one, and
+/// to its `IngestMeta`. Returns the sources further off than allowed by `config`, so an empty
+/// result also means there was nothing to compare to.
+pub fn time_position_consistency(
+    msg: &Message,
+    config: &TimeConsistencyConfig,
+) -> Vec<TimeInconsistency> {
+    let fix_time = msg.payload.gps().timestamp;
+    let gateways = msg.lora_gws.iter().filter_map(|gw| {
+        let source = TimeSource::Gateway {
+            pubkey: gw.pubkey.clone(),
+        };
+        gw.received_at
+            .map(|at| (source, at, config.max_gateway_offset_s))
+    });
+    let ingest = msg.ingest_meta.as_ref().map(|meta| {
+        (
+            TimeSource::Ingest,
+            meta.received_at,
+            config.max_ingest_offset_s,
+        )
+    });
+    gateways
+        .chain(ingest)
+        .filter_map(|(source, reference_time, max_offset_s)| {
+            let offset_s = (reference_time - fix_time).num_milliseconds() as f64 / 1000.0;
+            (offset_s.abs() > f64::from(max_offset_s)).then_some(TimeInconsistency {
+                source,
+                fix_time,
+                reference_time,
+                offset_s,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ingest::IngestMeta, keys, Gps, LoraGw, Payload};
+    use chrono::Duration;
+
+    #[test]
+    fn flags_far_off_clocks() {
+        let key = keys::file::File::create_key().unwrap();
+        let mut msg = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap();
+        let fix_time = msg.payload.gps().timestamp;
+        let config = TimeConsistencyConfig::default();
+        assert!(time_position_consistency(&msg, &config).is_empty());
+
+        let gateway = |offset_s| LoraGw {
+            received_at: Some(fix_time + Duration::seconds(offset_s)),
+            ..LoraGw::random()
+        };
+        let spoofed = gateway(-3_600);
+        msg.lora_gws = vec![gateway(2), spoofed.clone()].into();
+        msg.ingest_meta = Some(IngestMeta::new(fix_time + Duration::seconds(120)));
+
+        let flagged = time_position_consistency(&msg, &config);
+        assert_eq!(flagged.len(), 1);
+        assert_eq!(
+            flagged[0].source,
+            TimeSource::Gateway {
+                pubkey: spoofed.pubkey
+            }
+        );
+        assert_eq!(flagged[0].offset_s, -3_600.0);
+
+        msg.ingest_meta = Some(IngestMeta::new(fix_time + Duration::days(1)));
+        assert_eq!(time_position_consistency(&msg, &config).len(), 2);
+    }
+}
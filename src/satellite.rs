@@ -0,0 +1,306 @@
+//! Frames for mappers that backhaul over satellite short burst data (eg: Swarm, Iridium SBD),
+//! where every byte is billed and a message carries at most `SATELLITE_MTU` bytes.
+//!
+//! Compared to the LoRa frames the fix is cut down to time, position and a coarse HDOP:
+//!
+//! - lat/lon in 1e-4 degree steps, about 11 m instead of 1.1 m
+//! - HDOP in 0.5 steps, saturating at 7.5
+//! - altitude, speed and satellite count are dropped and decode as 0
+//!
+//! Use `PayloadProfile` to pick the encoding per backhaul.
+#[cfg(feature = "beacon")]
+use super::Beacon;
+use super::{
+    epoch::{Epoch, VersionedCodec},
+    gps::time,
+    lora_payload::split_fixed,
+    Deserialize, DumpFields, EncodeMode, Error, FieldDump, Gps, LoraDecode, LoraEncode, Payload,
+    Result, Serialize,
+};
+use modular_bitfield_msb::{bitfield, specifiers::*};
+use rust_decimal::Decimal;
+
+/// Largest message accepted by the satellite modems we deploy
+pub const SATELLITE_MTU: usize = 192;
+
+const FIX_SIZE: usize = 10;
+#[cfg(feature = "beacon")]
+const BEACON_SIZE: usize = FIX_SIZE + 2;
+
+const LAT_OFFSET: Decimal = Decimal::from_parts(90, 0, 0, false, 0);
+const LON_OFFSET: Decimal = Decimal::from_parts(180, 0, 0, false, 0);
+const LATLON_SCALE: Decimal = Decimal::from_parts(10_000, 0, 0, false, 0);
+const HDOP_SCALE: Decimal = Decimal::from_parts(2, 0, 0, false, 0);
+
+/// A payload in its satellite encoding
+#[derive(Debug, Clone, PartialEq)]
+pub struct Satellite<T>(pub T);
+
+impl<T> Satellite<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+#[bitfield]
+#[derive(Debug)]
+struct SatFix {
+    // seconds from 2023-01-01 00:00:00 UTC, as in the LoRa frames
+    time: B30,
+    // lat shifted by 90 in 1e-4 degrees => up to 1800000 => 21 bits
+    lat: B21,
+    // lon shifted by 180 in 1e-4 degrees => up to 3600000 => 22 bits
+    lon: B22,
+    // 0.5 steps => 0-7.5
+    hdop: B4,
+    #[allow(unused)]
+    padding: B3,
+}
+
+impl SatFix {
+    fn encode(gps: &Gps, epoch: &Epoch, mode: EncodeMode) -> Result<Self> {
+        let lat = (gps.lat + LAT_OFFSET) * LATLON_SCALE;
+        let lon = (gps.lon + LON_OFFSET) * LATLON_SCALE;
+        Ok(SatFix::new()
+            .with_time(time::to_lora_units_in(gps.timestamp, epoch, mode)?)
+            .with_lat(mode.fit_scaled("lat", lat, 21)? as u32)
+            .with_lon(mode.fit_scaled("lon", lon, 22)? as u32)
+            .with_hdop(mode.fit_scaled("hdop", gps.hdop * HDOP_SCALE, 4)? as u8))
+    }
+
+    fn decode(&self, epoch: &Epoch) -> Gps {
+        Gps {
+            timestamp: time::from_lora_units_in(self.time(), epoch),
+            lat: Decimal::new(self.lat().into(), 4) - LAT_OFFSET,
+            lon: Decimal::new(self.lon().into(), 4) - LON_OFFSET,
+            hdop: Decimal::from(self.hdop()) / HDOP_SCALE,
+            ..Gps::default()
+        }
+    }
+}
+
+impl SatFix {
+    fn dump(&self) -> Vec<FieldDump> {
+        let gps = self.decode(&Epoch::GENESIS);
+        vec![
+            FieldDump::new("time", self.time(), gps.timestamp.to_rfc3339()),
+            FieldDump::new("lat", self.lat(), gps.lat),
+            FieldDump::new("lon", self.lon(), gps.lon),
+            FieldDump::new("hdop", self.hdop(), gps.hdop),
+        ]
+    }
+}
+
+impl DumpFields for Satellite<Gps> {
+    fn dump_lora_frame(bytes: &[u8]) -> Result<Vec<FieldDump>> {
+        Ok(SatFix::from_bytes(split_fixed(Self::LABEL, bytes)?).dump())
+    }
+}
+
+impl LoraEncode for Satellite<Gps> {
+    type Bytes = [u8; FIX_SIZE];
+
+    fn to_lora_bytes_with_mode(&self, mode: EncodeMode) -> Result<Self::Bytes> {
+        Ok(SatFix::encode(&self.0, &Epoch::GENESIS, mode)?.into_bytes())
+    }
+}
+
+impl LoraDecode for Satellite<Gps> {
+    fn from_lora_slice(bytes: &[u8]) -> Result<(Self, usize)> {
+        Self::decode_versioned(bytes, Self::VERSION, &Epoch::GENESIS)
+    }
+}
+
+impl VersionedCodec for Satellite<Gps> {
+    const LABEL: &'static str = "SatelliteGps";
+    const VERSION: u8 = 1;
+    const VERSIONS: &'static [u8] = &[1];
+
+    fn encode_versioned(&self, version: u8, epoch: &Epoch, mode: EncodeMode) -> Result<Vec<u8>> {
+        match version {
+            1 => Ok(SatFix::encode(&self.0, epoch, mode)?.into_bytes().to_vec()),
+            _ => Err(Self::unsupported(version)),
+        }
+    }
+
+    fn decode_versioned(bytes: &[u8], version: u8, epoch: &Epoch) -> Result<(Self, usize)> {
+        match version {
+            1 => {
+                let frame = split_fixed(Self::LABEL, bytes)?;
+                Ok((Self(SatFix::from_bytes(frame).decode(epoch)), FIX_SIZE))
+            }
+            _ => Err(Self::unsupported(version)),
+        }
+    }
+}
+
+/// The fix followed by the same truncated scan signature as the LoRa beacon
+#[cfg(feature = "beacon")]
+impl LoraEncode for Satellite<Beacon> {
+    type Bytes = [u8; BEACON_SIZE];
+
+    fn to_lora_bytes_with_mode(&self, mode: EncodeMode) -> Result<Self::Bytes> {
+        self.encode_in(&Epoch::GENESIS, mode)
+    }
+}
+
+#[cfg(feature = "beacon")]
+impl Satellite<Beacon> {
+    fn encode_in(&self, epoch: &Epoch, mode: EncodeMode) -> Result<[u8; BEACON_SIZE]> {
+        let mut bytes = [0; BEACON_SIZE];
+        let fix = SatFix::encode(&self.0.gps, epoch, mode)?;
+        bytes[..FIX_SIZE].copy_from_slice(&fix.into_bytes());
+        bytes[FIX_SIZE..].copy_from_slice(&self.0.signature.lora_tail().to_be_bytes());
+        Ok(bytes)
+    }
+}
+
+#[cfg(feature = "beacon")]
+impl LoraDecode for Satellite<Beacon> {
+    fn from_lora_slice(bytes: &[u8]) -> Result<(Self, usize)> {
+        Self::decode_versioned(bytes, Self::VERSION, &Epoch::GENESIS)
+    }
+}
+
+#[cfg(feature = "beacon")]
+impl DumpFields for Satellite<Beacon> {
+    fn dump_lora_frame(bytes: &[u8]) -> Result<Vec<FieldDump>> {
+        let frame: [u8; BEACON_SIZE] = split_fixed(Self::LABEL, bytes)?;
+        let fix = SatFix::from_bytes(frame[..FIX_SIZE].try_into().expect("fix size"));
+        let signature = u16::from_be_bytes([frame[FIX_SIZE], frame[FIX_SIZE + 1]]);
+        let mut fields = fix.dump();
+        fields.push(FieldDump::new(
+            "signature",
+            signature,
+            format!("{signature:04x}"),
+        ));
+        Ok(fields)
+    }
+}
+
+#[cfg(feature = "beacon")]
+impl VersionedCodec for Satellite<Beacon> {
+    const LABEL: &'static str = "SatelliteBeacon";
+    const VERSION: u8 = 1;
+    const VERSIONS: &'static [u8] = &[1];
+
+    fn encode_versioned(&self, version: u8, epoch: &Epoch, mode: EncodeMode) -> Result<Vec<u8>> {
+        match version {
+            1 => Ok(self.encode_in(epoch, mode)?.to_vec()),
+            _ => Err(Self::unsupported(version)),
+        }
+    }
+
+    fn decode_versioned(bytes: &[u8], version: u8, epoch: &Epoch) -> Result<(Self, usize)> {
+        if version != 1 {
+            return Err(Self::unsupported(version));
+        }
+        let frame: [u8; BEACON_SIZE] = split_fixed(Self::LABEL, bytes)?;
+        let fix = SatFix::from_bytes(frame[..FIX_SIZE].try_into().expect("fix size"));
+        let beacon = Beacon::new(fix.decode(epoch), frame[FIX_SIZE..].to_vec());
+        Ok((Self(beacon), BEACON_SIZE))
+    }
+}
+
+/// Backhaul a mapper reports over, which decides how its payloads are framed
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadProfile {
+    /// The LoRa frames, full precision
+    #[default]
+    Lora,
+    /// The satellite frames of this module, for Beacon and Gps payloads only
+    Satellite,
+}
+
+impl PayloadProfile {
+    pub fn max_frame_len(&self) -> Option<usize> {
+        match self {
+            PayloadProfile::Lora => None,
+            PayloadProfile::Satellite => Some(SATELLITE_MTU),
+        }
+    }
+
+    /// Signed frame of `payload` for this profile. Fails with `Error::UnsupportedInProfile` for
+    /// payloads the profile has no encoding for, and `Error::OutOfRange` if the frame is larger
+    /// than `max_frame_len`.
+    pub fn encode_with_signature<K: crate::keys::KeyTrait + ?Sized>(
+        &self,
+        payload: &Payload,
+        key: &K,
+        mode: EncodeMode,
+    ) -> Result<Vec<u8>> {
+        let unsupported = || Error::UnsupportedInProfile {
+            profile: *self,
+            kind: payload.kind(),
+        };
+        let frame = match (self, payload) {
+            #[cfg(feature = "beacon")]
+            (PayloadProfile::Lora, Payload::Beacon(beacon)) => {
+                beacon.to_lora_bytes_with_signature_and_mode(key, mode)?
+            }
+            #[cfg(feature = "cell")]
+            (PayloadProfile::Lora, Payload::CellAttach(attach)) => {
+                attach.to_lora_bytes_with_signature_and_mode(key, mode)?
+            }
+            #[cfg(feature = "beacon")]
+            (PayloadProfile::Satellite, Payload::Beacon(beacon)) => {
+                Satellite(beacon.clone()).to_lora_bytes_with_signature_and_mode(key, mode)?
+            }
+            (PayloadProfile::Satellite, Payload::Gps(gps)) => {
+                Satellite(*gps).to_lora_bytes_with_signature_and_mode(key, mode)?
+            }
+            _ => return Err(unsupported()),
+        };
+        match self.max_frame_len() {
+            Some(max) if frame.len() > max => Err(Error::OutOfRange {
+                field: "frame",
+                value: frame.len() as i128,
+            }),
+            _ => Ok(frame),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::keys::{self, KeyTrait};
+
+    #[test]
+    fn satellite_fix_accuracy() {
+        let gps = Gps::rounded();
+        let bytes = Satellite(gps).to_lora_bytes();
+        assert_eq!(bytes.len(), FIX_SIZE);
+        let (Satellite(decoded), used) = Satellite::<Gps>::from_lora_slice(&bytes).unwrap();
+        assert_eq!(used, FIX_SIZE);
+        assert_eq!(decoded.timestamp, gps.timestamp);
+        assert!((decoded.lat - gps.lat).abs() <= Decimal::new(5, 5));
+        assert!((decoded.lon - gps.lon).abs() <= Decimal::new(5, 5));
+        // 9.05 saturates
+        assert_eq!(decoded.hdop, Decimal::new(75, 1));
+        assert_eq!((decoded.altitude, decoded.num_sats), (Decimal::ZERO, 0));
+    }
+
+    #[test]
+    fn profile_encodes_signed_beacon() {
+        let key = keys::file::File::create_key().unwrap();
+        let beacon = Beacon::new(Gps::rounded(), vec![0xAB, 0xCD]);
+        let frame = PayloadProfile::Satellite
+            .encode_with_signature(&Payload::Beacon(beacon.clone()), &key, EncodeMode::Strict)
+            .unwrap();
+        assert!(frame.len() <= SATELLITE_MTU);
+        let Satellite(decoded) = Satellite::<Beacon>::from_lora_slice_with_verified_signature(
+            &key.pubkey().unwrap(),
+            &frame,
+        )
+        .unwrap();
+        assert_eq!(decoded.signature, beacon.signature);
+
+        let scan = Payload::CellScan(crate::CellScan::random());
+        assert!(matches!(
+            PayloadProfile::Satellite.encode_with_signature(&scan, &key, EncodeMode::Strict),
+            Err(Error::UnsupportedInProfile { .. })
+        ));
+    }
+}
@@ -0,0 +1,151 @@
+//! CSV import and export of scan results. Headers are matched by name, accepting the proto
+//! names (`cid`, `fcn`, `pci`) as aliases, and blank optional columns are accepted.
+use super::{
+    cell_scan::parse_lenient_bool, BandwidthKhz, CellScan, CellScanResult, Error, Gps, Pci, Result,
+};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+#[derive(Debug, Deserialize)]
+struct CsvRow {
+    mcc: u16,
+    mnc: u16,
+    #[serde(alias = "fcn")]
+    earfcn: u32,
+    #[serde(alias = "pci", default)]
+    physical_cell_id: Option<u64>,
+    rsrp: i32,
+    #[serde(default)]
+    rsrq: Option<i32>,
+    #[serde(alias = "cid")]
+    cell_id: u64,
+    #[serde(default)]
+    bandwidth: Option<u32>,
+    #[serde(default)]
+    lte: Option<String>,
+}
+
+impl TryFrom<CsvRow> for CellScanResult {
+    type Error = Error;
+
+    fn try_from(row: CsvRow) -> Result<Self> {
+        let lte = row
+            .lte
+            .as_deref()
+            .filter(|lte| !lte.trim().is_empty())
+            .map(|lte| parse_lenient_bool(lte.trim()))
+            .transpose()?
+            .unwrap_or(true);
+        Ok(Self {
+            mcc: row.mcc,
+            mnc: row.mnc,
+            // the column is read as a number, so a leading zero is already lost
+            mnc_digits: None,
+            earfcn: row.earfcn,
+            physical_cell_id: row
+                .physical_cell_id
+                .map(|pci| Pci::try_from(pci)?.check_rat(lte))
+                .transpose()?
+                .unwrap_or_default(),
+            rsrp: row.rsrp,
+            rsrq: row.rsrq.unwrap_or_default(),
+            cell_id: row.cell_id,
+            bandwidth: row
+                .bandwidth
+                .map(BandwidthKhz::new)
+                .transpose()?
+                .unwrap_or_default(),
+            lte,
+            observed_at: None,
+        })
+    }
+}
+
+impl CellScanResult {
+    /// Reads results from CSV with a header row
+    pub fn read_csv<R: Read>(reader: R) -> Result<Vec<Self>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(reader);
+        reader
+            .deserialize::<CsvRow>()
+            .map(|row| row?.try_into())
+            .collect()
+    }
+
+    /// Writes results as CSV with a header row
+    pub fn write_csv<W: Write>(results: &[Self], writer: W) -> Result {
+        let mut writer = csv::Writer::from_writer(writer);
+        for result in results {
+            writer.serialize(CsvOut::from(result))?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct CsvOut {
+    mcc: u16,
+    mnc: u16,
+    earfcn: u32,
+    physical_cell_id: u16,
+    rsrp: i32,
+    rsrq: i32,
+    cell_id: u64,
+    bandwidth: u32,
+    lte: bool,
+}
+
+impl From<&CellScanResult> for CsvOut {
+    fn from(r: &CellScanResult) -> Self {
+        Self {
+            mcc: r.mcc,
+            mnc: r.mnc,
+            earfcn: r.earfcn,
+            physical_cell_id: r.physical_cell_id.get(),
+            rsrp: r.rsrp,
+            rsrq: r.rsrq,
+            cell_id: r.cell_id,
+            bandwidth: r.bandwidth.get(),
+            lte: r.lte,
+        }
+    }
+}
+
+impl CellScan {
+    /// CSV exports only carry results, so the scan counter and fix are provided by the caller
+    pub fn from_csv<R: Read>(scan_counter: u32, gps: Gps, reader: R) -> Result<Self> {
+        Ok(Self {
+            scan_counter,
+            gps,
+            results: CellScanResult::read_csv(reader)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn csv_roundtrip() {
+        let scan = CellScan::random();
+        let mut csv = Vec::new();
+        CellScanResult::write_csv(&scan.results, &mut csv).unwrap();
+        let read = CellScan::from_csv(scan.scan_counter, scan.gps, csv.as_slice()).unwrap();
+        assert_eq!(scan, read);
+    }
+
+    #[test]
+    fn csv_header_aliases_and_blanks() {
+        let csv = "cid,mcc,mnc,fcn,rsrp,pci,lte\n2524161,315,10,55990,-97,,\n";
+        let results = CellScanResult::read_csv(csv.as_bytes()).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].cell_id, 2524161);
+        assert_eq!(results[0].earfcn, 55990);
+        assert_eq!(results[0].physical_cell_id.get(), 0);
+        assert!(results[0].lte);
+    }
+}
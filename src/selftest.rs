@@ -0,0 +1,121 @@
+//! Factory QA self-test: signs a dummy Beacon with the device key, then decodes and verifies it
+//! through both the proto and the LoRa paths, timing each step.
+use super::{
+    keys::KeyTrait, Beacon, Error, Gps, LoraDecode, LoraEncode, Message, Payload, Result, Serialize,
+};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SelfTestCheck {
+    pub name: &'static str,
+    /// The failure, if any
+    pub error: Option<String>,
+    pub duration: Duration,
+}
+
+impl SelfTestCheck {
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SelfTestReport {
+    pub checks: Vec<SelfTestCheck>,
+    pub duration: Duration,
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(SelfTestCheck::passed)
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &SelfTestCheck> {
+        self.checks.iter().filter(|check| !check.passed())
+    }
+}
+
+impl std::fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let verdict = if self.passed() { "PASS" } else { "FAIL" };
+        write!(f, "{verdict} in {:?}", self.duration)?;
+        for check in &self.checks {
+            match &check.error {
+                None => write!(f, "\n  ok   {} {:?}", check.name, check.duration)?,
+                Some(error) => write!(f, "\n  FAIL {} {:?}: {error}", check.name, check.duration)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check(checks: &mut Vec<SelfTestCheck>, name: &'static str, f: impl FnOnce() -> Result) {
+    let start = Instant::now();
+    let error = f().err().map(|error| error.to_string());
+    checks.push(SelfTestCheck {
+        name,
+        error,
+        duration: start.elapsed(),
+    });
+}
+
+fn expect(ok: bool, what: &'static str) -> Result {
+    if ok {
+        Ok(())
+    } else {
+        Err(Error::SelfTestFailed(what))
+    }
+}
+
+/// Runs every check, even after a failure, so that the report shows all broken paths
+pub fn run_with_key<K: KeyTrait>(key: &K) -> SelfTestReport {
+    let start = Instant::now();
+    let mut checks = vec![];
+    let beacon = Beacon::new(Gps::rounded(), vec![0xAB, 0xCD]);
+
+    check(&mut checks, "pubkey", || {
+        key.pubkey()
+            .map(drop)
+            .map_err(|e| Error::Key(e.to_string()))
+    });
+    check(&mut checks, "proto_roundtrip", || {
+        let msg = Message::from_payload_signed(key, Payload::Beacon(beacon.clone()))?;
+        let mut bytes = vec![];
+        msg.encode_to(&mut bytes)?;
+        let decoded = Message::decode_from_with_signature_verification(&bytes)?;
+        expect(decoded == msg, "decoded message differs")
+    });
+    check(&mut checks, "lora_roundtrip", || {
+        let pubkey = key.pubkey().map_err(|e| Error::Key(e.to_string()))?;
+        let frame = beacon.to_lora_bytes_with_signature(key)?;
+        let decoded = Beacon::from_lora_slice_with_verified_signature(&pubkey, &frame)?;
+        expect(decoded == beacon, "decoded beacon differs")
+    });
+    check(&mut checks, "tamper_rejected", || {
+        let pubkey = key.pubkey().map_err(|e| Error::Key(e.to_string()))?;
+        let mut frame = beacon.to_lora_bytes_with_signature(key)?;
+        frame[0] ^= 0x01;
+        let verified = Beacon::from_lora_slice_with_verified_signature(&pubkey, &frame);
+        expect(verified.is_err(), "tampered frame verified")
+    });
+
+    SelfTestReport {
+        checks,
+        duration: start.elapsed(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::keys;
+
+    #[test]
+    fn passes_with_file_key() {
+        let key = keys::file::File::create_key().unwrap();
+        let report = run_with_key(&key);
+        assert!(report.passed(), "{report}");
+        assert_eq!(report.checks.len(), 4);
+        assert!(report.to_string().starts_with("PASS"));
+    }
+}
@@ -0,0 +1,171 @@
+//! `#[serde(with = ...)]` adapters for foreign types that do not implement serde themselves
+
+/// Public keys as their b58 string
+pub(crate) mod pubkey {
+    use crate::PublicKey;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(pubkey: &PublicKey, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(pubkey)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PublicKey, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+/// DataRates by their proto name (eg: "SF10BW125")
+pub(crate) mod data_rate {
+    use helium_proto::DataRate;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        data_rate: &DataRate,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(data_rate.as_str_name())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DataRate, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        DataRate::from_str_name(&s)
+            .ok_or_else(|| de::Error::custom(format!("unknown data rate: {s}")))
+    }
+}
+
+/// Any type through its Display and FromStr impls
+pub(crate) mod display_fromstr {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+    use std::{fmt::Display, str::FromStr};
+
+    pub fn serialize<T: Display, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Decimals as strings padded to the scale the proto carries them with (eg: "-50.12345",
+/// "9.00"), so that the JSON doesn't change with the serde features rust_decimal is built with.
+/// Digits beyond that scale are kept. Strings and numbers are both accepted when deserializing.
+///
+/// With the `json-float` feature they serialize as floats instead, for consumers that can't
+/// parse decimal strings and accept the precision loss.
+pub(crate) mod decimal {
+    use rust_decimal::Decimal;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    fn serialize_scaled<S: Serializer>(
+        value: &Decimal,
+        scale: u32,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if cfg!(feature = "json-float") {
+            use rust_decimal::prelude::ToPrimitive;
+            let float = value
+                .to_f64()
+                .ok_or_else(|| serde::ser::Error::custom(format!("{value} is not a float")))?;
+            return serializer.serialize_f64(float);
+        }
+        let mut padded = *value;
+        if padded.scale() < scale {
+            padded.rescale(scale);
+        }
+        serializer.collect_str(&padded)
+    }
+
+    /// A decimal deserialized from either a string or a number
+    struct AnyDecimal(Decimal);
+
+    impl<'de> Deserialize<'de> for AnyDecimal {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct Visitor;
+
+            impl de::Visitor<'_> for Visitor {
+                type Value = AnyDecimal;
+
+                fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    f.write_str("a decimal string or number")
+                }
+
+                fn visit_str<E: de::Error>(self, s: &str) -> Result<AnyDecimal, E> {
+                    s.parse().map(AnyDecimal).map_err(E::custom)
+                }
+
+                fn visit_i64<E: de::Error>(self, v: i64) -> Result<AnyDecimal, E> {
+                    Ok(AnyDecimal(v.into()))
+                }
+
+                fn visit_u64<E: de::Error>(self, v: u64) -> Result<AnyDecimal, E> {
+                    Ok(AnyDecimal(v.into()))
+                }
+
+                fn visit_f64<E: de::Error>(self, v: f64) -> Result<AnyDecimal, E> {
+                    Decimal::try_from(v).map(AnyDecimal).map_err(E::custom)
+                }
+            }
+
+            deserializer.deserialize_any(Visitor)
+        }
+    }
+
+    macro_rules! scaled {
+        ($name:ident, $scale:literal $(, $option:ident)?) => {
+            #[doc = concat!("Padded to ", $scale, " decimal places")]
+            pub(crate) mod $name {
+                use super::*;
+
+                pub fn serialize<S: Serializer>(
+                    value: &Decimal,
+                    serializer: S,
+                ) -> Result<S::Ok, S::Error> {
+                    serialize_scaled(value, $scale, serializer)
+                }
+
+                pub fn deserialize<'de, D: Deserializer<'de>>(
+                    deserializer: D,
+                ) -> Result<Decimal, D::Error> {
+                    Ok(AnyDecimal::deserialize(deserializer)?.0)
+                }
+
+                $(
+                pub(crate) mod $option {
+                    use super::*;
+
+                    pub fn serialize<S: Serializer>(
+                        value: &Option<Decimal>,
+                        serializer: S,
+                    ) -> Result<S::Ok, S::Error> {
+                        match value {
+                            Some(value) => serialize_scaled(value, $scale, serializer),
+                            None => serializer.serialize_none(),
+                        }
+                    }
+
+                    pub fn deserialize<'de, D: Deserializer<'de>>(
+                        deserializer: D,
+                    ) -> Result<Option<Decimal>, D::Error> {
+                        Ok(Option::<AnyDecimal>::deserialize(deserializer)?.map(|d| d.0))
+                    }
+                }
+                )?
+            }
+        };
+    }
+
+    scaled!(scale1, 1);
+    scaled!(scale2, 2, option);
+    scaled!(scale5, 5);
+}
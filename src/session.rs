@@ -0,0 +1,408 @@
+//! Per device state kept by servers across messages: the last absolute fix, which delta
+//! encoded fixes are relative to, and the last counters, which replayed reports reuse. Keeping
+//! both in one `DeviceSession` behind one `SessionStore` means every decoder of a device sees
+//! the same state.
+use super::{
+    DateTime, Deserialize, Error, Gps, Message, Payload, PublicKey, Result, Serialize, Utc,
+};
+use std::{collections::HashMap, path::PathBuf};
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct DeviceSession {
+    pub last_fix: Option<Gps>,
+    pub last_scan_counter: Option<u32>,
+    pub last_attach_counter: Option<u32>,
+    /// Reception time of the last message, or its fix time when it had no `IngestMeta`
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+impl DeviceSession {
+    /// A scan or attach is replayed if its counter is not newer than the last one. Other
+    /// payloads are replayed if their fix is older than the last fix; exact duplicates are left
+    /// to message ids.
+    pub fn is_replay(&self, msg: &Message) -> bool {
+        match (&msg.payload, self.last_counter(&msg.payload)) {
+            #[cfg(feature = "cell")]
+            (Payload::CellScan(scan), Some(last)) => !counter_is_newer(scan.scan_counter, last),
+            #[cfg(feature = "cell")]
+            (Payload::CellAttach(attach), Some(last)) => {
+                !counter_is_newer(attach.attach_counter, last)
+            }
+            #[cfg(feature = "cell")]
+            (Payload::CellScan(_) | Payload::CellAttach(_), None) => false,
+            (payload, _) => self
+                .last_fix
+                .is_some_and(|fix| payload.gps().timestamp < fix.timestamp),
+        }
+    }
+
+    /// Records an accepted message. The last fix only moves forward in time.
+    pub fn observe(&mut self, msg: &Message) {
+        let gps = msg.payload.gps();
+        if !self
+            .last_fix
+            .is_some_and(|fix| fix.timestamp > gps.timestamp)
+        {
+            self.last_fix = Some(*gps);
+        }
+        match &msg.payload {
+            #[cfg(feature = "cell")]
+            Payload::CellScan(scan) => self.last_scan_counter = Some(scan.scan_counter),
+            #[cfg(feature = "cell")]
+            Payload::CellAttach(attach) => self.last_attach_counter = Some(attach.attach_counter),
+            _ => (),
+        }
+        let seen = msg
+            .ingest_meta
+            .as_ref()
+            .map_or(gps.timestamp, |meta| meta.received_at);
+        self.last_seen = self.last_seen.max(Some(seen));
+    }
+
+    /// Whether the scan a CellAttach was built from is one the server could have seen. Only the
+    /// last scan counter is kept, so a reference is dangling when no scan of the device was seen
+    /// or when it is newer than the last one; older scans are assumed seen. `None` for other
+    /// payloads.
+    pub fn scan_linkage(&self, msg: &Message) -> Option<Linkage> {
+        match &msg.payload {
+            #[cfg(feature = "cell")]
+            Payload::CellAttach(attach) => {
+                let from_scan = attach.candidate.from_scan;
+                Some(match self.last_scan_counter {
+                    Some(last) if !counter_is_newer(from_scan, last) => Linkage::Linked,
+                    last_scan_counter => Linkage::Dangling {
+                        from_scan,
+                        last_scan_counter,
+                    },
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn last_counter(&self, payload: &Payload) -> Option<u32> {
+        match payload {
+            #[cfg(feature = "cell")]
+            Payload::CellScan(_) => self.last_scan_counter,
+            #[cfg(feature = "cell")]
+            Payload::CellAttach(_) => self.last_attach_counter,
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "cell")]
+fn counter_is_newer(a: u32, b: u32) -> bool {
+    a != b && a.wrapping_sub(b) < u32::MAX / 2
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Linkage {
+    Linked,
+    /// `from_scan` references a scan the server never saw
+    Dangling {
+        from_scan: u32,
+        last_scan_counter: Option<u32>,
+    },
+}
+
+/// Checks `DeviceSession::scan_linkage` against a store during verification, see
+/// `LazyVerified::verify_with_report`. Run it before the attach is observed.
+#[derive(Clone, Copy)]
+pub struct LinkageValidator<'a> {
+    store: &'a dyn SessionStore,
+}
+
+impl<'a> LinkageValidator<'a> {
+    pub fn new(store: &'a dyn SessionStore) -> Self {
+        Self { store }
+    }
+
+    pub fn check(&self, msg: &Message) -> Result<Option<Linkage>> {
+        Ok(self.store.load(&msg.pubkey)?.scan_linkage(msg))
+    }
+}
+
+impl std::fmt::Debug for LinkageValidator<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LinkageValidator").finish_non_exhaustive()
+    }
+}
+
+pub trait SessionStore {
+    /// The persisted session, or an empty one for devices never seen
+    fn load(&self, pubkey: &PublicKey) -> Result<DeviceSession>;
+
+    fn persist(&mut self, pubkey: &PublicKey, session: &DeviceSession) -> Result;
+
+    /// Records `msg` unless it is a replay. Returns whether it was recorded.
+    fn observe(&mut self, msg: &Message) -> Result<bool> {
+        let mut session = self.load(&msg.pubkey)?;
+        if session.is_replay(msg) {
+            return Ok(false);
+        }
+        session.observe(msg);
+        self.persist(&msg.pubkey, &session)?;
+        Ok(true)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InMemorySessionStore {
+    /// keyed by b58 pubkey
+    sessions: HashMap<String, DeviceSession>,
+}
+
+impl InMemorySessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+}
+
+impl SessionStore for InMemorySessionStore {
+    fn load(&self, pubkey: &PublicKey) -> Result<DeviceSession> {
+        Ok(self
+            .sessions
+            .get(&pubkey.to_string())
+            .copied()
+            .unwrap_or_default())
+    }
+
+    fn persist(&mut self, pubkey: &PublicKey, session: &DeviceSession) -> Result {
+        self.sessions.insert(pubkey.to_string(), *session);
+        Ok(())
+    }
+}
+
+/// Stores each session as a text file named after the b58 pubkey of the device, one
+/// `<field> <value>` line per field, with `-` for missing values. Writes go through a temporary
+/// file renamed over the old one, as in `counter_store::FileCounterStore`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSessionStore {
+    dir: PathBuf,
+}
+
+impl FileSessionStore {
+    /// `dir` must exist
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, pubkey: &PublicKey) -> PathBuf {
+        self.dir.join(format!("{pubkey}.session"))
+    }
+}
+
+impl SessionStore for FileSessionStore {
+    fn load(&self, pubkey: &PublicKey) -> Result<DeviceSession> {
+        let contents = match std::fs::read_to_string(self.path(pubkey)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(DeviceSession::default())
+            }
+            Err(e) => return Err(e.into()),
+        };
+        parse_session(&contents).ok_or(Error::InvalidSessionFile(contents))
+    }
+
+    fn persist(&mut self, pubkey: &PublicKey, session: &DeviceSession) -> Result {
+        use std::io::Write;
+        let path = self.path(pubkey);
+        let tmp = path.with_extension("session.tmp");
+        let mut file = std::fs::File::create(&tmp)?;
+        file.write_all(format_session(session).as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+fn format_session(session: &DeviceSession) -> String {
+    fn opt<T: ToString>(value: Option<T>) -> String {
+        value.map_or_else(|| "-".to_string(), |value| value.to_string())
+    }
+    let fix = session.last_fix.map_or_else(
+        || "-".to_string(),
+        |gps| {
+            format!(
+                "{} {} {} {} {} {} {} {} {}",
+                gps.timestamp.to_rfc3339(),
+                gps.lat,
+                gps.lon,
+                gps.hdop,
+                gps.altitude,
+                gps.num_sats,
+                gps.speed,
+                opt(gps.h_acc_m),
+                opt(gps.v_acc_m),
+            )
+        },
+    );
+    format!(
+        "last_seen {}\nscan_counter {}\nattach_counter {}\nfix {fix}\n",
+        opt(session.last_seen.map(|at| at.to_rfc3339())),
+        opt(session.last_scan_counter),
+        opt(session.last_attach_counter),
+    )
+}
+
+/// `None` if a line is missing or does not parse
+fn parse_session(contents: &str) -> Option<DeviceSession> {
+    fn opt<T: std::str::FromStr>(value: &str) -> Option<Option<T>> {
+        match value {
+            "-" => Some(None),
+            value => value.parse().ok().map(Some),
+        }
+    }
+    let mut lines = contents.lines();
+    let mut value = |name: &str| lines.next()?.strip_prefix(name)?.strip_prefix(' ');
+    let last_seen = opt::<DateTime<Utc>>(value("last_seen")?)?;
+    let last_scan_counter = opt(value("scan_counter")?)?;
+    let last_attach_counter = opt(value("attach_counter")?)?;
+    let last_fix = match value("fix")? {
+        "-" => None,
+        fix => {
+            let fields: Vec<&str> = fix.split(' ').collect();
+            let [timestamp, lat, lon, hdop, altitude, num_sats, speed, h_acc_m, v_acc_m] =
+                fields[..]
+            else {
+                return None;
+            };
+            Some(Gps {
+                timestamp: timestamp.parse().ok()?,
+                lat: lat.parse().ok()?,
+                lon: lon.parse().ok()?,
+                hdop: hdop.parse().ok()?,
+                altitude: altitude.parse().ok()?,
+                num_sats: num_sats.parse().ok()?,
+                speed: speed.parse().ok()?,
+                h_acc_m: opt(h_acc_m)?,
+                v_acc_m: opt(v_acc_m)?,
+                jamming: None,
+                spoofing: None,
+            })
+        }
+    };
+    Some(DeviceSession {
+        last_fix,
+        last_scan_counter,
+        last_attach_counter,
+        last_seen,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::keys;
+
+    #[cfg(feature = "cell")]
+    #[test]
+    fn replayed_counters_are_not_recorded() {
+        let key = keys::file::File::create_key().unwrap();
+        let scan = |scan_counter| {
+            let scan = crate::CellScan {
+                scan_counter,
+                ..crate::CellScan::random()
+            };
+            Message::from_payload_signed(&key, Payload::CellScan(scan)).unwrap()
+        };
+        let mut store = InMemorySessionStore::new();
+        assert!(store.observe(&scan(u32::MAX)).unwrap());
+        assert!(store.observe(&scan(0)).unwrap());
+        assert!(!store.observe(&scan(0)).unwrap());
+        assert!(!store.observe(&scan(u32::MAX - 3)).unwrap());
+        let session = store.load(&scan(1).pubkey).unwrap();
+        assert_eq!(session.last_scan_counter, Some(0));
+    }
+
+    #[cfg(feature = "cell")]
+    #[test]
+    fn flags_dangling_scan_references() {
+        use crate::{
+            AttachCandidate, CellAttach, CellAttachResult, CellScan, CellScanResult, LazyVerified,
+            MapperMsg,
+        };
+        let key = keys::file::File::create_key().unwrap();
+        let attach = |from_scan| {
+            let attach = CellAttach {
+                attach_counter: 1,
+                gps: Gps::rounded(),
+                candidate: AttachCandidate {
+                    from_scan,
+                    ..CellScanResult::random().into()
+                },
+                result: CellAttachResult::Connected,
+                failure_cause: None,
+                sim: None,
+            };
+            Message::from_payload_signed(&key, Payload::CellAttach(attach)).unwrap()
+        };
+        let mut store = InMemorySessionStore::new();
+        let validator = |store: &InMemorySessionStore, msg: Message| {
+            let lazy = LazyVerified::try_from(MapperMsg::try_from(msg).unwrap()).unwrap();
+            let (_, report) = lazy
+                .verify_with_report(
+                    &crate::InProcessVerifier,
+                    Some(&LinkageValidator::new(store)),
+                )
+                .unwrap();
+            report.linkage
+        };
+        assert_eq!(
+            validator(&store, attach(7)),
+            Some(Linkage::Dangling {
+                from_scan: 7,
+                last_scan_counter: None
+            })
+        );
+
+        let scan = CellScan {
+            scan_counter: 7,
+            ..CellScan::random()
+        };
+        store
+            .observe(&Message::from_payload_signed(&key, Payload::CellScan(scan)).unwrap())
+            .unwrap();
+        assert_eq!(validator(&store, attach(7)), Some(Linkage::Linked));
+        assert_eq!(validator(&store, attach(5)), Some(Linkage::Linked));
+        assert!(matches!(
+            validator(&store, attach(8)),
+            Some(Linkage::Dangling { .. })
+        ));
+        let gps = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap();
+        assert_eq!(validator(&store, gps), None);
+    }
+
+    #[test]
+    fn file_store_roundtrip() {
+        let key = keys::file::File::create_key().unwrap();
+        let msg = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap();
+        let dir = std::env::temp_dir().join(format!("sessions-{}", rand::random::<u64>()));
+        std::fs::create_dir(&dir).unwrap();
+        let mut store = FileSessionStore::new(&dir);
+        assert_eq!(store.load(&msg.pubkey).unwrap(), DeviceSession::default());
+        assert!(store.observe(&msg).unwrap());
+
+        let session = FileSessionStore::new(&dir).load(&msg.pubkey).unwrap();
+        assert_eq!(session.last_fix, Some(Gps::rounded()));
+        assert_eq!(session.last_seen, Some(Gps::rounded().timestamp));
+        assert_eq!(session.last_scan_counter, None);
+
+        std::fs::write(store.path(&msg.pubkey), "fix -\n").unwrap();
+        assert!(matches!(
+            store.load(&msg.pubkey),
+            Err(Error::InvalidSessionFile(_))
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
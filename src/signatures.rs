@@ -0,0 +1,126 @@
+//! The two signature layers of a message. They are distinct types so that one can't be passed
+//! where the other is expected:
+//!
+//! - `EnvelopeSig`, `Message::signature`: the device's full signature over the payload. It is the
+//!   only one that authenticates a message.
+//! - `TruncatedDeviceSig`, `Beacon::signature`: the tail of the signature over the mapper's last
+//!   scan, carried inside the beacon payload as a commitment to that scan. It is covered by the
+//!   envelope signature but too short to be verified on its own.
+use super::{Deserialize, Message, PublicKey, Result, Serialize, SignedBytes};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct EnvelopeSig(Vec<u8>);
+
+impl EnvelopeSig {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn verify(&self, pubkey: &PublicKey, signed_bytes: &SignedBytes) -> Result {
+        signed_bytes.verify(pubkey, &self.0)
+    }
+}
+
+impl From<Vec<u8>> for EnvelopeSig {
+    fn from(signature: Vec<u8>) -> Self {
+        Self(signature)
+    }
+}
+
+impl From<EnvelopeSig> for Vec<u8> {
+    fn from(signature: EnvelopeSig) -> Self {
+        signature.0
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TruncatedDeviceSig(Vec<u8>);
+
+impl TruncatedDeviceSig {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The last two bytes, which is all a LoRa beacon carries. Shorter signatures are zero
+    /// padded in front.
+    pub fn lora_tail(&self) -> u16 {
+        let mut tail = [0; 2];
+        let len = self.0.len().min(2);
+        tail[2 - len..].copy_from_slice(&self.0[self.0.len() - len..]);
+        u16::from_be_bytes(tail)
+    }
+
+    /// Whether this is the tail of `signature`. An empty commitment is the tail of nothing.
+    pub fn is_tail_of(&self, signature: &EnvelopeSig) -> bool {
+        !self.0.is_empty() && signature.as_bytes().ends_with(&self.0)
+    }
+}
+
+impl From<Vec<u8>> for TruncatedDeviceSig {
+    fn from(signature: Vec<u8>) -> Self {
+        Self(signature)
+    }
+}
+
+impl From<TruncatedDeviceSig> for Vec<u8> {
+    fn from(signature: TruncatedDeviceSig) -> Self {
+        signature.0
+    }
+}
+
+impl Message {
+    /// The signature authenticating the message
+    pub fn envelope_signature(&self) -> &EnvelopeSig {
+        &self.signature
+    }
+
+    /// Verifies the envelope signature over the canonical encoding of the payload
+    pub fn verify_envelope(&self) -> Result {
+        self.signature
+            .verify(&self.pubkey, &SignedBytes::from_payload(&self.payload)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{keys, Gps, Payload};
+
+    #[test]
+    fn envelope_verifies_and_tail_pads() {
+        let key = keys::file::File::create_key().unwrap();
+        let mut msg = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap();
+        msg.verify_envelope().unwrap();
+        msg.signature.as_bytes_mut()[10] ^= 0xFF;
+        assert!(msg.verify_envelope().is_err());
+
+        assert_eq!(
+            TruncatedDeviceSig::from(vec![1, 0xAB, 0xCD]).lora_tail(),
+            0xABCD
+        );
+        assert_eq!(TruncatedDeviceSig::from(vec![0xCD]).lora_tail(), 0xCD);
+        assert_eq!(TruncatedDeviceSig::default().lora_tail(), 0);
+    }
+}
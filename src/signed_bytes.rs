@@ -0,0 +1,109 @@
+use super::{
+    mapper_payload,
+    proto_ext::{MapperPayloadExt, PayloadExtV1},
+    Error, Payload, ProtoMessage, PublicKey, Result, Verify,
+};
+
+/// The exact bytes covered by a signature.
+///
+/// `SignedBytes` can only be produced by the canonical encodings of this crate (the proto
+/// encoding of a `MapperPayload` and its extension, or a LoRa frame), so the bytes that get
+/// signed and the bytes that get verified always come from the same code path. For the same reason it deliberately
+/// does not implement `Deserialize`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedBytes(Vec<u8>);
+
+impl SignedBytes {
+    /// Canonical encoding of a payload as it is signed by the mapper
+    pub fn from_payload(payload: &Payload) -> Result<Self> {
+        Self::encode_payload(payload, Vec::new())
+    }
+
+    /// Same as `from_payload` but encodes into `buf`, which is cleared first. The buffer can be
+    /// recovered with `into_inner` for reuse.
+    pub fn encode_payload(payload: &Payload, mut buf: Vec<u8>) -> Result<Self> {
+        buf.clear();
+        payload.to_proto().encode(&mut buf)?;
+        if let Some(ext) = payload.to_proto_ext()? {
+            MapperPayloadExt { ext: Some(ext) }.encode(&mut buf)?;
+        }
+        Ok(Self(buf))
+    }
+
+    /// Canonical encoding of a decoded proto payload, as used for verification
+    pub fn from_proto_payload(payload: &mapper_payload::Message) -> Self {
+        Self::from_proto_payload_with_ext(payload, None)
+    }
+
+    /// Same as `from_proto_payload` for a payload decoded with its extension
+    pub fn from_proto_payload_with_ext(
+        payload: &mapper_payload::Message,
+        ext: Option<&PayloadExtV1>,
+    ) -> Self {
+        // a MapperPayload only holds the oneof so both encode to the same bytes
+        let mut buf = Vec::with_capacity(payload.encoded_len());
+        payload.encode(&mut buf);
+        if let Some(ext) = ext {
+            MapperPayloadExt {
+                ext: Some(ext.clone()),
+            }
+            .encode(&mut buf)
+            .expect("a vec grows as needed");
+        }
+        Self(buf)
+    }
+
+    /// The payload bytes of a MapperMsg as received, see `ExtendedMsg::received_payload_bytes`
+    pub(crate) fn from_received_payload(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+
+    /// LoRa frames are fixed layouts, so the frame itself is the canonical encoding. Crate
+    /// private as it takes any bytes: outside callers sign frames with `sign_lora_frame`.
+    pub(crate) fn from_lora_frame(frame: &[u8]) -> Self {
+        Self(frame.to_vec())
+    }
+
+    pub fn verify(&self, pubkey: &PublicKey, signature: &[u8]) -> Result {
+        pubkey
+            .verify(&self.0, signature)
+            .map_err(|_| Error::SignatureVerification {
+                pubkey: Box::new(pubkey.clone()),
+                msg: self.0.clone(),
+                signature: signature.to_vec(),
+            })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+impl AsRef<[u8]> for SignedBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{keys, keys::KeyTrait, CellScan};
+
+    #[test]
+    fn signed_and_verified_bytes_match() {
+        let key = keys::file::File::create_key().unwrap();
+        let payload = Payload::CellScan(CellScan::random());
+        let signed = SignedBytes::from_payload(&payload).unwrap();
+        let signature = key.sign_bytes(&signed).unwrap();
+
+        let proto = payload.to_proto().message.unwrap();
+        let verified = SignedBytes::from_proto_payload(&proto);
+        assert_eq!(signed, verified);
+        verified.verify(&key.pubkey().unwrap(), &signature).unwrap();
+    }
+}
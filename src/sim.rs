@@ -0,0 +1,141 @@
+use super::{proto_ext::SimInfoV1, Deserialize, Error, Result, Serialize, SimError};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Subscription state of the SIM during an attach, to tell provisioning problems apart from RF
+/// ones. Carried in the proto extension, not in the LoRa frame.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SimInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub imsi_hash: Option<ImsiHash>,
+    /// Set when the SIM was not usable
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sim_error: Option<SimError>,
+    /// Whether the modem was registered on a visited network
+    pub roaming: bool,
+}
+
+impl SimInfo {
+    /// Whether a failed attach is better explained by the subscription than by coverage
+    pub fn is_subscription_issue(&self) -> bool {
+        self.sim_error.is_some() || self.roaming
+    }
+}
+
+/// HMAC-SHA256 of an IMSI under a deployment's secret key, so that hashes can be joined within
+/// a dataset without exposing the IMSI. There are few enough IMSIs to hash them all, so the key
+/// must stay secret: anyone holding it can tell which IMSI a hash is of.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ImsiHash(pub [u8; 32]);
+
+impl ImsiHash {
+    /// Hashes an IMSI of 6 to 15 digits
+    pub fn new(imsi: &str, key: &[u8]) -> Result<Self> {
+        if !(6..=15).contains(&imsi.len()) || !imsi.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(Error::InvalidImsi);
+        }
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac takes keys of any size");
+        mac.update(imsi.as_bytes());
+        Ok(Self(mac.finalize().into_bytes().into()))
+    }
+}
+
+impl From<SimInfo> for SimInfoV1 {
+    fn from(sim: SimInfo) -> Self {
+        Self {
+            imsi_hash: sim.imsi_hash.map(|hash| hash.0.to_vec()),
+            sim_error: sim.sim_error.map(Into::into),
+            roaming: sim.roaming,
+        }
+    }
+}
+
+impl TryFrom<SimInfoV1> for SimInfo {
+    type Error = Error;
+
+    fn try_from(proto: SimInfoV1) -> Result<Self> {
+        Ok(Self {
+            imsi_hash: proto
+                .imsi_hash
+                .map(|hash| {
+                    hash.try_into()
+                        .map(ImsiHash)
+                        .map_err(|_| Error::InvalidImsi)
+                })
+                .transpose()?,
+            sim_error: proto.sim_error.map(TryInto::try_into).transpose()?,
+            roaming: proto.roaming,
+        })
+    }
+}
+
+impl std::fmt::Display for ImsiHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+impl std::str::FromStr for ImsiHash {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.len() != 64 || !s.is_ascii() {
+            return Err(Error::InvalidImsi);
+        }
+        let mut hash = [0; 32];
+        for (byte, pair) in hash.iter_mut().zip(s.as_bytes().chunks(2)) {
+            let pair = std::str::from_utf8(pair).map_err(|_| Error::InvalidImsi)?;
+            *byte = u8::from_str_radix(pair, 16)?;
+        }
+        Ok(Self(hash))
+    }
+}
+
+impl Serialize for ImsiHash {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        crate::serde_helpers::display_fromstr::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ImsiHash {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        crate::serde_helpers::display_fromstr::deserialize(deserializer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn imsi_hash_is_keyed() {
+        let imsi = "315010123456789";
+        let a = ImsiHash::new(imsi, b"deployment-a").unwrap();
+        assert_eq!(a, ImsiHash::new(imsi, b"deployment-a").unwrap());
+        assert_ne!(a, ImsiHash::new(imsi, b"deployment-b").unwrap());
+        assert!(!a.to_string().contains(imsi));
+        assert_eq!(a, a.to_string().parse().unwrap());
+        assert!(matches!(
+            ImsiHash::new("31501O", b""),
+            Err(Error::InvalidImsi)
+        ));
+    }
+
+    #[test]
+    fn sim_info_json() {
+        let sim = SimInfo {
+            imsi_hash: Some(ImsiHash::new("315010123456789", b"key").unwrap()),
+            sim_error: None,
+            roaming: true,
+        };
+        assert!(sim.is_subscription_issue());
+        let json = serde_json::to_string(&sim).unwrap();
+        assert_eq!(sim, serde_json::from_str(&json).unwrap());
+        assert_eq!(sim, SimInfoV1::from(sim).try_into().unwrap());
+    }
+}
@@ -0,0 +1,379 @@
+//! Builds `Gps` fixes from the UBX binary output of a u-blox module. Every UBX-NAV-PVT with a
+//! fix gives one, with the accuracies the receiver reports. HDOP comes from the UBX-NAV-DOP of
+//! the same epoch, or the PVT's PDOP if there was none. Interference comes from the latest
+//! UBX-MON-RF (jamming, the worst of its RF blocks) and UBX-NAV-STATUS (spoofing).
+//!
+//! Frames with a bad checksum or a payload too short for their message are dropped. Messages of
+//! any other class or id are ignored. `UbxStats` counts what happened to every frame.
+use super::{Error, Gps, JammingState, Result, SpoofingState};
+use chrono::{NaiveDate, TimeZone, Utc};
+use rust_decimal::Decimal;
+
+const SYNC: [u8; 2] = [0xB5, 0x62];
+
+/// Sync, class, id and length before the payload, checksum after it
+const FRAME_OVERHEAD: usize = 8;
+
+/// Longer payloads are taken as a false sync. The messages used here are at most a few hundred
+/// bytes, the longest ones a module sends (eg: NAV-SAT) about 2 kB.
+pub const MAX_UBX_PAYLOAD_LEN: usize = 4096;
+
+const NAV: u8 = 0x01;
+const NAV_STATUS: u8 = 0x03;
+const NAV_DOP: u8 = 0x04;
+const NAV_PVT: u8 = 0x07;
+const MON: u8 = 0x0A;
+const MON_RF: u8 = 0x38;
+
+const PVT_LEN: usize = 92;
+const DOP_LEN: usize = 18;
+const STATUS_LEN: usize = 16;
+const RF_HEADER_LEN: usize = 4;
+const RF_BLOCK_LEN: usize = 24;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct UbxStats {
+    /// Frames of a message the parser uses
+    pub parsed: u64,
+    /// Frames with a bad checksum or payload, including false syncs in a byte stream
+    pub dropped: u64,
+    /// Well formed frames of a message the parser doesn't use, eg: NAV-SAT
+    pub ignored: u64,
+    /// NAV-PVTs without a valid fix, date and time
+    pub no_fix: u64,
+    /// Fixes emitted
+    pub fixes: u64,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct UbxParser {
+    stats: UbxStats,
+    buf: Vec<u8>,
+    /// End in `buf` of the last frame counted as dropped, so that the false syncs inside it
+    /// aren't counted again
+    dropped_until: usize,
+    /// iTOW and HDOP of the last NAV-DOP
+    dop: Option<(u32, Decimal)>,
+    jamming: Option<JammingState>,
+    spoofing: Option<SpoofingState>,
+}
+
+impl UbxParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stats(&self) -> UbxStats {
+        self.stats
+    }
+
+    /// Reads one frame, from its sync chars to its checksum, returning the fix it gives if any
+    pub fn push_frame(&mut self, frame: &[u8]) -> Result<Option<Gps>> {
+        match split_frame(frame) {
+            Ok((class, id, payload)) => Ok(self.message(class, id, payload)),
+            Err(reason) => {
+                self.stats.dropped += 1;
+                Err(Error::InvalidUbx(reason))
+            }
+        }
+    }
+
+    /// Fixes of the frames in a byte stream, which may start or end mid frame: the rest of a
+    /// frame is read with the next call. Bytes that don't start a valid frame are skipped.
+    pub fn push_bytes(&mut self, bytes: &[u8]) -> Vec<Gps> {
+        // taken out of self so that frames are read in place
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.extend_from_slice(bytes);
+        let mut fixes = Vec::new();
+        let mut start = 0;
+        loop {
+            let Some(offset) = buf[start..].windows(2).position(|w| w == SYNC) else {
+                // keep a trailing sync char, which may start the next frame
+                let end = match buf.last() {
+                    Some(&last) if last == SYNC[0] => buf.len() - 1,
+                    _ => buf.len(),
+                };
+                start = start.max(end);
+                break;
+            };
+            start += offset;
+            let Some(len) = buf.get(start + 4..start + 6) else {
+                break;
+            };
+            let len = usize::from(u16::from_le_bytes([len[0], len[1]]));
+            if len > MAX_UBX_PAYLOAD_LEN {
+                self.drop_frame_at(start, 0);
+                start += 1;
+                continue;
+            }
+            let Some(frame) = buf.get(start..start + FRAME_OVERHEAD + len) else {
+                break;
+            };
+            match split_frame(frame) {
+                Ok((class, id, payload)) => {
+                    fixes.extend(self.message(class, id, payload));
+                    start += frame.len();
+                }
+                // a false sync: look for the next one inside this frame
+                Err(_) => {
+                    self.drop_frame_at(start, frame.len());
+                    start += 1;
+                }
+            }
+        }
+        buf.drain(..start);
+        self.dropped_until = self.dropped_until.saturating_sub(start);
+        self.buf = buf;
+        fixes
+    }
+
+    /// Counts the frame at `start` of `buf` as dropped, unless it is inside one already counted
+    fn drop_frame_at(&mut self, start: usize, len: usize) {
+        if start >= self.dropped_until {
+            self.stats.dropped += 1;
+        }
+        self.dropped_until = self.dropped_until.max(start + len);
+    }
+
+    fn message(&mut self, class: u8, id: u8, payload: &[u8]) -> Option<Gps> {
+        let parsed = match (class, id) {
+            (NAV, NAV_PVT) if payload.len() >= PVT_LEN => {
+                self.stats.parsed += 1;
+                return self.pvt(payload);
+            }
+            (NAV, NAV_DOP) if payload.len() >= DOP_LEN => {
+                self.dop = Some((
+                    u32_at(payload, 0),
+                    Decimal::new(u16_at(payload, 12).into(), 2),
+                ));
+                true
+            }
+            (NAV, NAV_STATUS) if payload.len() >= STATUS_LEN => {
+                self.spoofing = SpoofingState::from_ubx_nav_status(payload[7]);
+                true
+            }
+            (MON, MON_RF) if payload.len() >= RF_HEADER_LEN => {
+                let blocks = usize::from(payload[1]);
+                match payload.get(RF_HEADER_LEN..RF_HEADER_LEN + blocks * RF_BLOCK_LEN) {
+                    Some(blocks) => {
+                        self.jamming = blocks
+                            .chunks(RF_BLOCK_LEN)
+                            .filter_map(|block| JammingState::from_ubx_mon_rf(block[1]))
+                            .max();
+                        true
+                    }
+                    None => false,
+                }
+            }
+            (NAV, NAV_PVT | NAV_DOP | NAV_STATUS) | (MON, MON_RF) => false,
+            _ => {
+                self.stats.ignored += 1;
+                return None;
+            }
+        };
+        if parsed {
+            self.stats.parsed += 1;
+        } else {
+            self.stats.dropped += 1;
+        }
+        None
+    }
+
+    fn pvt(&mut self, p: &[u8]) -> Option<Gps> {
+        let (valid, fix_type, flags) = (p[11], p[20], p[21]);
+        let valid_date_time = valid & 0x03 == 0x03;
+        let gnss_fix_ok = flags & 0x01 != 0;
+        let timestamp = NaiveDate::from_ymd_opt(u16_at(p, 4).into(), p[6].into(), p[7].into())
+            .and_then(|date| date.and_hms_opt(p[8].into(), p[9].into(), p[10].into()))
+            .map(|time| Utc.from_utc_datetime(&time))
+            .map(|time| time + chrono::Duration::nanoseconds(i32_at(p, 16).into()));
+        let (Some(timestamp), true, 2..=4, true) =
+            (timestamp, valid_date_time, fix_type, gnss_fix_ok)
+        else {
+            self.stats.no_fix += 1;
+            return None;
+        };
+        let itow = u32_at(p, 0);
+        let hdop = match self.dop {
+            Some((dop_itow, hdop)) if dop_itow == itow => hdop,
+            _ => Decimal::new(u16_at(p, 76).into(), 2),
+        };
+        let mm = |value: i64| Decimal::new(value, 3);
+        self.stats.fixes += 1;
+        Some(Gps {
+            timestamp,
+            lat: Decimal::new(i32_at(p, 28).into(), 7),
+            lon: Decimal::new(i32_at(p, 24).into(), 7),
+            hdop,
+            altitude: mm(i32_at(p, 36).into()),
+            num_sats: p[23],
+            // mm/s to km/h
+            speed: Decimal::new(i64::from(i32_at(p, 60)) * 36, 4),
+            h_acc_m: Some(mm(u32_at(p, 40).into())),
+            v_acc_m: Some(mm(u32_at(p, 44).into())),
+            jamming: self.jamming,
+            spoofing: self.spoofing,
+        })
+    }
+}
+
+/// Class, id and payload of a frame whose checksum matches
+fn split_frame(frame: &[u8]) -> std::result::Result<(u8, u8, &[u8]), &'static str> {
+    if !frame.starts_with(&SYNC) {
+        return Err("no sync");
+    }
+    if frame.len() < FRAME_OVERHEAD {
+        return Err("short frame");
+    }
+    let len = usize::from(u16_at(frame, 4));
+    if frame.len() != FRAME_OVERHEAD + len {
+        return Err("length mismatch");
+    }
+    let (body, checksum) = frame[2..].split_at(len + 4);
+    if checksum != fletcher(body) {
+        return Err("checksum mismatch");
+    }
+    Ok((body[0], body[1], &body[4..]))
+}
+
+/// The 8-bit Fletcher checksum of UBX, over class, id, length and payload
+fn fletcher(body: &[u8]) -> [u8; 2] {
+    body.iter().fold([0u8, 0u8], |[a, b], &byte| {
+        let a = a.wrapping_add(byte);
+        [a, b.wrapping_add(a)]
+    })
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn i32_at(bytes: &[u8], offset: usize) -> i32 {
+    i32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn frame(class: u8, id: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = SYNC.to_vec();
+        frame.extend([class, id]);
+        frame.extend((payload.len() as u16).to_le_bytes());
+        frame.extend(payload);
+        let checksum = fletcher(&frame[2..]);
+        frame.extend(checksum);
+        frame
+    }
+
+    /// The fix of `Gps::rounded`, 3.2 m and 6.5 m accurate
+    fn pvt(itow: u32) -> Vec<u8> {
+        let mut p = vec![0; PVT_LEN];
+        p[0..4].copy_from_slice(&itow.to_le_bytes());
+        p[4..6].copy_from_slice(&2023u16.to_le_bytes());
+        p[6..11].copy_from_slice(&[1, 1, 0, 0, 5]);
+        p[11] = 0x07;
+        p[20] = 3;
+        p[21] = 0x01;
+        p[23] = 5;
+        p[24..28].copy_from_slice(&1_201_234_500i32.to_le_bytes());
+        p[28..32].copy_from_slice(&(-501_234_500i32).to_le_bytes());
+        p[36..40].copy_from_slice(&9_250i32.to_le_bytes());
+        p[40..44].copy_from_slice(&3_200u32.to_le_bytes());
+        p[44..48].copy_from_slice(&6_500u32.to_le_bytes());
+        // 50.5 km/h
+        p[60..64].copy_from_slice(&14_028i32.to_le_bytes());
+        p[76..78].copy_from_slice(&1_210u16.to_le_bytes());
+        frame(NAV, NAV_PVT, &p)
+    }
+
+    fn dop(itow: u32, hdop: u16) -> Vec<u8> {
+        let mut p = vec![0; DOP_LEN];
+        p[0..4].copy_from_slice(&itow.to_le_bytes());
+        p[12..14].copy_from_slice(&hdop.to_le_bytes());
+        frame(NAV, NAV_DOP, &p)
+    }
+
+    #[test]
+    fn fixes_carry_accuracy_and_interference() {
+        let mut parser = UbxParser::new();
+        let fix = parser.push_frame(&pvt(5_000)).unwrap().unwrap();
+        let rounded = Gps::rounded();
+        assert_eq!(fix.timestamp, rounded.timestamp);
+        assert_eq!((fix.lat, fix.lon), (rounded.lat, rounded.lon));
+        assert_eq!(
+            (fix.altitude, fix.num_sats),
+            (rounded.altitude, rounded.num_sats)
+        );
+        assert_eq!(fix.speed.round_dp(1), Decimal::new(50_5, 1));
+        assert_eq!(fix.hdop, Decimal::new(12_10, 2));
+        assert_eq!(
+            (fix.h_acc_m, fix.v_acc_m),
+            (Some(Decimal::new(3_2, 1)), Some(Decimal::new(6_5, 1)))
+        );
+        assert_eq!((fix.jamming, fix.spoofing), (None, None));
+
+        let mut rf = vec![1, 2, 0, 0];
+        for flags in [1, 2] {
+            let mut block = [0; RF_BLOCK_LEN];
+            block[1] = flags;
+            rf.extend(block);
+        }
+        let mut status = vec![0; STATUS_LEN];
+        status[7] = 2 << 3;
+        let mut stream = [
+            frame(MON, MON_RF, &rf),
+            frame(NAV, NAV_STATUS, &status),
+            dop(6_000, 9_05),
+            // NAV-SAT
+            frame(NAV, 0x35, &[0; 8]),
+            pvt(6_000),
+        ]
+        .concat();
+        stream.splice(0..0, [0x00, 0xB5, 0x00]);
+        // split mid frame
+        let (first, second) = stream.split_at(40);
+        assert!(parser.push_bytes(first).is_empty());
+        let fixes = parser.push_bytes(second);
+        assert_eq!(fixes.len(), 1);
+        assert_eq!(fixes[0].hdop, rounded.hdop);
+        assert_eq!(fixes[0].jamming, Some(JammingState::Warning));
+        assert_eq!(fixes[0].spoofing, Some(SpoofingState::Indicated));
+
+        let mut corrupted = pvt(7_000);
+        corrupted[30] ^= 0x01;
+        assert!(matches!(
+            parser.push_frame(&corrupted),
+            Err(Error::InvalidUbx("checksum mismatch"))
+        ));
+        let stats = parser.stats();
+        assert_eq!((stats.parsed, stats.ignored, stats.fixes), (5, 1, 2));
+        assert_eq!(stats.dropped, 1);
+
+        // the proto extension carries what the receiver reported
+        let key = crate::keys::file::File::create_key().unwrap();
+        let msg = crate::Message::from_payload_signed(&key, crate::Payload::Gps(fixes[0])).unwrap();
+        let mut bytes = Vec::new();
+        msg.encode_to(&mut bytes).unwrap();
+        let received = crate::Message::decode_from_with_signature_verification(&bytes).unwrap();
+        assert_eq!(received.payload.gps(), &fixes[0]);
+    }
+
+    #[test]
+    fn corrupted_frame_in_stream_is_dropped_once() {
+        let mut corrupted = pvt(8_000);
+        // a false sync inside the payload, which breaks the checksum of the frame around it
+        corrupted[6 + 50..6 + 56].copy_from_slice(&[0xB5, 0x62, 0x00, 0x00, 4, 0]);
+        let stream = [corrupted, pvt(9_000)].concat();
+        let mut parser = UbxParser::new();
+        let (first, second) = stream.split_at(70);
+        assert!(parser.push_bytes(first).is_empty());
+        assert_eq!(parser.push_bytes(second).len(), 1);
+        let stats = parser.stats();
+        assert_eq!((stats.dropped, stats.fixes), (1, 1));
+    }
+}
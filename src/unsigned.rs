@@ -0,0 +1,109 @@
+//! Payloads that have not been signed. They encode to a MapperMsg with an empty pubkey and
+//! signature, which can never verify, rather than zero filled placeholders that look real.
+use super::{keys::KeyTrait, Error, MapperMsg, MapperMsgV1, Message, Payload, Result};
+use helium_proto::{mapper_msg, MapperPayload};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unsigned<T>(pub T);
+
+impl<T> Unsigned<T> {
+    pub fn new(payload: T) -> Self {
+        Self(payload)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: Into<Payload>> Unsigned<T> {
+    pub fn sign<K: KeyTrait>(self, key: &K) -> Result<Message> {
+        Message::from_payload_signed(key, self.0.into())
+    }
+}
+
+impl Message {
+    /// Wraps a payload for tooling that needs a MapperMsg without a key at hand
+    pub fn unsigned(payload: impl Into<Payload>) -> Unsigned<Payload> {
+        Unsigned(payload.into())
+    }
+}
+
+impl<T: Into<Payload>> From<Unsigned<T>> for MapperMsg {
+    fn from(unsigned: Unsigned<T>) -> Self {
+        let payload: Payload = unsigned.0.into();
+        MapperMsg {
+            version: Some(mapper_msg::Version::MsgV1(MapperMsgV1 {
+                pubkey: vec![],
+                payload: Some(payload.to_proto()),
+                signature: vec![],
+                lora_gws: vec![],
+            })),
+        }
+    }
+}
+
+/// Takes the payload of any MapperMsg, ignoring its pubkey and signature entirely
+impl<T> TryFrom<MapperMsg> for Unsigned<T>
+where
+    T: TryFrom<Payload>,
+    Error: From<T::Error>,
+{
+    type Error = Error;
+
+    fn try_from(value: MapperMsg) -> Result<Self> {
+        let Some(mapper_msg::Version::MsgV1(msg)) = value.version else {
+            return Err(Error::ProtoHasNone("version"));
+        };
+        let message = msg
+            .payload
+            .and_then(|MapperPayload { message }| message)
+            .ok_or(Error::ProtoHasNone("payload"))?;
+        Ok(Self(T::try_from(Payload::try_from(message)?)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{keys, Beacon, CellScan, Gps, PayloadKind};
+
+    #[test]
+    fn unsigned_roundtrip_all_payloads() {
+        let gps = Gps::rounded();
+        let payloads = [
+            Payload::Gps(gps),
+            Payload::Beacon(Beacon::new(gps, vec![1, 2])),
+            Payload::CellScan(CellScan {
+                scan_counter: 3,
+                gps,
+                results: vec![],
+            }),
+        ];
+        for payload in payloads {
+            let msg: MapperMsg = Message::unsigned(payload.clone()).into();
+            let returned: Unsigned<Payload> = msg.clone().try_into().unwrap();
+            assert_eq!(returned.into_inner(), payload);
+            assert!(Message::try_from_with_signature_verification(msg).is_err());
+        }
+    }
+
+    #[test]
+    fn unsigned_typed_payload() {
+        let beacon = Beacon::new(Gps::rounded(), vec![1, 2]);
+        let msg: MapperMsg = Unsigned::new(beacon.clone()).into();
+        let returned: Unsigned<Beacon> = msg.clone().try_into().unwrap();
+        assert_eq!(returned.0, beacon);
+        assert!(matches!(
+            Unsigned::<Gps>::try_from(msg),
+            Err(Error::UnexpectedPayloadKind {
+                expected: PayloadKind::Gps,
+                found: PayloadKind::Beacon
+            })
+        ));
+
+        let key = keys::file::File::create_key().unwrap();
+        let signed = Unsigned::new(beacon.clone()).sign(&key).unwrap();
+        assert_eq!(signed.payload, Payload::Beacon(beacon));
+    }
+}
@@ -0,0 +1,112 @@
+//! Signature verification backends. Verification defaults to helium_crypto in-process but may be
+//! offloaded (eg: to a secure enclave or a batching service) by implementing `VerifierBackend`.
+use super::{PublicKey, Result, SignedBytes};
+
+pub struct VerifyRequest<'a> {
+    pub pubkey: &'a PublicKey,
+    pub msg: &'a SignedBytes,
+    pub signature: &'a [u8],
+}
+
+pub trait VerifierBackend {
+    /// Returns `Error::SignatureVerification` if the signature does not match, or
+    /// `Error::VerifierBackend` if the backend itself failed
+    fn verify(&self, pubkey: &PublicKey, msg: &SignedBytes, signature: &[u8]) -> Result;
+
+    /// Verifies many signatures at once, returning one result per request in order. Backends
+    /// that can amortize work across signatures should override this.
+    fn verify_batch(&self, requests: &[VerifyRequest<'_>]) -> Vec<Result> {
+        requests
+            .iter()
+            .map(|r| self.verify(r.pubkey, r.msg, r.signature))
+            .collect()
+    }
+}
+
+/// Verifies with helium_crypto in the current process
+#[derive(Debug, Default, Copy, Clone)]
+pub struct InProcessVerifier;
+
+impl VerifierBackend for InProcessVerifier {
+    fn verify(&self, pubkey: &PublicKey, msg: &SignedBytes, signature: &[u8]) -> Result {
+        msg.verify(pubkey, signature)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{keys, CellScan, DecodeLimits, Error, MapperMsg, Message, Payload};
+    use std::cell::Cell;
+
+    #[derive(Default)]
+    struct CountingVerifier {
+        batches: Cell<usize>,
+        requests: Cell<usize>,
+    }
+
+    impl VerifierBackend for CountingVerifier {
+        fn verify(&self, pubkey: &PublicKey, msg: &SignedBytes, signature: &[u8]) -> Result {
+            InProcessVerifier.verify(pubkey, msg, signature)
+        }
+
+        fn verify_batch(&self, requests: &[VerifyRequest<'_>]) -> Vec<Result> {
+            self.batches.set(self.batches.get() + 1);
+            self.requests.set(self.requests.get() + requests.len());
+            requests
+                .iter()
+                .map(|r| self.verify(r.pubkey, r.msg, r.signature))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn batch_verification() {
+        let key = keys::file::File::create_key().unwrap();
+        let msg =
+            Message::from_payload_signed(&key, Payload::CellScan(CellScan::random())).unwrap();
+        let mut tampered = msg.clone();
+        tampered.signature.as_bytes_mut()[10] ^= 0xFF;
+        let batch: Vec<MapperMsg> = [msg.clone(), tampered, msg.clone()]
+            .into_iter()
+            .map(|msg| msg.try_into().unwrap())
+            .collect();
+
+        let verifier = CountingVerifier::default();
+        let results = Message::try_batch_from_with_verifier(batch, &verifier);
+        assert_eq!(verifier.batches.get(), 1);
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap(), &msg);
+        assert!(matches!(
+            results[1],
+            Err(Error::SignatureVerification { .. })
+        ));
+        assert_eq!(results[2].as_ref().unwrap(), &msg);
+    }
+
+    #[test]
+    fn batch_limits_apply_before_verification() {
+        let key = keys::file::File::create_key().unwrap();
+        let msg =
+            Message::from_payload_signed(&key, Payload::CellScan(CellScan::random())).unwrap();
+        let mut oversized = msg.clone();
+        oversized.signature = vec![0; 4096].into();
+        let batch: Vec<MapperMsg> = [oversized, msg.clone()]
+            .into_iter()
+            .map(|msg| msg.try_into().unwrap())
+            .collect();
+
+        let verifier = CountingVerifier::default();
+        let limits = DecodeLimits::DEFAULT.with_max_signature_len(128);
+        let results = Message::try_batch_from_with_limits(batch, &verifier, &limits);
+        assert_eq!(verifier.requests.get(), 1);
+        assert!(matches!(
+            results[0],
+            Err(Error::SignatureTooLong {
+                len: 4096,
+                max: 128
+            })
+        ));
+        assert_eq!(results[1].as_ref().unwrap(), &msg);
+    }
+}
@@ -0,0 +1,448 @@
+#[cfg(feature = "h3")]
+use super::Gps;
+use super::{Deserialize, Error, LoraGw, Message, PublicKey, Result, Serialize};
+#[cfg(feature = "h3")]
+use h3o::{CellIndex, LatLng, Resolution};
+use std::cmp::Ordering;
+#[cfg(feature = "h3")]
+use std::collections::BTreeSet;
+
+/// Gateways that witnessed a message. Read access goes through the slice, changes through the
+/// methods below.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Witnesses(Vec<LoraGw>);
+
+impl Witnesses {
+    /// Witnesses accepted per message when decoding, unless a different max is given
+    pub const DEFAULT_MAX: usize = 64;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, lora_gw: LoraGw) {
+        self.0.push(lora_gw)
+    }
+
+    /// Best witness first: highest SNR, then highest RSSI, then pubkey bytes, so that the order
+    /// does not depend on the order gateways were reported in
+    pub fn sort(&mut self) {
+        self.0.sort_by(compare_witnesses)
+    }
+
+    /// Keeps the `max` best witnesses, leaving them sorted
+    pub fn cap(&mut self, max: usize) {
+        self.sort();
+        self.0.truncate(max);
+    }
+
+    /// Removes every witness, eg: before echoing a message back to the device
+    pub fn strip(&mut self) {
+        self.0.clear()
+    }
+
+    /// Merges `other` in, keeping a single witness per gateway: the one with the best RSSI. On
+    /// an RSSI tie the witness already present is kept. Gateways keep the order they were first
+    /// seen in.
+    pub fn merge(&mut self, other: &Witnesses) {
+        let mut merged = Witnesses::new();
+        for witness in self.0.drain(..).chain(other.iter().cloned()) {
+            merged.insert_best(witness);
+        }
+        *self = merged;
+    }
+
+    fn insert_best(&mut self, witness: LoraGw) {
+        match self.0.iter_mut().find(|w| w.pubkey == witness.pubkey) {
+            Some(existing) if witness.rssi > existing.rssi => *existing = witness,
+            Some(_) => (),
+            None => self.0.push(witness),
+        }
+    }
+
+    pub fn into_inner(self) -> Vec<LoraGw> {
+        self.0
+    }
+
+    /// Witnesses that are most likely the reporting mapper hearing its own uplink relayed back,
+    /// eg: mappers also attached to a LoRaWAN network. See `SelfWitness`.
+    #[cfg(feature = "h3")]
+    pub fn self_witnesses<'a>(
+        &'a self,
+        reporter: &'a PublicKey,
+        gps: &'a Gps,
+        max_distance_m: f64,
+    ) -> impl Iterator<Item = (&'a LoraGw, SelfWitness)> + 'a {
+        self.0.iter().filter_map(move |witness| {
+            SelfWitness::of(witness, reporter, gps, max_distance_m).map(|why| (witness, why))
+        })
+    }
+
+    /// Removes the `self_witnesses`, returning how many there were
+    #[cfg(feature = "h3")]
+    pub fn strip_self(&mut self, reporter: &PublicKey, gps: &Gps, max_distance_m: f64) -> usize {
+        let before = self.0.len();
+        self.0
+            .retain(|witness| SelfWitness::of(witness, reporter, gps, max_distance_m).is_none());
+        before - self.0.len()
+    }
+
+    /// How spread out the witnesses are, with their cells taken at `resolution`. Gateways
+    /// asserted coarser than `resolution` keep their asserted cell.
+    #[cfg(feature = "h3")]
+    pub fn diversity(&self, resolution: Resolution) -> Diversity {
+        let cells: BTreeSet<CellIndex> = self
+            .0
+            .iter()
+            .map(|w| w.h3_cell.cell())
+            .map(|cell| cell.parent(resolution).unwrap_or(cell))
+            .collect();
+        let cells: Vec<CellIndex> = cells.into_iter().collect();
+        let max_grid_distance = cells
+            .iter()
+            .enumerate()
+            .flat_map(|(i, a)| cells[i + 1..].iter().map(move |b| a.grid_distance(*b)))
+            .try_fold(0, |max, distance| distance.ok().map(|d| max.max(d as u32)));
+        Diversity {
+            distinct_cells: cells.len(),
+            max_grid_distance,
+            dispersion_km: dispersion_km(&cells),
+        }
+    }
+
+    pub(crate) fn check_count(count: usize, max: usize) -> Result {
+        if count > max {
+            Err(Error::TooManyWitnesses { count, max })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Witnesses asserted closer than this to the fix are taken for the mapper itself. It only
+/// catches gateways asserted at a fine resolution: the centroid of a resolution 10 cell can be
+/// 75 m away from a fix inside it.
+#[cfg(feature = "h3")]
+pub const SELF_WITNESS_DISTANCE_M: f64 = 10.0;
+
+/// Why a witness is taken for the reporter, see `Witnesses::self_witnesses`
+#[cfg(feature = "h3")]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum SelfWitness {
+    /// The gateway signs with the mapper's key
+    SamePubkey,
+    /// The gateway's asserted cell centroid is `distance_m` from the fix
+    Colocated { distance_m: f64 },
+}
+
+#[cfg(feature = "h3")]
+impl SelfWitness {
+    fn of(witness: &LoraGw, reporter: &PublicKey, gps: &Gps, max_distance_m: f64) -> Option<Self> {
+        if &witness.pubkey == reporter {
+            return Some(SelfWitness::SamePubkey);
+        }
+        use rust_decimal::prelude::ToPrimitive;
+        let fix = LatLng::new(gps.lat.to_f64()?, gps.lon.to_f64()?).ok()?;
+        let distance_m = LatLng::from(witness.h3_cell.cell()).distance_m(fix);
+        (distance_m < max_distance_m).then_some(SelfWitness::Colocated { distance_m })
+    }
+}
+
+/// See `Witnesses::diversity`
+#[cfg(feature = "h3")]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Diversity {
+    pub distinct_cells: usize,
+    /// Largest grid distance between two witness cells, 0 under two cells. `None` if some
+    /// distance can't be computed: cells too far apart, across a pentagon or at different
+    /// resolutions.
+    pub max_grid_distance: Option<u32>,
+    /// Mean great circle distance from the cell centers to their centroid, 0 under two cells
+    pub dispersion_km: f64,
+}
+
+#[cfg(feature = "h3")]
+fn dispersion_km(cells: &[CellIndex]) -> f64 {
+    if cells.len() < 2 {
+        return 0.0;
+    }
+    let centers: Vec<LatLng> = cells.iter().map(|cell| LatLng::from(*cell)).collect();
+    // averaged as unit vectors, so that cells either side of the antimeridian don't average out
+    // to the other side of the earth
+    let (x, y, z) = centers.iter().fold((0.0, 0.0, 0.0), |(x, y, z), center| {
+        let (lat, lng) = (center.lat_radians(), center.lng_radians());
+        (
+            x + lat.cos() * lng.cos(),
+            y + lat.cos() * lng.sin(),
+            z + lat.sin(),
+        )
+    });
+    let Ok(centroid) = LatLng::from_radians(z.atan2(x.hypot(y)), y.atan2(x)) else {
+        return 0.0;
+    };
+    centers
+        .iter()
+        .map(|center| center.distance_km(centroid))
+        .sum::<f64>()
+        / centers.len() as f64
+}
+
+fn compare_witnesses(a: &LoraGw, b: &LoraGw) -> Ordering {
+    b.snr
+        .cmp(&a.snr)
+        .then_with(|| b.rssi.cmp(&a.rssi))
+        .then_with(|| pubkey_bytes(&a.pubkey).cmp(&pubkey_bytes(&b.pubkey)))
+}
+
+fn pubkey_bytes(pubkey: &PublicKey) -> Vec<u8> {
+    pubkey.to_vec()
+}
+
+impl std::ops::Deref for Witnesses {
+    type Target = [LoraGw];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl From<Vec<LoraGw>> for Witnesses {
+    fn from(witnesses: Vec<LoraGw>) -> Self {
+        Self(witnesses)
+    }
+}
+
+impl FromIterator<LoraGw> for Witnesses {
+    fn from_iter<I: IntoIterator<Item = LoraGw>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl IntoIterator for Witnesses {
+    type Item = LoraGw;
+    type IntoIter = std::vec::IntoIter<LoraGw>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Witnesses {
+    type Item = &'a LoraGw;
+    type IntoIter = std::slice::Iter<'a, LoraGw>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl Message {
+    /// The message as it is echoed back to the device, without witnesses
+    pub fn without_witnesses(mut self) -> Self {
+        self.lora_gws.strip();
+        self.original_bytes = None;
+        self
+    }
+
+    /// Whether any witness is the mapper itself, by pubkey or within `SELF_WITNESS_DISTANCE_M`
+    /// of the fix
+    #[cfg(feature = "h3")]
+    pub fn self_witnessed(&self) -> bool {
+        self.lora_gws
+            .self_witnesses(&self.pubkey, self.payload.gps(), SELF_WITNESS_DISTANCE_M)
+            .next()
+            .is_some()
+    }
+
+    /// The message without the witnesses `self_witnessed` looks for
+    #[cfg(feature = "h3")]
+    pub fn without_self_witnesses(mut self) -> Self {
+        let gps = *self.payload.gps();
+        if self
+            .lora_gws
+            .strip_self(&self.pubkey, &gps, SELF_WITNESS_DISTANCE_M)
+            > 0
+        {
+            self.original_bytes = None;
+        }
+        self
+    }
+
+    /// `Witnesses::diversity` of the gateways that heard the message
+    #[cfg(feature = "h3")]
+    pub fn witness_diversity(&self, resolution: Resolution) -> Diversity {
+        self.lora_gws.diversity(resolution)
+    }
+
+    /// Merges the witnesses of `other`, a retransmission of this message, see
+    /// `Witnesses::merge`. Fails if `other` does not carry the same payload from the same device,
+    /// as told by `Message::id`.
+    pub fn merge_witnesses(&mut self, other: &Message) -> Result {
+        let (expected, found) = (self.id()?, other.id()?);
+        if expected != found {
+            return Err(Error::MessageMismatch { expected, found });
+        }
+        self.lora_gws.merge(&other.lora_gws);
+        self.original_bytes = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::keys::{self, KeyTrait};
+    use rust_decimal::Decimal;
+
+    fn witness(snr: i64, rssi: i64) -> LoraGw {
+        LoraGw {
+            snr: Decimal::new(snr, 1),
+            rssi: Decimal::new(rssi, 0),
+            ..LoraGw::random()
+        }
+    }
+
+    #[test]
+    fn cap_keeps_best_by_snr() {
+        let mut witnesses: Witnesses = [witness(-50, -120), witness(55, -100), witness(55, -90)]
+            .into_iter()
+            .collect();
+        let mut reversed: Witnesses = witnesses.iter().rev().cloned().collect();
+        witnesses.sort();
+        reversed.sort();
+        assert_eq!(witnesses, reversed);
+
+        witnesses.cap(2);
+        let kept: Vec<_> = witnesses.iter().map(|w| (w.snr, w.rssi)).collect();
+        assert_eq!(
+            kept,
+            [
+                (Decimal::new(55, 1), Decimal::new(-90, 0)),
+                (Decimal::new(55, 1), Decimal::new(-100, 0))
+            ]
+        );
+        witnesses.strip();
+        assert!(witnesses.is_empty());
+    }
+
+    #[test]
+    fn merge_keeps_best_rssi_per_gateway() {
+        let key = keys::file::File::create_key().unwrap();
+        let payload = crate::Payload::Gps(crate::Gps::rounded());
+        let mut first = Message::from_payload_signed(&key, payload).unwrap();
+        let mut second = first.clone();
+        let (shared, only_first, only_second) =
+            (witness(10, -120), witness(20, -100), witness(30, -90));
+        first.lora_gws = vec![shared.clone(), only_first.clone()].into();
+        let better = LoraGw {
+            rssi: Decimal::new(-95, 0),
+            ..shared
+        };
+        second.lora_gws = vec![only_second.clone(), better.clone()].into();
+
+        first.merge_witnesses(&second).unwrap();
+        assert_eq!(&first.lora_gws[..], [better, only_first, only_second]);
+
+        // merging again changes nothing
+        let merged = first.lora_gws.clone();
+        first.merge_witnesses(&second).unwrap();
+        assert_eq!(first.lora_gws, merged);
+
+        let mut later = crate::Gps::rounded();
+        later.timestamp += chrono::Duration::seconds(1);
+        let other = Message::from_payload_signed(&key, crate::Payload::Gps(later)).unwrap();
+        assert!(matches!(
+            first.merge_witnesses(&other),
+            Err(Error::MessageMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn detects_self_witnesses() {
+        let key = keys::file::File::create_key().unwrap();
+        let gps = crate::Gps::rounded();
+        let mut msg = Message::from_payload_signed(&key, crate::Payload::Gps(gps)).unwrap();
+        msg.lora_gws.push(witness(55, -110));
+        assert!(!msg.self_witnessed());
+
+        let echo = LoraGw {
+            pubkey: key.pubkey().unwrap(),
+            ..witness(55, -40)
+        };
+        let colocated = LoraGw {
+            h3_cell: gps.to_h3_cell(Resolution::Fifteen).unwrap().into(),
+            ..witness(50, -45)
+        };
+        msg.lora_gws.push(echo);
+        msg.lora_gws.push(colocated);
+        assert!(msg.self_witnessed());
+        let found: Vec<_> = msg
+            .lora_gws
+            .self_witnesses(&msg.pubkey, &gps, SELF_WITNESS_DISTANCE_M)
+            .map(|(_, why)| why)
+            .collect();
+        assert!(matches!(
+            found[..],
+            [SelfWitness::SamePubkey, SelfWitness::Colocated { distance_m }] if distance_m < 1.0
+        ));
+
+        let stripped = msg.without_self_witnesses();
+        assert_eq!(stripped.lora_gws.len(), 1);
+        assert!(!stripped.self_witnessed());
+    }
+
+    #[test]
+    fn decode_enforces_max() {
+        let key = keys::file::File::create_key().unwrap();
+        let mut msg =
+            Message::from_payload_signed(&key, crate::Payload::Gps(crate::Gps::rounded())).unwrap();
+        for _ in 0..3 {
+            msg.lora_gws.push(witness(55, -110));
+        }
+        let proto = crate::MapperMsg::try_from(msg.clone()).unwrap();
+        assert_eq!(
+            Message::try_from_with_max_witnesses(proto.clone(), 3).unwrap(),
+            msg
+        );
+        assert!(matches!(
+            Message::try_from_with_max_witnesses(proto, 2),
+            Err(Error::TooManyWitnesses { count: 3, max: 2 })
+        ));
+        assert!(msg.without_witnesses().lora_gws.is_empty());
+    }
+
+    #[test]
+    fn diversity_of_spread_witnesses() {
+        let near = witness(10, -100);
+        let home = near.h3_cell.cell().parent(Resolution::Eight).unwrap();
+        let far_cell = home
+            .grid_ring_fast(3)
+            .flatten()
+            .next()
+            .unwrap()
+            .center_child(Resolution::Ten)
+            .unwrap();
+        let far = LoraGw {
+            h3_cell: far_cell.into(),
+            ..witness(10, -100)
+        };
+        let witnesses: Witnesses = vec![near.clone(), witness(5, -90), far].into();
+
+        let diversity = witnesses.diversity(Resolution::Eight);
+        assert_eq!(diversity.distinct_cells, 2);
+        assert_eq!(diversity.max_grid_distance, Some(3));
+        assert!(diversity.dispersion_km > 0.5, "{diversity:?}");
+
+        let single = Witnesses::from(vec![near]).diversity(Resolution::Eight);
+        assert_eq!(
+            (
+                single.distinct_cells,
+                single.max_grid_distance,
+                single.dispersion_km
+            ),
+            (1, Some(0), 0.0)
+        );
+    }
+}
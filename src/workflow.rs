@@ -0,0 +1,304 @@
+//! The mapper reporting loop as a state machine: `Idle → Scanning → Attaching → Reporting` and
+//! back to `Idle`. It owns the scan and attach counters, so every scan and attach it emits
+//! carries the next counter and attaches are always linked to the scan they came from. Each
+//! increment is persisted to the `CounterStore` before the payload carrying it is returned.
+use super::{
+    counter_store::{Counter, CounterStore, Counters, InMemoryCounterStore},
+    AttachCandidate, AttachCandidateConfig, CellAttach, CellAttachResult, CellScan, CellScanResult,
+    Deserialize, Error, Gps, Payload, Result, ScoreWeights, Serialize,
+};
+
+/// Fieldless view of `Workflow`'s state, for errors and logs
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkflowState {
+    Idle,
+    Scanning,
+    Attaching,
+    Reporting,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum State {
+    Idle,
+    Scanning,
+    Attaching(CellScan),
+    Reporting(CellScan, Option<CellAttach>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Workflow<S = InMemoryCounterStore> {
+    counters: Counters,
+    store: S,
+    state: State,
+}
+
+impl Default for Workflow {
+    fn default() -> Self {
+        Self::new(0, 0)
+    }
+}
+
+impl Workflow {
+    /// Starts idle with counters kept in memory only. The counters are the ones the next scan
+    /// and attach will carry.
+    pub fn new(next_scan_counter: u32, next_attach_counter: u32) -> Self {
+        let counters = Counters {
+            next_scan: next_scan_counter,
+            next_attach: next_attach_counter,
+        };
+        Self {
+            counters,
+            store: InMemoryCounterStore::new(counters),
+            state: State::Idle,
+        }
+    }
+}
+
+impl<S: CounterStore> Workflow<S> {
+    /// Starts idle with the counters loaded from `store`
+    pub fn with_store(store: S) -> Result<Self> {
+        Ok(Self {
+            counters: store.load()?,
+            store,
+            state: State::Idle,
+        })
+    }
+
+    pub fn into_store(self) -> S {
+        self.store
+    }
+
+    /// Persists the incremented counters and returns the value to use
+    fn take(&mut self, counter: Counter) -> Result<u32> {
+        let incremented = self.counters.incremented(counter);
+        self.store.persist(&incremented)?;
+        let value = self.counters.get(counter);
+        self.counters = incremented;
+        Ok(value)
+    }
+
+    pub fn state(&self) -> WorkflowState {
+        match self.state {
+            State::Idle => WorkflowState::Idle,
+            State::Scanning => WorkflowState::Scanning,
+            State::Attaching(_) => WorkflowState::Attaching,
+            State::Reporting(..) => WorkflowState::Reporting,
+        }
+    }
+
+    pub fn next_scan_counter(&self) -> u32 {
+        self.counters.next_scan
+    }
+
+    pub fn next_attach_counter(&self) -> u32 {
+        self.counters.next_attach
+    }
+
+    fn invalid(&self, event: &'static str) -> Error {
+        Error::InvalidTransition {
+            state: self.state(),
+            event,
+        }
+    }
+
+    /// `Idle → Scanning`
+    pub fn start_scan(&mut self) -> Result {
+        match self.state {
+            State::Idle => {
+                self.state = State::Scanning;
+                Ok(())
+            }
+            _ => Err(self.invalid("start_scan")),
+        }
+    }
+
+    /// `Scanning → Attaching`. Returns the scan, numbered with the next scan counter.
+    pub fn finish_scan(&mut self, gps: Gps, results: Vec<CellScanResult>) -> Result<&CellScan> {
+        if self.state != State::Scanning {
+            return Err(self.invalid("finish_scan"));
+        }
+        let scan = CellScan {
+            scan_counter: self.take(Counter::Scan)?,
+            gps,
+            results,
+        };
+        self.state = State::Attaching(scan);
+        match &self.state {
+            State::Attaching(scan) => Ok(scan),
+            _ => Err(self.invalid("finish_scan")),
+        }
+    }
+
+    /// The result of the scan being attached from that belongs to our network and ranks best
+    /// with the default `ScoreWeights`
+    pub fn best_candidate(&self) -> Option<&CellScanResult> {
+        self.best_candidate_with(&ScoreWeights::default())
+    }
+
+    /// Same as `best_candidate` ranked with `weights`. Ties go to the stronger RSRP.
+    pub fn best_candidate_with(&self, weights: &ScoreWeights) -> Option<&CellScanResult> {
+        match &self.state {
+            State::Attaching(scan) => scan
+                .results
+                .iter()
+                .filter(|r| r.is_our_network().unwrap_or(false))
+                .max_by_key(|r| (r.quality_score(weights), r.rsrp)),
+            _ => None,
+        }
+    }
+
+    /// `Attaching → Reporting`. Builds the attach from `candidate`, which must be one of the
+    /// scan's results, with the scan's counter and the seconds elapsed since the scan fix as
+    /// delay.
+    pub fn finish_attach(
+        &mut self,
+        candidate: &CellScanResult,
+        gps: Gps,
+        result: CellAttachResult,
+    ) -> Result<CellAttach> {
+        let State::Attaching(scan) = &self.state else {
+            return Err(self.invalid("finish_attach"));
+        };
+        let elapsed = (gps.timestamp - scan.gps.timestamp).num_seconds();
+        let delay = u32::try_from(elapsed).map_err(|_| Error::OutOfRange {
+            field: "delay",
+            value: elapsed.into(),
+        })?;
+        let config = AttachCandidateConfig::builder()
+            .scan(scan)
+            .delay(delay)
+            .build()?;
+        let candidate = AttachCandidate::from_scan_result_with_config(*candidate, &config)?;
+        let attach = CellAttach {
+            attach_counter: self.take(Counter::Attach)?,
+            gps,
+            candidate,
+            result,
+            failure_cause: None,
+            sim: None,
+        };
+        match std::mem::replace(&mut self.state, State::Idle) {
+            State::Attaching(scan) => {
+                self.state = State::Reporting(scan, Some(attach));
+                Ok(attach)
+            }
+            state => {
+                self.state = state;
+                Err(self.invalid("finish_attach"))
+            }
+        }
+    }
+
+    /// `Attaching → Reporting` without an attach, eg: when no candidate was found
+    pub fn skip_attach(&mut self) -> Result {
+        match std::mem::replace(&mut self.state, State::Idle) {
+            State::Attaching(scan) => {
+                self.state = State::Reporting(scan, None);
+                Ok(())
+            }
+            state => {
+                self.state = state;
+                Err(self.invalid("skip_attach"))
+            }
+        }
+    }
+
+    /// `Reporting → Idle`. Returns the payloads to send: the scan, then its attach if any.
+    pub fn finish_reporting(&mut self) -> Result<Vec<Payload>> {
+        match std::mem::replace(&mut self.state, State::Idle) {
+            State::Reporting(scan, attach) => Ok(std::iter::once(Payload::CellScan(scan))
+                .chain(attach.map(Payload::CellAttach))
+                .collect()),
+            state => {
+                self.state = state;
+                Err(self.invalid("finish_reporting"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn our_cell() -> CellScanResult {
+        CellScanResult {
+            cell_id: 0x0099D << 8,
+            ..CellScanResult::random_our_network()
+        }
+    }
+
+    #[test]
+    fn full_cycle_numbers_payloads() {
+        let mut workflow = Workflow::new(7, 3);
+        let scan_fix = Gps::rounded();
+        let mut attach_fix = scan_fix;
+        attach_fix.timestamp += chrono::Duration::seconds(12);
+
+        workflow.start_scan().unwrap();
+        let scan = workflow.finish_scan(scan_fix, vec![our_cell()]).unwrap();
+        assert_eq!(scan.scan_counter, 7);
+        let candidate = *workflow.best_candidate().unwrap();
+        let attach = workflow
+            .finish_attach(&candidate, attach_fix, CellAttachResult::Connected)
+            .unwrap();
+        assert_eq!(attach.attach_counter, 3);
+        assert_eq!(
+            (attach.candidate.from_scan, attach.candidate.delay),
+            (7, 12)
+        );
+
+        let payloads = workflow.finish_reporting().unwrap();
+        assert!(matches!(
+            payloads[..],
+            [Payload::CellScan(_), Payload::CellAttach(_)]
+        ));
+        assert_eq!(workflow.state(), WorkflowState::Idle);
+        assert_eq!(
+            (workflow.next_scan_counter(), workflow.next_attach_counter()),
+            (8, 4)
+        );
+    }
+
+    #[test]
+    fn increments_are_persisted() {
+        let mut store = InMemoryCounterStore::new(Counters {
+            next_scan: 5,
+            next_attach: 0,
+        });
+        let mut workflow = Workflow::with_store(&mut store).unwrap();
+        workflow.start_scan().unwrap();
+        let scan = workflow.finish_scan(Gps::rounded(), vec![]).unwrap();
+        assert_eq!(scan.scan_counter, 5);
+        drop(workflow);
+        assert_eq!(store.load().unwrap().next_scan, 6);
+
+        let resumed = Workflow::with_store(store).unwrap();
+        assert_eq!(resumed.next_scan_counter(), 6);
+    }
+
+    #[test]
+    fn rejects_out_of_order_events() {
+        let mut workflow = Workflow::default();
+        assert!(matches!(
+            workflow.finish_reporting(),
+            Err(Error::InvalidTransition {
+                state: WorkflowState::Idle,
+                event: "finish_reporting"
+            })
+        ));
+        workflow.start_scan().unwrap();
+        assert!(workflow.start_scan().is_err());
+        workflow.finish_scan(Gps::rounded(), vec![]).unwrap();
+        assert!(workflow.best_candidate().is_none());
+        // a candidate that is not in the scan is refused and the state is kept
+        assert!(workflow
+            .finish_attach(&our_cell(), Gps::rounded(), CellAttachResult::NoAttach)
+            .is_err());
+        assert_eq!(workflow.state(), WorkflowState::Attaching);
+        workflow.skip_attach().unwrap();
+        assert_eq!(workflow.finish_reporting().unwrap().len(), 1);
+        assert_eq!(workflow.next_attach_counter(), 0);
+    }
+}
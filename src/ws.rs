@@ -0,0 +1,192 @@
+//! Live feed of verified messages over WebSocket, one JSON text frame per message.
+//!
+//! Messages enter the feed through `LiveFeed::publish`, or by using the feed as the
+//! `ingest::MessageHandler` of an `Ingestor`, so only verified messages are ever sent. Clients
+//! pick what they receive with the query string of the upgrade request, eg:
+//! `ws://host/?kind=gps,cell_scan&h3=8528340bfffffff&pubkey=<b58>`. Every parameter is optional
+//! and may be repeated or comma separated; a message is sent if it matches all of them.
+//!
+//! Slow clients skip messages rather than hold the feed back.
+use super::{ingest::MessageHandler, Error, Message, PayloadKind, PublicKey, Result, Verified};
+use futures_util::{SinkExt, StreamExt};
+use h3o::{CellIndex, Resolution};
+use std::{collections::HashSet, future::Future, sync::Arc};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::broadcast,
+};
+use tokio_tungstenite::tungstenite::{
+    self,
+    handshake::server::{ErrorResponse, Request, Response},
+    http::StatusCode,
+};
+
+/// Messages a client receives. Empty sets match everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeedFilter {
+    pub kinds: HashSet<PayloadKind>,
+    /// Fixes inside any of these cells
+    pub h3_prefixes: HashSet<CellIndex>,
+    pub pubkeys: Vec<PublicKey>,
+}
+
+impl FeedFilter {
+    /// Parses the query string of an upgrade request, without the leading `?`
+    pub fn from_query(query: &str) -> Result<Self> {
+        let mut filter = Self::default();
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, values) = pair.split_once('=').unwrap_or((pair, ""));
+            for value in values.split(',').filter(|value| !value.is_empty()) {
+                let invalid = || Error::InvalidFeedFilter(pair.to_string());
+                match key {
+                    "kind" => {
+                        filter.kinds.insert(value.parse()?);
+                    }
+                    "h3" => {
+                        filter
+                            .h3_prefixes
+                            .insert(value.parse().map_err(|_| invalid())?);
+                    }
+                    "pubkey" => {
+                        filter.pubkeys.push(value.parse().map_err(|_| invalid())?);
+                    }
+                    _ => return Err(invalid()),
+                }
+            }
+        }
+        Ok(filter)
+    }
+
+    pub fn matches(&self, msg: &Message) -> bool {
+        (self.kinds.is_empty() || self.kinds.contains(&msg.payload.kind()))
+            && (self.pubkeys.is_empty() || self.pubkeys.contains(&msg.pubkey))
+            && (self.h3_prefixes.is_empty() || self.in_prefix(msg))
+    }
+
+    fn in_prefix(&self, msg: &Message) -> bool {
+        let Ok(cell) = msg.payload.gps().to_h3_cell(Resolution::Fifteen) else {
+            return false;
+        };
+        self.h3_prefixes
+            .iter()
+            .any(|prefix| cell.parent(prefix.resolution()) == Some(*prefix))
+    }
+}
+
+/// Fans verified messages out to every connected client. Cloning gives another handle on the
+/// same feed.
+#[derive(Debug, Clone)]
+pub struct LiveFeed {
+    sender: broadcast::Sender<Arc<Message>>,
+}
+
+impl LiveFeed {
+    /// `capacity` messages are buffered per client before it starts skipping
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            sender: broadcast::channel(capacity).0,
+        }
+    }
+
+    /// Returns the number of connected clients
+    pub fn publish(&self, msg: Verified<Message>) -> usize {
+        self.send(msg.into_inner())
+    }
+
+    fn send(&self, msg: Message) -> usize {
+        self.sender.send(Arc::new(msg)).unwrap_or(0)
+    }
+
+    /// Accepts clients until the listener fails. Each client is served on its own task.
+    pub async fn serve(&self, listener: TcpListener) -> Result {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let feed = self.clone();
+            tokio::spawn(async move { feed.serve_client(stream).await });
+        }
+    }
+
+    /// Serves a single client until it disconnects
+    pub async fn serve_client(&self, stream: TcpStream) -> Result {
+        let mut filter = Ok(FeedFilter::default());
+        let callback = |request: &Request, response: Response| {
+            filter = FeedFilter::from_query(request.uri().query().unwrap_or(""));
+            match &filter {
+                Ok(_) => Ok(response),
+                Err(error) => {
+                    let mut rejection = ErrorResponse::new(Some(error.to_string()));
+                    *rejection.status_mut() = StatusCode::BAD_REQUEST;
+                    Err(rejection)
+                }
+            }
+        };
+        let socket = tokio_tungstenite::accept_hdr_async(stream, callback).await?;
+        let filter = filter?;
+        let mut messages = self.sender.subscribe();
+        let (mut sink, mut incoming) = socket.split();
+        loop {
+            tokio::select! {
+                msg = messages.recv() => match msg {
+                    Ok(msg) if filter.matches(&msg) => {
+                        sink.send(tungstenite::Message::Text(to_frame(&msg)?.into())).await?;
+                    }
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => (),
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                frame = incoming.next() => match frame {
+                    Some(Ok(tungstenite::Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => (),
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Messages handed to an `Ingestor` handler have been verified
+impl MessageHandler for LiveFeed {
+    fn handle(&self, msg: Message) -> impl Future<Output = Result> + Send {
+        self.send(msg);
+        std::future::ready(Ok(()))
+    }
+}
+
+/// The JSON text frame of a message
+pub fn to_frame(msg: &Message) -> Result<String> {
+    Ok(serde_json::to_string(msg)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{keys, keys::KeyTrait, Gps, Payload};
+
+    #[test]
+    fn filters_by_query() {
+        let key = keys::file::File::create_key().unwrap();
+        let msg = Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap();
+        let home = msg.reward_cell().unwrap().parent::<5>().unwrap().index();
+        let pubkey = key.pubkey().unwrap();
+
+        let filter =
+            FeedFilter::from_query(&format!("kind=gps,beacon&h3={home}&pubkey={pubkey}")).unwrap();
+        assert_eq!(filter.kinds.len(), 2);
+        assert!(filter.matches(&msg));
+        assert!(FeedFilter::from_query("").unwrap().matches(&msg));
+        assert!(!FeedFilter::from_query("kind=cell_scan")
+            .unwrap()
+            .matches(&msg));
+        let sibling = home
+            .grid_disk::<Vec<_>>(1)
+            .into_iter()
+            .find(|cell| *cell != home)
+            .unwrap();
+        assert!(!FeedFilter::from_query(&format!("h3={sibling}"))
+            .unwrap()
+            .matches(&msg));
+        assert!(matches!(
+            FeedFilter::from_query("color=red"),
+            Err(Error::InvalidFeedFilter(_))
+        ));
+    }
+}
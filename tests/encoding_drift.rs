@@ -0,0 +1,195 @@
+//! Differential checks between the LoRa and proto encodings: for any payload the LoRa frames can
+//! carry without saturating, decoding its LoRa frame and decoding its proto must give values
+//! within the precision envelope of the two encodings, so a change to the rounding of one of
+//! them fails here rather than as a disagreement between backhauls.
+//!
+//! | field    | LoRa               | proto | envelope              |
+//! |----------|--------------------|-------|-----------------------|
+//! | time     | 1 s                | 1 s   | exact                 |
+//! | lat, lon | 1e-5 (1e-4 coarse) | 1e-5  | exact (5.5e-5 coarse) |
+//! | hdop     | 0.01               | 0.01  | exact                 |
+//! | altitude | 0.25 m             | 0.01  | 0.13 m                |
+//! | speed    | 0.25 km/h          | 0.01  | 0.13 km/h             |
+//! | num_sats | 1                  | 1     | exact                 |
+//!
+//! Where the steps differ both sides round to nearest, so they can be apart by at most half of
+//! each step. Where the steps are the same both round half to even and must agree exactly.
+#![cfg(all(feature = "beacon", feature = "cell"))]
+use chrono::{TimeZone, Utc};
+use proptest::prelude::*;
+use rust_decimal::Decimal;
+use spot_messages::{
+    epoch::{Epoch, Versioned},
+    keys, AttachCandidate, Beacon, CellAttach, CellAttachResult, Gps, LoraDecode, LoraEncode,
+    Message, Payload, BEACON_V4, COARSE_HDOP,
+};
+
+/// 2023-01-01, the start of the LoRa time field
+const LORA_REFERENCE: i64 = 1_672_531_200;
+
+fn step(units: i64, scale: u32) -> Decimal {
+    Decimal::new(units, scale)
+}
+
+/// Half a step of each encoding
+fn envelope(lora_step: Decimal, proto_step: Decimal) -> Decimal {
+    (lora_step + proto_step) / Decimal::TWO
+}
+
+/// Fixes inside the LoRa ranges, with more decimals than either encoding carries so that the
+/// rounding of both is exercised
+fn gps() -> impl Strategy<Value = Gps> {
+    (
+        (0..1i64 << 30, 0..1_000_000_000u32),
+        -90_0000000i64..=90_0000000,
+        -180_0000000i64..=180_0000000,
+        0..=10_2300i64,
+        -110_0000i64..=145_7500,
+        0..=15u8,
+        0..=127_7500i64,
+    )
+        .prop_map(
+            |((seconds, nanos), lat, lon, hdop, altitude, num_sats, speed)| Gps {
+                timestamp: Utc.timestamp_opt(LORA_REFERENCE + seconds, nanos).unwrap(),
+                lat: Decimal::new(lat, 7),
+                lon: Decimal::new(lon, 7),
+                hdop: Decimal::new(hdop, 4),
+                altitude: Decimal::new(altitude, 4),
+                num_sats,
+                speed: Decimal::new(speed, 4),
+                ..Gps::default()
+            },
+        )
+}
+
+fn attach() -> impl Strategy<Value = CellAttach> {
+    (
+        gps(),
+        any::<u32>(),
+        (any::<u32>(), 0..=1023u32, any::<u32>(), any::<u16>()),
+        (-150..=105i32, -30..=225i32),
+        prop::sample::select(vec![
+            CellAttachResult::NoAttach,
+            CellAttachResult::Connected,
+            CellAttachResult::LimitedService,
+            CellAttachResult::NoConnection,
+            CellAttachResult::Search,
+            CellAttachResult::NoNetworkService,
+        ]),
+    )
+        .prop_map(
+            |(gps, attach_counter, (from_scan, delay, cell_id, fcn), (rsrp, rsrq), result)| {
+                CellAttach {
+                    attach_counter,
+                    gps,
+                    candidate: AttachCandidate {
+                        from_scan,
+                        delay,
+                        cell_id,
+                        fcn,
+                        rsrp,
+                        rsrq,
+                    },
+                    result,
+                    failure_cause: None,
+                    sim: None,
+                }
+            },
+        )
+}
+
+fn proto_roundtrip(payload: Payload) -> Payload {
+    let key = keys::file::File::create_key().unwrap();
+    let mut bytes = Vec::new();
+    Message::from_payload_signed(&key, payload)
+        .unwrap()
+        .encode_to(&mut bytes)
+        .unwrap();
+    Message::decode_from(&bytes).unwrap().payload
+}
+
+fn within(
+    field: &str,
+    lora: Decimal,
+    proto: Decimal,
+    envelope: Decimal,
+) -> Result<(), TestCaseError> {
+    prop_assert!(
+        (lora - proto).abs() <= envelope,
+        "{field} drifted: lora {lora}, proto {proto}, envelope {envelope}"
+    );
+    Ok(())
+}
+
+fn compare_fixes(lora: &Gps, proto: &Gps, latlon_step: Decimal) -> Result<(), TestCaseError> {
+    let (fine, centi) = (step(1, 5), step(1, 2));
+    let latlon = if latlon_step == fine {
+        Decimal::ZERO
+    } else {
+        envelope(latlon_step, fine)
+    };
+    prop_assert_eq!(lora.timestamp, proto.timestamp);
+    within("lat", lora.lat, proto.lat, latlon)?;
+    within("lon", lora.lon, proto.lon, latlon)?;
+    within("hdop", lora.hdop, proto.hdop, Decimal::ZERO)?;
+    within(
+        "altitude",
+        lora.altitude,
+        proto.altitude,
+        envelope(step(25, 2), centi),
+    )?;
+    within(
+        "speed",
+        lora.speed,
+        proto.speed,
+        envelope(step(25, 2), centi),
+    )?;
+    prop_assert_eq!(lora.num_sats, proto.num_sats);
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn beacon_encodings_agree(
+        gps in gps(),
+        signature in prop::collection::vec(any::<u8>(), 2..64),
+    ) {
+        let beacon = Beacon::new(gps, signature);
+        let (lora, _) = Beacon::from_lora_slice(&beacon.to_lora_bytes()).unwrap();
+        let Payload::Beacon(proto) = proto_roundtrip(Payload::Beacon(beacon)) else {
+            panic!("not a beacon");
+        };
+        compare_fixes(&lora.gps, &proto.gps, step(1, 5))?;
+    }
+
+    #[test]
+    fn adaptive_beacon_encodings_agree(gps in gps()) {
+        let beacon = Beacon::new(gps, vec![0xAB, 0xCD]);
+        let bytes = Versioned::new(beacon.clone(), Epoch::GENESIS)
+            .with_version(BEACON_V4)
+            .unwrap()
+            .to_lora_bytes();
+        let (lora, _) = Versioned::<Beacon>::from_lora_slice(&bytes).unwrap();
+        let Payload::Beacon(proto) = proto_roundtrip(Payload::Beacon(beacon)) else {
+            panic!("not a beacon");
+        };
+        let latlon_step = if lora.payload.gps.hdop >= COARSE_HDOP {
+            step(1, 4)
+        } else {
+            step(1, 5)
+        };
+        compare_fixes(&lora.payload.gps, &proto.gps, latlon_step)?;
+    }
+
+    #[test]
+    fn attach_encodings_agree(attach in attach()) {
+        let (lora, _) = CellAttach::from_lora_slice(&attach.to_lora_bytes()).unwrap();
+        let Payload::CellAttach(proto) = proto_roundtrip(Payload::CellAttach(attach)) else {
+            panic!("not an attach");
+        };
+        compare_fixes(&lora.gps, &proto.gps, step(1, 5))?;
+        prop_assert_eq!(lora.attach_counter, proto.attach_counter);
+        prop_assert_eq!(lora.candidate, proto.candidate);
+        prop_assert_eq!(lora.result, proto.result);
+    }
+}
@@ -0,0 +1,107 @@
+//! Messages from devices built against a newer helium-proto, carrying fields this crate doesn't
+//! know. They must either be relayed byte for byte or rejected up front, never silently
+//! re-encoded without the new fields.
+use bytes::Bytes;
+use spot_messages::{
+    helium_proto::{mapper_msg, MapperMsg, MapperMsgV1, MapperPayload},
+    keys::{self, KeyTrait},
+    Error, Gps, Message, Payload, ProtoMessage,
+};
+
+/// Field 15 as a varint, unknown at every level
+const UNKNOWN_FIELD: [u8; 2] = [15 << 3, 1];
+
+/// The key byte of a length-delimited field, found by encoding it empty
+fn key_of(empty: Vec<u8>) -> u8 {
+    assert_eq!(empty.len(), 2, "field number above 15");
+    empty[0]
+}
+
+/// A length-delimited field, the length as a varint
+fn wrap(key: u8, body: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![key];
+    let mut len = body.len();
+    while len >= 0x80 {
+        bytes.push(len as u8 | 0x80);
+        len >>= 7;
+    }
+    bytes.push(len as u8);
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+fn signed() -> Message {
+    let key = keys::file::File::create_key().unwrap();
+    Message::from_payload_signed(&key, Payload::Gps(Gps::rounded())).unwrap()
+}
+
+#[test]
+fn unknown_envelope_fields_are_relayed_or_rejected() {
+    let msg = signed();
+    let mut bytes = Vec::new();
+    msg.encode_to(&mut bytes).unwrap();
+    bytes.extend_from_slice(&UNKNOWN_FIELD);
+
+    // lenient decoding drops the field, so only retained bytes still carry it
+    assert_eq!(
+        Message::decode_from_with_signature_verification(&bytes).unwrap(),
+        msg
+    );
+    let retained = Message::decode_retaining(Bytes::from(bytes.clone())).unwrap();
+    assert_eq!(retained.forward_bytes().unwrap(), bytes);
+    let mut reencoded = Vec::new();
+    retained.encode_to(&mut reencoded).unwrap();
+    assert_ne!(reencoded, bytes);
+
+    assert!(matches!(
+        Message::decode_from_strict(&bytes),
+        Err(Error::UnknownProtoFields { .. })
+    ));
+    assert_eq!(Message::decode_from_strict(&reencoded).unwrap(), msg);
+}
+
+#[test]
+fn unknown_payload_fields_are_rejected_before_verification() {
+    let key = keys::file::File::create_key().unwrap();
+    let payload = Payload::Gps(Gps::rounded()).to_proto();
+    // what a newer device signs: its payload with a field added upstream
+    let mut signed_payload = payload.encode_to_vec();
+    signed_payload.extend_from_slice(&UNKNOWN_FIELD);
+    // raw bytes can't go through KeyTrait, sign them with the keypair directly
+    let signature = helium_crypto::Sign::sign(key.keypair.as_ref(), &signed_payload).unwrap();
+
+    let v1 = MapperMsgV1 {
+        payload: Some(payload),
+        signature,
+        pubkey: key.pubkey().unwrap().to_vec(),
+        lora_gws: vec![],
+    };
+    let mut v1_bytes = v1.encode_to_vec();
+    // a second occurrence of the payload field merges into the first
+    let payload_key = key_of(
+        MapperMsgV1 {
+            payload: Some(MapperPayload::default()),
+            ..Default::default()
+        }
+        .encode_to_vec(),
+    );
+    v1_bytes.extend(wrap(payload_key, &UNKNOWN_FIELD));
+    let version_key = key_of(
+        MapperMsg {
+            version: Some(mapper_msg::Version::MsgV1(MapperMsgV1::default())),
+        }
+        .encode_to_vec(),
+    );
+    // a signed MapperMsgV1 is well over 127 bytes
+    assert!(v1_bytes.len() >= 0x80);
+    let bytes = wrap(version_key, &v1_bytes);
+
+    assert!(matches!(
+        Message::decode_from_with_signature_verification(&bytes),
+        Err(Error::SignatureVerification { .. })
+    ));
+    assert!(matches!(
+        Message::decode_from_strict_with_signature_verification(&bytes),
+        Err(Error::UnknownProtoFields { .. })
+    ));
+}
@@ -0,0 +1,61 @@
+//! Pins the JSON representation of Decimal fields: strings padded to the proto scale, or
+//! floats with the `json-float` feature. Both forms must keep deserializing.
+use rust_decimal::Decimal;
+use spot_messages::{Gps, LoraGw};
+
+fn lora_gw() -> LoraGw {
+    LoraGw {
+        snr: Decimal::new(-55, 1),
+        ..LoraGw::random()
+    }
+}
+
+#[cfg(not(feature = "json-float"))]
+#[test]
+fn decimals_are_fixed_scale_strings() {
+    let gps = Gps {
+        h_acc_m: Some(Decimal::new(3, 0)),
+        ..Gps::rounded()
+    };
+    assert_eq!(
+        serde_json::to_string(&gps).unwrap(),
+        r#"{"timestamp":"2023-01-01T00:00:05Z","lat":"-50.12345","lon":"120.12345","hdop":"9.05","altitude":"9.25","num_sats":5,"speed":"50.50","h_acc_m":"3.00"}"#
+    );
+    let json = serde_json::to_value(lora_gw()).unwrap();
+    assert_eq!(
+        (&json["snr"], &json["rssi"]),
+        (&"-5.5".into(), &"-110.00".into())
+    );
+
+    // digits beyond the proto scale are kept
+    let precise = Gps {
+        lat: Decimal::new(-50_1234567, 7),
+        ..Gps::rounded()
+    };
+    let json = serde_json::to_value(precise).unwrap();
+    assert_eq!(json["lat"], "-50.1234567");
+}
+
+#[cfg(feature = "json-float")]
+#[test]
+fn decimals_are_floats() {
+    let json = serde_json::to_value(Gps::rounded()).unwrap();
+    assert_eq!(json["lat"], -50.12345);
+    assert_eq!(json["speed"], 50.5);
+}
+
+#[test]
+fn strings_and_numbers_deserialize() {
+    let strings = serde_json::to_value(Gps::rounded()).unwrap();
+    let mut numbers = strings.clone();
+    for (field, value) in [("lat", -50.12345), ("hdop", 9.05), ("speed", 50.5)] {
+        numbers[field] = value.into();
+    }
+    numbers["num_sats"] = 5.into();
+    for json in [strings, numbers] {
+        assert_eq!(serde_json::from_value::<Gps>(json).unwrap(), Gps::rounded());
+    }
+    let gw = lora_gw();
+    let json = serde_json::to_string(&gw).unwrap();
+    assert_eq!(serde_json::from_str::<LoraGw>(&json).unwrap(), gw);
+}
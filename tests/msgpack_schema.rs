@@ -0,0 +1,52 @@
+//! Pins the MessagePack representation of payloads, which edge processes exchange on their local
+//! bus and may not upgrade together. Field names and value shapes must match the JSON ones.
+#![cfg(feature = "msgpack")]
+use spot_messages::{keys, Gps, Message, Payload};
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(not(feature = "json-float"))]
+#[test]
+fn gps_payload_bytes() {
+    let payload = Payload::Gps(Gps::rounded());
+    let bytes = payload.to_msgpack().unwrap();
+    assert_eq!(
+        hex(&bytes),
+        concat!(
+            "81a3677073",
+            "87a974696d657374616d70b4323032332d30312d30315430303a30303a30355a",
+            "a36c6174a92d35302e3132333435a36c6f6ea93132302e3132333435",
+            "a468646f70a4392e3035a8616c746974756465a4392e3235",
+            "a86e756d5f7361747305a57370656564a535302e3530",
+        )
+    );
+    assert_eq!(Payload::from_msgpack(&bytes).unwrap(), payload);
+}
+
+#[cfg(feature = "cell")]
+#[test]
+fn message_roundtrip() {
+    let key = keys::file::File::create_key().unwrap();
+    let msg =
+        Message::from_payload_signed(&key, Payload::CellScan(spot_messages::CellScan::random()))
+            .unwrap();
+    let bytes = msg.to_msgpack().unwrap();
+    assert_eq!(Message::from_msgpack(&bytes).unwrap(), msg);
+    // same shape as JSON, so both decode into the same value
+    let json: serde_json::Value = serde_json::to_value(&msg).unwrap();
+    let from_msgpack: serde_json::Value = rmp_serde::from_slice(&bytes).unwrap();
+    assert_eq!(from_msgpack, json);
+}
+
+#[test]
+fn tolerates_missing_optional_fields() {
+    let mut gps = Gps::rounded();
+    gps.h_acc_m = Some(rust_decimal::Decimal::new(3, 0));
+    let bytes = Payload::Gps(gps).to_msgpack().unwrap();
+    assert_eq!(Payload::from_msgpack(&bytes).unwrap(), Payload::Gps(gps));
+    // a map of 8 fields after the payload tag: unset options are skipped, not written as nil
+    assert_eq!(bytes[5], 0x88);
+    assert!(Payload::from_msgpack(&bytes[..bytes.len() - 1]).is_err());
+}
@@ -0,0 +1,60 @@
+//! Pins the wire output of `proto_version::golden_fixtures` to the bytes checked in under
+//! `tests/golden`, so that a helium-proto update changing field numbers or types fails here
+//! rather than in the field.
+//!
+//! The helium-proto built against must be listed in `tests/golden/versions`, and every fixture
+//! must have its golden file there. Only with `SPOT_MESSAGES_BLESS` set are the files written,
+//! to record them from the encoder after an intended wire change.
+use spot_messages::proto_version::{golden_fixtures, proto_version, HELIUM_PROTO_SOURCE};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// The fixture dir `versions` lists for `source`
+fn fixture_dir(golden: &Path, source: &str) -> Option<PathBuf> {
+    fs::read_to_string(golden.join("versions"))
+        .expect("tests/golden/versions is checked in")
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .filter_map(|line| line.split_once(char::is_whitespace))
+        .find(|(listed, _)| *listed == source)
+        .map(|(_, dir)| golden.join(dir.trim()))
+}
+
+#[test]
+fn encodings_match_golden_bytes() {
+    let golden = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let dir = fixture_dir(&golden, HELIUM_PROTO_SOURCE)
+        .unwrap_or_else(|| panic!("{HELIUM_PROTO_SOURCE} is not in tests/golden/versions"));
+    let bless = env::var_os("SPOT_MESSAGES_BLESS").is_some();
+    let mut mismatches = vec![];
+    for (name, bytes) in golden_fixtures() {
+        let path = dir.join(format!("{name}.hex"));
+        let encoded = hex(&bytes);
+        if bless {
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(&path, format!("{encoded}\n")).unwrap();
+            continue;
+        }
+        match fs::read_to_string(&path) {
+            Ok(golden) if golden.trim() == encoded => (),
+            Ok(golden) => mismatches.push(format!(
+                "{name}:\n  golden  {}\n  encoded {encoded}",
+                golden.trim()
+            )),
+            Err(err) => mismatches.push(format!("{name}: {}: {err}", path.display())),
+        }
+    }
+    assert!(
+        mismatches.is_empty(),
+        "wire output of {} differs from {}:\n{}\nre-record with SPOT_MESSAGES_BLESS=1 if intended",
+        proto_version(),
+        dir.display(),
+        mismatches.join("\n")
+    );
+}